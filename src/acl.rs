@@ -0,0 +1,962 @@
+use crate::client::{Client, CLIENT_MULTI, CLIENT_LUA, CLIENT_CLOSE_AFTER_REPLY};
+use crate::server::Server;
+use crate::ae::AeEventLoop;
+use crate::object::RobjPtr;
+use crate::command::{
+    Command, CMD_TABLE, CMD_WRITE, ACL_CATEGORY_NAMES, client_info_string,
+    command_acl_categories, get_keys_from_command,
+};
+use crate::glob::glob_match;
+use crate::sort::sort_store_index;
+use crate::util::*;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fs::{self, File};
+use std::io::Write;
+use std::rc::Rc;
+use std::time::SystemTime;
+
+pub const DEFAULT_USER: &str = "default";
+
+// permissions of a key pattern
+const KEY_READ: u8 = 0b01;
+const KEY_WRITE: u8 = 0b10;
+
+// failures of the same kind within this many seconds share a log entry
+const ACL_LOG_GROUPING_MAX_TIME_DELTA: u64 = 60;
+
+#[derive(Clone)]
+struct KeyPattern {
+    pattern: Vec<u8>,
+    flags: u8,
+}
+
+impl KeyPattern {
+    fn describe(&self) -> String {
+        let prefix = match self.flags {
+            KEY_READ => "%R~",
+            KEY_WRITE => "%W~",
+            _ => "~",
+        };
+        format!("{}{}", prefix, String::from_utf8_lossy(&self.pattern))
+    }
+}
+
+#[derive(Clone)]
+pub struct User {
+    pub name: String,
+    pub enabled: bool,
+    // no password is needed, any one is accepted
+    pub nopass: bool,
+    // SHA256 of the passwords, in hex
+    passwords: Vec<String>,
+    allowed_commands: BTreeSet<&'static str>,
+    // the command rules as given, compacted when all or no commands
+    // are allowed, the allowed commands are built from these
+    command_rules: Vec<String>,
+    keys: Vec<KeyPattern>,
+    channels: Vec<Vec<u8>>,
+}
+
+impl User {
+    // a new user can't do anything until it is given permissions
+    pub fn new(name: &str) -> User {
+        User {
+            name: name.to_string(),
+            enabled: false,
+            nopass: false,
+            passwords: vec![],
+            allowed_commands: BTreeSet::new(),
+            command_rules: vec!["-@all".to_string()],
+            keys: vec![],
+            channels: vec![],
+        }
+    }
+
+    // the user of the clients that didn't authenticate, with no password
+    // it can do anything, like a server without ACL
+    pub fn new_default() -> User {
+        let mut user = User::new(DEFAULT_USER);
+        for rule in ["on", "nopass", "~*", "&*", "+@all"].iter() {
+            user.set_rule(rule).unwrap();
+        }
+        user
+    }
+
+    // Applies one of the rules of ACL SETUSER:
+    //
+    //   on|off                  enables or disables the user
+    //   >password / <password   adds or removes a password
+    //   #hash / !hash           adds or removes the SHA256 of a password
+    //   nopass / resetpass      any password works / no password works
+    //   +command / -command     allows or denies a command
+    //   +@category / -@category allows or denies the commands of a category
+    //   allcommands/nocommands  the same as +@all and -@all
+    //   ~pattern                keys the user can read and write
+    //   %R~pattern / %W~pattern keys the user can only read / write
+    //   allkeys / resetkeys     the same as ~* / no key allowed
+    //   &pattern                pub/sub channels the user can access
+    //   allchannels             the same as &*
+    //   resetchannels           no channel allowed
+    //   reset                   back to a new user
+    pub fn set_rule(&mut self, rule: &str) -> Result<(), String> {
+        let lower = rule.to_ascii_lowercase();
+        match &lower[..] {
+            "on" => self.enabled = true,
+            "off" => self.enabled = false,
+            "nopass" => {
+                self.nopass = true;
+                self.passwords.clear();
+            }
+            "resetpass" => {
+                self.nopass = false;
+                self.passwords.clear();
+            }
+            "allcommands" => self.set_command_rule("+@all")?,
+            "nocommands" => self.set_command_rule("-@all")?,
+            "allkeys" => self.keys = vec![KeyPattern { pattern: b"*".to_vec(), flags: KEY_READ | KEY_WRITE }],
+            "resetkeys" => self.keys.clear(),
+            "allchannels" => self.channels = vec![b"*".to_vec()],
+            "resetchannels" => self.channels.clear(),
+            "reset" => *self = User::new(&self.name),
+            _ => match rule.as_bytes()[0] {
+                b'>' => {
+                    let hash = hash_password(&rule.as_bytes()[1..]);
+                    self.add_password_hash(hash);
+                }
+                b'#' => {
+                    let hash = &rule[1..];
+                    if hash.len() != 64 || !hash.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b)) {
+                        return Err("The password hash must be exactly 64 characters and contain \
+                            only lowercase hexadecimal characters".to_string());
+                    }
+                    self.add_password_hash(hash.to_string());
+                }
+                b'<' => {
+                    let hash = hash_password(&rule.as_bytes()[1..]);
+                    self.remove_password_hash(&hash)?;
+                }
+                b'!' => self.remove_password_hash(&rule[1..])?,
+                b'+' | b'-' => self.set_command_rule(rule)?,
+                b'~' => self.add_key_pattern(&rule[1..], KEY_READ | KEY_WRITE),
+                b'%' => {
+                    let (perms, pattern) = match rule[1..].find('~') {
+                        Some(pos) => (&rule[1..pos + 1], &rule[pos + 2..]),
+                        None => return Err("Syntax error".to_string()),
+                    };
+                    let mut flags = 0;
+                    for c in perms.chars() {
+                        flags |= match c.to_ascii_uppercase() {
+                            'R' => KEY_READ,
+                            'W' => KEY_WRITE,
+                            _ => return Err("Syntax error".to_string()),
+                        };
+                    }
+                    if flags == 0 {
+                        return Err("Syntax error".to_string());
+                    }
+                    self.add_key_pattern(pattern, flags);
+                }
+                b'&' => {
+                    let pattern = rule.as_bytes()[1..].to_vec();
+                    if !self.channels.contains(&pattern) {
+                        self.channels.push(pattern);
+                    }
+                }
+                _ => return Err("Syntax error".to_string()),
+            },
+        }
+        Ok(())
+    }
+
+    fn add_password_hash(&mut self, hash: String) {
+        self.nopass = false;
+        if !self.passwords.contains(&hash) {
+            self.passwords.push(hash);
+        }
+    }
+
+    fn remove_password_hash(&mut self, hash: &str) -> Result<(), String> {
+        match self.passwords.iter().position(|h| h == hash) {
+            Some(pos) => {
+                self.passwords.remove(pos);
+                Ok(())
+            }
+            None => Err("The password you are trying to remove from the user does not exist".to_string()),
+        }
+    }
+
+    fn add_key_pattern(&mut self, pattern: &str, flags: u8) {
+        let pattern = pattern.as_bytes().to_vec();
+        match self.keys.iter_mut().find(|k| k.pattern == pattern) {
+            Some(k) => k.flags |= flags,
+            None => self.keys.push(KeyPattern { pattern, flags }),
+        }
+    }
+
+    // +command, -command, +@category or -@category
+    fn set_command_rule(&mut self, rule: &str) -> Result<(), String> {
+        let allow = rule.starts_with('+');
+        let name = rule[1..].to_ascii_lowercase();
+        let commands: Vec<&'static str> = if name == "@all" {
+            CMD_TABLE.iter().map(|cmd| cmd.name).collect()
+        } else if let Some(category) = name.strip_prefix('@') {
+            let category = acl_category_by_name(category)
+                .ok_or_else(|| "Unknown command or category name in ACL".to_string())?;
+            CMD_TABLE.iter()
+                .filter(|cmd| command_acl_categories(cmd) & category != 0)
+                .map(|cmd| cmd.name)
+                .collect()
+        } else {
+            let cmd = CMD_TABLE.iter().find(|cmd| cmd.name == name)
+                .ok_or_else(|| "Unknown command or category name in ACL".to_string())?;
+            vec![cmd.name]
+        };
+
+        for name in commands {
+            if allow {
+                self.allowed_commands.insert(name);
+            } else {
+                self.allowed_commands.remove(name);
+            }
+        }
+        let rule = format!("{}{}", &rule[..1], name);
+        if name == "@all" {
+            self.command_rules.clear();
+        }
+        self.command_rules.push(rule);
+        Ok(())
+    }
+
+    pub fn check_password(&self, password: &[u8]) -> bool {
+        if self.nopass {
+            return true;
+        }
+        let hash = hash_password(password);
+        self.passwords.contains(&hash)
+    }
+
+    // read and write access may be granted by different patterns
+    fn can_access_key(&self, key: &[u8], flags: u8) -> bool {
+        [KEY_READ, KEY_WRITE].iter()
+            .filter(|f| flags & **f != 0)
+            .all(|f| self.keys.iter().any(|k| k.flags & f != 0 && glob_match(&k.pattern, key, false)))
+    }
+
+    pub fn can_read_key(&self, key: &[u8]) -> bool {
+        self.can_access_key(key, KEY_READ)
+    }
+
+    fn can_access_channel(&self, channel: &[u8], is_pattern: bool) -> bool {
+        self.channels.iter().any(|c| {
+            // a pattern is only allowed when it is an allowed one as well
+            if is_pattern {
+                c.as_slice() == b"*" || c.as_slice() == channel
+            } else {
+                glob_match(c, channel, false)
+            }
+        })
+    }
+
+    fn flags(&self) -> Vec<&'static str> {
+        let mut flags = vec![if self.enabled { "on" } else { "off" }];
+        if self.nopass {
+            flags.push("nopass");
+        }
+        flags
+    }
+
+    fn describe_keys(&self) -> String {
+        self.keys.iter()
+            .map(|k| k.describe())
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    fn describe_channels(&self) -> String {
+        self.channels.iter()
+            .map(|c| format!("&{}", String::from_utf8_lossy(c)))
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    fn describe_commands(&self) -> String {
+        self.command_rules.join(" ")
+    }
+
+    // the rules recreating the user, as in ACL LIST and the ACL file
+    pub fn describe(&self) -> String {
+        let mut rules: Vec<String> = self.flags().iter().map(|f| f.to_string()).collect();
+        for hash in self.passwords.iter() {
+            rules.push(format!("#{}", hash));
+        }
+        if !self.keys.is_empty() {
+            rules.push(self.describe_keys());
+        }
+        if self.channels.is_empty() {
+            rules.push("resetchannels".to_string());
+        } else {
+            rules.push(self.describe_channels());
+        }
+        rules.push(self.describe_commands());
+        format!("user {} {}", self.name, rules.join(" "))
+    }
+}
+
+fn hash_password(password: &[u8]) -> String {
+    Sha256::digest(password).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn acl_category_by_name(name: &str) -> Option<u64> {
+    ACL_CATEGORY_NAMES.iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, category)| *category)
+}
+
+// why a command was refused
+pub enum AclDenied {
+    Command,
+    // the index in argv of the key or channel
+    Key(usize),
+    Channel(usize),
+}
+
+impl AclDenied {
+    fn reason(&self) -> &'static str {
+        match self {
+            AclDenied::Command => "command",
+            AclDenied::Key(_) => "key",
+            AclDenied::Channel(_) => "channel",
+        }
+    }
+}
+
+pub struct AclLogEntry {
+    pub id: u64,
+    pub count: usize,
+    pub reason: &'static str,
+    // toplevel, multi or lua
+    pub context: &'static str,
+    // the command, key or channel refused, or AUTH
+    pub object: String,
+    pub username: String,
+    pub created: SystemTime,
+    pub updated: SystemTime,
+    pub client_info: String,
+}
+
+pub struct Acl {
+    pub users: BTreeMap<String, User>,
+    // the newest entries are at the front
+    pub log: VecDeque<AclLogEntry>,
+    pub log_next_id: u64,
+}
+
+impl Default for Acl {
+    fn default() -> Self {
+        let mut users = BTreeMap::new();
+        users.insert(DEFAULT_USER.to_string(), User::new_default());
+        Acl {
+            users,
+            log: VecDeque::new(),
+            log_next_id: 0,
+        }
+    }
+}
+
+impl Acl {
+    // the clients have to authenticate unless the default user is enabled
+    // and needs no password
+    pub fn auth_required(&self) -> bool {
+        match self.users.get(DEFAULT_USER) {
+            Some(u) => !u.enabled || !u.nopass,
+            None => true,
+        }
+    }
+
+    // The user `name` if it exists, is enabled and accepts the password.
+    pub fn authenticate(&self, name: &str, password: &[u8]) -> bool {
+        match self.users.get(name) {
+            Some(u) => u.enabled && u.check_password(password),
+            None => false,
+        }
+    }
+
+    // `requirepass` is the password of the default user
+    pub fn set_default_password(&mut self, password: Option<&str>) {
+        let user = self.users.entry(DEFAULT_USER.to_string())
+            .or_insert_with(User::new_default);
+        let _ = user.set_rule("resetpass");
+        match password {
+            Some(p) => user.set_rule(&format!(">{}", p)).unwrap(),
+            None => user.set_rule("nopass").unwrap(),
+        }
+    }
+
+    pub fn add_log_entry(&mut self, client: &Client, reason: &'static str, object: String, max_len: usize) {
+        let context = if client.flags & CLIENT_LUA != 0 {
+            "lua"
+        } else if client.flags & CLIENT_MULTI != 0 {
+            "multi"
+        } else {
+            "toplevel"
+        };
+        let now = SystemTime::now();
+        let username = client.user.clone();
+
+        // a failure repeated shortly after is counted in the same entry
+        let similar = self.log.iter_mut().find(|e| {
+            e.reason == reason && e.context == context && e.object == object &&
+                e.username == username &&
+                now.duration_since(e.updated).map_or(true, |d| d.as_secs() < ACL_LOG_GROUPING_MAX_TIME_DELTA)
+        });
+        if let Some(e) = similar {
+            e.count += 1;
+            e.updated = now;
+            e.client_info = client_info_string(client);
+            return;
+        }
+
+        let entry = AclLogEntry {
+            id: self.log_next_id,
+            count: 1,
+            reason,
+            context,
+            object,
+            username,
+            created: now,
+            updated: now,
+            client_info: client_info_string(client),
+        };
+        self.log_next_id += 1;
+        self.log.push_front(entry);
+        self.log.truncate(max_len);
+    }
+}
+
+// The keys of the command with the access each one needs. A write command
+// that returns or moves the data it finds reads it as well, the sources of
+// a *STORE command are only read.
+fn command_key_flags(cmd: &Command, argv: &[RobjPtr]) -> Vec<(usize, u8)> {
+    let mut keys: Vec<(usize, u8)> = get_keys_from_command(cmd, argv.len()).into_iter()
+        .map(|i| {
+            let flags = match cmd.name {
+                _ if cmd.flags & CMD_WRITE == 0 => KEY_READ,
+                "sort" => KEY_READ,
+                "sinterstore" | "sunionstore" | "sdiffstore" if i > 1 => KEY_READ,
+                "smove" | "rename" | "renamenx" if i == 1 => KEY_READ | KEY_WRITE,
+                "incr" | "decr" | "incrby" | "decrby" | "getset" | "getex" | "getdel" |
+                "lpop" | "rpop" | "spop" | "move" => KEY_READ | KEY_WRITE,
+                _ => KEY_WRITE,
+            };
+            (i, flags)
+        })
+        .collect();
+    if cmd.name == "sort" {
+        if let Some(i) = sort_store_index(argv) {
+            keys.push((i, KEY_WRITE));
+        }
+    }
+    keys
+}
+
+// Whether the user of the client may run the command in argv, checking the
+// command, then the keys and the pub/sub channels it accesses.
+pub fn acl_check_command_perm(user: &User, cmd: &Command, argv: &[RobjPtr]) -> Result<(), AclDenied> {
+    // anyone can try to authenticate
+    if cmd.name == "auth" || cmd.name == "hello" {
+        return Ok(());
+    }
+    if !user.allowed_commands.contains(cmd.name) {
+        return Err(AclDenied::Command);
+    }
+
+    for (i, flags) in command_key_flags(cmd, argv) {
+        if !user.can_access_key(argv[i].borrow().string(), flags) {
+            return Err(AclDenied::Key(i));
+        }
+    }
+
+    let (channels, is_pattern) = match cmd.name {
+        "publish" | "spublish" => (1..2, false),
+        "subscribe" | "ssubscribe" => (1..argv.len(), false),
+        "psubscribe" => (1..argv.len(), true),
+        _ => (0..0, false),
+    };
+    for i in channels {
+        if !user.can_access_channel(argv[i].borrow().string(), is_pattern) {
+            return Err(AclDenied::Channel(i));
+        }
+    }
+    Ok(())
+}
+
+// Checks the permissions of the client for the command in its argv, a
+// refused command is recorded in the ACL log.
+pub fn acl_check_client_perm(server: &mut Server, client: &Client, cmd: &Command) -> Result<(), AclDenied> {
+    let r = match server.acl.users.get(&client.user) {
+        Some(user) => acl_check_command_perm(user, cmd, &client.argv),
+        // the clients of a deleted user are being closed
+        None => Err(AclDenied::Command),
+    };
+    if let Err(denied) = &r {
+        let object = match denied {
+            AclDenied::Command => cmd.name.to_string(),
+            AclDenied::Key(i) | AclDenied::Channel(i) =>
+                String::from_utf8_lossy(client.argv[*i].borrow().string()).to_string(),
+        };
+        let max_len = server.acllog_max_len;
+        server.acl.add_log_entry(client, denied.reason(), object, max_len);
+    }
+    r
+}
+
+pub fn acl_denied_message(client: &Client, cmd: &Command, denied: &AclDenied) -> String {
+    match denied {
+        AclDenied::Command => format!(
+            "User {} has no permissions to run the '{}' command",
+            client.user, cmd.name,
+        ),
+        AclDenied::Key(_) => "No permissions to access a key".to_string(),
+        AclDenied::Channel(_) => "No permissions to access a channel".to_string(),
+    }
+}
+
+// AUTH [username] password
+pub fn auth_command(
+    client: &mut Client,
+    server: &mut Server,
+    _el: &mut AeEventLoop,
+) {
+    if client.argc() > 3 {
+        client.add_reply_error("syntax error");
+        return;
+    }
+    let (username, password) = if client.argc() == 2 {
+        if !server.acl.auth_required() {
+            client.add_reply_error("AUTH <password> called without any password configured for the \
+                default user. Are you sure your configuration is correct?");
+            return;
+        }
+        (DEFAULT_USER.to_string(), client.argv[1].borrow().string_to_vec())
+    } else {
+        (
+            String::from_utf8_lossy(client.argv[1].borrow().string()).to_string(),
+            client.argv[2].borrow().string_to_vec(),
+        )
+    };
+
+    if acl_authenticate_client(server, client, username, &password) {
+        client.add_reply_ok();
+    } else {
+        client.add_reply_error_code("WRONGPASS", WRONGPASS_ERR);
+    }
+}
+
+pub const WRONGPASS_ERR: &str = "invalid username-password pair or user is disabled.";
+
+// Authenticates the client as `username`, a failure is recorded in the
+// ACL log, for AUTH and HELLO.
+pub fn acl_authenticate_client(server: &mut Server, client: &mut Client, username: String, password: &[u8]) -> bool {
+    if server.acl.authenticate(&username, password) {
+        client.authenticate = true;
+        client.user = username;
+        return true;
+    }
+    // logged with the user that was tried, the client keeps its own
+    let max_len = server.acllog_max_len;
+    let user = std::mem::replace(&mut client.user, username);
+    server.acl.add_log_entry(client, "auth", "AUTH".to_string(), max_len);
+    client.user = user;
+    false
+}
+
+pub fn acl_command(
+    client: &mut Client,
+    server: &mut Server,
+    _el: &mut AeEventLoop,
+) {
+    let sub = client.argv[1].borrow().string_to_vec();
+    if case_eq(b"setuser", &sub) && client.argc() >= 3 {
+        acl_setuser_command(client, server);
+    } else if case_eq(b"getuser", &sub) && client.argc() == 3 {
+        acl_getuser_command(client, server);
+    } else if case_eq(b"deluser", &sub) && client.argc() >= 3 {
+        acl_deluser_command(client, server);
+    } else if case_eq(b"list", &sub) && client.argc() == 2 {
+        let lines: Vec<String> = server.acl.users.values().map(|u| u.describe()).collect();
+        client.add_reply_array_len(lines.len());
+        for line in lines {
+            client.add_reply_bulk_bytes(line.as_bytes());
+        }
+    } else if case_eq(b"users", &sub) && client.argc() == 2 {
+        let names: Vec<String> = server.acl.users.keys().cloned().collect();
+        client.add_reply_array_len(names.len());
+        for name in names {
+            client.add_reply_bulk_bytes(name.as_bytes());
+        }
+    } else if case_eq(b"whoami", &sub) && client.argc() == 2 {
+        let user = client.user.clone();
+        client.add_reply_bulk_bytes(user.as_bytes());
+    } else if case_eq(b"cat", &sub) && client.argc() <= 3 {
+        acl_cat_command(client);
+    } else if case_eq(b"log", &sub) && client.argc() <= 3 {
+        acl_log_command(client, server);
+    } else if case_eq(b"load", &sub) && client.argc() == 2 {
+        match acl_load(server) {
+            Ok(()) => {
+                if !server.acl.users.contains_key(&client.user) {
+                    client.flags |= CLIENT_CLOSE_AFTER_REPLY;
+                }
+                client.add_reply_ok();
+            }
+            Err(e) => client.add_reply_error(&e),
+        }
+    } else if case_eq(b"save", &sub) && client.argc() == 2 {
+        match acl_save(server) {
+            Ok(()) => client.add_reply_ok(),
+            Err(e) => client.add_reply_error(&e),
+        }
+    } else {
+        client.add_reply_error(&format!(
+            "Unknown ACL subcommand or wrong number of arguments for '{}'",
+            String::from_utf8_lossy(&sub),
+        ));
+    }
+}
+
+// ACL SETUSER username [rule ...], the rules are all applied or none is
+fn acl_setuser_command(client: &mut Client, server: &mut Server) {
+    let name = String::from_utf8_lossy(client.argv[2].borrow().string()).to_string();
+    let mut user = server.acl.users.get(&name)
+        .cloned()
+        .unwrap_or_else(|| User::new(&name));
+    for arg in client.argv[3..].iter() {
+        let rule = String::from_utf8_lossy(arg.borrow().string()).to_string();
+        if rule.is_empty() {
+            client.add_reply_error("Error in ACL SETUSER modifier '': Syntax error");
+            return;
+        }
+        if let Err(e) = user.set_rule(&rule) {
+            client.add_reply_error(&format!(
+                "Error in ACL SETUSER modifier '{}': {}",
+                rule, e,
+            ));
+            return;
+        }
+    }
+    let enabled = user.enabled;
+    server.acl.users.insert(name.clone(), user);
+    // the clients of a disabled user can't use it anymore
+    if !enabled {
+        kill_clients_of_users(server, client, &[name]);
+    }
+    client.add_reply_ok();
+}
+
+fn acl_getuser_command(client: &mut Client, server: &Server) {
+    let name = String::from_utf8_lossy(client.argv[2].borrow().string()).to_string();
+    let user = match server.acl.users.get(&name) {
+        Some(u) => u,
+        None => {
+            client.add_reply_null_array();
+            return;
+        }
+    };
+
+    client.add_reply_map_len(5);
+    client.add_reply_bulk_bytes(b"flags");
+    let flags = user.flags();
+    client.add_reply_array_len(flags.len());
+    for flag in flags.iter() {
+        client.add_reply_bulk_bytes(flag.as_bytes());
+    }
+    client.add_reply_bulk_bytes(b"passwords");
+    client.add_reply_array_len(user.passwords.len());
+    for password in user.passwords.iter() {
+        client.add_reply_bulk_bytes(password.as_bytes());
+    }
+    client.add_reply_bulk_bytes(b"commands");
+    client.add_reply_bulk_bytes(user.describe_commands().as_bytes());
+    client.add_reply_bulk_bytes(b"keys");
+    client.add_reply_bulk_bytes(user.describe_keys().as_bytes());
+    client.add_reply_bulk_bytes(b"channels");
+    client.add_reply_bulk_bytes(user.describe_channels().as_bytes());
+}
+
+// ACL DELUSER username [username ...]
+fn acl_deluser_command(client: &mut Client, server: &mut Server) {
+    let names: Vec<String> = client.argv[2..].iter()
+        .map(|n| String::from_utf8_lossy(n.borrow().string()).to_string())
+        .collect();
+    if names.iter().any(|n| n == DEFAULT_USER) {
+        client.add_reply_error("The 'default' user cannot be removed");
+        return;
+    }
+    let deleted: Vec<String> = names.into_iter()
+        .filter(|n| server.acl.users.remove(n).is_some())
+        .collect();
+    kill_clients_of_users(server, client, &deleted);
+    client.add_reply_integer(deleted.len() as i64);
+}
+
+// Closes the clients authenticated as one of the users, the current one
+// once it got its reply.
+fn kill_clients_of_users(server: &mut Server, current: &mut Client, names: &[String]) {
+    let others: Vec<Rc<RefCell<Client>>> = server.clients.iter()
+        .filter(|c| !std::ptr::eq(c.as_ptr(), current))
+        .filter(|c| names.contains(&c.borrow().user))
+        .map(Rc::clone)
+        .collect();
+    for c in others.iter() {
+        server.async_free_client(&mut c.borrow_mut());
+    }
+    if names.contains(&current.user) {
+        current.flags |= CLIENT_CLOSE_AFTER_REPLY;
+    }
+}
+
+// ACL CAT [category]
+fn acl_cat_command(client: &mut Client) {
+    let names: Vec<&str> = if client.argc() == 2 {
+        ACL_CATEGORY_NAMES.iter().map(|(name, _)| *name).collect()
+    } else {
+        let name = String::from_utf8_lossy(client.argv[2].borrow().string()).to_ascii_lowercase();
+        let category = match acl_category_by_name(&name) {
+            Some(c) => c,
+            None => {
+                client.add_reply_error(&format!("Unknown category '{}'", name));
+                return;
+            }
+        };
+        CMD_TABLE.iter()
+            .filter(|cmd| command_acl_categories(cmd) & category != 0)
+            .map(|cmd| cmd.name)
+            .collect()
+    };
+    client.add_reply_array_len(names.len());
+    for name in names {
+        client.add_reply_bulk_bytes(name.as_bytes());
+    }
+}
+
+// ACL LOG [count|RESET]
+fn acl_log_command(client: &mut Client, server: &mut Server) {
+    let mut count = usize::MAX;
+    if client.argc() == 3 {
+        let arg = client.argv[2].borrow().string_to_vec();
+        if case_eq(b"reset", &arg) {
+            server.acl.log.clear();
+            client.add_reply_ok();
+            return;
+        }
+        match bytes_to_usize(&arg) {
+            Ok(n) => count = n,
+            Err(_) => {
+                client.add_reply_error("value is out of range, must be positive");
+                return;
+            }
+        }
+    }
+
+    let now = SystemTime::now();
+    let entries: Vec<&AclLogEntry> = server.acl.log.iter().take(count).collect();
+    client.add_reply_array_len(entries.len());
+    for e in entries {
+        let age = now.duration_since(e.created)
+            .map(|d| d.as_secs_f64())
+            .unwrap_or(0.0);
+        client.add_reply_map_len(10);
+        client.add_reply_bulk_bytes(b"count");
+        client.add_reply_integer(e.count as i64);
+        client.add_reply_bulk_bytes(b"reason");
+        client.add_reply_bulk_bytes(e.reason.as_bytes());
+        client.add_reply_bulk_bytes(b"context");
+        client.add_reply_bulk_bytes(e.context.as_bytes());
+        client.add_reply_bulk_bytes(b"object");
+        client.add_reply_bulk_bytes(e.object.as_bytes());
+        client.add_reply_bulk_bytes(b"username");
+        client.add_reply_bulk_bytes(e.username.as_bytes());
+        client.add_reply_bulk_bytes(b"age-seconds");
+        client.add_reply_double((age * 1000.0).round() / 1000.0);
+        client.add_reply_bulk_bytes(b"client-info");
+        client.add_reply_bulk_bytes(e.client_info.as_bytes());
+        client.add_reply_bulk_bytes(b"entry-id");
+        client.add_reply_integer(e.id as i64);
+        client.add_reply_bulk_bytes(b"timestamp-created");
+        client.add_reply_integer(unix_timestamp(&e.created) as i64);
+        client.add_reply_bulk_bytes(b"timestamp-last-updated");
+        client.add_reply_integer(unix_timestamp(&e.updated) as i64);
+    }
+}
+
+// Parses an ACL file, one `user <name> [rule ...]` per line. The errors
+// name the line they are found on.
+pub fn parse_acl_file(content: &str, filename: &str) -> Result<BTreeMap<String, User>, String> {
+    let mut users = BTreeMap::new();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let argv: Vec<&str> = line.split_whitespace().collect();
+        if argv[0] != "user" || argv.len() < 2 {
+            return Err(format!("{}:{}: should start with user keyword", filename, i + 1));
+        }
+        let name = argv[1];
+        if users.contains_key(name) {
+            return Err(format!("{}:{}: duplicate user '{}' found", filename, i + 1, name));
+        }
+        let mut user = User::new(name);
+        for rule in argv[2..].iter() {
+            user.set_rule(rule).map_err(|e| {
+                format!("{}:{}: {}. Error in user declaration '{}'", filename, i + 1, e, name)
+            })?;
+        }
+        users.insert(name.to_string(), user);
+    }
+    // without a declaration the default user is the usual one
+    users.entry(DEFAULT_USER.to_string()).or_insert_with(User::new_default);
+    Ok(users)
+}
+
+fn acl_file(server: &Server) -> Result<String, String> {
+    server.acl_file.clone().ok_or_else(|| {
+        "This instance is not configured to use an ACL file. You may want to specify \
+        users via the ACL SETUSER command and then issue a CONFIG REWRITE (assuming \
+        you have a configuration file set) in order to store users in the configuration."
+            .to_string()
+    })
+}
+
+// Replaces the users with the ones of the ACL file, nothing changes when
+// the file has an error. The clients of the users that are gone are closed.
+pub fn acl_load(server: &mut Server) -> Result<(), String> {
+    let filename = acl_file(server)?;
+    let content = fs::read_to_string(&filename)
+        .map_err(|e| format!("Error loading ACLs, opening file '{}': {}", filename, e))?;
+    let users = parse_acl_file(&content, &filename)?;
+    server.acl.users = users;
+
+    let gone: Vec<Rc<RefCell<Client>>> = server.clients.iter()
+        .filter(|c| c.try_borrow().is_ok_and(|c| !server.acl.users.contains_key(&c.user)))
+        .map(Rc::clone)
+        .collect();
+    for c in gone.iter() {
+        server.async_free_client(&mut c.borrow_mut());
+    }
+    Ok(())
+}
+
+// Writes the users to the ACL file, through a temporary file so the
+// old one is kept if anything fails.
+pub fn acl_save(server: &Server) -> Result<(), String> {
+    let filename = acl_file(server)?;
+    let tmp = format!("{}.tmp-{}", filename, std::process::id());
+    let content: String = server.acl.users.values()
+        .map(|u| format!("{}\n", u.describe()))
+        .collect();
+    let r = File::create(&tmp)
+        .and_then(|mut f| {
+            f.write_all(content.as_bytes())?;
+            f.sync_all()
+        })
+        .and_then(|_| fs::rename(&tmp, &filename));
+    if let Err(e) = r {
+        let _ = fs::remove_file(&tmp);
+        return Err(format!("There was an error trying to save the ACLs: {}", e));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::command::lookup_command;
+    use crate::object::Robj;
+
+    fn argv(args: &[&str]) -> Vec<RobjPtr> {
+        args.iter().map(|a| Robj::create_string_object(a)).collect()
+    }
+
+    fn user(rules: &[&str]) -> User {
+        let mut user = User::new("alice");
+        for rule in rules {
+            user.set_rule(rule).unwrap();
+        }
+        user
+    }
+
+    #[test]
+    fn passwords() {
+        let u = user(&["on", ">p1", ">p2", "<p1"]);
+        assert!(!u.check_password(b"p1"));
+        assert!(u.check_password(b"p2"));
+        assert!(user(&["nopass"]).check_password(b"anything"));
+        assert!(user(&[">p1"]).set_rule("#abc").is_err());
+        assert!(user(&[]).set_rule("<p1").is_err());
+    }
+
+    #[test]
+    fn command_and_key_permissions() {
+        let u = user(&["on", "+@all", "-debug", "%R~cache:*", "~own:*"]);
+        let get = lookup_command(b"get").unwrap();
+        let set = lookup_command(b"set").unwrap();
+        let debug = lookup_command(b"debug").unwrap();
+        assert!(acl_check_command_perm(&u, get, &argv(&["get", "cache:1"])).is_ok());
+        assert!(acl_check_command_perm(&u, set, &argv(&["set", "cache:1", "v"])).is_err());
+        assert!(acl_check_command_perm(&u, set, &argv(&["set", "own:1", "v"])).is_ok());
+        assert!(acl_check_command_perm(&u, get, &argv(&["get", "other"])).is_err());
+        assert!(acl_check_command_perm(&u, debug, &argv(&["debug", "jmap"])).is_err());
+        assert_eq!(u.describe(), "user alice on %R~cache:* ~own:* resetchannels +@all -debug");
+    }
+
+    #[test]
+    fn write_commands_reading_keys() {
+        let u = user(&["on", "+@all", "%W~*", "%R~src:*"]);
+        let check = |args: &[&str]| {
+            let cmd = lookup_command(args[0].as_bytes()).unwrap();
+            acl_check_command_perm(&u, cmd, &argv(args)).is_ok()
+        };
+        assert!(check(&["set", "k", "v"]));
+        assert!(check(&["lpush", "k", "v"]));
+        assert!(check(&["del", "k"]));
+        assert!(!check(&["getdel", "k"]));
+        assert!(!check(&["lpop", "k"]));
+        assert!(!check(&["incr", "k"]));
+        assert!(!check(&["rename", "k", "src:1"]));
+        assert!(check(&["rename", "src:1", "k"]));
+        assert!(check(&["sinterstore", "k", "src:1", "src:2"]));
+        assert!(!check(&["sinterstore", "k", "src:1", "other"]));
+        assert!(check(&["sort", "src:1", "store", "k"]));
+        assert!(!check(&["sort", "k", "store", "src:1"]));
+
+        let u = user(&["on", "+@all", "%R~*", "%W~dst:*"]);
+        let sort = lookup_command(b"sort").unwrap();
+        assert!(acl_check_command_perm(&u, sort, &argv(&["sort", "k", "limit", "0", "1", "store", "dst:1"])).is_ok());
+        assert!(acl_check_command_perm(&u, sort, &argv(&["sort", "k", "get", "store", "store", "k"])).is_err());
+    }
+
+    #[test]
+    fn channel_permissions() {
+        let u = user(&["on", "+@pubsub", "&news.*"]);
+        let publish = lookup_command(b"publish").unwrap();
+        let psubscribe = lookup_command(b"psubscribe").unwrap();
+        assert!(acl_check_command_perm(&u, publish, &argv(&["publish", "news.it", "m"])).is_ok());
+        assert!(acl_check_command_perm(&u, publish, &argv(&["publish", "sport", "m"])).is_err());
+        assert!(acl_check_command_perm(&u, psubscribe, &argv(&["psubscribe", "news.*"])).is_ok());
+        assert!(acl_check_command_perm(&u, psubscribe, &argv(&["psubscribe", "*"])).is_err());
+    }
+
+    #[test]
+    fn acl_file() {
+        let users = parse_acl_file("user bob on >pw ~* +get\n", "users.acl").unwrap();
+        assert!(users.contains_key(DEFAULT_USER));
+        assert!(users["bob"].check_password(b"pw"));
+        let err = parse_acl_file("user bob on\nuser bob off\n", "users.acl").err().unwrap();
+        assert!(err.starts_with("users.acl:2:"));
+        let err = parse_acl_file("user bob +nosuchcommand\n", "users.acl").err().unwrap();
+        assert!(err.contains("Unknown command"));
+    }
+}
@@ -4,11 +4,14 @@ use std::ops::Add;
 use std::error::Error;
 use std::rc::Rc;
 use mio::net::{TcpListener, TcpStream};
+use mio_uds::{UnixListener, UnixStream};
 use crate::server::Server;
 use std::cell::RefCell;
 use crate::client::*;
 use std::collections::{VecDeque, HashMap};
 use std::sync::atomic::Ordering;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
 
 type AeTimeProc = fn(server: &mut Server, el: &mut AeEventLoop, id: i64, data: &ClientData) -> i32;
 type AeFileProc = fn(server: &mut Server, el: &mut AeEventLoop, fd: &Fd, data: &ClientData, mask: i32);
@@ -16,8 +19,8 @@ type AeEventFinalizerProc = fn(el: &mut AeEventLoop, data: &ClientData);
 pub type Fd = Rc<RefCell<Fdp>>;
 
 pub enum Fdp {
-    Listener(TcpListener),
-    Stream(TcpStream),
+    Listener(Listener),
+    Stream(Stream),
     Nil,
 }
 
@@ -38,27 +41,27 @@ impl Fdp {
 
     pub fn to_evented(&self) -> &dyn Evented {
         match self {
-            Fdp::Stream(s) => s,
-            Fdp::Listener(l) => l,
+            Fdp::Stream(s) => s.to_evented(),
+            Fdp::Listener(l) => l.to_evented(),
             _ => panic!("cannot make Nil to evented"),
         }
     }
 
-    pub fn unwrap_listener(&self) -> &TcpListener {
+    pub fn unwrap_listener(&self) -> &Listener {
         match self {
             Fdp::Listener(l) => l,
             _ => panic!("not a listener"),
         }
     }
 
-    pub fn unwrap_stream(&self) -> &TcpStream {
+    pub fn unwrap_stream(&self) -> &Stream {
         match self {
             Fdp::Stream(s) => s,
             _ => panic!("not a stream"),
         }
     }
 
-    pub fn unwrap_stream_mut(&mut self) -> &mut TcpStream {
+    pub fn unwrap_stream_mut(&mut self) -> &mut Stream {
         match self {
             Fdp::Stream(s) => s,
             _ => panic!("not a stream"),
@@ -66,6 +69,110 @@ impl Fdp {
     }
 }
 
+// the listening sockets, TCP and Unix ones are accepted by the same handler
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl Listener {
+    // the new connection with the address of the peer and the local one,
+    // Unix sockets use the path of the socket for both
+    pub fn accept(&self) -> io::Result<(Stream, String, String)> {
+        match self {
+            Listener::Tcp(l) => {
+                let (stream, addr) = l.accept()?;
+                let laddr = stream.local_addr()
+                    .map(|addr| addr.to_string())
+                    .unwrap_or_default();
+                Ok((Stream::Tcp(stream), addr.to_string(), laddr))
+            }
+            Listener::Unix(l) => {
+                let (stream, _) = l.accept()?
+                    .ok_or_else(|| io::Error::from(io::ErrorKind::WouldBlock))?;
+                let path = l.local_addr().ok()
+                    .and_then(|addr| addr.as_pathname().map(|p| p.display().to_string()))
+                    .unwrap_or_default();
+                let addr = format!("{}:0", path);
+                Ok((Stream::Unix(stream), addr.clone(), addr))
+            }
+        }
+    }
+
+    pub fn is_unix(&self) -> bool {
+        matches!(self, Listener::Unix(_))
+    }
+
+    fn to_evented(&self) -> &dyn Evented {
+        match self {
+            Listener::Tcp(l) => l,
+            Listener::Unix(l) => l,
+        }
+    }
+}
+
+impl AsRawFd for Listener {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Listener::Tcp(l) => l.as_raw_fd(),
+            Listener::Unix(l) => l.as_raw_fd(),
+        }
+    }
+}
+
+// the connection of a client, over TCP or a Unix socket
+pub enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Stream {
+    pub fn is_unix(&self) -> bool {
+        matches!(self, Stream::Unix(_))
+    }
+
+    fn to_evented(&self) -> &dyn Evented {
+        match self {
+            Stream::Tcp(s) => s,
+            Stream::Unix(s) => s,
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(s) => s.read(buf),
+            Stream::Unix(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(s) => s.write(buf),
+            Stream::Unix(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(s) => s.flush(),
+            Stream::Unix(s) => s.flush(),
+        }
+    }
+}
+
+impl AsRawFd for Stream {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Stream::Tcp(s) => s.as_raw_fd(),
+            Stream::Unix(s) => s.as_raw_fd(),
+        }
+    }
+}
+
 fn _default_ae_time_proc(_server: &mut Server, _el: &mut AeEventLoop, _id: i64,
                          _data: &ClientData) -> i32 { 1 }
 
@@ -107,8 +214,16 @@ impl AeEventLoop {
         el
     }
 
-    pub fn deregister_stream(&mut self, stream: &TcpStream) {
-        self.poll.deregister(stream).unwrap();
+    pub fn deregister_stream(&mut self, stream: &Stream) {
+        self.poll.deregister(stream.to_evented()).unwrap();
+    }
+
+    // stops accepting on a listening socket, e.g. when the bind addresses change
+    pub fn delete_listener_event(&mut self, fd: &Fd) {
+        let token = Token(fd.as_ptr() as usize);
+        if self.file_events_hash.remove(&token).is_some() {
+            self.poll.deregister(fd.borrow().to_evented()).unwrap();
+        }
     }
 
     pub fn stop(&mut self) {
@@ -144,14 +259,14 @@ impl AeEventLoop {
     pub fn modify_file_event(
         &mut self,
         token: Token,
-        socket: &TcpStream,
+        socket: &Stream,
         mask: i32,
         file_proc: AeFileProc,
     ) -> Result<(), ()> {
         let fe = self.file_events_hash.get_mut(&token).unwrap();
 
         self.poll.reregister(
-            socket,
+            socket.to_evented(),
             token,
             Self::readiness(fe.mask | mask),
             PollOpt::level(),
@@ -408,9 +523,12 @@ impl AeEventLoop {
                 let id = te.id;
                 let retval
                     = (&te.time_proc)(server, self, id, &te.client_data);
-                if retval != -1 {
-                    te.when = te.when.add(Duration::from_millis(retval as u64));
+                // -1 means the event is not fired anymore
+                if retval == -1 {
+                    (te.finalizer_proc)(self, &te.client_data);
+                    continue;
                 }
+                te.when = te.when.add(Duration::from_millis(retval as u64));
             }
             self.time_events.push_back(te);
         }
@@ -1,7 +1,7 @@
 use crate::ae::*;
-use std::time::SystemTime;
+use std::time::{SystemTime, Instant, Duration};
 use std::rc::Rc;
-use crate::env::{read_query_from_client, send_reply_to_client};
+use crate::env::{read_query_from_client, send_reply_to_client, REREDIS_MAX_COMMANDS_PER_EVENT};
 use std::cell::RefCell;
 use crate::object::{RobjPtr, Robj};
 use crate::protocol;
@@ -12,7 +12,15 @@ use crate::zalloc;
 use crate::replicate;
 use std::fs::File;
 use mio::Token;
-use crate::lua::LuaRedis;
+use crate::lua::{LuaRedis, LuaReplyBuilder};
+use crate::reply::{ReplyBuffer, ClientClass, OutputBufferLimit};
+use crate::shared::{OK, CZERO, CONE, WRONG_TYPE};
+use crate::multi::{MultiCmd, WatchedKey, queue_multi_command, flag_transaction};
+use crate::pubsub::{SUBSCRIBE_CONTEXT_COMMANDS, flush_pending_messages};
+use crate::notify::flush_keyspace_events;
+use crate::tracking::{flush_tracking_invalidations, tracking_remember_keys, tracking_reset_caching};
+use crate::slowlog::slowlog_push_entry_if_needed;
+use crate::acl::{DEFAULT_USER, acl_check_client_perm, acl_denied_message};
 
 pub const CLIENT_CLOSE: i32 = 0b0001;
 pub const CLIENT_SLAVE: i32 = 0b0010;
@@ -20,6 +28,39 @@ pub const CLIENT_MASTER: i32 = 0b0100;
 pub const CLIENT_MONITOR: i32 = 0b1000;
 
 pub const CLIENT_CLOSE_ASAP: i32 = 0b1_0000;
+pub const CLIENT_MULTI: i32 = 0b10_0000;
+pub const CLIENT_DIRTY_EXEC: i32 = 0b100_0000;
+
+pub const CLIENT_TRACKING: i32 = 0b1000_0000;
+pub const CLIENT_TRACKING_BCAST: i32 = 0b1_0000_0000;
+pub const CLIENT_TRACKING_OPTIN: i32 = 0b10_0000_0000;
+pub const CLIENT_TRACKING_OPTOUT: i32 = 0b100_0000_0000;
+pub const CLIENT_TRACKING_CACHING: i32 = 0b1000_0000_0000;
+pub const CLIENT_TRACKING_NOLOOP: i32 = 0b1_0000_0000_0000;
+pub const CLIENT_TRACKING_BROKEN_REDIR: i32 = 0b10_0000_0000_0000;
+
+pub const CLIENT_REPLY_OFF: i32 = 0b100_0000_0000_0000;
+pub const CLIENT_REPLY_SKIP_NEXT: i32 = 0b1000_0000_0000_0000;
+pub const CLIENT_REPLY_SKIP: i32 = 0b1_0000_0000_0000_0000;
+pub const CLIENT_CLOSE_AFTER_REPLY: i32 = 0b10_0000_0000_0000_0000;
+pub const CLIENT_PAUSED: i32 = 0b100_0000_0000_0000_0000;
+// the client of the commands called by a script
+pub const CLIENT_LUA: i32 = 0b1000_0000_0000_0000_0000;
+// the client ran the max commands in one go and has queries left
+pub const CLIENT_PENDING_INPUT: i32 = 0b1_0000_0000_0000_0000_0000;
+// the client is connected over the Unix socket
+pub const CLIENT_UNIX_SOCKET: i32 = 0b10_0000_0000_0000_0000_0000;
+
+// max length of an inline request, and of the count lines of a multibulk one
+pub const PROTO_INLINE_MAX_SIZE: usize = 64 * 1024;
+pub const PROTO_MAX_MULTIBULK_LEN: usize = 1024 * 1024;
+// the clients that did not authenticate yet only get requests big enough
+// for AUTH and HELLO, and a small query buffer
+pub const PROTO_UNAUTH_MAX_MULTIBULK_LEN: usize = 10;
+pub const PROTO_UNAUTH_MAX_BULK_LEN: usize = 16 * 1024;
+pub const PROTO_UNAUTH_MAX_QUERYBUF_LEN: usize = 1024 * 1024;
+// bulk arguments at least this big are read straight into their own buffer
+pub const PROTO_MBULK_BIG_ARG: usize = 32 * 1024;
 
 #[derive(Copy, Clone, PartialEq)]
 pub enum ReplyState {
@@ -45,6 +86,12 @@ enum ProcessQueryError {
 }
 
 pub struct Client {
+    pub id: usize,
+    pub name: Option<RobjPtr>,
+    pub addr: String,
+    pub laddr: String,
+    pub ctime: SystemTime,
+    pub last_cmd: &'static str,
     pub fd: Fd,
     pub token: Token,
     pub flags: i32,
@@ -53,8 +100,16 @@ pub struct Client {
     pub argv: Vec<RobjPtr>,
 
     pub authenticate: bool,
+    // RESP2 or RESP3, negotiated with HELLO
+    pub resp: u8,
+    // the ACL user the commands run as
+    pub user: String,
     pub reply_state: ReplyState,
-    pub reply: Vec<RobjPtr>,
+    pub reply: ReplyBuffer,
+    // when the output buffer went over the soft limit of the client class
+    pub obuf_soft_limit_reached_time: Option<Instant>,
+    // the replies of a script client, see CLIENT_LUA
+    pub lua_reply: LuaReplyBuilder,
     pub reply_db_file: Option<File>,
     pub reply_db_off: u64,
     pub reply_db_size: u64,
@@ -67,6 +122,17 @@ pub struct Client {
     pub db_idx: usize,
 
     pub slave_select_db: usize,
+
+    pub mstate: Vec<MultiCmd>,
+    pub watched_keys: Vec<WatchedKey>,
+
+    pub pubsub_channels: Vec<Vec<u8>>,
+    pub pubsub_patterns: Vec<Vec<u8>>,
+    pub pubsub_shard_channels: Vec<Vec<u8>>,
+
+    // id of the client receiving the invalidation messages, 0 for itself
+    pub tracking_redirection: usize,
+    pub tracking_prefixes: Vec<Vec<u8>>,
 }
 
 impl Client {
@@ -94,6 +160,12 @@ impl Client {
     fn new_default_client(fd: Fd) -> Client {
         let token = Token(fd.as_ptr() as usize);
         Client {
+            id: 0,
+            name: None,
+            addr: String::new(),
+            laddr: String::new(),
+            ctime: SystemTime::now(),
+            last_cmd: "NULL",
             fd,
             token,
             flags: 0,
@@ -101,8 +173,12 @@ impl Client {
             last_interaction: SystemTime::now(),
             argv: vec![],
             authenticate: false,
+            resp: protocol::RESP2,
+            user: DEFAULT_USER.to_string(),
             reply_state: ReplyState::None,
-            reply: vec![],
+            reply: ReplyBuffer::new(),
+            obuf_soft_limit_reached_time: None,
+            lua_reply: LuaReplyBuilder::default(),
             reply_db_file: None,
             reply_db_off: 0,
             reply_db_size: 0,
@@ -114,6 +190,16 @@ impl Client {
 
             db_idx: 0,
             slave_select_db: 0,
+
+            mstate: vec![],
+            watched_keys: vec![],
+
+            pubsub_channels: vec![],
+            pubsub_patterns: vec![],
+            pubsub_shard_channels: vec![],
+
+            tracking_redirection: 0,
+            tracking_prefixes: vec![],
         }
     }
 
@@ -156,7 +242,18 @@ impl Client {
     }
 
     pub fn process_input_buffer(&mut self, server: &mut Server, el: &mut AeEventLoop) {
-        while !self.query_buf.is_empty() {
+        // the queries of a client about to be closed are ignored, a paused
+        // client keeps the rest of its queries until it is resumed
+        let stop_flags = CLIENT_CLOSE_ASAP | CLIENT_CLOSE_AFTER_REPLY | CLIENT_PAUSED;
+        let mut processed = 0;
+        while !self.query_buf.is_empty() && self.flags & stop_flags == 0 {
+            // a client pipelining a lot of commands lets the others run,
+            // the rest of its queries are run by the next iteration
+            if processed == REREDIS_MAX_COMMANDS_PER_EVENT {
+                server.defer_client_input(self, el);
+                break;
+            }
+
             if let RequestType::Unknown = self.request_type {
                 if self.query_buf[0] == b'*' {
                     self.request_type = RequestType::MultiBulk;
@@ -167,10 +264,10 @@ impl Client {
 
             let result = match self.request_type {
                 RequestType::Inline => {
-                    self.process_inline_buffer()
+                    self.process_inline_buffer(server)
                 }
                 RequestType::MultiBulk => {
-                    self.process_multi_bulk_buffer()
+                    self.process_multi_bulk_buffer(server)
                 }
                 _ => {
                     unreachable!();
@@ -178,8 +275,8 @@ impl Client {
             };
 
             if let Err(e) = result {
-                if let ProcessQueryError::Protocol(_) = e {
-                    server.async_free_client(self);
+                if let ProcessQueryError::Protocol(pos) = e {
+                    self.set_protocol_error(pos);
                 }
                 break;
             }
@@ -187,33 +284,43 @@ impl Client {
             if self.argc() == 0 {
                 self.reset();
             } else {
-                if let Ok(_) = self.process_command(server, el) {
-                    self.reset()
-                }
+                // the client is reset by process_command once the command is done
+                let _ = self.process_command(server, el);
+                processed += 1;
             }
         }
     }
 
-    fn process_inline_buffer(&mut self) -> Result<(), ProcessQueryError> {
-        let mut pos: usize;
-        let new_line = self.query_buf.iter()
-            .enumerate()
-            .find(|(_, ch)| **ch == b'\n')
-            .map(|x| x.0);
-
-        pos = new_line.ok_or(ProcessQueryError::NotEnough)?;
-        // TODO: max inline buffer
+    // The client is closed once the protocol error is sent, `pos` is where
+    // the error was found in the query buffer.
+    fn set_protocol_error(&mut self, pos: usize) {
+        let end = self.query_buf.len().min(pos + 64);
+        debug!("Protocol error from client {}, query buffer: '{}'",
+               self.addr, String::from_utf8_lossy(&self.query_buf[pos.min(end)..end]).escape_debug());
+        self.flags |= CLIENT_CLOSE_AFTER_REPLY;
+        self.query_buf.clear();
+    }
 
-        if pos > 0 && self.query_buf[pos - 1] == b'\r' {
-            pos -= 1;
+    fn process_inline_buffer(&mut self, server: &Server) -> Result<(), ProcessQueryError> {
+        let new_line = match self.query_buf.iter().position(|ch| *ch == b'\n') {
+            Some(n) => n,
+            None => {
+                if self.query_buf.len() > PROTO_INLINE_MAX_SIZE {
+                    self.add_reply_error("Protocol error: too big inline request");
+                    return Err(ProcessQueryError::Protocol(0));
+                }
+                return Err(ProcessQueryError::NotEnough);
+            }
+        };
+        let mut end = new_line;
+        if end > 0 && self.query_buf[end - 1] == b'\r' {
+            end -= 1;
         }
 
-        let s = std::str::from_utf8(&self.query_buf[0..pos]);
-
-        let s = match s {
+        let s = match std::str::from_utf8(&self.query_buf[0..end]) {
             Ok(s) => s,
             Err(_) => {
-                self.add_str_reply("-ERR Protocol Error: Unknown char\r\n");
+                self.add_reply_error("Protocol error: Unknown char");
                 return Err(ProcessQueryError::Protocol(0));
             }
         };
@@ -225,51 +332,60 @@ impl Client {
         }
 
         if self.argv.is_empty() {
-            self.add_str_reply("-ERR Protocol error: unbalanced quotes in request\r\n");
+            self.add_reply_error("Protocol error: unbalanced quotes in request");
+            return Err(ProcessQueryError::Protocol(0));
+        }
+        if self.argc() > PROTO_UNAUTH_MAX_MULTIBULK_LEN && !self.is_authenticated(server) {
+            self.add_reply_error("Protocol error: unauthenticated inline request");
             return Err(ProcessQueryError::Protocol(0));
         }
 
-        self.query_buf.drain(0..pos + 2);
+        self.query_buf.drain(0..=new_line);
 
         Ok(())
     }
 
-    fn process_multi_bulk_buffer(&mut self) -> Result<(), ProcessQueryError> {
-        let mut new_line;
+    // the position of the "\r\n" ending the line starting at `pos`, None
+    // when the line is not complete yet
+    fn find_line_end(&self, pos: usize) -> Option<usize> {
+        self.query_buf[pos..].iter()
+            .position(|ch| *ch == b'\r')
+            .map(|n| pos + n)
+            .filter(|n| n + 1 < self.query_buf.len())
+    }
+
+    fn process_multi_bulk_buffer(&mut self, server: &Server) -> Result<(), ProcessQueryError> {
         let mut pos: usize = 0;
+        let authenticated = self.is_authenticated(server);
         if self.multi_bulk_len == 0 {
             assert_eq!(self.argc(), 0);
-            new_line = self.query_buf.iter()
-                .enumerate()
-                .find(|(_, ch)| **ch == b'\r')
-                .map(|x| x.0);
-
-            pos = new_line.ok_or(ProcessQueryError::NotEnough)?;
 
-            // TODO: if pos exceed max inline length, return error
-
-            // buffer should also contain '\n'
-            if pos > self.query_buf.len() - 2 {
-                return Err(ProcessQueryError::NotEnough);
-            }
+            let new_line = match self.find_line_end(pos) {
+                Some(n) => n,
+                None => {
+                    if self.query_buf.len() > PROTO_INLINE_MAX_SIZE {
+                        self.add_reply_error("Protocol error: too big mbulk count string");
+                        return Err(ProcessQueryError::Protocol(0));
+                    }
+                    return Err(ProcessQueryError::NotEnough);
+                }
+            };
 
             assert_eq!(self.query_buf[0], b'*');
 
-            let ll = bytes_to_i64(&self.query_buf[1..pos])
-                .map_err(|_| ())
-                .and_then(|x| {
-                    if x > 1024 * 1024 {
-                        Err(())
-                    } else {
-                        Ok(x)
-                    }
-                })
-                .map_err(|_| {
-                    self.add_str_reply("-ERR Protocol Error: invalid bulk length\r\n");
-                    ProcessQueryError::Protocol(1)
-                })?;
+            let ll = match bytes_to_i64(&self.query_buf[1..new_line]) {
+                Ok(ll) if ll <= PROTO_MAX_MULTIBULK_LEN as i64 => ll,
+                _ => {
+                    self.add_reply_error("Protocol error: invalid multibulk length");
+                    return Err(ProcessQueryError::Protocol(pos));
+                }
+            };
+            if ll > PROTO_UNAUTH_MAX_MULTIBULK_LEN as i64 && !authenticated {
+                self.add_reply_error("Protocol error: unauthenticated multibulk length");
+                return Err(ProcessQueryError::Protocol(pos));
+            }
 
-            pos += 2;
+            pos = new_line + 2;
 
             if ll <= 0 {
                 self.query_buf.drain(0..pos);
@@ -282,66 +398,76 @@ impl Client {
                 self.argv.clear();
             }
 
-            self.argv.reserve(self.multi_bulk_len);
+            // the count comes from the client, it is not trusted for the
+            // allocation
+            self.argv.reserve(self.multi_bulk_len.min(1024));
         }
 
         assert!(self.multi_bulk_len > 0);
 
         while self.multi_bulk_len > 0 {
-            if let None = self.bulk_len {
-                new_line = self.query_buf.iter()
-                    .enumerate()
-                    .skip(pos)
-                    .find(|(_, ch)| **ch == b'\r')
-                    .map(|x| x.0);
-
-                let new_line = match new_line {
-                    None => break,
+            if self.bulk_len.is_none() {
+                let new_line = match self.find_line_end(pos) {
                     Some(n) => n,
+                    None => {
+                        if self.query_buf.len() - pos > PROTO_INLINE_MAX_SIZE {
+                            self.add_reply_error("Protocol error: too big bulk count string");
+                            return Err(ProcessQueryError::Protocol(pos));
+                        }
+                        break;
+                    }
                 };
-                // TODO: if pos exceed max inline length, return error
-
-                if pos > self.query_buf.len() - 2 {
-                    break;
-                }
 
                 if self.query_buf[pos] != b'$' {
-                    self.add_reply_from_string(
-                        format!("-ERR Protocol Error: expected '$', got {}\r\n",
-                                self.query_buf[pos] as char)
-                    );
+                    self.add_reply_error(&format!(
+                        "Protocol error: expected '$', got '{}'", self.query_buf[pos] as char,
+                    ));
                     return Err(ProcessQueryError::Protocol(pos));
                 }
 
-                let ll = bytes_to_usize(&self.query_buf[pos + 1..new_line])
-                    .map_err(|_| ())
-                    .and_then(|x| {
-                        if x > 512 * 1024 * 1024 {
-                            Err(())
-                        } else {
-                            Ok(x)
-                        }
-                    })
-                    .map_err(|_| {
-                        self.add_str_reply("-ERR Protocol Error: invalid bulk length\r\n");
-                        ProcessQueryError::Protocol(pos)
-                    })?;
+                let ll = match bytes_to_usize(&self.query_buf[pos + 1..new_line]) {
+                    Ok(ll) if ll <= server.proto_max_bulk_len => ll,
+                    _ => {
+                        self.add_reply_error("Protocol error: invalid bulk length");
+                        return Err(ProcessQueryError::Protocol(pos));
+                    }
+                };
+                if ll > PROTO_UNAUTH_MAX_BULK_LEN && !authenticated {
+                    self.add_reply_error("Protocol error: unauthenticated bulk length");
+                    return Err(ProcessQueryError::Protocol(pos));
+                }
 
                 pos = new_line + 2;
                 self.bulk_len = Some(ll);
+
+                // a big argument gets the query buffer for itself: the
+                // argument starts at its head, and the reads never go past
+                // its end, so it can become the argument object as it is
+                if ll >= PROTO_MBULK_BIG_ARG && self.query_buf.len() - pos <= ll + 2 {
+                    self.query_buf.drain(0..pos);
+                    pos = 0;
+                    let len = self.query_buf.len();
+                    self.query_buf.reserve_exact(ll + 2 - len);
+                }
             }
 
-            if self.query_buf.len() - pos < self.bulk_len.unwrap() + 2 {
+            let bulk_len = self.bulk_len.unwrap();
+            if self.query_buf.len() - pos < bulk_len + 2 {
                 break;
-            } else {
-                let arg = Robj::create_bytes_object(
-                    &self.query_buf[pos..pos + self.bulk_len.unwrap()]
-                );
-                pos += self.bulk_len.unwrap() + 2;
-                self.argv.push(arg);
-                self.bulk_len = None;
-                self.multi_bulk_len -= 1;
             }
+            let arg = if pos == 0 && bulk_len >= PROTO_MBULK_BIG_ARG &&
+                self.query_buf.len() == bulk_len + 2 {
+                let mut bytes = std::mem::take(&mut self.query_buf);
+                bytes.truncate(bulk_len);
+                Robj::from_bytes(bytes)
+            } else {
+                let arg = Robj::create_bytes_object(&self.query_buf[pos..pos + bulk_len]);
+                pos += bulk_len + 2;
+                arg
+            };
+            self.argv.push(arg);
+            self.bulk_len = None;
+            self.multi_bulk_len -= 1;
         }
         if pos > 0 {
             self.query_buf.drain(0..pos);
@@ -354,6 +480,21 @@ impl Client {
         Err(ProcessQueryError::NotEnough)
     }
 
+    // the bytes still missing from the big argument being read, if any
+    pub fn pending_big_arg(&self) -> Option<usize> {
+        match (&self.request_type, self.bulk_len) {
+            (RequestType::MultiBulk, Some(ll)) if ll >= PROTO_MBULK_BIG_ARG => {
+                (ll + 2).checked_sub(self.query_buf.len()).filter(|&n| n > 0)
+            }
+            _ => None,
+        }
+    }
+
+    // the clients that did not authenticate yet only get small requests
+    fn is_authenticated(&self, server: &Server) -> bool {
+        self.authenticate || !server.acl.auth_required()
+    }
+
     pub fn process_command(
         &mut self,
         server: &mut Server,
@@ -374,43 +515,90 @@ impl Client {
         );
         let cmd = match cmd {
             None => {
-                self.add_str_reply("-Error unknown command\r\n");
+                flag_transaction(self);
+                self.add_reply_error("unknown command");
                 self.reset();
                 return Err(CommandError::Unknown);
             }
             Some(c) => c,
         };
+        self.last_cmd = cmd.name;
 
         if (cmd.arity > 0 && cmd.arity as usize != self.argc())
             || (cmd.arity < 0 && (self.argc() < (-cmd.arity) as usize)) {
-            self.add_str_reply("-Error wrong number of arguments\r\n");
+            flag_transaction(self);
+            self.add_reply_error("wrong number of arguments");
             self.reset();
             return Err(CommandError::WrongNumber);
         } else if server.max_memory > 0 &&
             cmd.flags & CMD_DENY_OOM != 0 &&
             zalloc::allocated_memory() > server.max_memory {
-            self.add_str_reply("-ERR command not allowed when used memory > 'maxmemory'\r\n");
+            flag_transaction(self);
+            self.add_reply_error("command not allowed when used memory > 'maxmemory'");
             self.reset();
             return Err(CommandError::OOM);
         }
 
         // TODO: share objects to save memory
 
-        if server.require_pass.is_some() && !self.authenticate && cmd.name != "auth" {
-            self.add_str_reply("-ERR operation not permitted\r\n");
+        // the master is trusted, it replicates what its clients were allowed to do
+        if self.flags & CLIENT_MASTER == 0 {
+            if server.acl.auth_required() && !self.authenticate && cmd.name != "auth" && cmd.name != "hello" {
+                flag_transaction(self);
+                self.add_reply_error_code("NOAUTH", "Authentication required.");
+                self.reset();
+                return Err(CommandError::NotPermitted);
+            }
+
+            if let Err(denied) = acl_check_client_perm(server, self, cmd) {
+                flag_transaction(self);
+                let msg = acl_denied_message(self, cmd, &denied);
+                self.add_reply_error_code("NOPERM", &msg);
+                self.reset();
+                return Err(CommandError::NotPermitted);
+            }
+        }
+
+        // RESP3 can tell the messages from the replies, so it has no such context
+        if self.resp == protocol::RESP2 && self.subscription_count() > 0 &&
+            !SUBSCRIBE_CONTEXT_COMMANDS.contains(&cmd.name) {
+            self.add_reply_error(&format!(
+                "Can't execute '{}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / \
+                PING / QUIT are allowed in this context",
+                cmd.name
+            ));
             self.reset();
             return Err(CommandError::NotPermitted);
         }
 
-        // TODO: save server dirty bit and tackle problems with slave server ans monitors
+        if self.flags & CLIENT_MULTI != 0 &&
+            !["exec", "discard", "multi", "watch"].contains(&cmd.name) {
+            queue_multi_command(self, cmd);
+            self.reset();
+            return Ok(());
+        }
+
+        // the command is kept in argv until the client is resumed
+        if server.is_command_paused(self, cmd) {
+            self.flags |= CLIENT_PAUSED;
+            return Err(CommandError::Paused);
+        }
+
         let dirty = server.dirty;
+        let start = Instant::now();
         (&cmd.proc)(self, server, el);
-        if !server.monitors.is_empty() {
-            replicate::feed_slaves(el, self, &server.monitors, self.db_idx);
-        }
-        if !server.slaves.is_empty() && dirty < server.dirty {
-            replicate::feed_slaves(el, self, &server.slaves, self.db_idx);
+        slowlog_push_entry_if_needed(server, self, start.elapsed());
+        self.propagate(server, el, dirty);
+        // the caller of a script is borrowed until the script returns, so
+        // what its commands raise is flushed after EVAL with the caller
+        if !self.is_lua() {
+            flush_pending_messages(server, el, Some(self));
+            flush_keyspace_events(server, el, Some(self));
+            flush_tracking_invalidations(server, el, Some(self));
         }
+        server.close_client_on_output_buffer_limit_reached(self);
+        tracking_remember_keys(server, self, cmd);
+        tracking_reset_caching(self, cmd);
 
         server.stat_num_commands += 1;
 
@@ -421,6 +609,20 @@ impl Client {
         Ok(())
     }
 
+    // monitors see every command, slaves only the ones changing the dataset
+    pub fn propagate(&mut self, server: &mut Server, el: &mut AeEventLoop, dirty: usize) {
+        if !server.monitors.is_empty() {
+            replicate::feed_slaves(el, self, &server.monitors, self.db_idx);
+            let monitors: Vec<Rc<RefCell<Client>>> = server.monitors.iter().map(Rc::clone).collect();
+            server.close_clients_on_output_buffer_limit_reached(self, &monitors);
+        }
+        if !server.slaves.is_empty() && dirty < server.dirty {
+            replicate::feed_slaves(el, self, &server.slaves, self.db_idx);
+            let slaves: Vec<Rc<RefCell<Client>>> = server.slaves.iter().map(Rc::clone).collect();
+            server.close_clients_on_output_buffer_limit_reached(self, &slaves);
+        }
+    }
+
     pub fn prepare_to_write(&mut self, el: &mut AeEventLoop) -> Result<(), ()> {
         if self.flags & CLIENT_MASTER != 0 {
             return Err(());
@@ -435,41 +637,275 @@ impl Client {
         self.argv.len()
     }
 
+    // slaves and monitors are fed with `argv` after the command is
+    // executed, so a command can replace it with a deterministic form
+    pub fn rewrite_argv(&mut self, argv: Vec<RobjPtr>) {
+        self.argv = argv;
+    }
+
     pub fn reset(&mut self) {
         self.argv.clear();
         self.bulk_len = None;
         self.request_type = RequestType::Unknown;
+
+        // CLIENT REPLY SKIP skips the reply of the command after it
+        self.flags &= !CLIENT_REPLY_SKIP;
+        if self.flags & CLIENT_REPLY_SKIP_NEXT != 0 {
+            self.flags |= CLIENT_REPLY_SKIP;
+            self.flags &= !CLIENT_REPLY_SKIP_NEXT;
+        }
     }
 
-    pub fn add_reply(&mut self, r: RobjPtr) {
-        self.reply.push(r);
+    // runs the command the client was paused on and the queries after it,
+    // the caller has to install the write handler for the replies
+    pub fn resume(&mut self, server: &mut Server, el: &mut AeEventLoop) {
+        self.flags &= !CLIENT_PAUSED;
+        if !self.argv.is_empty() {
+            let _ = self.process_command(server, el);
+        }
+        self.process_input_buffer(server, el);
     }
 
-    pub fn add_str_reply(&mut self, s: &str) {
-        self.add_reply(
-            Robj::create_string_object(s),
-        );
+    // Appends data already encoded in the protocol of the client, the
+    // replies of the commands go through the typed functions below.
+    pub fn add_reply_raw(&mut self, r: RobjPtr) {
+        if self.flags & (CLIENT_REPLY_OFF | CLIENT_REPLY_SKIP | CLIENT_CLOSE_ASAP) != 0 {
+            return;
+        }
+        self.reply.add_object(r);
+    }
+
+    fn add_reply_bytes(&mut self, b: &[u8]) {
+        if self.flags & (CLIENT_REPLY_OFF | CLIENT_REPLY_SKIP | CLIENT_CLOSE_ASAP) != 0 {
+            return;
+        }
+        self.reply.add_bytes(b);
+    }
+
+    fn add_reply_proto(&mut self, s: String) {
+        self.add_reply_bytes(s.as_bytes());
+    }
+
+    fn is_lua(&self) -> bool {
+        self.flags & CLIENT_LUA != 0
+    }
+
+    pub fn add_reply_ok(&mut self) {
+        if self.is_lua() {
+            return self.lua_reply.push(LuaRedis::Status(b"OK".to_vec()));
+        }
+        self.add_reply_raw(shared_object!(OK));
+    }
+
+    pub fn add_reply_status(&mut self, s: &str) {
+        if self.is_lua() {
+            return self.lua_reply.push(LuaRedis::Status(s.as_bytes().to_vec()));
+        }
+        self.add_reply_proto(format!("+{}\r\n", s));
+    }
+
+    pub fn add_reply_error(&mut self, msg: &str) {
+        self.add_reply_error_code("ERR", msg);
+    }
+
+    // `code` is the first word of the error, like WRONGTYPE or NOAUTH
+    pub fn add_reply_error_code(&mut self, code: &str, msg: &str) {
+        // a newline in the message would break the framing
+        let msg = msg.replace(['\r', '\n'], " ");
+        if self.is_lua() {
+            let error = format!("{} {}", code, msg).into_bytes();
+            return self.lua_reply.push(LuaRedis::Error(error));
+        }
+        self.add_reply_proto(format!("-{} {}\r\n", code, msg));
     }
 
-    pub fn add_reply_from_string(&mut self, s: String) {
-        self.add_reply(
-            Robj::from_bytes(s.into_bytes())
-        )
+    pub fn add_reply_wrong_type(&mut self) {
+        if self.is_lua() {
+            let error = b"WRONGTYPE Operation against a key holding the wrong kind of value";
+            return self.lua_reply.push(LuaRedis::Error(error.to_vec()));
+        }
+        self.add_reply_raw(shared_object!(WRONG_TYPE));
     }
 
-    pub fn glue_reply(&mut self) {
-        let mut glued: Vec<u8> = vec![];
-        for obj in self.reply.iter() {
-            glued.extend_from_slice(obj.borrow().string())
+    pub fn add_reply_integer(&mut self, n: i64) {
+        if self.is_lua() {
+            return self.lua_reply.push(LuaRedis::Integer(n));
+        }
+        match n {
+            0 => self.add_reply_raw(shared_object!(CZERO)),
+            1 => self.add_reply_raw(shared_object!(CONE)),
+            n => self.add_reply_proto(format!(":{}\r\n", n)),
         }
-        self.reply.clear();
-        self.reply.push(Robj::from_bytes(glued));
     }
 
-    pub fn parse_reply_to_lua(&mut self) -> LuaRedis {
-        let ret = LuaRedis::new(&self.reply);
-        self.reply.clear();
-        ret
+    // long values are queued by reference, without copying them
+    pub fn add_reply_bulk(&mut self, o: RobjPtr) {
+        if self.is_lua() {
+            let bulk = o.borrow().string().to_vec();
+            return self.lua_reply.push(LuaRedis::Bulk(bulk));
+        }
+        let len = o.borrow().string_len();
+        self.add_reply_proto(format!("${}\r\n", len));
+        self.add_reply_raw(o);
+        self.add_reply_bytes(b"\r\n");
+    }
+
+    pub fn add_reply_bulk_bytes(&mut self, b: &[u8]) {
+        if self.is_lua() {
+            return self.lua_reply.push(LuaRedis::Bulk(b.to_vec()));
+        }
+        self.add_reply_proto(format!("${}\r\n", b.len()));
+        self.add_reply_bytes(b);
+        self.add_reply_bytes(b"\r\n");
+    }
+
+    pub fn add_reply_null(&mut self) {
+        if self.is_lua() {
+            return self.lua_reply.push(LuaRedis::Nil);
+        }
+        let null = protocol::null(self.resp);
+        self.add_reply_bytes(null.as_bytes());
+    }
+
+    pub fn add_reply_null_array(&mut self) {
+        if self.is_lua() {
+            return self.lua_reply.push(LuaRedis::Nil);
+        }
+        let null = protocol::null_array(self.resp);
+        self.add_reply_bytes(null.as_bytes());
+    }
+
+    pub fn add_reply_double(&mut self, d: f64) {
+        if self.is_lua() {
+            let bulk = protocol::double_to_string(d).into_bytes();
+            return self.lua_reply.push(LuaRedis::Bulk(bulk));
+        }
+        self.add_reply_proto(protocol::double(self.resp, d));
+    }
+
+    pub fn add_reply_bool(&mut self, b: bool) {
+        if self.is_lua() {
+            return self.lua_reply.push(LuaRedis::Integer(b as i64));
+        }
+        let b = protocol::boolean(self.resp, b);
+        self.add_reply_bytes(b.as_bytes());
+    }
+
+    // text meant to be shown as is, `format` is "txt" or "mkd"
+    pub fn add_reply_verbatim(&mut self, s: &str, format: &str) {
+        if self.is_lua() {
+            return self.lua_reply.push(LuaRedis::Bulk(s.as_bytes().to_vec()));
+        }
+        self.add_reply_proto(protocol::verbatim(self.resp, s, format));
+    }
+
+    // the elements of the aggregates follow their length
+    pub fn add_reply_array_len(&mut self, n: usize) {
+        if self.is_lua() {
+            return self.lua_reply.open_array(n);
+        }
+        self.add_reply_proto(format!("*{}\r\n", n));
+    }
+
+    // `n` is the number of pairs
+    pub fn add_reply_map_len(&mut self, n: usize) {
+        if self.is_lua() {
+            return self.lua_reply.open_array(n * 2);
+        }
+        self.add_reply_proto(protocol::map_len(self.resp, n));
+    }
+
+    pub fn add_reply_set_len(&mut self, n: usize) {
+        if self.is_lua() {
+            return self.lua_reply.open_array(n);
+        }
+        self.add_reply_proto(protocol::set_len(self.resp, n));
+    }
+
+    pub fn add_reply_push_len(&mut self, n: usize) {
+        if self.is_lua() {
+            return self.lua_reply.open_array(n);
+        }
+        self.add_reply_proto(protocol::push_len(self.resp, n));
+    }
+
+    // Reserves the length of an aggregate whose elements are counted while
+    // they are added, it is filled in by one of the set_deferred functions.
+    pub fn add_reply_deferred_len(&mut self) -> DeferredLen {
+        if self.is_lua() {
+            return DeferredLen(Some(self.lua_reply.open_deferred()));
+        }
+        if self.flags & (CLIENT_REPLY_OFF | CLIENT_REPLY_SKIP | CLIENT_CLOSE_ASAP) != 0 {
+            return DeferredLen(None);
+        }
+        DeferredLen(Some(self.reply.add_deferred()))
+    }
+
+    pub fn set_deferred_array_len(&mut self, d: DeferredLen, n: usize) {
+        self.set_deferred_len(d, n, format!("*{}\r\n", n));
+    }
+
+    pub fn set_deferred_map_len(&mut self, d: DeferredLen, n: usize) {
+        let header = protocol::map_len(self.resp, n);
+        self.set_deferred_len(d, n * 2, header);
+    }
+
+    pub fn set_deferred_set_len(&mut self, d: DeferredLen, n: usize) {
+        let header = protocol::set_len(self.resp, n);
+        self.set_deferred_len(d, n, header);
+    }
+
+    fn set_deferred_len(&mut self, d: DeferredLen, elements: usize, header: String) {
+        let at = match d.0 {
+            None => return,
+            Some(at) => at,
+        };
+        if self.is_lua() {
+            self.lua_reply.set_deferred_len(at, elements);
+        } else {
+            self.reply.set_deferred(at, header.as_bytes());
+        }
+    }
+
+    pub fn take_lua_reply(&mut self) -> LuaRedis {
+        self.lua_reply.take()
+    }
+
+    pub fn subscription_count(&self) -> usize {
+        self.pubsub_channels.len() + self.pubsub_patterns.len() +
+            self.pubsub_shard_channels.len()
+    }
+
+    pub fn class(&self) -> ClientClass {
+        if self.flags & CLIENT_SLAVE != 0 && self.flags & CLIENT_MONITOR == 0 {
+            ClientClass::Replica
+        } else if self.subscription_count() > 0 {
+            ClientClass::PubSub
+        } else {
+            ClientClass::Normal
+        }
+    }
+
+    // Whether the output buffer went over the limits of the client class,
+    // the soft limit is only reached when the buffer stays over it for more
+    // than soft_seconds.
+    pub fn output_buffer_limit_reached(&mut self, limits: &[OutputBufferLimit; 3]) -> bool {
+        let used = self.reply.memory();
+        let limit = limits[self.class() as usize];
+        let hard = limit.hard > 0 && used >= limit.hard;
+        let mut soft = limit.soft > 0 && used >= limit.soft;
+        if soft {
+            match self.obuf_soft_limit_reached_time {
+                None => {
+                    self.obuf_soft_limit_reached_time = Some(Instant::now());
+                    soft = false;
+                }
+                Some(t) => soft = t.elapsed() > Duration::from_secs(limit.soft_seconds),
+            }
+        } else {
+            self.obuf_soft_limit_reached_time = None;
+        }
+        hard || soft
     }
 
     pub fn is_slave(&self) -> bool {
@@ -485,6 +921,10 @@ impl Client {
     }
 }
 
+// The place of a length reserved by add_reply_deferred_len, None when the
+// client gets no reply
+pub struct DeferredLen(Option<usize>);
+
 pub enum ClientData {
     Client(Rc<RefCell<Client>>),
     Nil(),
@@ -514,6 +954,7 @@ pub enum CommandError {
     WrongNumber,
     OOM,
     NotPermitted,
+    Paused,
 }
 
 impl CommandError {
@@ -526,6 +967,7 @@ impl CommandError {
             Self::WrongNumber => "Wrong number of arguments",
             Self::OOM => "Out of memory",
             Self::NotPermitted => "Client's action is not permitted",
+            Self::Paused => "Client is paused",
         }
     }
 }
@@ -1,9 +1,8 @@
 use std::mem::swap;
 use std::rc::Rc;
-use crate::client::{Client, CLIENT_SLAVE, CLIENT_MONITOR, ReplyState};
-use crate::server::Server;
+use crate::client::*;
+use crate::server::{Server, ClientPauseType};
 use crate::ae::{AeEventLoop, Fdp};
-use crate::shared::{OK, ERR, NULL_BULK, CRLF, CZERO, CONE, COLON, WRONG_TYPE, PONG, EMPTY_MULTI_BULK};
 use crate::util::*;
 use crate::object::{Robj, RobjPtr, RobjEncoding, RobjType};
 use crate::object::list::ListWhere;
@@ -13,8 +12,22 @@ use std::time::{SystemTime, Duration};
 use crate::sort::*;
 use crate::rdb::*;
 use std::process::exit;
-use crate::lua::{to_lua, LuaRobj, RobjFromLua};
+use crate::lua::{to_lua, wrap_script, add_lua_reply, LuaRobj, RobjFromLua, LuaRedis};
+use crate::multi::*;
+use crate::pubsub::*;
+use crate::notify::*;
+use crate::tracking::*;
+use crate::config::config_command;
+use crate::info::info_command;
+use crate::slowlog::slowlog_command;
+use crate::latency::latency_command;
+use crate::memory::memory_command;
+use crate::debug::debug_command;
+use crate::acl::{acl_command, auth_command, acl_authenticate_client, WRONGPASS_ERR};
+use crate::protocol::*;
+use crate::env::REREDIS_VERSION;
 use std::cell::RefCell;
+use std::os::unix::io::AsRawFd;
 
 
 type CommandProc = fn(
@@ -28,12 +41,75 @@ pub const CMD_BULK: i32 = 0b0001;
 pub const CMD_INLINE: i32 = 0b0010;
 pub const CMD_DENY_OOM: i32 = 0b0100;
 pub const CMD_LUA: i32 = 0b1000;
+pub const CMD_WRITE: i32 = 0b1_0000;
+pub const CMD_READONLY: i32 = 0b10_0000;
+pub const CMD_ADMIN: i32 = 0b100_0000;
+pub const CMD_PUBSUB: i32 = 0b1000_0000;
+pub const CMD_NOSCRIPT: i32 = 0b1_0000_0000;
+pub const CMD_FAST: i32 = 0b10_0000_0000;
+
+// ACL categories, the ones implied by the flags (read, write, admin,
+// dangerous, pubsub, fast and slow) are added by command_acl_categories
+pub const ACL_CATEGORY_KEYSPACE: u64 = 1 << 0;
+pub const ACL_CATEGORY_READ: u64 = 1 << 1;
+pub const ACL_CATEGORY_WRITE: u64 = 1 << 2;
+pub const ACL_CATEGORY_SET: u64 = 1 << 3;
+pub const ACL_CATEGORY_SORTEDSET: u64 = 1 << 4;
+pub const ACL_CATEGORY_LIST: u64 = 1 << 5;
+pub const ACL_CATEGORY_HASH: u64 = 1 << 6;
+pub const ACL_CATEGORY_STRING: u64 = 1 << 7;
+pub const ACL_CATEGORY_PUBSUB: u64 = 1 << 8;
+pub const ACL_CATEGORY_ADMIN: u64 = 1 << 9;
+pub const ACL_CATEGORY_FAST: u64 = 1 << 10;
+pub const ACL_CATEGORY_SLOW: u64 = 1 << 11;
+pub const ACL_CATEGORY_DANGEROUS: u64 = 1 << 12;
+pub const ACL_CATEGORY_CONNECTION: u64 = 1 << 13;
+pub const ACL_CATEGORY_TRANSACTION: u64 = 1 << 14;
+pub const ACL_CATEGORY_SCRIPTING: u64 = 1 << 15;
+
+pub const ACL_CATEGORY_NAMES: &[(&str, u64)] = &[
+    ("keyspace", ACL_CATEGORY_KEYSPACE),
+    ("read", ACL_CATEGORY_READ),
+    ("write", ACL_CATEGORY_WRITE),
+    ("set", ACL_CATEGORY_SET),
+    ("sortedset", ACL_CATEGORY_SORTEDSET),
+    ("list", ACL_CATEGORY_LIST),
+    ("hash", ACL_CATEGORY_HASH),
+    ("string", ACL_CATEGORY_STRING),
+    ("pubsub", ACL_CATEGORY_PUBSUB),
+    ("admin", ACL_CATEGORY_ADMIN),
+    ("fast", ACL_CATEGORY_FAST),
+    ("slow", ACL_CATEGORY_SLOW),
+    ("dangerous", ACL_CATEGORY_DANGEROUS),
+    ("connection", ACL_CATEGORY_CONNECTION),
+    ("transaction", ACL_CATEGORY_TRANSACTION),
+    ("scripting", ACL_CATEGORY_SCRIPTING),
+];
+
+// the flags reported by COMMAND, the others are internal
+const COMMAND_FLAG_NAMES: &[(&str, i32)] = &[
+    ("write", CMD_WRITE),
+    ("readonly", CMD_READONLY),
+    ("denyoom", CMD_DENY_OOM),
+    ("admin", CMD_ADMIN),
+    ("pubsub", CMD_PUBSUB),
+    ("noscript", CMD_NOSCRIPT),
+    ("fast", CMD_FAST),
+];
 
 pub struct Command {
     pub name: &'static str,
     pub proc: CommandProc,
     pub arity: i32,
     pub flags: i32,
+    // the categories not implied by the flags, mostly the data type
+    pub acl_categories: u64,
+    // position of the first key, the last key (negative counts from the
+    // end of argv) and the step between keys, all zero for commands
+    // without keys
+    pub first_key: i32,
+    pub last_key: i32,
+    pub key_step: i32,
 }
 
 #[derive(Copy, Clone, PartialEq)]
@@ -52,7 +128,7 @@ pub fn get_command(
     );
 
     match r {
-        None => client.add_reply(shared_object!(NULL_BULK)),
+        None => client.add_reply_null(),
         Some(s) => {
             let enc = s.borrow().encoding();
             let rep = match enc {
@@ -60,11 +136,11 @@ pub fn get_command(
                 RobjEncoding::EmbStr => s,
                 RobjEncoding::Int => s,
                 _ => {
-                    client.add_reply(shared_object!(WRONG_TYPE));
+                    client.add_reply_wrong_type();
                     return;
                 }
             };
-            add_single_reply(client, rep);
+            client.add_reply_bulk(rep);
         }
     }
 }
@@ -107,18 +183,19 @@ fn set_generic_command(
                 o,
             );
         } else {
-            client.add_reply(shared_object!(CZERO));
+            client.add_reply_integer(0);
             return;
         }
     }
 
     server.dirty += 1;
     let _ = db.remove_expire(&client.argv[1]);
-    let reply = match nx {
-        true => shared_object!(CONE),
-        false => shared_object!(OK),
-    };
-    client.add_reply(reply);
+    db.signal_modified_key(&client.argv[1]);
+    server.notify_keyspace_event(NOTIFY_STRING, "set", &client.argv[1], client.db_idx);
+    match nx {
+        true => client.add_reply_integer(1),
+        false => client.add_reply_ok(),
+    }
 }
 
 pub fn to_int_if_needed(o: &RobjPtr) -> RobjPtr {
@@ -134,18 +211,20 @@ pub fn del_command(
     server: &mut Server,
     _el: &mut AeEventLoop,
 ) {
-    let db = &mut server.db[client.db_idx];
     let mut deleted: usize = 0;
     for key in client.argv
         .iter()
         .skip(1) {
+        let db = &mut server.db[client.db_idx];
         if db.delete_key(key).is_ok() {
+            db.signal_modified_key(key);
+            server.notify_keyspace_event(NOTIFY_GENERIC, "del", key, client.db_idx);
             deleted += 1;
             server.dirty += 1;
         }
     }
 
-    client.add_reply(gen_usize_reply(deleted));
+    client.add_reply_integer(deleted as i64);
 }
 
 pub fn exists_command(
@@ -154,11 +233,10 @@ pub fn exists_command(
     _el: &mut AeEventLoop,
 ) {
     let db = &mut server.db[client.db_idx];
-    let r = match db.look_up_key_read(&client.argv[1]) {
-        Some(_) => shared_object!(CONE),
-        None => shared_object!(CZERO),
-    };
-    client.add_reply(r);
+    match db.look_up_key_read(&client.argv[1]) {
+        Some(_) => client.add_reply_integer(1),
+        None => client.add_reply_integer(0),
+    }
 }
 
 pub fn incr_command(
@@ -187,7 +265,7 @@ pub fn incr_decr_command(
     let db = &mut server.db[client.db_idx];
     let mut val: i64;
 
-    let r = db.look_up_key_read(&client.argv[1]);
+    let r = db.look_up_key_write(&client.argv[1]);
 
     val = match r {
         None => 0,
@@ -196,7 +274,7 @@ pub fn incr_decr_command(
             match enc {
                 RobjEncoding::Int => v.borrow().integer(),
                 _ => {
-                    client.add_str_reply("-ERR value is not an integer or out of range\r\n");
+                    client.add_reply_error("value is not an integer or out of range");
                     return;
                 }
             }
@@ -204,16 +282,17 @@ pub fn incr_decr_command(
     };
     val = match val.checked_add(incr) {
         None => {
-            client.add_str_reply("-ERR increment or decrement would overflow\r\n");
+            client.add_reply_error("increment or decrement would overflow");
             return;
         }
         Some(v) => v,
     };
     let o = Robj::create_int_object(val);
-    db.dict.replace(Rc::clone(&client.argv[1]), Rc::clone(&o));
-    client.add_reply(shared_object!(COLON));
-    client.add_reply(o);
-    client.add_reply(shared_object!(CRLF));
+    db.dict.replace(Rc::clone(&client.argv[1]), o);
+    db.signal_modified_key(&client.argv[1]);
+    server.notify_keyspace_event(NOTIFY_STRING, "incrby", &client.argv[1], client.db_idx);
+    server.dirty += 1;
+    client.add_reply_integer(val);
 }
 
 pub fn mget_command(
@@ -223,7 +302,7 @@ pub fn mget_command(
 ) {
     let n = client.argc() - 1;
     let db = &mut server.db[client.db_idx];
-    client.add_reply_from_string(format!("*{}\r\n", n));
+    client.add_reply_array_len(n);
     let mut argv: Vec<RobjPtr> = vec![];
     swap(&mut argv, &mut client.argv);
     for key in argv
@@ -231,16 +310,17 @@ pub fn mget_command(
         .skip(1) {
         let r = db.look_up_key_read(key);
         match r {
-            None => client.add_reply(shared_object!(NULL_BULK)),
+            None => client.add_reply_null(),
             Some(o) => {
                 if !o.borrow().is_string() {
-                    client.add_reply(shared_object!(NULL_BULK));
+                    client.add_reply_null();
                 } else {
-                    add_single_reply(client, o);
+                    client.add_reply_bulk(o);
                 }
             }
         }
     }
+    client.argv = argv;
 }
 
 pub fn rpush_command(
@@ -266,7 +346,7 @@ pub fn push_generic_command(
     w: ListWhere,
 ) {
     let db = &mut server.db[client.db_idx];
-    let r = db.look_up_key_read(&client.argv[1]);
+    let r = db.look_up_key_write(&client.argv[1]);
     let mut create_new: bool = false;
 
     let list_obj = match r {
@@ -278,7 +358,7 @@ pub fn push_generic_command(
     };
 
     if !list_obj.borrow().is_list() {
-        client.add_reply(shared_object!(WRONG_TYPE));
+        client.add_reply_wrong_type();
         return;
     }
 
@@ -294,11 +374,17 @@ pub fn push_generic_command(
         db.dict.add(Rc::clone(&client.argv[1]), list_obj).unwrap();
     }
 
+    db.signal_modified_key(&client.argv[1]);
+    let event = match w {
+        ListWhere::Head => "lpush",
+        ListWhere::Tail => "rpush",
+    };
+    server.notify_keyspace_event(NOTIFY_LIST, event, &client.argv[1], client.db_idx);
     server.dirty += 1;
     if len == 0 {
-        client.add_reply(shared_object!(CZERO));
+        client.add_reply_integer(0);
     } else {
-        client.add_reply_from_string(format!(":{}\r\n", len));
+        client.add_reply_integer(len as i64);
     }
 }
 
@@ -325,25 +411,35 @@ fn pop_generic_command(
     w: ListWhere,
 ) {
     let db = &mut server.db[client.db_idx];
-    let r = db.look_up_key_read(&client.argv[1]);
+    let r = db.look_up_key_write(&client.argv[1]);
 
     let list_obj = match r {
         None => {
-            client.add_reply(shared_object!(NULL_BULK));
+            client.add_reply_null();
             return;
         }
         Some(o) => o,
     };
 
     let o = list_obj.borrow_mut().list_pop(w);
-    if list_obj.borrow().list_len() == 0 {
+    let emptied = list_obj.borrow().list_len() == 0;
+    if emptied {
         let _ = db.delete_key(&client.argv[1]);
     }
 
     match o {
-        None => client.add_reply(shared_object!(NULL_BULK)),
+        None => client.add_reply_null(),
         Some(o) => {
-            add_single_reply(client, o);
+            client.add_reply_bulk(o);
+            db.signal_modified_key(&client.argv[1]);
+            let event = match w {
+                ListWhere::Head => "lpop",
+                ListWhere::Tail => "rpop",
+            };
+            server.notify_keyspace_event(NOTIFY_LIST, event, &client.argv[1], client.db_idx);
+            if emptied {
+                server.notify_keyspace_event(NOTIFY_GENERIC, "del", &client.argv[1], client.db_idx);
+            }
             server.dirty += 1;
         }
     }
@@ -356,12 +452,12 @@ pub fn llen_command(
 ) {
     let db = &mut server.db[client.db_idx];
     match db.look_up_key_read(&client.argv[1]) {
-        None => client.add_reply(shared_object!(CZERO)),
+        None => client.add_reply_integer(0),
         Some(o) => {
             if o.borrow().object_type() != RobjType::List {
-                client.add_reply(shared_object!(WRONG_TYPE));
+                client.add_reply_wrong_type();
             } else {
-                client.add_reply(gen_usize_reply(o.borrow().list_len()));
+                client.add_reply_integer(o.borrow().list_len() as i64);
             }
         }
     }
@@ -379,29 +475,29 @@ pub fn lindex_command(
     let idx = match to_int {
         Ok(i) => i,
         Err(_) => {
-            client.add_str_reply("-ERR value is not an integer or out of range\r\n");
+            client.add_reply_error("value is not an integer or out of range");
             return;
         }
     };
 
     match db.look_up_key_read(&client.argv[1]) {
-        None => client.add_reply(shared_object!(NULL_BULK)),
+        None => client.add_reply_null(),
         Some(o) => {
             if o.borrow().object_type() != RobjType::List {
-                client.add_reply(shared_object!(WRONG_TYPE));
+                client.add_reply_wrong_type();
             } else {
                 let len = o.borrow().list_len();
                 let real_idx = real_list_index(idx, len);
 
                 if real_idx < 0 {
-                    client.add_reply(shared_object!(NULL_BULK));
+                    client.add_reply_null();
                     return;
                 }
 
                 match o.borrow().list_index(real_idx as usize) {
-                    None => client.add_reply(shared_object!(NULL_BULK)),
+                    None => client.add_reply_null(),
                     Some(r) => {
-                        add_single_reply(client, r);
+                        client.add_reply_bulk(r);
                     }
                 }
             }
@@ -421,33 +517,35 @@ pub fn lset_command(
     let idx = match to_int {
         Ok(i) => i,
         Err(_) => {
-            client.add_str_reply("-ERR value is not an integer or out of range\r\n");
+            client.add_reply_error("value is not an integer or out of range");
             return;
         }
     };
 
-    match db.look_up_key_read(&client.argv[1]) {
-        None => client.add_str_reply("-ERR no such key\r\n"),
+    match db.look_up_key_write(&client.argv[1]) {
+        None => client.add_reply_error("no such key"),
         Some(o) => {
             if o.borrow().object_type() != RobjType::List {
-                client.add_reply(shared_object!(WRONG_TYPE));
+                client.add_reply_wrong_type();
             } else {
                 let len = o.borrow().list_len();
                 let real_idx = real_list_index(idx, len);
 
                 if real_idx < 0 {
-                    client.add_str_reply("-ERR index out of range\r\n");
+                    client.add_reply_error("index out of range");
                     return;
                 }
 
                 match o.borrow_mut()
                     .list_set(real_idx as usize, Rc::clone(&client.argv[3])) {
-                    Ok(_) => client.add_reply(shared_object!(OK)),
-                    Err(_) => client.add_str_reply("-ERR index out of range\r\n"),
+                    Ok(_) => client.add_reply_ok(),
+                    Err(_) => client.add_reply_error("index out of range"),
                 }
             }
         }
     }
+    db.signal_modified_key(&client.argv[1]);
+    server.notify_keyspace_event(NOTIFY_LIST, "lset", &client.argv[1], client.db_idx);
     server.dirty += 1;
 }
 
@@ -463,7 +561,7 @@ pub fn lrange_command(
            client.argv[3].borrow().object_to_long());
 
     if left.is_err() || right.is_err() {
-        client.add_str_reply("-ERR value is not an integer or out of range\r\n");
+        client.add_reply_error("value is not an integer or out of range");
         return;
     }
 
@@ -471,12 +569,12 @@ pub fn lrange_command(
 
     let o = match db.look_up_key_read(&client.argv[1]) {
         None => {
-            client.add_reply(shared_object!(EMPTY_MULTI_BULK));
+            client.add_reply_array_len(0);
             return;
         }
         Some(obj) => {
             if obj.borrow().object_type() != RobjType::List {
-                client.add_reply(shared_object!(WRONG_TYPE));
+                client.add_reply_wrong_type();
                 return;
             }
             obj
@@ -489,7 +587,7 @@ pub fn lrange_command(
                                  real_list_index(right, len));
 
     if (left < 0 && right < 0) || (left >= 0 && left as usize >= len) || left > right {
-        client.add_reply(shared_object!(EMPTY_MULTI_BULK));
+        client.add_reply_array_len(0);
         return;
     }
 
@@ -501,12 +599,10 @@ pub fn lrange_command(
         right = len as i64 - 1;
     }
 
-    client.add_str_reply(
-        &format!("*{}\r\n", right - left + 1)
-    );
+    client.add_reply_array_len((right - left + 1) as usize);
 
     for r in o.borrow().list_iter().skip(left as usize) {
-        add_single_reply(client, r);
+        client.add_reply_bulk(r);
         right -= 1;
         if right < left {
             break;
@@ -526,20 +622,20 @@ pub fn ltrim_command(
            client.argv[3].borrow().object_to_long());
 
     if left.is_err() || right.is_err() {
-        client.add_str_reply("-ERR value is not an integer or out of range\r\n");
+        client.add_reply_error("value is not an integer or out of range");
         return;
     }
 
     let (left, right) = (left.unwrap(), right.unwrap());
 
-    let o = match db.look_up_key_read(&client.argv[1]) {
+    let o = match db.look_up_key_write(&client.argv[1]) {
         None => {
-            client.add_reply(shared_object!(EMPTY_MULTI_BULK));
+            client.add_reply_array_len(0);
             return;
         }
         Some(obj) => {
             if obj.borrow().object_type() != RobjType::List {
-                client.add_reply(shared_object!(WRONG_TYPE));
+                client.add_reply_wrong_type();
                 return;
             }
             obj
@@ -562,10 +658,16 @@ pub fn ltrim_command(
         }
         o.borrow_mut().list_trim(left as usize, right as usize);
     }
-    if o.borrow().list_len() == 0 {
+    let emptied = o.borrow().list_len() == 0;
+    if emptied {
         let _ = db.delete_key(&client.argv[1]);
     }
-    client.add_reply(shared_object!(OK));
+    client.add_reply_ok();
+    db.signal_modified_key(&client.argv[1]);
+    server.notify_keyspace_event(NOTIFY_LIST, "ltrim", &client.argv[1], client.db_idx);
+    if emptied {
+        server.notify_keyspace_event(NOTIFY_GENERIC, "del", &client.argv[1], client.db_idx);
+    }
     server.dirty += 1;
 }
 
@@ -579,20 +681,20 @@ pub fn lrem_command(
     let to_int = client.argv[2].borrow().object_to_long();
     let i = match to_int {
         Err(_) => {
-            client.add_str_reply("-ERR value is not an integer or out of range\r\n");
+            client.add_reply_error("value is not an integer or out of range");
             return;
         }
         Ok(i) => i,
     };
 
-    let o = match db.look_up_key_read(&client.argv[1]) {
+    let o = match db.look_up_key_write(&client.argv[1]) {
         None => {
-            client.add_reply(shared_object!(CZERO));
+            client.add_reply_integer(0);
             return;
         }
         Some(obj) => {
             if obj.borrow().object_type() != RobjType::List {
-                client.add_reply(shared_object!(WRONG_TYPE));
+                client.add_reply_wrong_type();
                 return;
             }
             obj
@@ -608,11 +710,19 @@ pub fn lrem_command(
     };
 
     let n = o.borrow_mut().list_del_n(w, n, &client.argv[3]);
-    if o.borrow().list_len() == 0 {
+    let emptied = o.borrow().list_len() == 0;
+    if emptied {
         let _ = db.delete_key(&client.argv[1]);
     }
+    db.signal_modified_key(&client.argv[1]);
+    if n > 0 {
+        server.notify_keyspace_event(NOTIFY_LIST, "lrem", &client.argv[1], client.db_idx);
+    }
+    if emptied {
+        server.notify_keyspace_event(NOTIFY_GENERIC, "del", &client.argv[1], client.db_idx);
+    }
     server.dirty += 1;
-    client.add_reply(gen_usize_reply(n));
+    client.add_reply_integer(n as i64);
 }
 
 pub fn sadd_command(
@@ -623,7 +733,7 @@ pub fn sadd_command(
     let db = &mut server.db[client.db_idx];
     let mut old_len: usize = 0;
 
-    let set_obj = match db.look_up_key_read(&client.argv[1]) {
+    let set_obj = match db.look_up_key_write(&client.argv[1]) {
         None => {
             let o = Robj::create_int_set_object();
             let _ = db.dict.add(Rc::clone(&client.argv[1]), Rc::clone(&o));
@@ -631,7 +741,7 @@ pub fn sadd_command(
         }
         Some(o) => {
             if !o.borrow().is_set() {
-                client.add_reply(shared_object!(WRONG_TYPE));
+                client.add_reply_wrong_type();
                 return;
             }
             old_len = o.borrow().set_len();
@@ -644,7 +754,9 @@ pub fn sadd_command(
         let _ = set_obj.borrow_mut().set_add(new);
     }
 
-    client.add_reply(gen_usize_reply(set_obj.borrow().set_len() - old_len));
+    client.add_reply_integer((set_obj.borrow().set_len() - old_len) as i64);
+    db.signal_modified_key(&client.argv[1]);
+    server.notify_keyspace_event(NOTIFY_SET, "sadd", &client.argv[1], client.db_idx);
     server.dirty += 1;
 }
 
@@ -657,14 +769,14 @@ pub fn srem_command(
     let old_len: usize;
     let cur_len: usize;
 
-    let set_obj = match db.look_up_key_read(&client.argv[1]) {
+    let set_obj = match db.look_up_key_write(&client.argv[1]) {
         None => {
-            client.add_reply(shared_object!(CZERO));
+            client.add_reply_integer(0);
             return;
         }
         Some(o) => {
             if !o.borrow().is_set() {
-                client.add_reply(shared_object!(WRONG_TYPE));
+                client.add_reply_wrong_type();
                 return;
             }
             o
@@ -682,11 +794,18 @@ pub fn srem_command(
 
     cur_len = set_obj.borrow().set_len();
 
-    client.add_reply(gen_usize_reply(old_len - cur_len));
+    client.add_reply_integer((old_len - cur_len) as i64);
     if cur_len == 0 {
         let _ = db.delete_key(&client.argv[1]);
     }
 
+    db.signal_modified_key(&client.argv[1]);
+    if old_len != cur_len {
+        server.notify_keyspace_event(NOTIFY_SET, "srem", &client.argv[1], client.db_idx);
+    }
+    if cur_len == 0 {
+        server.notify_keyspace_event(NOTIFY_GENERIC, "del", &client.argv[1], client.db_idx);
+    }
     server.dirty += 1;
 }
 
@@ -699,22 +818,22 @@ pub fn smembers_command(
 
     let set_obj = match db.look_up_key_read(&client.argv[1]) {
         None => {
-            client.add_reply(shared_object!(EMPTY_MULTI_BULK));
+            client.add_reply_set_len(0);
             return;
         }
         Some(o) => {
             if !o.borrow().is_set() {
-                client.add_reply(shared_object!(WRONG_TYPE));
+                client.add_reply_wrong_type();
                 return;
             }
             o
         }
     };
 
-    client.add_reply_from_string(format!("*{}\r\n", set_obj.borrow().set_len()));
+    client.add_reply_set_len(set_obj.borrow().set_len());
 
     for o in set_obj.borrow().set_iter() {
-        add_single_reply(client, o);
+        client.add_reply_bulk(o);
     }
 }
 
@@ -725,27 +844,27 @@ pub fn smove_command(
 ) {
     let db = &mut server.db[client.db_idx];
 
-    let src_set = match db.look_up_key_read(&client.argv[1]) {
+    let src_set = match db.look_up_key_write(&client.argv[1]) {
         None => {
-            client.add_reply(shared_object!(CZERO));
+            client.add_reply_integer(0);
             return;
         }
         Some(o) => {
             if !o.borrow().is_set() {
-                client.add_reply(shared_object!(WRONG_TYPE));
+                client.add_reply_wrong_type();
                 return;
             }
             o
         }
     };
 
-    let dst_set = match db.look_up_key_read(&client.argv[2]) {
+    let dst_set = match db.look_up_key_write(&client.argv[2]) {
         None => {
             None
         }
         Some(o) => {
             if !o.borrow().is_set() {
-                client.add_reply(shared_object!(WRONG_TYPE));
+                client.add_reply_wrong_type();
                 return;
             }
             Some(o)
@@ -754,6 +873,7 @@ pub fn smove_command(
 
     let r = src_set.borrow_mut().set_delete(&client.argv[3]);
 
+    let moved = r.is_ok();
     match r {
         Ok(_) => {
             let dst_set = match dst_set {
@@ -768,16 +888,26 @@ pub fn smove_command(
                 }
             };
             let _ = dst_set.borrow_mut().set_add(Rc::clone(&client.argv[3]));
-            client.add_reply(shared_object!(CONE));
+            client.add_reply_integer(1);
         }
         Err(_) => {
-            client.add_reply(shared_object!(CZERO));
+            client.add_reply_integer(0);
         }
     }
 
-    if src_set.borrow().set_len() == 0 {
+    let emptied = src_set.borrow().set_len() == 0;
+    if emptied {
         let _ = db.delete_key(&client.argv[1]);
     }
+    db.signal_modified_key(&client.argv[1]);
+    db.signal_modified_key(&client.argv[2]);
+    if moved {
+        server.notify_keyspace_event(NOTIFY_SET, "srem", &client.argv[1], client.db_idx);
+        if emptied {
+            server.notify_keyspace_event(NOTIFY_GENERIC, "del", &client.argv[1], client.db_idx);
+        }
+        server.notify_keyspace_event(NOTIFY_SET, "sadd", &client.argv[2], client.db_idx);
+    }
     server.dirty += 1;
 }
 
@@ -790,12 +920,12 @@ pub fn sismember_command(
 
     let set_obj = match db.look_up_key_read(&client.argv[1]) {
         None => {
-            client.add_reply(shared_object!(CZERO));
+            client.add_reply_integer(0);
             return;
         }
         Some(o) => {
             if !o.borrow().is_set() {
-                client.add_reply(shared_object!(WRONG_TYPE));
+                client.add_reply_wrong_type();
                 return;
             }
             o
@@ -805,8 +935,8 @@ pub fn sismember_command(
     let r = set_obj.borrow().set_exists(&client.argv[2]);
 
     match r {
-        true => client.add_reply(shared_object!(CONE)),
-        false => client.add_reply(shared_object!(CZERO)),
+        true => client.add_reply_integer(1),
+        false => client.add_reply_integer(0),
     }
 }
 
@@ -819,21 +949,19 @@ pub fn scard_command(
 
     let set_obj = match db.look_up_key_read(&client.argv[1]) {
         None => {
-            client.add_reply(shared_object!(CZERO));
+            client.add_reply_integer(0);
             return;
         }
         Some(o) => {
             if !o.borrow().is_set() {
-                client.add_reply(shared_object!(WRONG_TYPE));
+                client.add_reply_wrong_type();
                 return;
             }
             o
         }
     };
 
-    client.add_reply(gen_usize_reply(
-        set_obj.borrow().set_len()
-    ));
+    client.add_reply_integer(set_obj.borrow().set_len() as i64);
 }
 
 pub fn spop_command(
@@ -845,14 +973,14 @@ pub fn spop_command(
     let old_len: usize;
     let deleted: usize;
 
-    let set_obj = match db.look_up_key_read(&client.argv[1]) {
+    let set_obj = match db.look_up_key_write(&client.argv[1]) {
         None => {
-            client.add_reply(shared_object!(EMPTY_MULTI_BULK));
+            client.add_reply_array_len(0);
             return;
         }
         Some(o) => {
             if !o.borrow().is_set() {
-                client.add_reply(shared_object!(WRONG_TYPE));
+                client.add_reply_wrong_type();
                 return;
             }
             o
@@ -862,17 +990,21 @@ pub fn spop_command(
     old_len = set_obj.borrow().set_len();
     deleted = rand::thread_rng().gen_range(0, old_len + 1);
 
-    client.add_reply_from_string(format!("*{}\r\n", deleted));
+    client.add_reply_array_len(deleted);
     for _ in 0..deleted {
-        add_single_reply(
-            client,
-            set_obj.borrow_mut().set_pop_random(),
-        );
+        client.add_reply_bulk(set_obj.borrow_mut().set_pop_random());
     }
 
     if deleted == old_len {
         let _ = db.delete_key(&client.argv[1]);
     }
+    db.signal_modified_key(&client.argv[1]);
+    if deleted > 0 {
+        server.notify_keyspace_event(NOTIFY_SET, "spop", &client.argv[1], client.db_idx);
+    }
+    if deleted == old_len {
+        server.notify_keyspace_event(NOTIFY_GENERIC, "del", &client.argv[1], client.db_idx);
+    }
     server.dirty += 1;
 }
 
@@ -893,7 +1025,10 @@ pub fn sinterstore_command(
     let new_key = client.argv.drain(1..2).next().unwrap();
     sinter_general_command(client, server, Some(Rc::clone(&new_set)));
     if new_set.borrow().set_len() > 0 {
-        server.db[client.db_idx].dict.replace(new_key, new_set);
+        let db = &mut server.db[client.db_idx];
+        db.signal_modified_key(&new_key);
+        db.dict.replace(Rc::clone(&new_key), new_set);
+        server.notify_keyspace_event(NOTIFY_SET, "sinterstore", &new_key, client.db_idx);
         server.dirty += 1;
     }
 }
@@ -907,18 +1042,20 @@ fn sinter_general_command(
     let mut sets: Vec<RobjPtr> = Vec::with_capacity(client.argc());
 
     for key in client.argv.iter().skip(1) {
-        let set_obj = match db.look_up_key_read(key) {
+        // the sources of a store are not counted as keyspace hits
+        let found = if dst.is_some() { db.look_up_key_write(key) } else { db.look_up_key_read(key) };
+        let set_obj = match found {
             None => {
                 if dst.is_none() {
-                    client.add_reply(shared_object!(EMPTY_MULTI_BULK));
+                    client.add_reply_set_len(0);
                 } else {
-                    client.add_reply(shared_object!(CZERO));
+                    client.add_reply_integer(0);
                 }
                 return;
             }
             Some(o) => {
                 if !o.borrow().is_set() {
-                    client.add_reply(shared_object!(WRONG_TYPE));
+                    client.add_reply_wrong_type();
                     return;
                 }
                 o
@@ -934,21 +1071,23 @@ fn sinter_general_command(
     let obj_ref = sets[0].borrow();
     let iter = obj_ref.set_inter_iter(&sets[1..]);
     let mut cnt: usize = 0;
-    let num = Robj::create_string_object("");
-    client.add_reply(Rc::clone(&num));
+    let num = match dst {
+        None => Some(client.add_reply_deferred_len()),
+        Some(_) => None,
+    };
 
     for r in iter {
         match dst.as_mut() {
-            None => add_single_reply(client, r),
+            None => client.add_reply_bulk(r),
             Some(set) => { let _ = set.borrow_mut().set_add(r); }
         }
         cnt += 1;
     }
 
-    match dst.as_ref() {
-        None => num.borrow_mut().change_to_str(&format!("*{}\r\n", cnt)),
-        Some(r) =>
-            num.borrow_mut().change_to_str(&format!(":{}\r\n", r.borrow().set_len())),
+    match (dst.as_ref(), num) {
+        (Some(r), _) => client.add_reply_integer(r.borrow().set_len() as i64),
+        (None, Some(num)) => client.set_deferred_set_len(num, cnt),
+        (None, None) => unreachable!(),
     }
 }
 
@@ -996,20 +1135,21 @@ fn sdiff_general_command(
     let mut cardinality: usize = 0;
 
     for (i, key) in client.argv.iter().skip(1).enumerate() {
-        match db.look_up_key_read(key) {
+        let found = if dst { db.look_up_key_write(key) } else { db.look_up_key_read(key) };
+        match found {
             None => {
                 if op == DiffOperation::Diff && i == 0 {
                     if dst {
-                        client.add_reply(shared_object!(CZERO));
+                        client.add_reply_integer(0);
                     } else {
-                        client.add_reply(shared_object!(EMPTY_MULTI_BULK));
+                        client.add_reply_set_len(0);
                     }
                     return;
                 }
             }
             Some(o) => {
                 if !o.borrow().is_set() {
-                    client.add_reply(shared_object!(WRONG_TYPE));
+                    client.add_reply_wrong_type();
                     return;
                 }
                 sets.push(o);
@@ -1040,14 +1180,20 @@ fn sdiff_general_command(
     }
 
     if !dst {
-        client.add_reply_from_string(format!("*{}\r\n", cardinality));
+        client.add_reply_set_len(cardinality);
         assert_eq!(cardinality, tmp_set.borrow().set_len());
         for obj in tmp_set.borrow().set_iter() {
-            add_single_reply(client, obj);
+            client.add_reply_bulk(obj);
         }
     } else {
-        client.add_reply(gen_usize_reply(cardinality));
+        client.add_reply_integer(cardinality as i64);
         db.dict.replace(Rc::clone(&client.argv[1]), tmp_set);
+        db.signal_modified_key(&client.argv[1]);
+        let event = match op {
+            DiffOperation::Union => "sunionstore",
+            DiffOperation::Diff => "sdiffstore",
+        };
+        server.notify_keyspace_event(NOTIFY_SET, event, &client.argv[1], client.db_idx);
         server.dirty += 1;
     }
 }
@@ -1060,7 +1206,7 @@ pub fn incr_by_command(
     let r = client.argv[2].borrow().object_to_long();
     match r {
         Ok(n) => incr_decr_command(client, server, _el, n),
-        Err(_) => client.add_str_reply("-ERR value is not an integer or out of range\r\n"),
+        Err(_) => client.add_reply_error("value is not an integer or out of range"),
     }
 }
 
@@ -1073,12 +1219,12 @@ pub fn decr_by_command(
     match r {
         Ok(n) => {
             if n == std::i64::MIN {
-                client.add_str_reply("-ERR value is not an integer or out of range\r\n");
+                client.add_reply_error("value is not an integer or out of range");
                 return;
             }
             incr_decr_command(client, server, _el, -n)
         }
-        Err(_) => client.add_str_reply("-ERR value is not an integer or out of range\r\n"),
+        Err(_) => client.add_reply_error("value is not an integer or out of range"),
     }
 }
 
@@ -1093,9 +1239,209 @@ pub fn get_set_command(
     db.dict.replace(Rc::clone(&client.argv[1]),
                     o);
     let _ = db.remove_expire(&client.argv[1]);
+    db.signal_modified_key(&client.argv[1]);
+    server.notify_keyspace_event(NOTIFY_STRING, "set", &client.argv[1], client.db_idx);
     server.dirty += 1;
 }
 
+pub fn getex_command(
+    client: &mut Client,
+    server: &mut Server,
+    _el: &mut AeEventLoop,
+) {
+    let mut when: Option<u64> = None;
+    let mut persist = false;
+    let now = unix_timestamp(&SystemTime::now());
+
+    let mut j = 2;
+    while j < client.argc() {
+        let opt = client.argv[j].borrow().string().to_ascii_lowercase();
+        let has_next = j + 1 < client.argc();
+        match &opt[..] {
+            b"persist" if when.is_none() && !persist => persist = true,
+            b"ex" | b"px" | b"exat" | b"pxat" if has_next && when.is_none() && !persist => {
+                let r = client.argv[j + 1].borrow().object_to_long();
+                let n = match r {
+                    Ok(n) => n,
+                    Err(_) => {
+                        client.add_reply_error("value is not an integer or out of range");
+                        return;
+                    }
+                };
+                let at = match &opt[..] {
+                    b"ex" => (n as u64).checked_mul(1000).and_then(|ms| ms.checked_add(now)),
+                    b"px" => (n as u64).checked_add(now),
+                    b"exat" => (n as u64).checked_mul(1000),
+                    _ => Some(n as u64),
+                };
+                match at {
+                    Some(at) if n > 0 => when = Some(at),
+                    _ => {
+                        client.add_reply_error("invalid expire time in 'getex' command");
+                        return;
+                    }
+                }
+                j += 1;
+            }
+            _ => {
+                client.add_reply_error("syntax error");
+                return;
+            }
+        }
+        j += 1;
+    }
+
+    let db = &mut server.db[client.db_idx];
+    let o = match db.look_up_key_read(&client.argv[1]) {
+        None => {
+            client.add_reply_null();
+            return;
+        }
+        Some(o) => o,
+    };
+
+    if !o.borrow().is_string() {
+        client.add_reply_wrong_type();
+        return;
+    }
+    client.add_reply_bulk(o);
+
+    let key = Rc::clone(&client.argv[1]);
+    if let Some(at) = when {
+        db.signal_modified_key(&key);
+        if at <= now {
+            let _ = db.delete_key(&key);
+            server.notify_keyspace_event(NOTIFY_GENERIC, "del", &key, client.db_idx);
+            client.rewrite_argv(vec![Robj::create_string_object("DEL"), key]);
+        } else {
+            db.replace_expire(Rc::clone(&key), to_system_time(at));
+            server.notify_keyspace_event(NOTIFY_GENERIC, "expire", &key, client.db_idx);
+            client.rewrite_argv(vec![
+                Robj::create_string_object("PEXPIREAT"),
+                key,
+                Robj::create_string_object_from_long(at as i64),
+            ]);
+        }
+        server.dirty += 1;
+    } else if persist && db.remove_expire(&key).is_ok() {
+        db.signal_modified_key(&key);
+        server.notify_keyspace_event(NOTIFY_GENERIC, "persist", &key, client.db_idx);
+        client.rewrite_argv(vec![Robj::create_string_object("PERSIST"), key]);
+        server.dirty += 1;
+    }
+}
+
+pub fn getdel_command(
+    client: &mut Client,
+    server: &mut Server,
+    _el: &mut AeEventLoop,
+) {
+    let db = &mut server.db[client.db_idx];
+    let o = match db.look_up_key_read(&client.argv[1]) {
+        None => {
+            client.add_reply_null();
+            return;
+        }
+        Some(o) => o,
+    };
+
+    if !o.borrow().is_string() {
+        client.add_reply_wrong_type();
+        return;
+    }
+    client.add_reply_bulk(o);
+
+    let key = Rc::clone(&client.argv[1]);
+    let _ = db.delete_key(&key);
+    db.signal_modified_key(&key);
+    server.notify_keyspace_event(NOTIFY_GENERIC, "del", &key, client.db_idx);
+    client.rewrite_argv(vec![Robj::create_string_object("DEL"), key]);
+    server.dirty += 1;
+}
+
+pub fn lcs_command(
+    client: &mut Client,
+    server: &mut Server,
+    _el: &mut AeEventLoop,
+) {
+    let mut get_len = false;
+    let mut get_idx = false;
+    let mut with_match_len = false;
+    let mut min_match_len: usize = 0;
+
+    let mut j = 3;
+    while j < client.argc() {
+        let opt = client.argv[j].borrow().string().to_ascii_lowercase();
+        match &opt[..] {
+            b"len" => get_len = true,
+            b"idx" => get_idx = true,
+            b"withmatchlen" => with_match_len = true,
+            b"minmatchlen" if j + 1 < client.argc() => {
+                let r = client.argv[j + 1].borrow().object_to_long();
+                min_match_len = match r {
+                    Ok(n) => std::cmp::max(n, 0) as usize,
+                    Err(_) => {
+                        client.add_reply_error("value is not an integer or out of range");
+                        return;
+                    }
+                };
+                j += 1;
+            }
+            _ => {
+                client.add_reply_error("syntax error");
+                return;
+            }
+        }
+        j += 1;
+    }
+
+    if get_len && get_idx {
+        client.add_reply_error("If you want both the length and indexes, \
+            please just use IDX.");
+        return;
+    }
+
+    let db = &mut server.db[client.db_idx];
+    let mut strings: Vec<Vec<u8>> = Vec::with_capacity(2);
+    for key in client.argv[1..3].iter() {
+        match db.look_up_key_read(key) {
+            None => strings.push(vec![]),
+            Some(o) => {
+                if !o.borrow().is_string() {
+                    client.add_reply_error("The specified keys must contain string values");
+                    return;
+                }
+                strings.push(o.borrow().string_to_vec());
+            }
+        }
+    }
+
+    let (result, matches) = lcs(&strings[0], &strings[1], min_match_len);
+
+    if get_len {
+        client.add_reply_integer(result.len() as i64);
+    } else if get_idx {
+        client.add_reply_map_len(2);
+        client.add_reply_bulk_bytes(b"matches");
+        client.add_reply_array_len(matches.len());
+        for m in matches.iter() {
+            client.add_reply_array_len(if with_match_len { 3 } else { 2 });
+            for (start, end) in [m.a, m.b] {
+                client.add_reply_array_len(2);
+                client.add_reply_integer(start as i64);
+                client.add_reply_integer(end as i64);
+            }
+            if with_match_len {
+                client.add_reply_integer(m.len as i64);
+            }
+        }
+        client.add_reply_bulk_bytes(b"len");
+        client.add_reply_integer(result.len() as i64);
+    } else {
+        client.add_reply_bulk(Robj::from_bytes(result));
+    }
+}
+
 pub fn randomkey_command(
     client: &mut Client,
     server: &mut Server,
@@ -1103,10 +1449,10 @@ pub fn randomkey_command(
 ) {
     let db = &server.db[client.db_idx];
     if db.dict.len() == 0 {
-        client.add_reply(shared_object!(NULL_BULK));
+        client.add_reply_null();
     } else {
         let (key, _) = db.dict.random_key_value();
-        add_single_reply(client, Rc::clone(key));
+        client.add_reply_bulk(Rc::clone(key));
     }
 }
 
@@ -1118,15 +1464,15 @@ pub fn select_command(
     let idx = client.argv[1].borrow().object_to_long();
     match idx {
         Err(_) => {
-            client.add_str_reply("-ERR invalid DB index\r\n")
+            client.add_reply_error("invalid DB index")
         }
         Ok(idx) => {
             if idx < 0 || idx >= server.db.len() as i64 {
-                client.add_str_reply("-ERR invalid DB index\r\n");
+                client.add_reply_error("invalid DB index");
                 return;
             }
             client.db_idx = idx as usize;
-            client.add_reply(shared_object!(OK));
+            client.add_reply_ok();
         }
     }
 }
@@ -1140,13 +1486,13 @@ pub fn move_command(
     let dst = match r {
         Ok(i) => i,
         Err(_) => {
-            client.add_reply(shared_object!(CZERO));
+            client.add_reply_integer(0);
             return;
         }
     };
 
     if dst < 0 || dst as usize == client.db_idx || dst as usize >= server.db.len() {
-        client.add_reply(shared_object!(CZERO));
+        client.add_reply_integer(0);
         return;
     }
 
@@ -1160,19 +1506,23 @@ pub fn move_command(
         (&mut right[0], &mut left[dst])
     };
 
-    let value = match src_db.look_up_key_read(&client.argv[1]) {
+    let value = match src_db.look_up_key_write(&client.argv[1]) {
         None => {
-            client.add_reply(shared_object!(CZERO));
+            client.add_reply_integer(0);
             return;
         }
         Some(o) => o,
     };
 
     match dst_db.dict.add(Rc::clone(&client.argv[1]), value) {
-        Err(_) => client.add_reply(shared_object!(CZERO)),
+        Err(_) => client.add_reply_integer(0),
         Ok(_) => {
             let _ = src_db.delete_key(&client.argv[1]);
-            client.add_reply(shared_object!(CONE));
+            src_db.signal_modified_key(&client.argv[1]);
+            dst_db.signal_modified_key(&client.argv[1]);
+            server.notify_keyspace_event(NOTIFY_GENERIC, "move_from", &client.argv[1], client.db_idx);
+            server.notify_keyspace_event(NOTIFY_GENERIC, "move_to", &client.argv[1], dst);
+            client.add_reply_integer(1);
             server.dirty += 1;
         }
     }
@@ -1201,19 +1551,19 @@ pub fn rename_general_command(
 ) {
     let db = &mut server.db[client.db_idx];
 
-    let value = match db.look_up_key_read(&client.argv[1]) {
+    let value = match db.look_up_key_write(&client.argv[1]) {
         Some(o) => o,
         None => {
-            client.add_str_reply("-ERR no such key\r\n");
+            client.add_reply_error("no such key");
             return;
         }
     };
 
     if client.argv[1].borrow().string() == client.argv[2].borrow().string() {
         if nx {
-            client.add_reply(shared_object!(CZERO));
+            client.add_reply_integer(0);
         } else {
-            client.add_reply(shared_object!(OK));
+            client.add_reply_ok();
         }
         return;
     }
@@ -1223,17 +1573,21 @@ pub fn rename_general_command(
         Rc::clone(&value),
     ) {
         if nx {
-            client.add_reply(shared_object!(CZERO));
+            client.add_reply_integer(0);
             return;
         }
         db.dict.replace(Rc::clone(&client.argv[2]), value);
     }
 
     let _ = db.delete_key(&client.argv[1]);
+    db.signal_modified_key(&client.argv[1]);
+    db.signal_modified_key(&client.argv[2]);
+    server.notify_keyspace_event(NOTIFY_GENERIC, "rename_from", &client.argv[1], client.db_idx);
+    server.notify_keyspace_event(NOTIFY_GENERIC, "rename_to", &client.argv[2], client.db_idx);
     if nx {
-        client.add_reply(shared_object!(CONE));
+        client.add_reply_integer(1);
     } else {
-        client.add_reply(shared_object!(OK));
+        client.add_reply_ok();
     }
     server.dirty += 1;
 }
@@ -1249,16 +1603,16 @@ pub fn expire_command(
     };
     let seconds = match r {
         Err(_) => {
-            client.add_reply(shared_object!(CZERO));
+            client.add_reply_integer(0);
             return;
         }
         Ok(i) => i,
     };
     let db = &mut server.db[client.db_idx];
 
-    match db.look_up_key_read(&client.argv[1]) {
+    match db.look_up_key_write(&client.argv[1]) {
         None => {
-            client.add_reply(shared_object!(CZERO));
+            client.add_reply_integer(0);
             return;
         }
         Some(_) => {
@@ -1266,15 +1620,76 @@ pub fn expire_command(
                 SystemTime::now() + Duration::from_secs(seconds as u64);
             match db.set_expire(Rc::clone(&client.argv[1]), when) {
                 Ok(_) => {
-                    client.add_reply(shared_object!(CONE));
+                    db.signal_modified_key(&client.argv[1]);
+                    server.notify_keyspace_event(NOTIFY_GENERIC, "expire", &client.argv[1], client.db_idx);
+                    client.add_reply_integer(1);
                     server.dirty += 1;
                 }
-                Err(_) => client.add_reply(shared_object!(CZERO)),
+                Err(_) => client.add_reply_integer(0),
             }
         }
     }
 }
 
+pub fn pexpireat_command(
+    client: &mut Client,
+    server: &mut Server,
+    _el: &mut AeEventLoop,
+) {
+    let r = {
+        let obj_ref = client.argv[2].borrow();
+        bytes_to_usize(obj_ref.string())
+    };
+    let at = match r {
+        Err(_) => {
+            client.add_reply_error("value is not an integer or out of range");
+            return;
+        }
+        Ok(i) => i as u64,
+    };
+    let db = &mut server.db[client.db_idx];
+
+    if db.look_up_key_write(&client.argv[1]).is_none() {
+        client.add_reply_integer(0);
+        return;
+    }
+
+    let event = if at <= unix_timestamp(&SystemTime::now()) {
+        let _ = db.delete_key(&client.argv[1]);
+        "del"
+    } else {
+        db.replace_expire(Rc::clone(&client.argv[1]), to_system_time(at));
+        "expire"
+    };
+    db.signal_modified_key(&client.argv[1]);
+    server.notify_keyspace_event(NOTIFY_GENERIC, event, &client.argv[1], client.db_idx);
+    client.add_reply_integer(1);
+    server.dirty += 1;
+}
+
+pub fn persist_command(
+    client: &mut Client,
+    server: &mut Server,
+    _el: &mut AeEventLoop,
+) {
+    let db = &mut server.db[client.db_idx];
+
+    if db.look_up_key_write(&client.argv[1]).is_none() {
+        client.add_reply_integer(0);
+        return;
+    }
+
+    match db.remove_expire(&client.argv[1]) {
+        Ok(_) => {
+            db.signal_modified_key(&client.argv[1]);
+            server.notify_keyspace_event(NOTIFY_GENERIC, "persist", &client.argv[1], client.db_idx);
+            client.add_reply_integer(1);
+            server.dirty += 1;
+        }
+        Err(_) => client.add_reply_integer(0),
+    }
+}
+
 pub fn keys_command(
     client: &mut Client,
     server: &mut Server,
@@ -1285,19 +1700,18 @@ pub fn keys_command(
     let pat_ref = pat_obj.borrow();
     let pat = pat_ref.string();
 
-    let num = Robj::create_string_object(&format!("*{}\r\n", 0));
+    let num = client.add_reply_deferred_len();
     let mut n: usize = 0;
-    client.add_reply(Rc::clone(&num));
 
     for key in db.dict.iter()
         .map(|x| x.0)
         .filter(|x|
             glob_match(pat, x.borrow().string(), false)) {
-        add_single_reply(client, Rc::clone(&key));
+        client.add_reply_bulk(Rc::clone(&key));
         n += 1;
     }
 
-    num.borrow_mut().change_to_str(&format!("*{}\r\n", n));
+    client.set_deferred_array_len(num, n);
 }
 
 pub fn dbsize_command(
@@ -1306,22 +1720,7 @@ pub fn dbsize_command(
     _el: &mut AeEventLoop,
 ) {
     let db = &server.db[client.db_idx];
-    client.add_reply(gen_usize_reply(db.dict.len()));
-}
-
-pub fn auth_command(
-    client: &mut Client,
-    server: &mut Server,
-    _el: &mut AeEventLoop,
-) {
-    if server.require_pass.is_none() ||
-        client.argv[1].borrow().string() == server.require_pass.as_ref().unwrap().as_bytes() {
-        client.authenticate = true;
-        client.add_reply(shared_object!(OK));
-    } else {
-        client.authenticate = false;
-        client.add_str_reply("-ERR\r\n");
-    }
+    client.add_reply_integer(db.dict.len() as i64);
 }
 
 pub fn save_command(
@@ -1330,12 +1729,13 @@ pub fn save_command(
     _el: &mut AeEventLoop,
 ) {
     if server.bg_save_in_progress {
-        client.add_str_reply("-ERR background save in progress\r\n")
+        client.add_reply_error("background save in progress");
+        return;
     }
     if let Ok(_) = rdb_save(server) {
-        client.add_reply(shared_object!(OK));
+        client.add_reply_ok();
     } else {
-        client.add_reply(shared_object!(ERR));
+        client.add_reply_error("Error saving the DB");
     }
 }
 
@@ -1345,13 +1745,13 @@ pub fn bgsave_command(
     _el: &mut AeEventLoop,
 ) {
     if server.bg_save_in_progress {
-        client.add_str_reply("-ERR background save already in progress\r\n");
+        client.add_reply_error("background save already in progress");
         return;
     }
 
     match rdb_save_in_background(server) {
-        Ok(()) => client.add_reply(shared_object!(OK)),
-        Err(()) => client.add_reply(shared_object!(ERR)),
+        Ok(()) => client.add_reply_ok(),
+        Err(()) => client.add_reply_error("Background saving failed"),
     }
 }
 
@@ -1369,6 +1769,7 @@ pub fn shutdown_command(
 
     match rdb_save(server) {
         Ok(_) => {
+            server.remove_unix_socket();
             warn!("{} bytes used at exit", crate::zalloc::allocated_memory());
             warn!("Server exit now, bye bye...");
             if server.clean_rdb {
@@ -1378,7 +1779,7 @@ pub fn shutdown_command(
         }
         Err(_) => {
             warn!("Error trying to save the DB, can't exit");
-            client.add_str_reply("-ERR can't quit, problems saving the DB\r\n");
+            client.add_reply_error("can't quit, problems saving the DB");
         }
     }
 }
@@ -1392,7 +1793,7 @@ pub fn lastsave_command(
         .duration_since(SystemTime::UNIX_EPOCH)
         .unwrap()
         .as_secs();
-    client.add_reply(gen_usize_reply(timestamp as usize));
+    client.add_reply_integer(timestamp as usize as i64);
 }
 
 pub fn type_command(
@@ -1403,7 +1804,7 @@ pub fn type_command(
     let db = &mut server.db[client.db_idx];
 
     match db.look_up_key_read(&client.argv[1]) {
-        None => client.add_reply(shared_object!(NULL_BULK)),
+        None => client.add_reply_null(),
         Some(o) => {
             let t = match o.borrow().object_type() {
                 RobjType::String => "string",
@@ -1413,7 +1814,7 @@ pub fn type_command(
                 RobjType::Zset => "zset",
             };
             let rep = Robj::create_string_object(t);
-            add_single_reply(client, rep);
+            client.add_reply_bulk(rep);
         }
     }
 }
@@ -1428,7 +1829,7 @@ pub fn sync_command(
     }
 
     if !client.reply.is_empty() {
-        client.add_str_reply("-ERR SYNC is invalid with pending input\r\n");
+        client.add_reply_error("SYNC is invalid with pending input");
         return;
     }
 
@@ -1456,7 +1857,7 @@ pub fn sync_command(
         info!("Starting BGSAVE for SYNC");
         if let Err(_) = rdb_save_in_background(server) {
             info!("Replication failed, can't BGSAVE");
-            client.add_str_reply("-ERR Unable to perform background save\r\n");
+            client.add_reply_error("Unable to perform background save");
             return;
         }
         client.reply_state = ReplyState::WaitBgSaveEnd;
@@ -1472,7 +1873,7 @@ pub fn flushdb_command(
     _el: &mut AeEventLoop,
 ) {
     server.flush_db(client.db_idx);
-    client.add_reply(shared_object!(OK));
+    client.add_reply_ok();
 }
 
 pub fn flushall_command(
@@ -1481,7 +1882,7 @@ pub fn flushall_command(
     _el: &mut AeEventLoop,
 ) {
     server.flush_all();
-    client.add_reply(shared_object!(OK));
+    client.add_reply_ok();
 }
 
 pub fn sort_command(
@@ -1489,118 +1890,624 @@ pub fn sort_command(
     server: &mut Server,
     _el: &mut AeEventLoop,
 ) {
-    let db = &mut server.db[client.db_idx];
+    sort_generic_command(client, server, false);
+}
 
-    let target = match db.look_up_key_read(&client.argv[1]) {
-        None => {
-            client.add_reply(shared_object!(EMPTY_MULTI_BULK));
+pub fn sort_ro_command(
+    client: &mut Client,
+    server: &mut Server,
+    _el: &mut AeEventLoop,
+) {
+    sort_generic_command(client, server, true);
+}
+
+fn sort_generic_command(
+    client: &mut Client,
+    server: &mut Server,
+    read_only: bool,
+) {
+    let mut sort_info = match parse_sort_command(&client.argv[2..]) {
+        Ok(info) => info,
+        Err(e) => {
+            if let SortSyntaxError::LimitInvalid = e {
+                client.add_reply_error("value is not an integer or out of range");
+            } else {
+                client.add_reply_error("syntax error");
+            }
             return;
         }
+    };
+    if read_only && sort_info.dst.is_some() {
+        client.add_reply_error("syntax error");
+        return;
+    }
+
+    let db = &mut server.db[client.db_idx];
+    // the keys read through the patterns are checked like the sorted one
+    let user = if client.flags & CLIENT_MASTER != 0 {
+        None
+    } else {
+        server.acl.users.get(&client.user)
+    };
+
+    let found = if sort_info.dst.is_some() {
+        db.look_up_key_write(&client.argv[1])
+    } else {
+        db.look_up_key_read(&client.argv[1])
+    };
+    let target = match found {
+        None => None,
         Some(o) => {
             let t = o.borrow().object_type();
             match t {
-                RobjType::Set => o,
-                RobjType::List => o,
+                RobjType::Set | RobjType::List | RobjType::Zset => Some(o),
                 _ => {
-                    client.add_reply(shared_object!(WRONG_TYPE));
+                    client.add_reply_wrong_type();
                     return;
                 }
             }
         }
     };
 
-    let target_ref = target.borrow();
-    let mut sort_info = match parse_sort_command(&client.argv[2..]) {
-        Ok(info) => info,
-        Err(e) => {
-            if let SortSyntaxError::LimitInvalid = e {
-                client.add_str_reply("-ERR value is not an integer or out of range\r\n");
-            } else {
-                client.add_str_reply("-ERR syntax error\r\n");
-            }
-            return;
-        }
-    };
-
-    let by = sort_info.by.take();
+    let mut by = sort_info.by.take();
     let get = sort_info.get.take();
     let limit = sort_info.limit.take();
+    let dst = sort_info.dst.take();
 
-    let mut v: Vec<(RobjPtr, RobjPtr)> =
-        Vec::with_capacity(target_ref.linear_len());
-    for o in target_ref.linear_iter() {
-        if let Some(pat) = by.as_ref() {
-            let key = generate_key_from_pattern(pat, o.borrow().string());
-            let sort_key =
-                match db.look_up_key_read(&Robj::from_bytes(key)) {
-                    Some(k) => k,
-                    None => Robj::create_int_object(0),
-                };
-            v.push((sort_key, o));
-        } else {
-            let sort_key = Rc::clone(&o);
+    // a BY pattern without '*' means the elements are returned as they are
+    let mut dont_sort = match by.as_ref() {
+        Some(pat) => !pat.contains(&b'*'),
+        None => false,
+    };
+
+    let mut v: Vec<(RobjPtr, RobjPtr)> = vec![];
+    if let Some(target) = target.as_ref() {
+        let target_ref = target.borrow();
+        let t = target_ref.object_type();
+
+        // the iteration order of a set is not defined, so the result
+        // must be sorted when it is going to be stored and replicated
+        if dont_sort && t == RobjType::Set && dst.is_some() {
+            dont_sort = false;
+            by = None;
+            sort_info.options.sort_type = SortType::Alphabetic;
+        }
+
+        v.reserve(target_ref.linear_len());
+        for o in target_ref.linear_iter() {
+            let sort_key = match by.as_ref() {
+                Some(pat) if !dont_sort => {
+                    match lookup_key_by_pattern(db, user, pat, &o) {
+                        Ok(Some(k)) => k,
+                        Ok(None) => Robj::create_int_object(0),
+                        Err(_) => {
+                            client.add_reply_error(SORT_BY_DENIED_ERR);
+                            return;
+                        }
+                    }
+                }
+                _ => Rc::clone(&o),
+            };
             v.push((sort_key, o));
         }
+
+        if dont_sort && t == RobjType::Zset &&
+            sort_info.options.sort_order == SortOrder::Desc {
+            v.reverse();
+        }
+    }
+
+    if !dont_sort {
+        if let Err(_) = sort_info.options.sort(&mut v) {
+            client.add_reply_error("One or more scores \
+                    can't be converted into double");
+            return;
+        }
     }
 
     let limit = match limit {
         None => 0..v.len(),
         Some(l) => {
-            if l.start >= l.end || l.start >= v.len() {
-                client.add_reply(shared_object!(EMPTY_MULTI_BULK));
-                return;
-            }
-            let left = std::cmp::max(l.start, 0);
-            let right = std::cmp::min(l.end, v.len());
+            let left = std::cmp::min(l.start, v.len());
+            let right = std::cmp::max(left, std::cmp::min(l.end, v.len()));
             left..right
         }
     };
-
-
-    if let Err(_) = sort_info.options.sort(&mut v) {
-        client.add_str_reply("-ERR One or more scores \
-                can't be converted into double\r\n");
-        return;
-    }
-
     let out = &v[limit];
 
-    match get {
+    let dst = match dst {
         None => {
-            client.add_reply_from_string(format!("*{}\r\n", out.len()));
-            for p in out.iter() {
-                add_single_reply(client, Rc::clone(&p.1));
+            match get {
+                None => {
+                    client.add_reply_array_len(out.len());
+                    for p in out.iter() {
+                        client.add_reply_bulk(Rc::clone(&p.1));
+                    }
+                }
+                Some(get) => {
+                    // all the keys are read before replying, any of them
+                    // may be denied
+                    let mut values = Vec::with_capacity(out.len() * get.len());
+                    for p in out.iter() {
+                        for pat in get.iter() {
+                            match lookup_key_by_pattern(db, user, pat, &p.1) {
+                                Ok(o) => values.push(o),
+                                Err(_) => {
+                                    client.add_reply_error(SORT_GET_DENIED_ERR);
+                                    return;
+                                }
+                            }
+                        }
+                    }
+                    client.add_reply_array_len(values.len());
+                    for o in values {
+                        match o {
+                            Some(o) => client.add_reply_bulk(o),
+                            None => client.add_reply_null(),
+                        }
+                    }
+                }
             }
+            return;
         }
-        Some(get) => {
-            client.add_reply_from_string(format!("*{}\r\n", out.len() * get.len()));
-            for (j, key) in out.iter()
-                .map(|p| &p.1)
-                .enumerate() {
+        Some(dst) => Robj::from_bytes(dst),
+    };
+
+    let list = Robj::create_zip_list_object();
+    for p in out.iter() {
+        match get.as_ref() {
+            None => list.borrow_mut().list_push(Rc::clone(&p.1), ListWhere::Tail),
+            Some(get) => {
                 for pat in get.iter() {
-                    if pat.len() == 1 && pat[0] == b'#' {
-                        add_single_reply(client, Robj::create_int_object(j as i64));
-                    } else {
-                        let key =
-                            generate_key_from_pattern(&pat[..], key.borrow().string());
-                        match db.look_up_key_read(&Robj::from_bytes(key)) {
-                            Some(o) => add_single_reply(client, o),
-                            None => client.add_reply(shared_object!(NULL_BULK)),
+                    let o = match lookup_key_by_pattern(db, user, pat, &p.1) {
+                        Ok(Some(o)) => o,
+                        Ok(None) => Robj::create_string_object(""),
+                        Err(_) => {
+                            client.add_reply_error(SORT_GET_DENIED_ERR);
+                            return;
                         }
-                    }
+                    };
+                    list.borrow_mut().list_push(o, ListWhere::Tail);
                 }
             }
         }
     }
+
+    let len = list.borrow().list_len();
+    db.signal_modified_key(&dst);
+    if len > 0 {
+        let _ = db.remove_expire(&dst);
+        db.dict.replace(Rc::clone(&dst), list);
+        server.notify_keyspace_event(NOTIFY_LIST, "sortstore", &dst, client.db_idx);
+        server.dirty += len;
+    } else if db.delete_key(&dst).is_ok() {
+        server.notify_keyspace_event(NOTIFY_GENERIC, "del", &dst, client.db_idx);
+        server.dirty += 1;
+    }
+    client.add_reply_integer(len as i64);
 }
 
-pub fn info_command(
+pub fn client_command(
     client: &mut Client,
-    _server: &mut Server,
-    _el: &mut AeEventLoop,
+    server: &mut Server,
+    el: &mut AeEventLoop,
 ) {
-// TODO
-    client.add_str_reply("-ERR not yet implemented\r\n");
+    let sub = client.argv[1].borrow().string_to_vec();
+    if case_eq(b"id", &sub) && client.argc() == 2 {
+        client.add_reply_integer(client.id as i64);
+    } else if case_eq(b"list", &sub) {
+        client_list_command(client, server);
+    } else if case_eq(b"info", &sub) && client.argc() == 2 {
+        let info = client_info_string(client);
+        client.add_reply_verbatim(&info, "txt");
+    } else if case_eq(b"kill", &sub) && client.argc() >= 3 {
+        client_kill_command(client, server);
+    } else if case_eq(b"setname", &sub) && client.argc() == 3 {
+        client_setname_command(client);
+    } else if case_eq(b"getname", &sub) && client.argc() == 2 {
+        match client.name.as_ref().map(Rc::clone) {
+            None => client.add_reply_null(),
+            Some(name) => client.add_reply_bulk(name),
+        }
+    } else if case_eq(b"pause", &sub) && (client.argc() == 3 || client.argc() == 4) {
+        client_pause_command(client, server, el);
+    } else if case_eq(b"unpause", &sub) && client.argc() == 2 {
+        server.unpause_clients(el);
+        client.add_reply_ok();
+    } else if case_eq(b"reply", &sub) && client.argc() == 3 {
+        client_reply_command(client);
+    } else if case_eq(b"tracking", &sub) && client.argc() >= 3 {
+        client_tracking_command(client, server);
+    } else if case_eq(b"caching", &sub) && client.argc() == 3 {
+        client_caching_command(client);
+    } else if case_eq(b"getredir", &sub) && client.argc() == 2 {
+        client_getredir_command(client);
+    } else if case_eq(b"trackinginfo", &sub) && client.argc() == 2 {
+        client_trackinginfo_command(client);
+    } else {
+        client.add_reply_error(&format!(
+            "Unknown CLIENT subcommand or wrong number of arguments for '{}'",
+            String::from_utf8_lossy(&sub),
+        ));
+    }
+}
+
+// "replica" is accepted as an alias of "slave"
+fn client_type_by_name(name: &[u8]) -> Option<&'static str> {
+    if case_eq(b"normal", name) {
+        Some("normal")
+    } else if case_eq(b"slave", name) || case_eq(b"replica", name) {
+        Some("slave")
+    } else if case_eq(b"master", name) {
+        Some("master")
+    } else if case_eq(b"pubsub", name) {
+        Some("pubsub")
+    } else {
+        None
+    }
+}
+
+fn client_type(c: &Client) -> &'static str {
+    if c.flags & CLIENT_MASTER != 0 {
+        "master"
+    } else if c.flags & CLIENT_SLAVE != 0 && c.flags & CLIENT_MONITOR == 0 {
+        "slave"
+    } else if c.subscription_count() > 0 {
+        "pubsub"
+    } else {
+        "normal"
+    }
+}
+
+fn client_flags_string(c: &Client) -> String {
+    let mut flags = String::new();
+    for (flag, ch) in [
+        (CLIENT_MONITOR, 'O'),
+        (CLIENT_MASTER, 'M'),
+        (CLIENT_MULTI, 'x'),
+        (CLIENT_DIRTY_EXEC, 'd'),
+        (CLIENT_PAUSED, 'b'),
+        (CLIENT_TRACKING, 't'),
+        (CLIENT_TRACKING_BROKEN_REDIR, 'R'),
+        (CLIENT_TRACKING_BCAST, 'B'),
+        (CLIENT_CLOSE_AFTER_REPLY, 'c'),
+        (CLIENT_CLOSE_ASAP, 'A'),
+        (CLIENT_UNIX_SOCKET, 'U'),
+    ].iter() {
+        if c.flags & flag != 0 {
+            flags.push(*ch);
+        }
+    }
+    if c.flags & CLIENT_SLAVE != 0 && c.flags & CLIENT_MONITOR == 0 {
+        flags.push('S');
+    }
+    if c.subscription_count() > 0 {
+        flags.push('P');
+    }
+    if flags.is_empty() {
+        flags.push('N');
+    }
+    flags
+}
+
+// one line of CLIENT LIST, without the trailing newline
+pub(crate) fn client_info_string(c: &Client) -> String {
+    let now = SystemTime::now();
+    let secs_since = |t: SystemTime| now.duration_since(t)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let fd = match &*c.fd.borrow() {
+        Fdp::Stream(s) => s.as_raw_fd(),
+        _ => -1,
+    };
+    let name = match &c.name {
+        Some(name) => String::from_utf8_lossy(name.borrow().string()).to_string(),
+        None => String::new(),
+    };
+    let multi = if c.flags & CLIENT_MULTI != 0 {
+        c.mstate.len() as i64
+    } else {
+        -1
+    };
+    let redir = if c.flags & CLIENT_TRACKING != 0 {
+        c.tracking_redirection as i64
+    } else {
+        -1
+    };
+    format!(
+        "id={} addr={} laddr={} fd={} name={} age={} idle={} flags={} db={} \
+         sub={} psub={} ssub={} multi={} qbuf={} obl={} oll={} omem={} events={} cmd={} user={} redir={}",
+        c.id,
+        c.addr,
+        c.laddr,
+        fd,
+        name,
+        secs_since(c.ctime),
+        secs_since(c.last_interaction),
+        client_flags_string(c),
+        c.db_idx,
+        c.pubsub_channels.len(),
+        c.pubsub_patterns.len(),
+        c.pubsub_shard_channels.len(),
+        multi,
+        c.query_buf.len(),
+        c.reply.buf_len(),
+        c.reply.blocks_len(),
+        c.reply.memory(),
+        if c.reply.is_empty() { "r" } else { "rw" },
+        c.last_cmd,
+        c.user,
+        redir,
+    )
+}
+
+fn client_list_command(client: &mut Client, server: &Server) {
+    let mut ctype: Option<&str> = None;
+    let mut ids: Option<Vec<usize>> = None;
+
+    let mut i = 2;
+    while i < client.argc() {
+        let arg = client.argv[i].borrow().string_to_vec();
+        if case_eq(b"type", &arg) && i + 1 < client.argc() {
+            let name = client.argv[i + 1].borrow().string_to_vec();
+            ctype = client_type_by_name(&name);
+            if ctype.is_none() {
+                client.add_reply_error(&format!(
+                    "Unknown client type '{}'",
+                    String::from_utf8_lossy(&name),
+                ));
+                return;
+            }
+            i += 2;
+        } else if case_eq(b"id", &arg) && i + 1 < client.argc() {
+            let list: Vec<usize> = client.argv[i + 1..].iter()
+                .filter_map(|arg| bytes_to_usize(arg.borrow().string()).ok())
+                .filter(|&id| id > 0)
+                .collect();
+            if list.len() != client.argc() - i - 1 {
+                client.add_reply_error("Invalid client ID");
+                return;
+            }
+            ids = Some(list);
+            i = client.argc();
+        } else {
+            client.add_reply_error("syntax error");
+            return;
+        }
+    }
+
+    let mut list = String::new();
+    for c in server.clients.iter() {
+        let line = if std::ptr::eq(c.as_ptr(), client) {
+            if !client_list_match(client, ctype, &ids) {
+                continue;
+            }
+            client_info_string(client)
+        } else {
+            let c = c.borrow();
+            if !client_list_match(&c, ctype, &ids) {
+                continue;
+            }
+            client_info_string(&c)
+        };
+        list.push_str(&line);
+        list.push('\n');
+    }
+    client.add_reply_verbatim(&list, "txt");
+}
+
+fn client_list_match(c: &Client, ctype: Option<&str>, ids: &Option<Vec<usize>>) -> bool {
+    if let Some(t) = ctype {
+        if client_type(c) != t {
+            return false;
+        }
+    }
+    match ids {
+        Some(ids) => ids.contains(&c.id),
+        None => true,
+    }
+}
+
+#[derive(Default)]
+struct ClientKillFilter {
+    id: Option<usize>,
+    ctype: Option<&'static str>,
+    addr: Option<String>,
+    laddr: Option<String>,
+    user: Option<String>,
+    max_age: Option<u64>,
+    skip_me: bool,
+}
+
+impl ClientKillFilter {
+    fn matches(&self, c: &Client) -> bool {
+        if let Some(id) = self.id {
+            if c.id != id {
+                return false;
+            }
+        }
+        if let Some(t) = self.ctype {
+            if client_type(c) != t {
+                return false;
+            }
+        }
+        if let Some(addr) = &self.addr {
+            if c.addr != *addr {
+                return false;
+            }
+        }
+        if let Some(laddr) = &self.laddr {
+            if c.laddr != *laddr {
+                return false;
+            }
+        }
+        if let Some(user) = &self.user {
+            if c.user != *user {
+                return false;
+            }
+        }
+        if let Some(max_age) = self.max_age {
+            let age = SystemTime::now()
+                .duration_since(c.ctime)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            if age < max_age {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+fn client_kill_command(client: &mut Client, server: &mut Server) {
+    let argc = client.argc();
+    // the old form only takes an address and replies with +OK
+    let old_style = argc == 3;
+    let mut filter = ClientKillFilter { skip_me: true, ..Default::default() };
+
+    if old_style {
+        filter.addr = Some(String::from_utf8_lossy(client.argv[2].borrow().string()).to_string());
+        filter.skip_me = false;
+    } else if argc.is_multiple_of(2) {
+        for i in (2..argc).step_by(2) {
+            let opt = client.argv[i].borrow().string_to_vec();
+            let val = client.argv[i + 1].borrow().string_to_vec();
+            if case_eq(b"id", &opt) {
+                match bytes_to_usize(&val) {
+                    Ok(id) if id > 0 => filter.id = Some(id),
+                    _ => {
+                        client.add_reply_error("client-id should be greater than 0");
+                        return;
+                    }
+                }
+            } else if case_eq(b"type", &opt) {
+                filter.ctype = client_type_by_name(&val);
+                if filter.ctype.is_none() {
+                    client.add_reply_error(&format!(
+                        "Unknown client type '{}'",
+                        String::from_utf8_lossy(&val),
+                    ));
+                    return;
+                }
+            } else if case_eq(b"addr", &opt) {
+                filter.addr = Some(String::from_utf8_lossy(&val).to_string());
+            } else if case_eq(b"laddr", &opt) {
+                filter.laddr = Some(String::from_utf8_lossy(&val).to_string());
+            } else if case_eq(b"user", &opt) {
+                filter.user = Some(String::from_utf8_lossy(&val).to_string());
+            } else if case_eq(b"skipme", &opt) {
+                if case_eq(b"yes", &val) {
+                    filter.skip_me = true;
+                } else if case_eq(b"no", &val) {
+                    filter.skip_me = false;
+                } else {
+                    client.add_reply_error("syntax error");
+                    return;
+                }
+            } else if case_eq(b"maxage", &opt) {
+                match bytes_to_usize(&val) {
+                    Ok(age) => filter.max_age = Some(age as u64),
+                    Err(_) => {
+                        client.add_reply_error("syntax error");
+                        return;
+                    }
+                }
+            } else {
+                client.add_reply_error("syntax error");
+                return;
+            }
+        }
+    } else {
+        client.add_reply_error("syntax error");
+        return;
+    }
+
+    let mut killed: usize = 0;
+    let mut kill_me = false;
+    // the clients already about to be closed are not killed twice
+    let others: Vec<Rc<RefCell<Client>>> = server.clients.iter()
+        .filter(|c| !std::ptr::eq(c.as_ptr(), client))
+        .filter(|c| c.borrow().flags & CLIENT_CLOSE_ASAP == 0)
+        .filter(|c| filter.matches(&c.borrow()))
+        .map(Rc::clone)
+        .collect();
+    for c in others.iter() {
+        server.async_free_client(&mut c.borrow_mut());
+        killed += 1;
+    }
+    if !filter.skip_me && filter.matches(client) {
+        // the client is closed once the reply has been sent
+        kill_me = true;
+        killed += 1;
+    }
+
+    if old_style {
+        if killed == 0 {
+            client.add_reply_error("No such client");
+            return;
+        }
+        client.add_reply_ok();
+    } else {
+        client.add_reply_integer(killed as i64);
+    }
+    if kill_me {
+        client.flags |= CLIENT_CLOSE_AFTER_REPLY;
+    }
+}
+
+fn client_setname_command(client: &mut Client) {
+    let name = Rc::clone(&client.argv[2]);
+    if name.borrow().string().is_empty() {
+        client.name = None;
+        client.add_reply_ok();
+        return;
+    }
+    if name.borrow().string().iter().any(|b| !(b'!'..=b'~').contains(b)) {
+        client.add_reply_error("Client names cannot contain spaces, newlines or special characters.");
+        return;
+    }
+    client.name = Some(name);
+    client.add_reply_ok();
+}
+
+fn client_pause_command(client: &mut Client, server: &mut Server, el: &mut AeEventLoop) {
+    let r = bytes_to_usize(client.argv[2].borrow().string());
+    let timeout = match r {
+        Ok(ms) => ms,
+        Err(_) => {
+            client.add_reply_error("timeout is not an integer or out of range");
+            return;
+        }
+    };
+    let mut pause_type = ClientPauseType::All;
+    if client.argc() == 4 {
+        let mode = client.argv[3].borrow().string_to_vec();
+        if case_eq(b"write", &mode) {
+            pause_type = ClientPauseType::Write;
+        } else if !case_eq(b"all", &mode) {
+            client.add_reply_error("syntax error");
+            return;
+        }
+    }
+    let end = SystemTime::now() + Duration::from_millis(timeout as u64);
+    server.pause_clients(el, end, pause_type);
+    client.add_reply_ok();
+}
+
+fn client_reply_command(client: &mut Client) {
+    let mode = client.argv[2].borrow().string_to_vec();
+    if case_eq(b"on", &mode) {
+        client.flags &= !(CLIENT_REPLY_OFF | CLIENT_REPLY_SKIP_NEXT);
+        client.add_reply_ok();
+    } else if case_eq(b"off", &mode) {
+        client.flags |= CLIENT_REPLY_OFF;
+    } else if case_eq(b"skip", &mode) {
+        if client.flags & CLIENT_REPLY_OFF == 0 {
+            client.flags |= CLIENT_REPLY_SKIP_NEXT;
+        }
+    } else {
+        client.add_reply_error("syntax error");
+    }
 }
 
 pub fn monitor_command(
@@ -1614,7 +2521,7 @@ pub fn monitor_command(
 
     client.flags |= CLIENT_SLAVE | CLIENT_MONITOR;
     server.transfer_client_to_slaves(client, true);
-    client.add_reply(shared_object!(OK));
+    client.add_reply_ok();
 }
 
 pub fn slaveof_command(
@@ -1639,12 +2546,12 @@ pub fn slaveof_command(
             parse_port_from_bytes(
                 client.argv[2].borrow().string()
             ).unwrap_or(0);
-        info!("SLAVE OF {}:{} enabled (user request)", host, port);
+        info!("SLAVE OF {} enabled (user request)", format_addr(&host, port));
         server.master_host = Some(host);
         server.master_port = port;
         server.reply_state = ReplyState::Connect;
     }
-    client.add_reply(shared_object!(OK));
+    client.add_reply_ok();
 }
 
 pub fn eval_command(
@@ -1652,48 +2559,55 @@ pub fn eval_command(
     server: &mut Server,
     el: &mut AeEventLoop,
 ) {
-    let keys_len_obj = Rc::clone(&client.argv[1]);
+    let keys_len_obj = Rc::clone(&client.argv[2]);
     let keys_len = match keys_len_obj.borrow().object_to_long() {
         Ok(i) => {
             if i < 0 {
-                client.add_str_reply("-ERR Number of keys can't be negative");
+                client.add_reply_error("Number of keys can't be negative");
                 return;
             }
             i as usize
         }
         Err(_) => {
-            client.add_str_reply("-ERR value is not an integer or out of range");
+            client.add_reply_error("value is not an integer or out of range");
             return;
         }
     };
 
-    if keys_len > client.argc() - 2 {
-        client.add_str_reply("-ERR Number of keys can't be greater than number of args");
+    if keys_len > client.argc() - 3 {
+        client.add_reply_error("Number of keys can't be greater than number of args");
         return;
     }
 
-    let real_script = client.argv[1].borrow().string().to_vec();
-    let lua_keys: Vec<LuaRobj> = client.argv[2..(2 + keys_len)].iter().map(|x| {
+    let real_script = wrap_script(client.argv[1].borrow().string());
+    let lua_keys: Vec<LuaRobj> = client.argv[3..(3 + keys_len)].iter().map(|x| {
         to_lua(Rc::clone(x))
     }).collect();
-    let lua_args: Vec<LuaRobj> = client.argv[(2 + keys_len)..].iter().map(|x| {
+    let lua_args: Vec<LuaRobj> = client.argv[(3 + keys_len)..].iter().map(|x| {
         to_lua(Rc::clone(x))
     }).collect();
     let lua_client =
         Client::with_fd(Rc::new(RefCell::new(Fdp::Nil)));
+    {
+        // the script can only do what its caller can
+        let mut c = lua_client.borrow_mut();
+        c.flags |= CLIENT_LUA;
+        c.authenticate = true;
+        c.user = client.user.clone();
+    }
     let lua_state = Rc::clone(&server.lua);
 
     let r = lua_state.borrow_mut().context(
-        move |ctx| -> Result<(), rlua::Error> {
+        move |ctx| -> Result<LuaRedis, rlua::Error> {
             let globals = ctx.globals();
             globals.set("KEYS", lua_keys)?;
             globals.set("ARGS", lua_args)?;
 
-            ctx.scope(|scp| -> Result<(), rlua::Error> {
+            ctx.scope(|scp| -> Result<LuaRedis, rlua::Error> {
                 let func = scp.create_function_mut(
                     |ctx, t: rlua::Table| -> Result<Vec<LuaRobj>, rlua::Error> {
                         let len = t.len()?;
-                        for i in 0..len {
+                        for i in 1..=len {
                             let arg: RobjFromLua = t.get(i)?;
                             match arg {
                                 RobjFromLua::Nil => continue,
@@ -1704,8 +2618,15 @@ pub fn eval_command(
                                 _ => {}
                             }
                         }
-                        lua_client.borrow_mut().process_input_buffer(server, el);
-                        let back_to_lua = lua_client.borrow_mut().parse_reply_to_lua();
+                        let mut c = lua_client.borrow_mut();
+                        if c.argv.is_empty() {
+                            return Err(rlua::Error::RuntimeError(
+                                "Please specify at least one argument for redis.call()".to_string()
+                            ));
+                        }
+                        let _ = c.process_command(server, el);
+                        // the reply comes back as a value, nothing to parse
+                        let back_to_lua = c.take_lua_reply();
                         let globals = ctx.globals();
                         globals.set("RETURN_FROM_RUST", back_to_lua)?;
                         Ok(vec![])
@@ -1714,16 +2635,16 @@ pub fn eval_command(
                 globals.set("redis_call_internal", func)?;
                 ctx.load(&real_script).exec()?;
                 let globals = ctx.globals();
-                let _ret: RobjFromLua = globals.get("RET")?;
-                Ok(())
-            })?;
-            Ok(())
+                globals.get("BACK_TO_RUST")
+            })
         }
     );
 
-    if let Err(_) = r {
-        client.add_str_reply("-ERR Lua error");
-        return;
+    match r {
+        Ok(reply) => add_lua_reply(client, reply),
+        Err(e) => client.add_reply_error(&format!(
+            "Error running script: {}",
+            e)),
     }
 }
 
@@ -1732,7 +2653,100 @@ pub fn ping_command(
     _server: &mut Server,
     _el: &mut AeEventLoop,
 ) {
-    client.add_reply(shared_object!(PONG));
+    if client.resp == RESP2 && client.subscription_count() > 0 {
+        client.add_reply_array_len(2);
+        client.add_reply_bulk_bytes(b"pong");
+        client.add_reply_bulk_bytes(b"");
+        return;
+    }
+    client.add_reply_status("PONG");
+}
+
+// HELLO [protover [AUTH username password] [SETNAME clientname]]
+pub fn hello_command(
+    client: &mut Client,
+    server: &mut Server,
+    _el: &mut AeEventLoop,
+) {
+    let mut resp = client.resp;
+    if client.argc() >= 2 {
+        let r = bytes_to_i64(client.argv[1].borrow().string());
+        resp = match r {
+            Ok(v) if v == RESP2 as i64 || v == RESP3 as i64 => v as u8,
+            Ok(_) => {
+                client.add_reply_error_code("NOPROTO", "unsupported protocol version");
+                return;
+            }
+            Err(_) => {
+                client.add_reply_error("Protocol version is not an integer or out of range");
+                return;
+            }
+        };
+    }
+
+    let mut auth: Option<(String, Vec<u8>)> = None;
+    let mut name: Option<RobjPtr> = None;
+    let mut i = 2;
+    while i < client.argc() {
+        let opt = client.argv[i].borrow().string_to_vec();
+        if case_eq(b"auth", &opt) && i + 2 < client.argc() {
+            auth = Some((
+                String::from_utf8_lossy(client.argv[i + 1].borrow().string()).to_string(),
+                client.argv[i + 2].borrow().string_to_vec(),
+            ));
+            i += 3;
+        } else if case_eq(b"setname", &opt) && i + 1 < client.argc() {
+            name = Some(Rc::clone(&client.argv[i + 1]));
+            i += 2;
+        } else {
+            client.add_reply_error(&format!(
+                "Syntax error in HELLO option '{}'", String::from_utf8_lossy(&opt),
+            ));
+            return;
+        }
+    }
+
+    if let Some((username, password)) = auth {
+        if !acl_authenticate_client(server, client, username, &password) {
+            client.add_reply_error_code("WRONGPASS", WRONGPASS_ERR);
+            return;
+        }
+    }
+    if server.acl.auth_required() && !client.authenticate {
+        client.add_reply_error_code(
+            "NOAUTH",
+            "HELLO must be called with the client already authenticated, otherwise \
+            the HELLO <proto> AUTH <user> <pass> option can be used to authenticate the \
+            client and select the RESP protocol version at the same time",
+        );
+        return;
+    }
+    if let Some(name) = name {
+        if name.borrow().string().iter().any(|b| !(b'!'..=b'~').contains(b)) {
+            client.add_reply_error("Client names cannot contain spaces, newlines or special characters.");
+            return;
+        }
+        client.name = Some(name);
+    }
+
+    // the reply is already in the new protocol
+    client.resp = resp;
+    let role = if server.is_slave { "replica" } else { "master" };
+    client.add_reply_map_len(7);
+    client.add_reply_bulk_bytes(b"server");
+    client.add_reply_bulk_bytes(b"redis");
+    client.add_reply_bulk_bytes(b"version");
+    client.add_reply_bulk_bytes(REREDIS_VERSION.as_bytes());
+    client.add_reply_bulk_bytes(b"proto");
+    client.add_reply_integer(resp as i64);
+    client.add_reply_bulk_bytes(b"id");
+    client.add_reply_integer(client.id as i64);
+    client.add_reply_bulk_bytes(b"mode");
+    client.add_reply_bulk_bytes(b"standalone");
+    client.add_reply_bulk_bytes(b"role");
+    client.add_reply_bulk_bytes(role.as_bytes());
+    client.add_reply_bulk_bytes(b"modules");
+    client.add_reply_array_len(0);
 }
 
 pub fn echo_command(
@@ -1740,7 +2754,7 @@ pub fn echo_command(
     _server: &mut Server,
     _el: &mut AeEventLoop,
 ) {
-    add_single_reply(client, Rc::clone(&client.argv[1]));
+    client.add_reply_bulk(Rc::clone(&client.argv[1]));
 }
 
 pub fn command_command(
@@ -1748,7 +2762,292 @@ pub fn command_command(
     _server: &mut Server,
     _el: &mut AeEventLoop,
 ) {
-    client.add_reply(shared_object!(OK));
+    if client.argc() == 1 {
+        client.add_reply_array_len(CMD_TABLE.len());
+        for cmd in CMD_TABLE.iter() {
+            add_command_info_reply(client, cmd);
+        }
+        return;
+    }
+    let sub = client.argv[1].borrow().string_to_vec();
+    if case_eq(b"count", &sub) && client.argc() == 2 {
+        client.add_reply_integer(CMD_TABLE.len() as i64);
+    } else if case_eq(b"info", &sub) {
+        let cmds: Vec<Option<&Command>> = if client.argc() == 2 {
+            CMD_TABLE.iter().map(Some).collect()
+        } else {
+            client.argv[2..].iter()
+                .map(|name| lookup_command(name.borrow().string()))
+                .collect()
+        };
+        client.add_reply_array_len(cmds.len());
+        for cmd in cmds {
+            match cmd {
+                Some(cmd) => add_command_info_reply(client, cmd),
+                None => client.add_reply_null_array(),
+            }
+        }
+    } else if case_eq(b"list", &sub) {
+        command_list_command(client);
+    } else if case_eq(b"docs", &sub) {
+        let cmds: Vec<&Command> = if client.argc() == 2 {
+            CMD_TABLE.iter().collect()
+        } else {
+            client.argv[2..].iter()
+                .filter_map(|name| lookup_command(name.borrow().string()))
+                .collect()
+        };
+        client.add_reply_map_len(cmds.len());
+        for cmd in cmds {
+            let group = command_group(cmd);
+            let summary = command_summary(cmd.name);
+            client.add_reply_bulk_bytes(cmd.name.as_bytes());
+            client.add_reply_map_len(2);
+            client.add_reply_bulk_bytes(b"summary");
+            client.add_reply_bulk_bytes(summary.as_bytes());
+            client.add_reply_bulk_bytes(b"group");
+            client.add_reply_bulk_bytes(group.as_bytes());
+        }
+    } else if case_eq(b"getkeys", &sub) && client.argc() >= 3 {
+        command_getkeys_command(client);
+    } else {
+        client.add_reply_error(&format!(
+            "Unknown COMMAND subcommand or wrong number of arguments for '{}'",
+            String::from_utf8_lossy(&sub),
+        ));
+    }
+}
+
+// the categories of a command, with the ones implied by its flags
+pub fn command_acl_categories(cmd: &Command) -> u64 {
+    let mut categories = cmd.acl_categories;
+    if cmd.flags & CMD_WRITE != 0 {
+        categories |= ACL_CATEGORY_WRITE;
+    }
+    if cmd.flags & CMD_READONLY != 0 {
+        categories |= ACL_CATEGORY_READ;
+    }
+    if cmd.flags & CMD_ADMIN != 0 {
+        categories |= ACL_CATEGORY_ADMIN | ACL_CATEGORY_DANGEROUS;
+    }
+    if cmd.flags & CMD_PUBSUB != 0 {
+        categories |= ACL_CATEGORY_PUBSUB;
+    }
+    if cmd.flags & CMD_FAST != 0 {
+        categories |= ACL_CATEGORY_FAST;
+    } else {
+        categories |= ACL_CATEGORY_SLOW;
+    }
+    categories
+}
+
+// name, arity, flags, first key, last key, key step and ACL categories
+fn add_command_info_reply(client: &mut Client, cmd: &Command) {
+    let flags: Vec<&str> = COMMAND_FLAG_NAMES.iter()
+        .filter(|(_, flag)| cmd.flags & flag != 0)
+        .map(|(name, _)| *name)
+        .collect();
+    let categories = command_acl_categories(cmd);
+    let categories: Vec<&str> = ACL_CATEGORY_NAMES.iter()
+        .filter(|(_, category)| categories & category != 0)
+        .map(|(name, _)| *name)
+        .collect();
+
+    client.add_reply_array_len(7);
+    client.add_reply_bulk_bytes(cmd.name.as_bytes());
+    client.add_reply_integer(cmd.arity as i64);
+    client.add_reply_set_len(flags.len());
+    for flag in flags {
+        client.add_reply_status(flag);
+    }
+    client.add_reply_integer(cmd.first_key as i64);
+    client.add_reply_integer(cmd.last_key as i64);
+    client.add_reply_integer(cmd.key_step as i64);
+    client.add_reply_set_len(categories.len());
+    for category in categories {
+        client.add_reply_status(&format!("@{}", category));
+    }
+}
+
+// COMMAND LIST [FILTERBY MODULE name|ACLCAT category|PATTERN pattern]
+fn command_list_command(client: &mut Client) {
+    let names: Vec<&str> = if client.argc() == 2 {
+        CMD_TABLE.iter().map(|cmd| cmd.name).collect()
+    } else if client.argc() == 5 && case_eq(b"filterby", client.argv[2].borrow().string()) {
+        let filter = client.argv[3].borrow().string_to_vec();
+        let arg = client.argv[4].borrow().string_to_vec();
+        if case_eq(b"module", &filter) {
+            vec![]
+        } else if case_eq(b"aclcat", &filter) {
+            let category = ACL_CATEGORY_NAMES.iter()
+                .find(|(name, _)| case_eq(name.as_bytes(), &arg))
+                .map_or(0, |(_, category)| *category);
+            CMD_TABLE.iter()
+                .filter(|cmd| command_acl_categories(cmd) & category != 0)
+                .map(|cmd| cmd.name)
+                .collect()
+        } else if case_eq(b"pattern", &filter) {
+            CMD_TABLE.iter()
+                .filter(|cmd| glob_match(&arg, cmd.name.as_bytes(), true))
+                .map(|cmd| cmd.name)
+                .collect()
+        } else {
+            client.add_reply_error("syntax error");
+            return;
+        }
+    } else {
+        client.add_reply_error("syntax error");
+        return;
+    };
+    client.add_reply_array_len(names.len());
+    for name in names {
+        client.add_reply_bulk_bytes(name.as_bytes());
+    }
+}
+
+// COMMAND GETKEYS command [arg ...]
+fn command_getkeys_command(client: &mut Client) {
+    let r = lookup_command(client.argv[2].borrow().string());
+    let cmd = match r {
+        Some(cmd) => cmd,
+        None => {
+            client.add_reply_error("Invalid command specified");
+            return;
+        }
+    };
+    let argv: Vec<RobjPtr> = client.argv[2..].to_vec();
+    if (cmd.arity > 0 && cmd.arity as usize != argv.len()) ||
+        (cmd.arity < 0 && argv.len() < (-cmd.arity) as usize) {
+        client.add_reply_error("Invalid number of arguments specified for command");
+        return;
+    }
+    let keys = get_keys_from_command(cmd, argv.len());
+    if keys.is_empty() {
+        client.add_reply_error("The command has no key arguments");
+        return;
+    }
+    client.add_reply_array_len(keys.len());
+    for i in keys {
+        client.add_reply_bulk(Rc::clone(&argv[i]));
+    }
+}
+
+// the group COMMAND DOCS reports, after the categories of the command
+fn command_group(cmd: &Command) -> &'static str {
+    let types = [
+        (ACL_CATEGORY_STRING, "string"),
+        (ACL_CATEGORY_LIST, "list"),
+        (ACL_CATEGORY_SET, "set"),
+        (ACL_CATEGORY_SORTEDSET, "sorted-set"),
+        (ACL_CATEGORY_HASH, "hash"),
+    ];
+    let categories = command_acl_categories(cmd);
+    let mut groups = types.iter().filter(|(category, _)| categories & category != 0);
+    match (groups.next(), groups.next()) {
+        (Some((_, group)), None) => group,
+        (Some(_), Some(_)) => "generic",
+        _ if categories & ACL_CATEGORY_PUBSUB != 0 => "pubsub",
+        _ if categories & ACL_CATEGORY_TRANSACTION != 0 => "transactions",
+        _ if categories & ACL_CATEGORY_SCRIPTING != 0 => "scripting",
+        _ if categories & ACL_CATEGORY_CONNECTION != 0 => "connection",
+        _ if categories & ACL_CATEGORY_KEYSPACE != 0 => "generic",
+        _ => "server",
+    }
+}
+
+fn command_summary(name: &str) -> &'static str {
+    match name {
+        "get" => "Returns the string value of a key.",
+        "set" => "Sets the string value of a key.",
+        "setnx" => "Sets the string value of a key only when the key doesn't exist.",
+        "del" => "Deletes one or more keys.",
+        "exists" => "Determines whether a key exists.",
+        "incr" => "Increments the integer value of a key by one.",
+        "decr" => "Decrements the integer value of a key by one.",
+        "mget" => "Returns the string values of one or more keys.",
+        "rpush" => "Appends one or more elements to a list.",
+        "lpush" => "Prepends one or more elements to a list.",
+        "lpop" => "Returns and removes the first element of a list.",
+        "rpop" => "Returns and removes the last element of a list.",
+        "llen" => "Returns the length of a list.",
+        "lindex" => "Returns an element from a list by its index.",
+        "lset" => "Sets the value of an element in a list by its index.",
+        "lrange" => "Returns a range of elements from a list.",
+        "ltrim" => "Removes elements from both ends of a list.",
+        "lrem" => "Removes elements from a list.",
+        "sadd" => "Adds one or more members to a set.",
+        "srem" => "Removes one or more members from a set.",
+        "smove" => "Moves a member from one set to another.",
+        "sismember" => "Determines whether a member belongs to a set.",
+        "scard" => "Returns the number of members in a set.",
+        "spop" => "Returns and removes a random member from a set.",
+        "sinter" => "Returns the intersect of multiple sets.",
+        "sinterstore" => "Stores the intersect of multiple sets in a key.",
+        "sunion" => "Returns the union of multiple sets.",
+        "sunionstore" => "Stores the union of multiple sets in a key.",
+        "sdiff" => "Returns the difference of multiple sets.",
+        "sdiffstore" => "Stores the difference of multiple sets in a key.",
+        "smembers" => "Returns all members of a set.",
+        "incrby" => "Increments the integer value of a key by a number.",
+        "decrby" => "Decrements the integer value of a key by a number.",
+        "getset" => "Returns the previous string value of a key after setting it to a new value.",
+        "getex" => "Returns the string value of a key after setting its expiration time.",
+        "getdel" => "Returns the string value of a key after deleting the key.",
+        "lcs" => "Finds the longest common substring.",
+        "randomkey" => "Returns a random key name from the database.",
+        "select" => "Changes the selected database.",
+        "move" => "Moves a key to another database.",
+        "rename" => "Renames a key and overwrites the destination.",
+        "renamenx" => "Renames a key only when the target key name doesn't exist.",
+        "expire" => "Sets the expiration time of a key in seconds.",
+        "pexpireat" => "Sets the expiration time of a key to a Unix milliseconds timestamp.",
+        "persist" => "Removes the expiration time of a key.",
+        "keys" => "Returns all key names that match a pattern.",
+        "dbsize" => "Returns the number of keys in the database.",
+        "auth" => "Authenticates the connection.",
+        "ping" => "Returns the server's liveliness response.",
+        "echo" => "Returns the given string.",
+        "save" => "Synchronously saves the database to disk.",
+        "bgsave" => "Asynchronously saves the database to disk.",
+        "shutdown" => "Synchronously saves the database to disk and shuts down the server.",
+        "lastsave" => "Returns the Unix timestamp of the last successful save to disk.",
+        "type" => "Determines the type of value stored at a key.",
+        "sync" => "An internal command used in replication.",
+        "flushdb" => "Removes all keys from the current database.",
+        "flushall" => "Removes all keys from all databases.",
+        "sort" => "Sorts the elements in a list, a set, or a sorted set, optionally storing the result.",
+        "sort_ro" => "Returns the sorted elements of a list, a set, or a sorted set.",
+        "info" => "Returns information and statistics about the server.",
+        "client" => "Manages the client connections.",
+        "monitor" => "Listens for all requests received by the server in real-time.",
+        "ttl" => "Returns the expiration time in seconds of a key.",
+        "slaveof" => "Sets a server as a replica of another, or promotes it to being a master.",
+        "eval" => "Executes a server-side Lua script.",
+        "object" => "Returns the internal encoding of a value.",
+        "command" => "Returns detailed information about the commands.",
+        "multi" => "Starts a transaction.",
+        "exec" => "Executes all commands in a transaction.",
+        "discard" => "Discards a transaction.",
+        "watch" => "Monitors changes to keys to determine the execution of a transaction.",
+        "unwatch" => "Forgets about watched keys of a transaction.",
+        "subscribe" => "Listens for messages published to channels.",
+        "unsubscribe" => "Stops listening to messages posted to channels.",
+        "psubscribe" => "Listens for messages published to channels that match one or more patterns.",
+        "punsubscribe" => "Stops listening to messages published to channels that match one or more patterns.",
+        "publish" => "Posts a message to a channel.",
+        "pubsub" => "Inspects the state of the Pub/Sub subsystem.",
+        "slowlog" => "Manages the slow log.",
+        "latency" => "Inspects the latency spikes recorded by the latency monitor.",
+        "memory" => "Reports the memory usage of the server and of single keys.",
+        "debug" => "Runs the testing and diagnostics subcommands.",
+        "acl" => "Manages the users and their permissions.",
+        "hello" => "Handshakes with the server, selecting the protocol version.",
+        "config" => "Manages the server configuration.",
+        "ssubscribe" => "Listens for messages published to shard channels.",
+        "sunsubscribe" => "Stops listening to messages posted to shard channels.",
+        "spublish" => "Posts a message to a shard channel.",
+        _ => "",
+    }
 }
 
 pub fn ttl_command(
@@ -1763,16 +3062,16 @@ pub fn ttl_command(
             let now = SystemTime::now();
             if *t < now {
                 let _ = db.delete_key(&client.argv[1]);
-                client.add_reply_from_string(format!(":{}\r\n", -2));
+                client.add_reply_integer(-2);
             } else {
                 let second: u64 = t.duration_since(now).unwrap().as_secs();
-                client.add_reply_from_string(format!(":{}\r\n", second));
+                client.add_reply_integer(second as i64);
             };
         }
         None => {
             match db.look_up_key_read(&client.argv[1]) {
-                None => client.add_reply_from_string(format!(":{}\r\n", -2)),
-                Some(_) => client.add_reply_from_string(format!(":{}\r\n", -1)),
+                None => client.add_reply_integer(-2),
+                Some(_) => client.add_reply_integer(-1),
             }
         }
     }
@@ -1787,7 +3086,7 @@ pub fn object_command(
     match &sub[..] {
         b"encoding" => object_encoding_command(client, server, _el),
         _ => {
-            client.add_str_reply("-Error unknown command\r\n");
+            client.add_reply_error("unknown command");
         }
     }
 }
@@ -1798,50 +3097,24 @@ pub fn object_encoding_command(
     _el: &mut AeEventLoop,
 ) {
     if client.argc() != 3 {
-        client.add_str_reply("-Error wrong number of arguments\r\n");
+        client.add_reply_error("wrong number of arguments");
         return;
     }
 
     let db = &mut server.db[client.db_idx];
-    let o = db.look_up_key_read(&client.argv[2]);
+    let o = db.look_up_key_write(&client.argv[2]);
 
     let o = match o {
         None => {
-            client.add_reply(shared_object!(NULL_BULK));
+            client.add_reply_null();
             return;
         }
         Some(obj) => obj,
     };
 
-    let s = match o.borrow().encoding() {
-        RobjEncoding::LinkedList => "linkedlist",
-        RobjEncoding::Raw => "raw",
-        RobjEncoding::Int => "int",
-        RobjEncoding::Ht => "hashtable",
-        RobjEncoding::ZipMap => "ziplist",
-        RobjEncoding::ZipList => "ziplist",
-        RobjEncoding::IntSet => "intset",
-        RobjEncoding::SkipList => "skiplist",
-        RobjEncoding::EmbStr => "embstr",
-    };
+    let s = o.borrow().encoding().name();
 
-    client.add_reply_from_string(format!("${}\r\n", s.len()));
-    client.add_str_reply(s);
-    client.add_reply(shared_object!(CRLF));
-}
-
-fn gen_usize_reply(i: usize) -> RobjPtr {
-    match i {
-        0 => shared_object!(CZERO),
-        1 => shared_object!(CONE),
-        k => Robj::create_string_object(&format!(":{}\r\n", k)),
-    }
-}
-
-fn add_single_reply(c: &mut Client, o: RobjPtr) {
-    c.add_reply_from_string(format!("${}\r\n", o.borrow().string_len()));
-    c.add_reply(o);
-    c.add_reply(shared_object!(CRLF));
+    client.add_reply_bulk_bytes(s.as_bytes());
 }
 
 fn real_list_index(idx: i64, len: usize) -> i64 {
@@ -1852,71 +3125,116 @@ fn real_list_index(idx: i64, len: usize) -> i64 {
     }
 }
 
-const CMD_TABLE: &[Command] = &[
-    Command { name: "get", proc: get_command, arity: 2, flags: CMD_INLINE | CMD_LUA },
-    Command { name: "set", proc: set_command, arity: 3, flags: CMD_INLINE | CMD_DENY_OOM | CMD_LUA },
-    Command { name: "setnx", proc: setnx_command, arity: 3, flags: CMD_INLINE | CMD_DENY_OOM | CMD_LUA },
-    Command { name: "del", proc: del_command, arity: -2, flags: CMD_INLINE | CMD_LUA },
-    Command { name: "exists", proc: exists_command, arity: 2, flags: CMD_INLINE | CMD_LUA },
-    Command { name: "incr", proc: incr_command, arity: 2, flags: CMD_INLINE | CMD_DENY_OOM | CMD_LUA },
-    Command { name: "decr", proc: decr_command, arity: 2, flags: CMD_INLINE | CMD_DENY_OOM | CMD_LUA },
-    Command { name: "mget", proc: mget_command, arity: -2, flags: CMD_INLINE | CMD_LUA },
-    Command { name: "rpush", proc: rpush_command, arity: -3, flags: CMD_INLINE | CMD_DENY_OOM | CMD_LUA },
-    Command { name: "lpush", proc: lpush_command, arity: -3, flags: CMD_INLINE | CMD_DENY_OOM | CMD_LUA },
-    Command { name: "lpop", proc: lpop_command, arity: 2, flags: CMD_INLINE | CMD_LUA },
-    Command { name: "rpop", proc: rpop_command, arity: 2, flags: CMD_INLINE | CMD_LUA },
-    Command { name: "llen", proc: llen_command, arity: 2, flags: CMD_INLINE | CMD_LUA },
-    Command { name: "lindex", proc: lindex_command, arity: 3, flags: CMD_INLINE | CMD_LUA },
-    Command { name: "lset", proc: lset_command, arity: 4, flags: CMD_INLINE | CMD_DENY_OOM | CMD_LUA },
-    Command { name: "lrange", proc: lrange_command, arity: 4, flags: CMD_INLINE | CMD_LUA },
-    Command { name: "ltrim", proc: ltrim_command, arity: 4, flags: CMD_INLINE | CMD_LUA },
-    Command { name: "lrem", proc: lrem_command, arity: 4, flags: CMD_INLINE | CMD_LUA },
-    Command { name: "sadd", proc: sadd_command, arity: -3, flags: CMD_INLINE | CMD_DENY_OOM | CMD_LUA },
-    Command { name: "srem", proc: srem_command, arity: -3, flags: CMD_INLINE | CMD_LUA },
-    Command { name: "smove", proc: smove_command, arity: 4, flags: CMD_INLINE | CMD_LUA },
-    Command { name: "sismember", proc: sismember_command, arity: 3, flags: CMD_INLINE | CMD_LUA },
-    Command { name: "scard", proc: scard_command, arity: 2, flags: CMD_INLINE | CMD_LUA },
-    Command { name: "spop", proc: spop_command, arity: 2, flags: CMD_INLINE | CMD_LUA },
-    Command { name: "sinter", proc: sinter_command, arity: -2, flags: CMD_INLINE | CMD_DENY_OOM | CMD_LUA },
-    Command { name: "sinterstore", proc: sinterstore_command, arity: -3, flags: CMD_INLINE | CMD_DENY_OOM | CMD_LUA },
-    Command { name: "sunion", proc: sunion_command, arity: -2, flags: CMD_INLINE | CMD_DENY_OOM | CMD_LUA },
-    Command { name: "sunionstore", proc: sunionstore_command, arity: -3, flags: CMD_INLINE | CMD_DENY_OOM | CMD_LUA },
-    Command { name: "sdiff", proc: sdiff_command, arity: -2, flags: CMD_INLINE | CMD_DENY_OOM | CMD_LUA },
-    Command { name: "sdiffstore", proc: sdiffstore_command, arity: -3, flags: CMD_INLINE | CMD_DENY_OOM | CMD_LUA },
-    Command { name: "smembers", proc: smembers_command, arity: 2, flags: CMD_INLINE | CMD_LUA },
-    Command { name: "incrby", proc: incr_by_command, arity: 3, flags: CMD_INLINE | CMD_DENY_OOM | CMD_LUA },
-    Command { name: "decrby", proc: decr_by_command, arity: 3, flags: CMD_INLINE | CMD_DENY_OOM | CMD_LUA },
-    Command { name: "getset", proc: get_set_command, arity: 3, flags: CMD_INLINE | CMD_DENY_OOM | CMD_LUA },
-    Command { name: "randomkey", proc: randomkey_command, arity: 1, flags: CMD_INLINE | CMD_LUA },
-    Command { name: "select", proc: select_command, arity: 2, flags: CMD_INLINE | CMD_LUA },
-    Command { name: "move", proc: move_command, arity: 3, flags: CMD_INLINE | CMD_LUA },
-    Command { name: "rename", proc: rename_command, arity: 3, flags: CMD_INLINE | CMD_LUA },
-    Command { name: "renamenx", proc: renamenx_command, arity: 3, flags: CMD_INLINE },
-    Command { name: "expire", proc: expire_command, arity: 3, flags: CMD_INLINE | CMD_LUA },
-    Command { name: "keys", proc: keys_command, arity: 2, flags: CMD_INLINE | CMD_LUA },
-    Command { name: "dbsize", proc: dbsize_command, arity: 1, flags: CMD_INLINE | CMD_LUA },
-    Command { name: "auth", proc: auth_command, arity: 2, flags: CMD_INLINE },
-    Command { name: "ping", proc: ping_command, arity: 1, flags: CMD_INLINE },
-    Command { name: "echo", proc: echo_command, arity: 2, flags: CMD_INLINE },
-    Command { name: "save", proc: save_command, arity: 1, flags: CMD_INLINE },
-    Command { name: "bgsave", proc: bgsave_command, arity: 1, flags: CMD_INLINE },
-    Command { name: "shutdown", proc: shutdown_command, arity: 1, flags: CMD_INLINE },
-    Command { name: "lastsave", proc: lastsave_command, arity: 1, flags: CMD_INLINE },
-    Command { name: "type", proc: type_command, arity: 2, flags: CMD_INLINE | CMD_LUA },
-    Command { name: "sync", proc: sync_command, arity: 1, flags: CMD_INLINE },
-    Command { name: "flushdb", proc: flushdb_command, arity: 1, flags: CMD_INLINE },
-    Command { name: "flushall", proc: flushall_command, arity: 1, flags: CMD_INLINE },
-    Command { name: "sort", proc: sort_command, arity: -2, flags: CMD_INLINE | CMD_DENY_OOM | CMD_LUA },
-    Command { name: "info", proc: info_command, arity: 1, flags: CMD_INLINE },
-    Command { name: "monitor", proc: monitor_command, arity: 1, flags: CMD_INLINE },
-    Command { name: "ttl", proc: ttl_command, arity: 2, flags: CMD_INLINE | CMD_LUA },
-    Command { name: "slaveof", proc: slaveof_command, arity: 3, flags: CMD_INLINE },
-    Command { name: "eval", proc: eval_command, arity: -2, flags: CMD_INLINE | CMD_DENY_OOM },
-    Command { name: "object", proc: object_command, arity: -2, flags: CMD_INLINE | CMD_LUA },
-    Command { name: "command", proc: command_command, arity: 1, flags: CMD_INLINE },
+pub const CMD_TABLE: &[Command] = &[
+    Command { name: "get", proc: get_command, arity: 2, flags: CMD_INLINE | CMD_READONLY | CMD_LUA | CMD_FAST, acl_categories: ACL_CATEGORY_STRING, first_key: 1, last_key: 1, key_step: 1 },
+    Command { name: "set", proc: set_command, arity: 3, flags: CMD_INLINE | CMD_WRITE | CMD_DENY_OOM | CMD_LUA, acl_categories: ACL_CATEGORY_STRING, first_key: 1, last_key: 1, key_step: 1 },
+    Command { name: "setnx", proc: setnx_command, arity: 3, flags: CMD_INLINE | CMD_WRITE | CMD_DENY_OOM | CMD_LUA | CMD_FAST, acl_categories: ACL_CATEGORY_STRING, first_key: 1, last_key: 1, key_step: 1 },
+    Command { name: "del", proc: del_command, arity: -2, flags: CMD_INLINE | CMD_WRITE | CMD_LUA, acl_categories: ACL_CATEGORY_KEYSPACE, first_key: 1, last_key: -1, key_step: 1 },
+    Command { name: "exists", proc: exists_command, arity: 2, flags: CMD_INLINE | CMD_READONLY | CMD_LUA | CMD_FAST, acl_categories: ACL_CATEGORY_KEYSPACE, first_key: 1, last_key: 1, key_step: 1 },
+    Command { name: "incr", proc: incr_command, arity: 2, flags: CMD_INLINE | CMD_WRITE | CMD_DENY_OOM | CMD_LUA | CMD_FAST, acl_categories: ACL_CATEGORY_STRING, first_key: 1, last_key: 1, key_step: 1 },
+    Command { name: "decr", proc: decr_command, arity: 2, flags: CMD_INLINE | CMD_WRITE | CMD_DENY_OOM | CMD_LUA | CMD_FAST, acl_categories: ACL_CATEGORY_STRING, first_key: 1, last_key: 1, key_step: 1 },
+    Command { name: "mget", proc: mget_command, arity: -2, flags: CMD_INLINE | CMD_READONLY | CMD_LUA | CMD_FAST, acl_categories: ACL_CATEGORY_STRING, first_key: 1, last_key: -1, key_step: 1 },
+    Command { name: "rpush", proc: rpush_command, arity: -3, flags: CMD_INLINE | CMD_WRITE | CMD_DENY_OOM | CMD_LUA | CMD_FAST, acl_categories: ACL_CATEGORY_LIST, first_key: 1, last_key: 1, key_step: 1 },
+    Command { name: "lpush", proc: lpush_command, arity: -3, flags: CMD_INLINE | CMD_WRITE | CMD_DENY_OOM | CMD_LUA | CMD_FAST, acl_categories: ACL_CATEGORY_LIST, first_key: 1, last_key: 1, key_step: 1 },
+    Command { name: "lpop", proc: lpop_command, arity: 2, flags: CMD_INLINE | CMD_WRITE | CMD_LUA | CMD_FAST, acl_categories: ACL_CATEGORY_LIST, first_key: 1, last_key: 1, key_step: 1 },
+    Command { name: "rpop", proc: rpop_command, arity: 2, flags: CMD_INLINE | CMD_WRITE | CMD_LUA | CMD_FAST, acl_categories: ACL_CATEGORY_LIST, first_key: 1, last_key: 1, key_step: 1 },
+    Command { name: "llen", proc: llen_command, arity: 2, flags: CMD_INLINE | CMD_READONLY | CMD_LUA | CMD_FAST, acl_categories: ACL_CATEGORY_LIST, first_key: 1, last_key: 1, key_step: 1 },
+    Command { name: "lindex", proc: lindex_command, arity: 3, flags: CMD_INLINE | CMD_READONLY | CMD_LUA, acl_categories: ACL_CATEGORY_LIST, first_key: 1, last_key: 1, key_step: 1 },
+    Command { name: "lset", proc: lset_command, arity: 4, flags: CMD_INLINE | CMD_WRITE | CMD_DENY_OOM | CMD_LUA, acl_categories: ACL_CATEGORY_LIST, first_key: 1, last_key: 1, key_step: 1 },
+    Command { name: "lrange", proc: lrange_command, arity: 4, flags: CMD_INLINE | CMD_READONLY | CMD_LUA, acl_categories: ACL_CATEGORY_LIST, first_key: 1, last_key: 1, key_step: 1 },
+    Command { name: "ltrim", proc: ltrim_command, arity: 4, flags: CMD_INLINE | CMD_WRITE | CMD_LUA, acl_categories: ACL_CATEGORY_LIST, first_key: 1, last_key: 1, key_step: 1 },
+    Command { name: "lrem", proc: lrem_command, arity: 4, flags: CMD_INLINE | CMD_WRITE | CMD_LUA, acl_categories: ACL_CATEGORY_LIST, first_key: 1, last_key: 1, key_step: 1 },
+    Command { name: "sadd", proc: sadd_command, arity: -3, flags: CMD_INLINE | CMD_WRITE | CMD_DENY_OOM | CMD_LUA | CMD_FAST, acl_categories: ACL_CATEGORY_SET, first_key: 1, last_key: 1, key_step: 1 },
+    Command { name: "srem", proc: srem_command, arity: -3, flags: CMD_INLINE | CMD_WRITE | CMD_LUA | CMD_FAST, acl_categories: ACL_CATEGORY_SET, first_key: 1, last_key: 1, key_step: 1 },
+    Command { name: "smove", proc: smove_command, arity: 4, flags: CMD_INLINE | CMD_WRITE | CMD_LUA | CMD_FAST, acl_categories: ACL_CATEGORY_SET, first_key: 1, last_key: 2, key_step: 1 },
+    Command { name: "sismember", proc: sismember_command, arity: 3, flags: CMD_INLINE | CMD_READONLY | CMD_LUA | CMD_FAST, acl_categories: ACL_CATEGORY_SET, first_key: 1, last_key: 1, key_step: 1 },
+    Command { name: "scard", proc: scard_command, arity: 2, flags: CMD_INLINE | CMD_READONLY | CMD_LUA | CMD_FAST, acl_categories: ACL_CATEGORY_SET, first_key: 1, last_key: 1, key_step: 1 },
+    Command { name: "spop", proc: spop_command, arity: 2, flags: CMD_INLINE | CMD_WRITE | CMD_LUA | CMD_FAST, acl_categories: ACL_CATEGORY_SET, first_key: 1, last_key: 1, key_step: 1 },
+    Command { name: "sinter", proc: sinter_command, arity: -2, flags: CMD_INLINE | CMD_READONLY | CMD_DENY_OOM | CMD_LUA, acl_categories: ACL_CATEGORY_SET, first_key: 1, last_key: -1, key_step: 1 },
+    Command { name: "sinterstore", proc: sinterstore_command, arity: -3, flags: CMD_INLINE | CMD_WRITE | CMD_DENY_OOM | CMD_LUA, acl_categories: ACL_CATEGORY_SET, first_key: 1, last_key: -1, key_step: 1 },
+    Command { name: "sunion", proc: sunion_command, arity: -2, flags: CMD_INLINE | CMD_READONLY | CMD_DENY_OOM | CMD_LUA, acl_categories: ACL_CATEGORY_SET, first_key: 1, last_key: -1, key_step: 1 },
+    Command { name: "sunionstore", proc: sunionstore_command, arity: -3, flags: CMD_INLINE | CMD_WRITE | CMD_DENY_OOM | CMD_LUA, acl_categories: ACL_CATEGORY_SET, first_key: 1, last_key: -1, key_step: 1 },
+    Command { name: "sdiff", proc: sdiff_command, arity: -2, flags: CMD_INLINE | CMD_READONLY | CMD_DENY_OOM | CMD_LUA, acl_categories: ACL_CATEGORY_SET, first_key: 1, last_key: -1, key_step: 1 },
+    Command { name: "sdiffstore", proc: sdiffstore_command, arity: -3, flags: CMD_INLINE | CMD_WRITE | CMD_DENY_OOM | CMD_LUA, acl_categories: ACL_CATEGORY_SET, first_key: 1, last_key: -1, key_step: 1 },
+    Command { name: "smembers", proc: smembers_command, arity: 2, flags: CMD_INLINE | CMD_READONLY | CMD_LUA, acl_categories: ACL_CATEGORY_SET, first_key: 1, last_key: 1, key_step: 1 },
+    Command { name: "incrby", proc: incr_by_command, arity: 3, flags: CMD_INLINE | CMD_WRITE | CMD_DENY_OOM | CMD_LUA | CMD_FAST, acl_categories: ACL_CATEGORY_STRING, first_key: 1, last_key: 1, key_step: 1 },
+    Command { name: "decrby", proc: decr_by_command, arity: 3, flags: CMD_INLINE | CMD_WRITE | CMD_DENY_OOM | CMD_LUA | CMD_FAST, acl_categories: ACL_CATEGORY_STRING, first_key: 1, last_key: 1, key_step: 1 },
+    Command { name: "getset", proc: get_set_command, arity: 3, flags: CMD_INLINE | CMD_WRITE | CMD_DENY_OOM | CMD_LUA | CMD_FAST, acl_categories: ACL_CATEGORY_STRING, first_key: 1, last_key: 1, key_step: 1 },
+    Command { name: "getex", proc: getex_command, arity: -2, flags: CMD_INLINE | CMD_WRITE | CMD_LUA | CMD_FAST, acl_categories: ACL_CATEGORY_STRING, first_key: 1, last_key: 1, key_step: 1 },
+    Command { name: "getdel", proc: getdel_command, arity: 2, flags: CMD_INLINE | CMD_WRITE | CMD_LUA | CMD_FAST, acl_categories: ACL_CATEGORY_STRING, first_key: 1, last_key: 1, key_step: 1 },
+    Command { name: "lcs", proc: lcs_command, arity: -3, flags: CMD_INLINE | CMD_READONLY | CMD_LUA, acl_categories: ACL_CATEGORY_STRING, first_key: 1, last_key: 2, key_step: 1 },
+    Command { name: "randomkey", proc: randomkey_command, arity: 1, flags: CMD_INLINE | CMD_READONLY | CMD_LUA, acl_categories: ACL_CATEGORY_KEYSPACE, first_key: 0, last_key: 0, key_step: 0 },
+    Command { name: "select", proc: select_command, arity: 2, flags: CMD_INLINE | CMD_LUA | CMD_FAST, acl_categories: ACL_CATEGORY_CONNECTION, first_key: 0, last_key: 0, key_step: 0 },
+    Command { name: "move", proc: move_command, arity: 3, flags: CMD_INLINE | CMD_WRITE | CMD_LUA | CMD_FAST, acl_categories: ACL_CATEGORY_KEYSPACE, first_key: 1, last_key: 1, key_step: 1 },
+    Command { name: "rename", proc: rename_command, arity: 3, flags: CMD_INLINE | CMD_WRITE | CMD_LUA, acl_categories: ACL_CATEGORY_KEYSPACE, first_key: 1, last_key: 2, key_step: 1 },
+    Command { name: "renamenx", proc: renamenx_command, arity: 3, flags: CMD_INLINE | CMD_WRITE | CMD_FAST, acl_categories: ACL_CATEGORY_KEYSPACE, first_key: 1, last_key: 2, key_step: 1 },
+    Command { name: "expire", proc: expire_command, arity: 3, flags: CMD_INLINE | CMD_WRITE | CMD_LUA | CMD_FAST, acl_categories: ACL_CATEGORY_KEYSPACE, first_key: 1, last_key: 1, key_step: 1 },
+    Command { name: "pexpireat", proc: pexpireat_command, arity: 3, flags: CMD_INLINE | CMD_WRITE | CMD_LUA | CMD_FAST, acl_categories: ACL_CATEGORY_KEYSPACE, first_key: 1, last_key: 1, key_step: 1 },
+    Command { name: "persist", proc: persist_command, arity: 2, flags: CMD_INLINE | CMD_WRITE | CMD_LUA | CMD_FAST, acl_categories: ACL_CATEGORY_KEYSPACE, first_key: 1, last_key: 1, key_step: 1 },
+    Command { name: "keys", proc: keys_command, arity: 2, flags: CMD_INLINE | CMD_READONLY | CMD_LUA, acl_categories: ACL_CATEGORY_KEYSPACE | ACL_CATEGORY_DANGEROUS, first_key: 0, last_key: 0, key_step: 0 },
+    Command { name: "dbsize", proc: dbsize_command, arity: 1, flags: CMD_INLINE | CMD_READONLY | CMD_LUA | CMD_FAST, acl_categories: ACL_CATEGORY_KEYSPACE, first_key: 0, last_key: 0, key_step: 0 },
+    Command { name: "auth", proc: auth_command, arity: -2, flags: CMD_INLINE | CMD_FAST | CMD_NOSCRIPT, acl_categories: ACL_CATEGORY_CONNECTION, first_key: 0, last_key: 0, key_step: 0 },
+    Command { name: "ping", proc: ping_command, arity: 1, flags: CMD_INLINE | CMD_FAST, acl_categories: ACL_CATEGORY_CONNECTION, first_key: 0, last_key: 0, key_step: 0 },
+    Command { name: "echo", proc: echo_command, arity: 2, flags: CMD_INLINE | CMD_FAST, acl_categories: ACL_CATEGORY_CONNECTION, first_key: 0, last_key: 0, key_step: 0 },
+    Command { name: "save", proc: save_command, arity: 1, flags: CMD_INLINE | CMD_ADMIN | CMD_NOSCRIPT, acl_categories: 0, first_key: 0, last_key: 0, key_step: 0 },
+    Command { name: "bgsave", proc: bgsave_command, arity: 1, flags: CMD_INLINE | CMD_ADMIN | CMD_NOSCRIPT, acl_categories: 0, first_key: 0, last_key: 0, key_step: 0 },
+    Command { name: "shutdown", proc: shutdown_command, arity: 1, flags: CMD_INLINE | CMD_ADMIN | CMD_NOSCRIPT, acl_categories: 0, first_key: 0, last_key: 0, key_step: 0 },
+    Command { name: "lastsave", proc: lastsave_command, arity: 1, flags: CMD_INLINE | CMD_ADMIN | CMD_FAST, acl_categories: 0, first_key: 0, last_key: 0, key_step: 0 },
+    Command { name: "type", proc: type_command, arity: 2, flags: CMD_INLINE | CMD_READONLY | CMD_LUA | CMD_FAST, acl_categories: ACL_CATEGORY_KEYSPACE, first_key: 1, last_key: 1, key_step: 1 },
+    Command { name: "sync", proc: sync_command, arity: 1, flags: CMD_INLINE | CMD_ADMIN | CMD_NOSCRIPT, acl_categories: 0, first_key: 0, last_key: 0, key_step: 0 },
+    Command { name: "flushdb", proc: flushdb_command, arity: 1, flags: CMD_INLINE | CMD_WRITE, acl_categories: ACL_CATEGORY_KEYSPACE | ACL_CATEGORY_DANGEROUS, first_key: 0, last_key: 0, key_step: 0 },
+    Command { name: "flushall", proc: flushall_command, arity: 1, flags: CMD_INLINE | CMD_WRITE, acl_categories: ACL_CATEGORY_KEYSPACE | ACL_CATEGORY_DANGEROUS, first_key: 0, last_key: 0, key_step: 0 },
+    Command { name: "sort", proc: sort_command, arity: -2, flags: CMD_INLINE | CMD_WRITE | CMD_DENY_OOM | CMD_LUA, acl_categories: ACL_CATEGORY_SET | ACL_CATEGORY_SORTEDSET | ACL_CATEGORY_LIST | ACL_CATEGORY_DANGEROUS, first_key: 1, last_key: 1, key_step: 1 },
+    Command { name: "sort_ro", proc: sort_ro_command, arity: -2, flags: CMD_INLINE | CMD_READONLY | CMD_LUA, acl_categories: ACL_CATEGORY_SET | ACL_CATEGORY_SORTEDSET | ACL_CATEGORY_LIST | ACL_CATEGORY_DANGEROUS, first_key: 1, last_key: 1, key_step: 1 },
+    Command { name: "info", proc: info_command, arity: -1, flags: CMD_INLINE, acl_categories: ACL_CATEGORY_DANGEROUS, first_key: 0, last_key: 0, key_step: 0 },
+    Command { name: "client", proc: client_command, arity: -2, flags: CMD_INLINE | CMD_ADMIN | CMD_NOSCRIPT, acl_categories: ACL_CATEGORY_CONNECTION, first_key: 0, last_key: 0, key_step: 0 },
+    Command { name: "monitor", proc: monitor_command, arity: 1, flags: CMD_INLINE | CMD_ADMIN | CMD_NOSCRIPT, acl_categories: 0, first_key: 0, last_key: 0, key_step: 0 },
+    Command { name: "ttl", proc: ttl_command, arity: 2, flags: CMD_INLINE | CMD_READONLY | CMD_LUA | CMD_FAST, acl_categories: ACL_CATEGORY_KEYSPACE, first_key: 1, last_key: 1, key_step: 1 },
+    Command { name: "slaveof", proc: slaveof_command, arity: 3, flags: CMD_INLINE | CMD_ADMIN | CMD_NOSCRIPT, acl_categories: 0, first_key: 0, last_key: 0, key_step: 0 },
+    Command { name: "eval", proc: eval_command, arity: -3, flags: CMD_INLINE | CMD_DENY_OOM | CMD_NOSCRIPT, acl_categories: ACL_CATEGORY_SCRIPTING, first_key: 0, last_key: 0, key_step: 0 },
+    Command { name: "object", proc: object_command, arity: -2, flags: CMD_INLINE | CMD_READONLY | CMD_LUA, acl_categories: ACL_CATEGORY_KEYSPACE, first_key: 2, last_key: 2, key_step: 1 },
+    Command { name: "command", proc: command_command, arity: -1, flags: CMD_INLINE, acl_categories: ACL_CATEGORY_CONNECTION, first_key: 0, last_key: 0, key_step: 0 },
+    Command { name: "multi", proc: multi_command, arity: 1, flags: CMD_INLINE | CMD_FAST | CMD_NOSCRIPT, acl_categories: ACL_CATEGORY_TRANSACTION, first_key: 0, last_key: 0, key_step: 0 },
+    Command { name: "exec", proc: exec_command, arity: 1, flags: CMD_INLINE | CMD_NOSCRIPT, acl_categories: ACL_CATEGORY_TRANSACTION, first_key: 0, last_key: 0, key_step: 0 },
+    Command { name: "discard", proc: discard_command, arity: 1, flags: CMD_INLINE | CMD_FAST | CMD_NOSCRIPT, acl_categories: ACL_CATEGORY_TRANSACTION, first_key: 0, last_key: 0, key_step: 0 },
+    Command { name: "watch", proc: watch_command, arity: -2, flags: CMD_INLINE | CMD_FAST | CMD_NOSCRIPT, acl_categories: ACL_CATEGORY_TRANSACTION, first_key: 1, last_key: -1, key_step: 1 },
+    Command { name: "unwatch", proc: unwatch_command, arity: 1, flags: CMD_INLINE | CMD_FAST | CMD_NOSCRIPT, acl_categories: ACL_CATEGORY_TRANSACTION, first_key: 0, last_key: 0, key_step: 0 },
+    Command { name: "subscribe", proc: subscribe_command, arity: -2, flags: CMD_INLINE | CMD_PUBSUB | CMD_NOSCRIPT, acl_categories: 0, first_key: 0, last_key: 0, key_step: 0 },
+    Command { name: "unsubscribe", proc: unsubscribe_command, arity: -1, flags: CMD_INLINE | CMD_PUBSUB | CMD_NOSCRIPT, acl_categories: 0, first_key: 0, last_key: 0, key_step: 0 },
+    Command { name: "psubscribe", proc: psubscribe_command, arity: -2, flags: CMD_INLINE | CMD_PUBSUB | CMD_NOSCRIPT, acl_categories: 0, first_key: 0, last_key: 0, key_step: 0 },
+    Command { name: "punsubscribe", proc: punsubscribe_command, arity: -1, flags: CMD_INLINE | CMD_PUBSUB | CMD_NOSCRIPT, acl_categories: 0, first_key: 0, last_key: 0, key_step: 0 },
+    Command { name: "publish", proc: publish_command, arity: 3, flags: CMD_INLINE | CMD_LUA | CMD_PUBSUB | CMD_FAST, acl_categories: 0, first_key: 0, last_key: 0, key_step: 0 },
+    Command { name: "pubsub", proc: pubsub_command, arity: -2, flags: CMD_INLINE | CMD_PUBSUB, acl_categories: 0, first_key: 0, last_key: 0, key_step: 0 },
+    Command { name: "slowlog", proc: slowlog_command, arity: -2, flags: CMD_INLINE | CMD_ADMIN, acl_categories: 0, first_key: 0, last_key: 0, key_step: 0 },
+    Command { name: "latency", proc: latency_command, arity: -2, flags: CMD_INLINE | CMD_ADMIN | CMD_NOSCRIPT, acl_categories: 0, first_key: 0, last_key: 0, key_step: 0 },
+    Command { name: "memory", proc: memory_command, arity: -2, flags: CMD_INLINE | CMD_READONLY, acl_categories: 0, first_key: 2, last_key: 2, key_step: 1 },
+    Command { name: "debug", proc: debug_command, arity: -2, flags: CMD_INLINE | CMD_ADMIN | CMD_NOSCRIPT, acl_categories: 0, first_key: 0, last_key: 0, key_step: 0 },
+    Command { name: "hello", proc: hello_command, arity: -1, flags: CMD_INLINE | CMD_FAST | CMD_NOSCRIPT, acl_categories: ACL_CATEGORY_CONNECTION, first_key: 0, last_key: 0, key_step: 0 },
+    Command { name: "acl", proc: acl_command, arity: -2, flags: CMD_INLINE | CMD_ADMIN | CMD_NOSCRIPT, acl_categories: 0, first_key: 0, last_key: 0, key_step: 0 },
+    Command { name: "config", proc: config_command, arity: -2, flags: CMD_INLINE | CMD_ADMIN | CMD_NOSCRIPT, acl_categories: 0, first_key: 0, last_key: 0, key_step: 0 },
+    Command { name: "ssubscribe", proc: ssubscribe_command, arity: -2, flags: CMD_INLINE | CMD_PUBSUB | CMD_NOSCRIPT, acl_categories: 0, first_key: 0, last_key: 0, key_step: 0 },
+    Command { name: "sunsubscribe", proc: sunsubscribe_command, arity: -1, flags: CMD_INLINE | CMD_PUBSUB | CMD_NOSCRIPT, acl_categories: 0, first_key: 0, last_key: 0, key_step: 0 },
+    Command { name: "spublish", proc: spublish_command, arity: 3, flags: CMD_INLINE | CMD_LUA | CMD_PUBSUB | CMD_FAST, acl_categories: 0, first_key: 0, last_key: 0, key_step: 0 },
 ];
 
 pub fn lookup_command(name: &[u8]) -> Option<&'static Command> {
     CMD_TABLE.iter()
         .find(|x| case_eq(x.name.as_bytes(), name))
 }
+
+// indexes of the keys in argv according to the key positions of the command
+pub fn get_keys_from_command(cmd: &Command, argc: usize) -> Vec<usize> {
+    if cmd.first_key == 0 {
+        return vec![];
+    }
+    let last = if cmd.last_key < 0 {
+        argc as i32 + cmd.last_key
+    } else {
+        cmd.last_key
+    };
+    (cmd.first_key..=last)
+        .step_by(cmd.key_step as usize)
+        .filter(|i| (*i as usize) < argc)
+        .map(|i| i as usize)
+        .collect()
+}
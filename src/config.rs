@@ -0,0 +1,617 @@
+use crate::client::Client;
+use crate::server::{Server, parse_bind_addr};
+use crate::ae::AeEventLoop;
+use crate::glob::glob_match;
+use crate::notify::{keyspace_events_from_str, keyspace_events_to_string};
+use crate::reply::{output_buffer_limits_string, parse_output_buffer_limits};
+use crate::util::*;
+use crate::env::REREDIS_BINDADDR_MAX;
+use log::LevelFilter;
+use std::env::{current_dir, set_current_dir};
+use std::fs;
+use std::io::Write;
+
+type ConfigGetProc = fn(&Server) -> String;
+type ConfigSetProc = fn(&mut Server, &str) -> Result<(), String>;
+type ConfigApplyProc = fn(&mut Server, &mut AeEventLoop) -> Result<(), String>;
+
+// A parameter of CONFIG GET / SET / REWRITE. The value is always handled
+// as a string in the config file syntax, `set` is None for the parameters
+// only settable at startup.
+pub struct ConfigParam {
+    pub name: &'static str,
+    // CONFIG REWRITE appends a missing parameter only when its value differs
+    // from the default, None means it is never appended
+    pub default: Option<&'static str>,
+    pub get: ConfigGetProc,
+    pub set: Option<ConfigSetProc>,
+    // run by CONFIG SET once all the parameters are set, for the changes
+    // that can still fail, e.g. binding sockets
+    pub apply: Option<ConfigApplyProc>,
+}
+
+pub static CONFIG_TABLE: &[ConfigParam] = &[
+    ConfigParam { name: "timeout", default: Some("300"), get: get_timeout, set: Some(set_timeout), apply: None },
+    ConfigParam { name: "port", default: Some("6379"), get: get_port, set: None, apply: None },
+    ConfigParam { name: "bind", default: Some("127.0.0.1"), get: get_bind, set: Some(set_bind), apply: Some(apply_bind) },
+    ConfigParam { name: "unixsocket", default: Some(""), get: get_unixsocket, set: None, apply: None },
+    ConfigParam { name: "unixsocketperm", default: Some("0"), get: get_unixsocketperm, set: None, apply: None },
+    ConfigParam { name: "save", default: Some("3600 1 300 100 60 10000"), get: get_save, set: Some(set_save), apply: None },
+    ConfigParam { name: "dir", default: None, get: get_dir, set: Some(set_dir), apply: None },
+    ConfigParam { name: "loglevel", default: Some("debug"), get: get_loglevel, set: Some(set_loglevel), apply: None },
+    ConfigParam { name: "databases", default: Some("16"), get: get_databases, set: None, apply: None },
+    ConfigParam { name: "maxclients", default: Some("0"), get: get_maxclients, set: Some(set_maxclients), apply: None },
+    ConfigParam { name: "maxmemory", default: Some("0"), get: get_maxmemory, set: Some(set_maxmemory), apply: None },
+    ConfigParam { name: "slaveof", default: Some(""), get: get_slaveof, set: None, apply: None },
+    ConfigParam { name: "glueoutputbuf", default: Some("yes"), get: get_glueoutputbuf, set: Some(set_glueoutputbuf), apply: None },
+    ConfigParam { name: "daemonize", default: Some("no"), get: get_daemonize, set: None, apply: None },
+    ConfigParam { name: "requirepass", default: Some(""), get: get_requirepass, set: Some(set_requirepass), apply: None },
+    ConfigParam { name: "aclfile", default: Some(""), get: get_aclfile, set: None, apply: None },
+    ConfigParam { name: "acllog-max-len", default: Some("128"), get: get_acllog_max_len, set: Some(set_acllog_max_len), apply: None },
+    ConfigParam { name: "dbfilename", default: Some("dump.rdb"), get: get_dbfilename, set: Some(set_dbfilename), apply: None },
+    ConfigParam { name: "notify-keyspace-events", default: Some(""), get: get_notify_keyspace_events, set: Some(set_notify_keyspace_events), apply: None },
+    ConfigParam { name: "tracking-table-max-keys", default: Some("1000000"), get: get_tracking_table_max_keys, set: Some(set_tracking_table_max_keys), apply: None },
+    ConfigParam { name: "slowlog-log-slower-than", default: Some("10000"), get: get_slowlog_log_slower_than, set: Some(set_slowlog_log_slower_than), apply: None },
+    ConfigParam { name: "slowlog-max-len", default: Some("128"), get: get_slowlog_max_len, set: Some(set_slowlog_max_len), apply: None },
+    ConfigParam { name: "enable-debug-command", default: Some("no"), get: get_enable_debug_command, set: None, apply: None },
+    ConfigParam { name: "latency-monitor-threshold", default: Some("0"), get: get_latency_monitor_threshold, set: Some(set_latency_monitor_threshold), apply: None },
+    ConfigParam { name: "client-query-buffer-limit", default: Some("1073741824"), get: get_client_query_buffer_limit, set: Some(set_client_query_buffer_limit), apply: None },
+    ConfigParam { name: "proto-max-bulk-len", default: Some("536870912"), get: get_proto_max_bulk_len, set: Some(set_proto_max_bulk_len), apply: None },
+    ConfigParam { name: "client-output-buffer-limit", default: Some("normal 0 0 0 slave 268435456 67108864 60 pubsub 33554432 8388608 60"), get: get_client_output_buffer_limit, set: Some(set_client_output_buffer_limit), apply: None },
+];
+
+pub fn lookup_config_param(name: &str) -> Option<&'static ConfigParam> {
+    CONFIG_TABLE.iter().find(|p| p.name.eq_ignore_ascii_case(name))
+}
+
+fn parse_yes_or_no(value: &str) -> Result<bool, String> {
+    yes_or_no(value).ok_or_else(|| "argument must be 'yes' or 'no'".to_string())
+}
+
+fn parse_integer(value: &str) -> Result<usize, String> {
+    parse_usize(value).map_err(|_| "argument couldn't be parsed into an integer".to_string())
+}
+
+fn parse_memory_at_least(value: &str, min: usize) -> Result<usize, String> {
+    match human_size(value) {
+        Ok(n) if n >= min => Ok(n),
+        Ok(_) => Err(format!("argument must be at least {}", min)),
+        Err(_) => Err("argument must be a memory value".to_string()),
+    }
+}
+
+fn get_timeout(server: &Server) -> String {
+    server.max_idle_time.to_string()
+}
+
+fn set_timeout(server: &mut Server, value: &str) -> Result<(), String> {
+    server.max_idle_time = parse_integer(value)?;
+    Ok(())
+}
+
+fn get_port(server: &Server) -> String {
+    server.port.to_string()
+}
+
+fn get_bind(server: &Server) -> String {
+    server.bind_addrs.join(" ")
+}
+
+fn set_bind(server: &mut Server, value: &str) -> Result<(), String> {
+    let addrs: Vec<String> = value.split_ascii_whitespace().map(|a| a.to_string()).collect();
+    if addrs.is_empty() {
+        return Err("At least one bind address is needed.".to_string());
+    }
+    if addrs.len() > REREDIS_BINDADDR_MAX {
+        return Err("Too many bind addresses specified.".to_string());
+    }
+    for addr in addrs.iter() {
+        parse_bind_addr(addr)?;
+    }
+    server.bind_addrs = addrs;
+    Ok(())
+}
+
+fn apply_bind(server: &mut Server, el: &mut AeEventLoop) -> Result<(), String> {
+    server.change_bind_addrs(el)
+}
+
+fn get_unixsocket(server: &Server) -> String {
+    server.unix_socket.clone().unwrap_or_default()
+}
+
+// the permissions are written in octal, as in the config file
+fn get_unixsocketperm(server: &Server) -> String {
+    format!("{:o}", server.unix_socket_perm)
+}
+
+fn get_save(server: &Server) -> String {
+    server.save_params.iter()
+        .map(|(seconds, changes)| format!("{} {}", seconds, changes))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+// "<seconds> <changes> [<seconds> <changes> ...]", empty to disable saving
+fn set_save(server: &mut Server, value: &str) -> Result<(), String> {
+    let args: Vec<&str> = value.split_ascii_whitespace().collect();
+    if !args.len().is_multiple_of(2) {
+        return Err("Invalid save parameters".to_string());
+    }
+    let mut params = vec![];
+    for pair in args.chunks(2) {
+        let pair = parse_usize_pair(pair[0], pair[1])
+            .map_err(|_| "Invalid save parameters".to_string())?;
+        params.push(pair);
+    }
+    server.save_params = params;
+    Ok(())
+}
+
+fn get_dir(_server: &Server) -> String {
+    current_dir()
+        .map(|d| d.display().to_string())
+        .unwrap_or_default()
+}
+
+fn set_dir(_server: &mut Server, value: &str) -> Result<(), String> {
+    set_current_dir(value).map_err(|e| e.to_string())
+}
+
+fn get_loglevel(server: &Server) -> String {
+    match server.verbosity {
+        LevelFilter::Debug | LevelFilter::Trace => "debug",
+        LevelFilter::Info => "notice",
+        _ => "warning",
+    }.to_string()
+}
+
+fn set_loglevel(server: &mut Server, value: &str) -> Result<(), String> {
+    server.verbosity = match &value.to_ascii_lowercase()[..] {
+        "debug" => LevelFilter::Debug,
+        "notice" => LevelFilter::Info,
+        "warning" => LevelFilter::Warn,
+        _ => return Err("Invalid log level. Must be one of debug, notice, warning".to_string()),
+    };
+    log::set_max_level(server.verbosity);
+    Ok(())
+}
+
+fn get_databases(server: &Server) -> String {
+    server.db.len().to_string()
+}
+
+fn get_maxclients(server: &Server) -> String {
+    server.max_clients.to_string()
+}
+
+fn set_maxclients(server: &mut Server, value: &str) -> Result<(), String> {
+    server.max_clients = parse_integer(value)?;
+    Ok(())
+}
+
+fn get_maxmemory(server: &Server) -> String {
+    server.max_memory.to_string()
+}
+
+fn set_maxmemory(server: &mut Server, value: &str) -> Result<(), String> {
+    server.max_memory = human_size(value)
+        .map_err(|_| "argument must be a memory value".to_string())?;
+    Ok(())
+}
+
+fn get_slaveof(server: &Server) -> String {
+    match &server.master_host {
+        Some(host) => format!("{} {}", host, server.master_port),
+        None => String::new(),
+    }
+}
+
+fn get_glueoutputbuf(server: &Server) -> String {
+    yes_or_no_string(server.glue_output)
+}
+
+fn set_glueoutputbuf(server: &mut Server, value: &str) -> Result<(), String> {
+    server.glue_output = parse_yes_or_no(value)?;
+    Ok(())
+}
+
+fn get_daemonize(server: &Server) -> String {
+    yes_or_no_string(server.daemonize)
+}
+
+fn get_requirepass(server: &Server) -> String {
+    server.require_pass.clone().unwrap_or_default()
+}
+
+// an empty password disables the authentication
+fn set_requirepass(server: &mut Server, value: &str) -> Result<(), String> {
+    server.require_pass = if value.is_empty() {
+        None
+    } else {
+        Some(value.to_string())
+    };
+    server.acl.set_default_password(server.require_pass.as_deref());
+    Ok(())
+}
+
+fn get_aclfile(server: &Server) -> String {
+    server.acl_file.clone().unwrap_or_default()
+}
+
+fn get_acllog_max_len(server: &Server) -> String {
+    server.acllog_max_len.to_string()
+}
+
+fn set_acllog_max_len(server: &mut Server, value: &str) -> Result<(), String> {
+    server.acllog_max_len = parse_integer(value)?;
+    server.acl.log.truncate(server.acllog_max_len);
+    Ok(())
+}
+
+fn get_dbfilename(server: &Server) -> String {
+    server.db_filename.clone()
+}
+
+fn set_dbfilename(server: &mut Server, value: &str) -> Result<(), String> {
+    if value.is_empty() || value.contains('/') {
+        return Err("dbfilename can't be a path, just a filename".to_string());
+    }
+    server.db_filename = value.to_string();
+    Ok(())
+}
+
+fn get_notify_keyspace_events(server: &Server) -> String {
+    keyspace_events_to_string(server.notify_keyspace_events)
+}
+
+fn set_notify_keyspace_events(server: &mut Server, value: &str) -> Result<(), String> {
+    server.notify_keyspace_events = keyspace_events_from_str(value)
+        .ok_or_else(|| "Invalid event class character. Use 'Ag$lshzxeKEtmdn'.".to_string())?;
+    Ok(())
+}
+
+fn get_tracking_table_max_keys(server: &Server) -> String {
+    server.tracking_table_max_keys.to_string()
+}
+
+fn set_tracking_table_max_keys(server: &mut Server, value: &str) -> Result<(), String> {
+    server.tracking_table_max_keys = parse_integer(value)?;
+    Ok(())
+}
+
+fn get_slowlog_log_slower_than(server: &Server) -> String {
+    server.slowlog_log_slower_than.to_string()
+}
+
+fn set_slowlog_log_slower_than(server: &mut Server, value: &str) -> Result<(), String> {
+    server.slowlog_log_slower_than = value.parse()
+        .map_err(|_| "argument couldn't be parsed into an integer".to_string())?;
+    Ok(())
+}
+
+fn get_slowlog_max_len(server: &Server) -> String {
+    server.slowlog_max_len.to_string()
+}
+
+fn set_slowlog_max_len(server: &mut Server, value: &str) -> Result<(), String> {
+    server.slowlog_max_len = parse_integer(value)?;
+    server.slowlog.trim(server.slowlog_max_len);
+    Ok(())
+}
+
+fn get_enable_debug_command(server: &Server) -> String {
+    server.enable_debug_command.as_str().to_string()
+}
+
+fn get_latency_monitor_threshold(server: &Server) -> String {
+    server.latency_monitor_threshold.to_string()
+}
+
+fn set_latency_monitor_threshold(server: &mut Server, value: &str) -> Result<(), String> {
+    server.latency_monitor_threshold = parse_integer(value)?;
+    Ok(())
+}
+
+fn yes_or_no_string(b: bool) -> String {
+    if b { "yes" } else { "no" }.to_string()
+}
+
+pub fn config_command(
+    client: &mut Client,
+    server: &mut Server,
+    el: &mut AeEventLoop,
+) {
+    let sub = client.argv[1].borrow().string_to_vec();
+    if case_eq(b"get", &sub) && client.argc() >= 3 {
+        config_get_command(client, server);
+    } else if case_eq(b"set", &sub) && client.argc() >= 4 && client.argc().is_multiple_of(2) {
+        config_set_command(client, server, el);
+    } else if case_eq(b"rewrite", &sub) && client.argc() == 2 {
+        match rewrite_config(server) {
+            Ok(()) => client.add_reply_ok(),
+            Err(e) => {
+                warn!("CONFIG REWRITE failed: {}", e);
+                client.add_reply_error(&format!("Rewriting config file: {}", e));
+            }
+        }
+    } else if case_eq(b"resetstat", &sub) && client.argc() == 2 {
+        server.reset_stats();
+        client.add_reply_ok();
+    } else {
+        client.add_reply_error(&format!(
+            "Unknown CONFIG subcommand or wrong number of arguments for '{}'",
+            String::from_utf8_lossy(&sub),
+        ));
+    }
+}
+
+fn config_get_command(client: &mut Client, server: &Server) {
+    let patterns: Vec<Vec<u8>> = client.argv[2..].iter()
+        .map(|p| p.borrow().string_to_vec())
+        .collect();
+    let matched: Vec<&ConfigParam> = CONFIG_TABLE.iter()
+        .filter(|p| patterns.iter().any(|pat| glob_match(pat, p.name.as_bytes(), true)))
+        .collect();
+
+    client.add_reply_map_len(matched.len());
+    for param in matched {
+        client.add_reply_bulk_bytes(param.name.as_bytes());
+        client.add_reply_bulk_bytes((param.get)(server).as_bytes());
+    }
+}
+
+// All the parameters are set or none of them: the ones already set are
+// restored when a later one fails, or when applying them fails.
+fn config_set_command(client: &mut Client, server: &mut Server, el: &mut AeEventLoop) {
+    let mut pairs: Vec<(&'static ConfigParam, String)> = vec![];
+    for i in (2..client.argc()).step_by(2) {
+        let name = String::from_utf8_lossy(client.argv[i].borrow().string()).to_string();
+        let value = String::from_utf8_lossy(client.argv[i + 1].borrow().string()).to_string();
+        let param = match lookup_config_param(&name) {
+            Some(p) => p,
+            None => {
+                client.add_reply_error(&format!(
+                    "Unknown option or number of arguments for CONFIG SET - '{}'",
+                    name,
+                ));
+                return;
+            }
+        };
+        if pairs.iter().any(|(p, _)| p.name == param.name) {
+            client.add_reply_error(&format!(
+                "CONFIG SET failed (possibly related to argument '{}') - \
+                duplicate parameter",
+                param.name,
+            ));
+            return;
+        }
+        if param.set.is_none() {
+            client.add_reply_error(&format!(
+                "CONFIG SET failed (possibly related to argument '{}') - \
+                can't set immutable config",
+                param.name,
+            ));
+            return;
+        }
+        pairs.push((param, value));
+    }
+
+    let mut old_values: Vec<(&'static ConfigParam, String)> = vec![];
+    for (param, value) in pairs {
+        let old = (param.get)(server);
+        if let Err(e) = (param.set.unwrap())(server, &value) {
+            for (param, old) in old_values.iter().rev() {
+                let _ = (param.set.unwrap())(server, old);
+            }
+            client.add_reply_error(&format!(
+                "CONFIG SET failed (possibly related to argument '{}') - {}",
+                param.name, e,
+            ));
+            return;
+        }
+        old_values.push((param, old));
+    }
+
+    for (param, _) in old_values.iter() {
+        if let Some(apply) = param.apply {
+            if let Err(e) = apply(server, el) {
+                restore_config_params(server, el, &old_values);
+                client.add_reply_error(&format!(
+                    "CONFIG SET failed (possibly related to argument '{}') - {}",
+                    param.name, e,
+                ));
+                return;
+            }
+        }
+    }
+    client.add_reply_ok();
+}
+
+fn restore_config_params(
+    server: &mut Server,
+    el: &mut AeEventLoop,
+    old_values: &[(&'static ConfigParam, String)],
+) {
+    for (param, old) in old_values.iter().rev() {
+        let _ = (param.set.unwrap())(server, old);
+    }
+    for (param, _) in old_values.iter() {
+        if let Some(apply) = param.apply {
+            if let Err(e) = apply(server, el) {
+                warn!("Failed to restore '{}' after a CONFIG SET error: {}", param.name, e);
+            }
+        }
+    }
+}
+
+fn get_client_query_buffer_limit(server: &Server) -> String {
+    server.client_max_querybuf_len.to_string()
+}
+
+fn set_client_query_buffer_limit(server: &mut Server, value: &str) -> Result<(), String> {
+    server.client_max_querybuf_len = parse_memory_at_least(value, 1024 * 1024)?;
+    Ok(())
+}
+
+fn get_proto_max_bulk_len(server: &Server) -> String {
+    server.proto_max_bulk_len.to_string()
+}
+
+fn set_proto_max_bulk_len(server: &mut Server, value: &str) -> Result<(), String> {
+    server.proto_max_bulk_len = parse_memory_at_least(value, 1024 * 1024)?;
+    Ok(())
+}
+
+fn get_client_output_buffer_limit(server: &Server) -> String {
+    output_buffer_limits_string(&server.client_obuf_limits)
+}
+
+// "<class> <hard> <soft> <soft seconds> [<class> ...]", the classes not
+// given keep their limits
+fn set_client_output_buffer_limit(server: &mut Server, value: &str) -> Result<(), String> {
+    parse_output_buffer_limits(value, &mut server.client_obuf_limits)
+}
+
+// the lines a parameter is written as in the config file
+fn config_lines(name: &str, value: &str) -> Vec<String> {
+    if name == "client-output-buffer-limit" {
+        let args: Vec<&str> = value.split_ascii_whitespace().collect();
+        return args.chunks(4)
+            .map(|limit| format!("{} {}", name, limit.join(" ")))
+            .collect();
+    }
+    if name == "save" {
+        let args: Vec<&str> = value.split_ascii_whitespace().collect();
+        if args.is_empty() {
+            return vec!["save \"\"".to_string()];
+        }
+        return args.chunks(2)
+            .map(|pair| format!("save {}", pair.join(" ")))
+            .collect();
+    }
+    if value.is_empty() {
+        return vec![];
+    }
+    vec![format!("{} {}", name, value)]
+}
+
+// Rewrites `old` with the given parameter values: the first line of every
+// parameter is replaced by its current value and the other ones are dropped,
+// comments and unknown lines are kept as they are. The parameters missing
+// from `old` are appended when they are not set to their default.
+pub fn rewrite_config_string(old: &str, values: &[(&str, Option<&str>, String)]) -> String {
+    let mut lines: Vec<String> = vec![];
+    let mut written: Vec<&str> = vec![];
+
+    for line in old.lines() {
+        let trimmed = line.trim();
+        let name = trimmed.split_ascii_whitespace()
+            .next()
+            .unwrap_or("")
+            .to_ascii_lowercase();
+        let param = if trimmed.starts_with('#') {
+            None
+        } else {
+            values.iter().find(|(n, _, _)| *n == name)
+        };
+        match param {
+            None => lines.push(line.to_string()),
+            Some((name, _, value)) => {
+                if !written.contains(name) {
+                    lines.extend(config_lines(name, value));
+                    written.push(name);
+                }
+            }
+        }
+    }
+
+    let mut appended = false;
+    for (name, default, value) in values.iter() {
+        if written.contains(name) {
+            continue;
+        }
+        match default {
+            Some(default) if default != value => {}
+            _ => continue,
+        }
+        if !appended {
+            lines.push("# Generated by CONFIG REWRITE".to_string());
+            appended = true;
+        }
+        lines.extend(config_lines(name, value));
+    }
+
+    let mut s = lines.join("\n");
+    s.push('\n');
+    s
+}
+
+fn rewrite_config(server: &Server) -> Result<(), String> {
+    let path = match &server.config_file {
+        Some(p) => p,
+        None => return Err("The server is running without a config file".to_string()),
+    };
+    // a missing file is written from scratch
+    let old = fs::read_to_string(path).unwrap_or_default();
+    let values: Vec<(&str, Option<&str>, String)> = CONFIG_TABLE.iter()
+        .map(|p| (p.name, p.default, (p.get)(server)))
+        .collect();
+    let content = rewrite_config_string(&old, &values);
+
+    // the new file is moved over the old one so it is never half-written
+    let tmp = format!("{}.tmp-{}", path, std::process::id());
+    let mut file = fs::File::create(&tmp).map_err(|e| e.to_string())?;
+    file.write_all(content.as_bytes())
+        .and_then(|_| file.sync_all())
+        .and_then(|_| fs::rename(&tmp, path))
+        .map_err(|e| {
+            let _ = fs::remove_file(&tmp);
+            e.to_string()
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rewrite_keeps_comments_and_unknown_lines() {
+        let old = "# a comment\ntimeout 10\nsave 900 1\nfoo bar\nsave 300 10\n\nport 7000\n";
+        let values = vec![
+            ("timeout", Some("300"), "20".to_string()),
+            ("port", Some("6379"), "7000".to_string()),
+            ("save", Some("3600 1"), "60 5".to_string()),
+            ("maxmemory", Some("0"), "1024".to_string()),
+            ("requirepass", Some(""), String::new()),
+            ("dir", None, "/tmp".to_string()),
+        ];
+        let new = rewrite_config_string(old, &values);
+        assert_eq!(
+            new,
+            "# a comment\ntimeout 20\nsave 60 5\nfoo bar\n\nport 7000\n\
+             # Generated by CONFIG REWRITE\nmaxmemory 1024\n",
+        );
+    }
+
+    #[test]
+    fn rewrite_disabled_save() {
+        let values = vec![("save", Some("3600 1"), String::new())];
+        assert_eq!(rewrite_config_string("save 1 1\n", &values), "save \"\"\n");
+    }
+
+    #[test]
+    fn rewrite_output_buffer_limits() {
+        let values = vec![(
+            "client-output-buffer-limit",
+            Some("normal 0 0 0"),
+            "normal 0 0 0 pubsub 100 50 10".to_string(),
+        )];
+        let old = "client-output-buffer-limit pubsub 32mb 8mb 60\n";
+        assert_eq!(
+            rewrite_config_string(old, &values),
+            "client-output-buffer-limit normal 0 0 0\n\
+             client-output-buffer-limit pubsub 100 50 10\n",
+        );
+    }
+}
@@ -1,14 +1,32 @@
-use crate::object::RobjPtr;
+use crate::object::{RobjPtr, Robj};
 use crate::object::dict::Dict;
 use crate::hash::string_object_hash;
 use rand::Rng;
 use std::time::SystemTime;
 use std::rc::Rc;
+use std::collections::HashMap;
+
+// Every modification of a watched key bumps its version, so EXEC can
+// tell whether a key changed since the client watched it.
+pub struct KeyWatchers {
+    pub clients: usize,
+    pub version: usize,
+}
 
 pub struct DB {
     pub id: usize,
     pub dict: Dict<RobjPtr, RobjPtr>,
     pub expires: Dict<RobjPtr, SystemTime>,
+    pub watched_keys: HashMap<Vec<u8>, KeyWatchers>,
+    // expired keys waiting to be reported as keyspace events
+    pub expired_keys: Vec<RobjPtr>,
+    // modified keys waiting to be invalidated for tracking clients
+    pub modified_keys: Vec<RobjPtr>,
+
+    // for stats
+    pub stat_keyspace_hits: usize,
+    pub stat_keyspace_misses: usize,
+    pub stat_expired_keys: usize,
 }
 
 impl DB {
@@ -18,6 +36,12 @@ impl DB {
             id,
             dict: Dict::new(string_object_hash, rng.gen()),
             expires: Dict::new(string_object_hash, rng.gen()),
+            watched_keys: HashMap::new(),
+            expired_keys: vec![],
+            modified_keys: vec![],
+            stat_keyspace_hits: 0,
+            stat_keyspace_misses: 0,
+            stat_expired_keys: 0,
         }
     }
 
@@ -30,6 +54,10 @@ impl DB {
         self.expires.add(key, when)
     }
 
+    pub fn replace_expire(&mut self, key: RobjPtr, when: SystemTime) {
+        self.expires.replace(key, when);
+    }
+
     pub fn get_expire(&mut self, key: &RobjPtr) -> Option<&SystemTime> {
         self.expires.find_by_mut(key).map(|p| p.1)
     }
@@ -51,6 +79,9 @@ impl DB {
         }
 
         self.expires.delete(key).unwrap();
+        self.signal_modified_key(key);
+        self.expired_keys.push(Rc::clone(key));
+        self.stat_expired_keys += 1;
 
         let _ = self.dict.delete(key)?;
 
@@ -76,10 +107,71 @@ impl DB {
     }
 
     pub fn look_up_key_read(&mut self, key: &RobjPtr) -> Option<RobjPtr> {
+        let _ = self.expire_if_needed(key);
+        let r = self.look_up_key(key);
+        if r.is_some() {
+            self.stat_keyspace_hits += 1;
+        } else {
+            self.stat_keyspace_misses += 1;
+        }
+        r
+    }
+
+    // like look_up_key_read without counting a hit or a miss, for the
+    // commands writing the key and the ones only inspecting it
+    pub fn look_up_key_write(&mut self, key: &RobjPtr) -> Option<RobjPtr> {
         let _ = self.expire_if_needed(key);
         self.look_up_key(key)
     }
 
+    pub fn watch_key(&mut self, key: &RobjPtr) -> usize {
+        let w = self.watched_keys
+            .entry(key.borrow().string_to_vec())
+            .or_insert(KeyWatchers { clients: 0, version: 0 });
+        w.clients += 1;
+        w.version
+    }
+
+    pub fn unwatch_key(&mut self, key: &RobjPtr) {
+        let key = key.borrow().string_to_vec();
+        let remove = match self.watched_keys.get_mut(&key) {
+            None => false,
+            Some(w) => {
+                w.clients -= 1;
+                w.clients == 0
+            }
+        };
+        if remove {
+            self.watched_keys.remove(&key);
+        }
+    }
+
+    pub fn watched_key_version(&self, key: &RobjPtr) -> Option<usize> {
+        self.watched_keys
+            .get(&key.borrow().string_to_vec())
+            .map(|w| w.version)
+    }
+
+    pub fn signal_modified_key(&mut self, key: &RobjPtr) {
+        self.modified_keys.push(Rc::clone(key));
+        if self.watched_keys.is_empty() {
+            return;
+        }
+        if let Some(w) = self.watched_keys.get_mut(&key.borrow().string_to_vec()) {
+            w.version += 1;
+        }
+    }
+
+    // the watched keys outlive a flush, only the existing ones are touched
+    pub fn signal_flushed_db(&mut self) {
+        let dict = &self.dict;
+        for (key, w) in self.watched_keys.iter_mut() {
+            if dict.find(&Robj::create_bytes_object(key)).is_some() {
+                w.version += 1;
+            }
+        }
+    }
+
     pub fn look_up_key(&mut self, key: &RobjPtr) -> Option<RobjPtr> {
         let e = self.dict.find_by_mut(key);
         match e {
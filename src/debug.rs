@@ -0,0 +1,337 @@
+use crate::client::Client;
+use crate::server::Server;
+use crate::ae::AeEventLoop;
+use crate::object::{Robj, RobjPtr, RobjType};
+use crate::rdb::{rdb_save, rdb_object_len};
+use crate::util::*;
+use sha1_smol::Sha1;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::rc::Rc;
+use std::time::{Duration, SystemTime};
+
+const DIGEST_LEN: usize = 20;
+type Digest = [u8; DIGEST_LEN];
+
+// who may run DEBUG, it can block or corrupt the server so it is
+// refused unless enabled in the configuration file
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum EnableDebugCommand {
+    No,
+    Yes,
+    // only from loopback connections
+    Local,
+}
+
+impl EnableDebugCommand {
+    pub fn from_name(s: &str) -> Option<EnableDebugCommand> {
+        match &s.to_ascii_lowercase()[..] {
+            "no" => Some(EnableDebugCommand::No),
+            "yes" => Some(EnableDebugCommand::Yes),
+            "local" => Some(EnableDebugCommand::Local),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EnableDebugCommand::No => "no",
+            EnableDebugCommand::Yes => "yes",
+            EnableDebugCommand::Local => "local",
+        }
+    }
+}
+
+fn debug_command_allowed(server: &Server, client: &Client) -> bool {
+    match server.enable_debug_command {
+        EnableDebugCommand::Yes => true,
+        EnableDebugCommand::No => false,
+        EnableDebugCommand::Local => client.addr.parse::<SocketAddr>()
+            .map(|addr| addr.ip().is_loopback())
+            .unwrap_or(false),
+    }
+}
+
+pub fn debug_command(
+    client: &mut Client,
+    server: &mut Server,
+    _el: &mut AeEventLoop,
+) {
+    if !debug_command_allowed(server, client) {
+        client.add_reply_error("DEBUG command not allowed. If the enable-debug-command option is set \
+            to \"local\", you can run it from a local connection, otherwise you need to \
+            set this option in the configuration file, and then restart the server.");
+        return;
+    }
+
+    let sub = client.argv[1].borrow().string_to_vec();
+    if case_eq(b"object", &sub) && client.argc() == 3 {
+        debug_object_command(client, server);
+    } else if case_eq(b"reload", &sub) && client.argc() == 2 {
+        debug_reload_command(client, server);
+    } else if case_eq(b"sleep", &sub) && client.argc() == 3 {
+        let r = bytes_to_f64(client.argv[2].borrow().string());
+        // inf and huge values parse as floats but are no duration
+        match r.map(Duration::try_from_secs_f64) {
+            Ok(Ok(d)) => {
+                std::thread::sleep(d);
+                client.add_reply_ok();
+            }
+            _ => client.add_reply_error("value is not a valid float"),
+        }
+    } else if case_eq(b"populate", &sub) && client.argc() >= 3 && client.argc() <= 5 {
+        debug_populate_command(client, server);
+    } else if case_eq(b"set-active-expire", &sub) && client.argc() == 3 {
+        let r = bytes_to_usize(client.argv[2].borrow().string());
+        match r {
+            Ok(n) if n <= 1 => {
+                server.active_expire_enabled = n == 1;
+                client.add_reply_ok();
+            }
+            _ => client.add_reply_error("value must be 0 or 1"),
+        }
+    } else if case_eq(b"change-repl-id", &sub) && client.argc() == 2 {
+        server.change_replication_id();
+        client.add_reply_ok();
+    } else if case_eq(b"jmap", &sub) && client.argc() == 2 {
+        let report = debug_jmap_report(server);
+        client.add_reply_bulk_bytes(report.as_bytes());
+    } else if case_eq(b"digest", &sub) && client.argc() == 2 {
+        let digest = to_hex(&dataset_digest(server));
+        client.add_reply_status(&digest);
+    } else if case_eq(b"digest-value", &sub) {
+        let digests: Vec<String> = client.argv[2..].iter()
+            .map(|key| {
+                server.db[client.db_idx].dict.find(key)
+                    .map_or([0; DIGEST_LEN], |(_, v)| value_digest(v))
+            })
+            .map(|d| to_hex(&d))
+            .collect();
+        client.add_reply_array_len(digests.len());
+        for d in digests {
+            client.add_reply_status(&d);
+        }
+    } else {
+        client.add_reply_error(&format!(
+            "Unknown DEBUG subcommand or wrong number of arguments for '{}'",
+            String::from_utf8_lossy(&sub),
+        ));
+    }
+}
+
+// DEBUG OBJECT key
+fn debug_object_command(client: &mut Client, server: &Server) {
+    let value = server.db[client.db_idx].dict.find(&client.argv[2])
+        .map(|(_, v)| Rc::clone(v));
+    let value = match value {
+        Some(v) => v,
+        None => {
+            client.add_reply_error("no such key");
+            return;
+        }
+    };
+    let o = value.borrow();
+    let lru = unix_timestamp(&o.lru()) / 1000;
+    let idle = SystemTime::now().duration_since(o.lru())
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    client.add_reply_status(&format!(
+        "Value at:{:p} refcount:{} encoding:{} serializedlength:{} lru:{} lru_seconds_idle:{}",
+        Rc::as_ptr(&value),
+        // the reference held here is not counted
+        Rc::strong_count(&value) - 1,
+        o.encoding().name(),
+        rdb_object_len(&value),
+        lru,
+        idle,
+    ));
+}
+
+// DEBUG RELOAD, saves the dataset and loads it back
+fn debug_reload_command(client: &mut Client, server: &mut Server) {
+    if let Err(e) = rdb_save(server) {
+        client.add_reply_error(&format!("Error trying to save the DB: {}", e));
+        return;
+    }
+    server.flush_all();
+    if let Err(e) = server.rdb_load() {
+        client.add_reply_error(&format!("Error trying to load the RDB dump: {}", e));
+        return;
+    }
+    info!("DB reloaded by DEBUG RELOAD");
+    client.add_reply_ok();
+}
+
+// DEBUG POPULATE count [prefix] [size], creates the keys prefix:0 to
+// prefix:count-1 with the values value:0 to value:count-1, padded with
+// zeros or truncated to size. The existing keys are left alone.
+fn debug_populate_command(client: &mut Client, server: &mut Server) {
+    let r = bytes_to_usize(client.argv[2].borrow().string());
+    let count = match r {
+        Ok(n) => n,
+        Err(_) => {
+            client.add_reply_error("value is out of range, must be positive");
+            return;
+        }
+    };
+    let prefix = client.argv.get(3)
+        .map_or(b"key".to_vec(), |p| p.borrow().string_to_vec());
+    let size = match client.argv.get(4).map(|s| bytes_to_usize(s.borrow().string())) {
+        None => None,
+        Some(Ok(size)) => Some(size),
+        Some(Err(_)) => {
+            client.add_reply_error("value is out of range, must be positive");
+            return;
+        }
+    };
+
+    let db = &mut server.db[client.db_idx];
+    for i in 0..count {
+        let mut key = prefix.clone();
+        key.extend_from_slice(format!(":{}", i).as_bytes());
+        let key = Robj::from_bytes(key);
+        if db.dict.find(&key).is_some() {
+            continue;
+        }
+        let mut value = format!("value:{}", i).into_bytes();
+        if let Some(size) = size {
+            value.resize(size, 0);
+        }
+        let _ = db.dict.add(key, Robj::from_bytes(value));
+    }
+    client.add_reply_ok();
+}
+
+// the number of values of every type and encoding
+fn debug_jmap_report(server: &Server) -> String {
+    let mut counts: BTreeMap<(&str, &str), usize> = BTreeMap::new();
+    for db in server.db.iter() {
+        for (_, v) in db.dict.iter() {
+            let v = v.borrow();
+            *counts.entry((type_name(v.object_type()), v.encoding().name())).or_default() += 1;
+        }
+    }
+    let mut report = String::new();
+    for ((t, encoding), n) in counts {
+        let _ = writeln!(report, "{} {}: {}", t, encoding, n);
+    }
+    report
+}
+
+fn type_name(t: RobjType) -> &'static str {
+    match t {
+        RobjType::String => "string",
+        RobjType::List => "list",
+        RobjType::Set => "set",
+        RobjType::Zset => "zset",
+        RobjType::Hash => "hash",
+    }
+}
+
+fn to_hex(digest: &Digest) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// digest ^= SHA1(data), the order of the calls doesn't matter
+fn xor_digest(digest: &mut Digest, data: &[u8]) {
+    let hash = Sha1::from(data).digest().bytes();
+    for (d, h) in digest.iter_mut().zip(hash.iter()) {
+        *d ^= h;
+    }
+}
+
+// digest = SHA1(digest ^ SHA1(data)), the order of the calls matters
+fn mix_digest(digest: &mut Digest, data: &[u8]) {
+    xor_digest(digest, data);
+    *digest = Sha1::from(&digest[..]).digest().bytes();
+}
+
+fn mix_object_digest(digest: &mut Digest, o: &RobjPtr) {
+    let o = o.borrow();
+    mix_digest(digest, &o.string_to_vec());
+}
+
+fn value_digest(v: &RobjPtr) -> Digest {
+    let mut digest = [0; DIGEST_LEN];
+    let v = v.borrow();
+    match v.object_type() {
+        RobjType::String => mix_digest(&mut digest, &v.string_to_vec()),
+        RobjType::List => {
+            for e in v.list_iter() {
+                mix_object_digest(&mut digest, &e);
+            }
+        }
+        RobjType::Set => {
+            for e in v.set_iter() {
+                xor_digest(&mut digest, &e.borrow().string_to_vec());
+            }
+        }
+        RobjType::Zset => {
+            for (e, score) in v.zset_iter() {
+                let mut ele = [0; DIGEST_LEN];
+                mix_object_digest(&mut ele, &e);
+                mix_digest(&mut ele, format!("{}", score).as_bytes());
+                xor_digest(&mut digest, &ele);
+            }
+        }
+        // no command creates hashes yet
+        RobjType::Hash => {}
+    }
+    digest
+}
+
+// A digest of the whole dataset that doesn't depend on the order the keys
+// are stored in, two servers with the same data have the same digest.
+// All zeros when the dataset is empty.
+pub fn dataset_digest(server: &Server) -> Digest {
+    let mut fin = [0; DIGEST_LEN];
+    for db in server.db.iter() {
+        if db.dict.len() == 0 {
+            continue;
+        }
+        mix_digest(&mut fin, &(db.id as u32).to_be_bytes());
+        for (k, v) in db.dict.iter() {
+            let mut digest = [0; DIGEST_LEN];
+            mix_object_digest(&mut digest, k);
+            let value = value_digest(v);
+            mix_digest(&mut digest, &value);
+            if let Some((_, when)) = db.expires.find(k) {
+                mix_digest(&mut digest, b"!!expire!!");
+                mix_digest(&mut digest, &unix_timestamp(when).to_be_bytes());
+            }
+            xor_digest(&mut fin, &digest);
+        }
+    }
+    fin
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn set_digest_is_order_independent() {
+        let mut a = [0; DIGEST_LEN];
+        xor_digest(&mut a, b"x");
+        xor_digest(&mut a, b"y");
+        let mut b = [0; DIGEST_LEN];
+        xor_digest(&mut b, b"y");
+        xor_digest(&mut b, b"x");
+        assert_eq!(a, b);
+
+        let mut c = [0; DIGEST_LEN];
+        mix_digest(&mut c, b"x");
+        mix_digest(&mut c, b"y");
+        let mut d = [0; DIGEST_LEN];
+        mix_digest(&mut d, b"y");
+        mix_digest(&mut d, b"x");
+        assert_ne!(c, d);
+    }
+
+    #[test]
+    fn enable_debug_command_from_name() {
+        assert_eq!(EnableDebugCommand::from_name("LOCAL"), Some(EnableDebugCommand::Local));
+        assert_eq!(EnableDebugCommand::from_name("maybe"), None);
+    }
+}
@@ -1,38 +1,46 @@
-use crate::server::Server;
-use crate::ae::{AE_READABLE, default_ae_event_finalizer_proc, AeEventLoop, Fd, Fdp, AE_WRITABLE};
+use crate::server::{Server, ClientPauseType};
+use crate::ae::{AE_READABLE, default_ae_event_finalizer_proc, AeEventLoop, Fd, Fdp, AE_WRITABLE, Stream};
 use std::rc::Rc;
 use chrono::Local;
 use std::io::{Write, Read, ErrorKind, SeekFrom, Seek};
 use log::{Level, LevelFilter};
 use std::cell::RefCell;
 use crate::client::*;
-use mio::net::TcpStream;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, SystemTime, Instant};
 use std::fs::File;
+use std::os::unix::io::AsRawFd;
 use std::process::exit;
 use std::io;
 use crate::util::*;
 use std::env::set_current_dir;
 use std::fmt;
-use crate::zalloc;
-use crate::object::RobjEncoding;
 use crate::rdb;
 use nix::sys::wait::*;
 use nix::unistd::Pid;
 use crate::replicate;
 use std::ops::Deref;
+use crate::notify::{keyspace_events_from_str, flush_keyspace_events};
+use crate::tracking::{flush_tracking_invalidations, tracking_limit_used_slots};
+use crate::latency::latency_add_sample_if_needed;
+use crate::debug::EnableDebugCommand;
+use crate::command::client_info_string;
+use crate::reply::{OutputBufferLimit, DEFAULT_OUTPUT_BUFFER_LIMITS, parse_output_buffer_limits};
 
 pub const REREDIS_VERSION: &str = "0.0.1";
-pub const REREDIS_REQUEST_MAX_SIZE: usize = 1024 * 1024 * 256;
 pub const REREDIS_MAX_WRITE_PER_EVENT: usize = 64 * 1024;
 pub const REREDIS_EXPIRE_LOOKUPS_PER_CRON: usize = 100;
-pub const REREIDS_IO_BUF_LEN: usize = 1024;
+pub const REREIDS_IO_BUF_LEN: usize = 16 * 1024;
+pub const REREDIS_MAX_READ_PER_EVENT: usize = 1024 * 1024;
+pub const REREDIS_MAX_COMMANDS_PER_EVENT: usize = 1000;
+pub const REREDIS_BINDADDR_MAX: usize = 16;
 
 pub struct Config {
     pub config_file: Option<String>,
     pub max_idle_time: usize,
     pub port: u16,
-    pub bind_addr: String,
+    pub bind_addrs: Vec<String>,
+    pub unix_socket: Option<String>,
+    pub unix_socket_perm: u32,
     pub log_level: LevelFilter,
     pub log_file: Option<String>,
     pub db_num: usize,
@@ -43,8 +51,19 @@ pub struct Config {
     pub glue_output: bool,
     pub daemonize: bool,
     pub require_pass: Option<String>,
+    pub acl_file: Option<String>,
+    pub acllog_max_len: usize,
     pub db_filename: String,
     pub save_params: Vec<(usize, usize)>,
+    pub notify_keyspace_events: i32,
+    pub tracking_table_max_keys: usize,
+    pub slowlog_log_slower_than: i64,
+    pub slowlog_max_len: usize,
+    pub latency_monitor_threshold: usize,
+    pub client_obuf_limits: [OutputBufferLimit; 3],
+    pub client_max_querybuf_len: usize,
+    pub proto_max_bulk_len: usize,
+    pub enable_debug_command: EnableDebugCommand,
 }
 
 impl Config {
@@ -53,7 +72,9 @@ impl Config {
             config_file: None,
             max_idle_time: 5 * 60,
             port: 6379,
-            bind_addr: "127.0.0.1".to_string(),
+            bind_addrs: vec!["127.0.0.1".to_string()],
+            unix_socket: None,
+            unix_socket_perm: 0,
             log_level: LevelFilter::Debug,
             log_file: None,
             db_num: 16,
@@ -64,8 +85,19 @@ impl Config {
             glue_output: true,
             daemonize: false,
             require_pass: None,
+            acl_file: None,
+            acllog_max_len: 128,
             db_filename: "dump.rdb".to_string(),
             save_params: vec![(3600, 1), (300, 100), (60, 10000)],
+            notify_keyspace_events: 0,
+            tracking_table_max_keys: 1_000_000,
+            slowlog_log_slower_than: 10_000,
+            slowlog_max_len: 128,
+            latency_monitor_threshold: 0,
+            client_obuf_limits: DEFAULT_OUTPUT_BUFFER_LIMITS,
+            client_max_querybuf_len: 1024 * 1024 * 1024,
+            proto_max_bulk_len: 512 * 1024 * 1024,
+            enable_debug_command: EnableDebugCommand::No,
         }
     }
 
@@ -99,6 +131,12 @@ impl Config {
         let mut contents = String::new();
 
         if filename != "-" {
+            // kept absolute for CONFIG REWRITE, `dir` may change the working directory
+            self.config_file = Some(
+                std::fs::canonicalize(filename)
+                    .map(|p| p.display().to_string())
+                    .unwrap_or_else(|_| filename.to_string())
+            );
             let mut file = File::open(filename).unwrap_or_else(|e| {
                 eprintln!("Fatal error, can't open config file {}: {}",
                           filename, &e.to_string());
@@ -159,8 +197,23 @@ impl Config {
                         0
                     });
                 }
-                ("bind", 2) => {
-                    self.bind_addr = argv[1].to_string();
+                ("bind", n) if n >= 2 => {
+                    if n - 1 > REREDIS_BINDADDR_MAX {
+                        Self::load_error(i, line, "Too many bind addresses specified.");
+                    }
+                    self.bind_addrs = argv[1..].iter().map(|a| a.to_string()).collect();
+                }
+                ("unixsocket", 2) => {
+                    self.unix_socket = Some(argv[1].to_string());
+                }
+                ("unixsocketperm", 2) => {
+                    self.unix_socket_perm = u32::from_str_radix(argv[1], 8).unwrap_or_else(|e| {
+                        Self::load_error(i, line, &e.to_string());
+                        0
+                    });
+                }
+                ("save", 2) if argv[1] == "\"\"" => {
+                    self.save_params.clear();
                 }
                 ("save", 3) => {
                     let pair =
@@ -242,9 +295,76 @@ impl Config {
                 ("requirepass", 2) => {
                     self.require_pass = Some(argv[1].to_string());
                 }
+                ("aclfile", 2) => {
+                    self.acl_file = Some(argv[1].to_string());
+                }
+                ("acllog-max-len", 2) => {
+                    self.acllog_max_len = parse_usize(argv[1]).unwrap_or_else(|e| {
+                        Self::load_error(i, line, &e.to_string());
+                        0
+                    });
+                }
                 ("dbfilename", 2) => {
                     self.db_filename = argv[1].to_string();
                 }
+                ("notify-keyspace-events", 1) => {
+                    self.notify_keyspace_events = 0;
+                }
+                ("notify-keyspace-events", 2) => {
+                    let arg = argv[1].trim_matches('"');
+                    self.notify_keyspace_events =
+                        keyspace_events_from_str(arg).unwrap_or_else(|| {
+                            Self::load_error(i, line, "Invalid event class character. \
+                                Use 'Ag$lshzxeKEtmdn'.");
+                            0
+                        });
+                }
+                ("tracking-table-max-keys", 2) => {
+                    self.tracking_table_max_keys = parse_usize(argv[1]).unwrap_or_else(|e| {
+                        Self::load_error(i, line, &e.to_string());
+                        0
+                    });
+                }
+                ("slowlog-log-slower-than", 2) => {
+                    self.slowlog_log_slower_than = argv[1].parse::<i64>().unwrap_or_else(|e| {
+                        Self::load_error(i, line, &e.to_string());
+                        0
+                    });
+                }
+                ("slowlog-max-len", 2) => {
+                    self.slowlog_max_len = parse_usize(argv[1]).unwrap_or_else(|e| {
+                        Self::load_error(i, line, &e.to_string());
+                        0
+                    });
+                }
+                ("latency-monitor-threshold", 2) => {
+                    self.latency_monitor_threshold = parse_usize(argv[1]).unwrap_or_else(|e| {
+                        Self::load_error(i, line, &e.to_string());
+                        0
+                    });
+                }
+                ("client-output-buffer-limit", 5) => {
+                    parse_output_buffer_limits(&argv[1..].join(" "), &mut self.client_obuf_limits)
+                        .unwrap_or_else(|e| Self::load_error(i, line, &e));
+                }
+                ("client-query-buffer-limit", 2) => {
+                    self.client_max_querybuf_len = human_size(argv[1]).unwrap_or_else(|_| {
+                        Self::load_error(i, line, "cannot parse size");
+                        0
+                    });
+                }
+                ("proto-max-bulk-len", 2) => {
+                    self.proto_max_bulk_len = human_size(argv[1]).unwrap_or_else(|_| {
+                        Self::load_error(i, line, "cannot parse size");
+                        0
+                    });
+                }
+                ("enable-debug-command", 2) => {
+                    self.enable_debug_command = EnableDebugCommand::from_name(argv[1]).unwrap_or_else(|| {
+                        Self::load_error(i, line, "must be 'yes', 'no' or 'local'");
+                        EnableDebugCommand::No
+                    });
+                }
                 (_, _) => {
                     println!("Warning: '{}' is not supported or argument number is incorrect",
                              main);
@@ -290,6 +410,7 @@ impl Env {
             ClientData::Nil(),
             default_ae_event_finalizer_proc,
         );
+        self.server.initial_memory_usage = crate::zalloc::allocated_memory();
     }
 
     pub fn rdb_load(&mut self) -> Result<(), ()> {
@@ -304,12 +425,14 @@ impl Env {
     }
 
     pub fn create_first_file_event(&mut self) -> Result<(), ()> {
-        self.el.create_file_event(
-            Rc::clone(&self.server.fd),
-            AE_READABLE,
-            accept_handler,
-            ClientData::Nil(),
-        )?;
+        for fd in self.server.ipfd.iter().chain(self.server.unix_fd.iter()) {
+            self.el.create_file_event(
+                Rc::clone(fd),
+                AE_READABLE,
+                accept_handler,
+                ClientData::Nil(),
+            )?;
+        }
         Ok(())
     }
 
@@ -328,8 +451,10 @@ fn level_to_sign(level: Level) -> &'static str {
 }
 
 pub fn init_logger(level: log::LevelFilter) {
+    // the level is only enforced by the log facade, so CONFIG SET loglevel
+    // can change it at runtime
     let mut builder = env_logger::Builder::new();
-    builder.filter_level(level);
+    builder.filter_level(LevelFilter::Trace);
     builder.format(
         |buf, record|
             writeln!(
@@ -342,6 +467,7 @@ pub fn init_logger(level: log::LevelFilter) {
     );
 
     builder.init();
+    log::set_max_level(level);
 }
 
 pub fn accept_handler(
@@ -355,16 +481,15 @@ pub fn accept_handler(
     let listener = fd.unwrap_listener();
 
     debug!("ready to accept");
-    let r = listener.accept();
-
-    let (stream, info) = match r {
+    let (stream, addr, laddr) = match listener.accept() {
         Ok(p) => p,
         Err(e) => {
             debug!("Accepting client connection: {}", e);
             return;
         }
     };
-    debug!("Accepted {}:{}", info.ip(), info.port());
+    debug!("Accepted {}", addr);
+    let unix = stream.is_unix();
 
     let c = match Client::with_fd_and_el(
         Rc::new(RefCell::new(Fdp::Stream(stream))
@@ -374,7 +499,19 @@ pub fn accept_handler(
             return;
         }
         Ok(e) => {
-            server.clients.push_back(Rc::clone(&e));
+            {
+                let mut c = e.borrow_mut();
+                c.id = server.create_client_id();
+                c.addr = addr;
+                c.laddr = laddr;
+                if unix {
+                    c.flags |= CLIENT_UNIX_SOCKET;
+                }
+                // without a password for the default user the client is
+                // authenticated as it connects, setting one later keeps it so
+                c.authenticate = !server.acl.auth_required();
+            }
+            server.link_client(&e);
             e
         }
     };
@@ -394,7 +531,7 @@ fn free_client_occupied_in_el(
     server: &mut Server,
     el: &mut AeEventLoop,
     client_ptr: &Rc<RefCell<Client>>,
-    stream: &TcpStream,
+    stream: &Stream,
     flags: i32,
 ) {
     server.free_client_with_flags(&client_ptr, flags);
@@ -406,7 +543,7 @@ fn free_active_client(
     server: &mut Server,
     el: &mut AeEventLoop,
     client: &Client,
-    socket: &TcpStream,
+    socket: &Stream,
 ) {
     server.free_client_by_ref(client);
     el.async_delete_active_file_event();
@@ -423,20 +560,28 @@ pub fn read_query_from_client(
     let client_ptr = Rc::clone(data.unwrap_client());
     let mut client = client_ptr.borrow_mut();
     let n_read;
-    let curr_len;
 
     {
         let mut fd_ref = fd.borrow_mut();
         let socket = fd_ref.unwrap_stream_mut();
 
-        let read_len = REREIDS_IO_BUF_LEN;
-
+        // a big argument is read up to its end and no further, so that it
+        // can be used as it is, anything else greedily while the socket
+        // has data, up to a limit per event
+        let read_len = match client.pending_big_arg() {
+            Some(remaining) => remaining,
+            None => {
+                client.query_buf.reserve(REREIDS_IO_BUF_LEN);
+                REREDIS_MAX_READ_PER_EVENT
+            }
+        };
 
-        curr_len = client.query_buf.len();
-        client.query_buf.resize(curr_len + read_len, 0u8);
+        let curr_len = client.query_buf.len();
+        let result = socket.take(read_len as u64).read_to_end(&mut client.query_buf);
+        n_read = client.query_buf.len() - curr_len;
 
-        n_read = match socket.read(&mut client.query_buf[curr_len..]) {
-            Ok(n) => n,
+        match result {
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock && n_read > 0 => {}
             Err(e) => {
                 if let ErrorKind::WouldBlock = e.kind() {} else {
                     debug!("Reading from client: {}", e);
@@ -444,12 +589,12 @@ pub fn read_query_from_client(
                 }
                 return;
             }
-        };
-
-        if n_read == 0 {
-            debug!("Reading from client: {}", "Client closed connection");
-            free_active_client(server, el, client.deref(), socket);
-            return;
+            Ok(_) if n_read == 0 => {
+                debug!("Reading from client: {}", "Client closed connection");
+                free_active_client(server, el, client.deref(), socket);
+                return;
+            }
+            Ok(_) => {}
         }
     }
 
@@ -458,10 +603,39 @@ pub fn read_query_from_client(
         client.reply_off += n_read;
     }
 
-    // TODO: close client if query buffer's len exceed max value
-    client.query_buf.resize(curr_len + n_read, 0);
+    // the queries of a client about to be closed are dropped as they come
+    if client.flags & (CLIENT_CLOSE_ASAP | CLIENT_CLOSE_AFTER_REPLY) != 0 {
+        client.query_buf.clear();
+        return;
+    }
+
+    // the clients that did not authenticate yet get a smaller limit
+    let mut max_querybuf_len = server.client_max_querybuf_len;
+    if !client.authenticate && server.acl.auth_required() {
+        max_querybuf_len = max_querybuf_len.min(PROTO_UNAUTH_MAX_QUERYBUF_LEN);
+    }
+    if client.flags & CLIENT_MASTER == 0 && client.query_buf.len() > max_querybuf_len {
+        let head = &client.query_buf[..client.query_buf.len().min(64)];
+        warn!("Closing client that reached max query buffer length: {} (qbuf initial bytes: '{}')",
+              client_info_string(&client), String::from_utf8_lossy(head).escape_debug());
+        server.stat_client_qbuf_limit_disconnections += 1;
+        client.query_buf.clear();
+        server.async_free_client(&mut client);
+        return;
+    }
 
-    client.process_input_buffer(server, el);
+    // a client with queries left from the last iteration waits for its turn
+    if client.flags & CLIENT_PENDING_INPUT == 0 {
+        client.process_input_buffer(server, el);
+    }
+    if client.query_buf.is_empty() && client.query_buf.capacity() > 2 * REREIDS_IO_BUF_LEN {
+        client.query_buf.shrink_to(REREIDS_IO_BUF_LEN);
+    }
+    if client.flags & CLIENT_CLOSE_AFTER_REPLY != 0 && client.reply.is_empty() {
+        let mut fd_ref = fd.borrow_mut();
+        free_active_client(server, el, client.deref(), fd_ref.unwrap_stream_mut());
+        return;
+    }
     if !client.reply.is_empty() {
         if let Err(()) = client.prepare_to_write(el) {
             client.reply.clear();
@@ -477,45 +651,28 @@ pub fn send_reply_to_client(
     _mask: i32,
 ) {
     let mut client = data.unwrap_client().as_ref().borrow_mut();
-    let mut written_bytes: usize = 0;
-    let mut written_elem: usize = 0;
     if client.reply.is_empty() {
+        el.async_reduce_active_file_event(AE_WRITABLE);
         return;
     }
 
     let mut fd_ref = fd.as_ref().borrow_mut();
     let stream = fd_ref.unwrap_stream_mut();
 
-    for rep in client.reply
-        .iter()
-        .map(|x| x.borrow()) {
-        let (write_result, n) = match rep.encoding() {
-            RobjEncoding::Int => {
-                let s = rep.integer().to_string();
-                (stream.write_all(s.as_bytes()), s.as_bytes().len())
-            }
-            _ => (stream.write_all(rep.string()), rep.string().len()),
-        };
-        match write_result {
-            Err(e) => if e.kind() != ErrorKind::WouldBlock {
-                debug!("Error writing to client: {}", e);
-                free_client_occupied_in_el(server, el, data.unwrap_client(), stream, client.flags);
-                return;
-            }
-            Ok(_) => written_bytes += n,
-        }
-        written_elem += 1;
-        if written_bytes >= REREDIS_MAX_WRITE_PER_EVENT {
-            break;
+    if let Err(e) = client.reply.write_to(stream.as_raw_fd(), REREDIS_MAX_WRITE_PER_EVENT) {
+        if e.kind() != ErrorKind::WouldBlock {
+            debug!("Error writing to client: {}", e);
+            free_active_client(server, el, &client, stream);
         }
+        return;
     }
 
     client.last_interaction = SystemTime::now();
-    if written_elem == client.reply.len() {
+    if client.reply.is_empty() {
         el.async_reduce_active_file_event(AE_WRITABLE);
-        client.reply.clear();
-    } else {
-        client.reply.drain(0..written_elem);
+        if client.flags & CLIENT_CLOSE_AFTER_REPLY != 0 {
+            free_active_client(server, el, &client, stream);
+        }
     }
 }
 
@@ -574,7 +731,6 @@ pub fn send_bulk_to_slave(
         let _ = slave.reply_db_file.take();
         slave.reply_state = ReplyState::Online;
         el.async_modify_active_file_event(AE_WRITABLE, send_reply_to_client);
-        slave.add_str_reply("");
         info!("Synchronization with slave succeeded");
     }
 }
@@ -588,7 +744,7 @@ pub fn server_cron(
     server.cron_loops += 1;
 
     // update global state with the amount of used memory
-    server.used_memory = zalloc::allocated_memory();
+    server.update_memory_stats();
 
     let loops = server.cron_loops;
 
@@ -657,11 +813,15 @@ pub fn server_cron(
         }
     }
 
-    // try to expire a few timeout keys
+    // try to expire a few timeout keys, but not while clients are paused:
+    // the dataset must stay still until they are resumed
+    let paused = server.client_pause_type() != ClientPauseType::Off;
+    let active_expire = server.active_expire_enabled;
+    let start = Instant::now();
     for db in server.db.iter_mut() {
         let mut num: usize = db.expires.len();
 
-        if num > 0 {
+        if num > 0 && !paused && active_expire {
             let now: SystemTime = SystemTime::now();
 
             if num > REREDIS_EXPIRE_LOOKUPS_PER_CRON {
@@ -673,10 +833,14 @@ pub fn server_cron(
                 if *t < now {
                     let key = Rc::clone(key);
                     let _ = db.delete_key(&key);
+                    db.signal_modified_key(&key);
+                    db.expired_keys.push(key);
+                    db.stat_expired_keys += 1;
                 }
             }
         }
     }
+    latency_add_sample_if_needed(server, "expire-cycle", start.elapsed());
 
     if let ReplyState::Connect = server.reply_state {
         info!("Connecting to MASTER...");
@@ -685,6 +849,10 @@ pub fn server_cron(
         }
     }
 
+    flush_keyspace_events(server, el, None);
+    flush_tracking_invalidations(server, el, None);
+    tracking_limit_used_slots(server, el);
+
     server.free_clients_in_async_free_queue(el);
 
     1000
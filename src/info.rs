@@ -0,0 +1,271 @@
+use crate::client::{Client, ReplyState};
+use crate::server::Server;
+use crate::ae::AeEventLoop;
+use crate::env::REREDIS_VERSION;
+use crate::util::*;
+use nix::libc;
+use nix::sys::utsname::uname;
+use std::fmt::Write;
+use std::time::SystemTime;
+
+type InfoSectionProc = fn(&Server, &mut String);
+
+// the sections of a plain INFO, "all" and "everything" add the ones
+// not listed here
+const DEFAULT_SECTIONS: &[&str] = &[
+    "server", "clients", "memory", "persistence", "stats",
+    "replication", "cpu", "keyspace",
+];
+
+pub fn info_command(
+    client: &mut Client,
+    server: &mut Server,
+    _el: &mut AeEventLoop,
+) {
+    let sections: Vec<String> = client.argv[1..].iter()
+        .map(|s| String::from_utf8_lossy(s.borrow().string()).to_ascii_lowercase())
+        .collect();
+    let info = gen_info_string(server, &sections);
+    client.add_reply_verbatim(&info, "txt");
+}
+
+fn section_selected(sections: &[String], name: &str) -> bool {
+    if sections.is_empty() {
+        return DEFAULT_SECTIONS.contains(&name);
+    }
+    sections.iter().any(|s| match &s[..] {
+        "all" | "everything" => true,
+        "default" => DEFAULT_SECTIONS.contains(&name),
+        s => s == name,
+    })
+}
+
+pub fn gen_info_string(server: &mut Server, sections: &[String]) -> String {
+    let mut info = String::new();
+    server.update_memory_stats();
+
+    let generators: &[(&str, InfoSectionProc)] = &[
+        ("server", info_server),
+        ("clients", info_clients),
+        ("memory", info_memory),
+        ("persistence", info_persistence),
+        ("stats", info_stats),
+        ("replication", info_replication),
+        ("cpu", info_cpu),
+        ("keyspace", info_keyspace),
+    ];
+    for (name, generator) in generators.iter() {
+        if !section_selected(sections, name) {
+            continue;
+        }
+        if !info.is_empty() {
+            info.push_str("\r\n");
+        }
+        generator(server, &mut info);
+    }
+    info
+}
+
+fn info_server(server: &Server, info: &mut String) {
+    let uptime = SystemTime::now()
+        .duration_since(server.stat_start_time)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let os = uname();
+    let _ = write!(
+        info,
+        "# Server\r\n\
+        redis_version:{}\r\n\
+        redis_mode:standalone\r\n\
+        os:{} {} {}\r\n\
+        arch_bits:{}\r\n\
+        process_id:{}\r\n\
+        tcp_port:{}\r\n\
+        uptime_in_seconds:{}\r\n\
+        uptime_in_days:{}\r\n\
+        config_file:{}\r\n",
+        REREDIS_VERSION,
+        os.sysname(), os.release(), os.machine(),
+        std::mem::size_of::<usize>() * 8,
+        std::process::id(),
+        server.port,
+        uptime,
+        uptime / (3600 * 24),
+        server.config_file.as_ref().map_or("", |f| &f[..]),
+    );
+}
+
+fn info_clients(server: &Server, info: &mut String) {
+    // the client running INFO is borrowed already and skipped
+    let max_output_buffer = server.clients.iter()
+        .filter_map(|c| c.try_borrow().ok().map(|c| c.reply.memory()))
+        .max()
+        .unwrap_or(0);
+    let _ = write!(
+        info,
+        "# Clients\r\n\
+        connected_clients:{}\r\n\
+        maxclients:{}\r\n\
+        client_recent_max_output_buffer:{}\r\n\
+        tracking_clients:{}\r\n",
+        server.clients.len() - server.slaves.len(),
+        server.max_clients,
+        max_output_buffer,
+        server.tracking_clients,
+    );
+}
+
+fn info_memory(server: &Server, info: &mut String) {
+    let _ = write!(
+        info,
+        "# Memory\r\n\
+        used_memory:{}\r\n\
+        used_memory_human:{}\r\n\
+        used_memory_peak:{}\r\n\
+        used_memory_peak_human:{}\r\n\
+        maxmemory:{}\r\n\
+        maxmemory_human:{}\r\n",
+        server.used_memory,
+        bytes_to_human(server.used_memory),
+        server.stat_peak_memory,
+        bytes_to_human(server.stat_peak_memory),
+        server.max_memory,
+        bytes_to_human(server.max_memory),
+    );
+}
+
+fn info_persistence(server: &Server, info: &mut String) {
+    let _ = write!(
+        info,
+        "# Persistence\r\n\
+        loading:0\r\n\
+        rdb_changes_since_last_save:{}\r\n\
+        rdb_bgsave_in_progress:{}\r\n\
+        rdb_last_save_time:{}\r\n",
+        server.dirty,
+        server.bg_save_in_progress as i32,
+        unix_timestamp(&server.last_save) / 1000,
+    );
+}
+
+fn info_stats(server: &Server, info: &mut String) {
+    let (hits, misses, expired) = server.db.iter()
+        .fold((0, 0, 0), |(h, m, e), db| {
+            (h + db.stat_keyspace_hits, m + db.stat_keyspace_misses, e + db.stat_expired_keys)
+        });
+    let _ = write!(
+        info,
+        "# Stats\r\n\
+        total_connections_received:{}\r\n\
+        total_commands_processed:{}\r\n\
+        expired_keys:{}\r\n\
+        evicted_keys:{}\r\n\
+        keyspace_hits:{}\r\n\
+        keyspace_misses:{}\r\n\
+        pubsub_channels:{}\r\n\
+        pubsub_patterns:{}\r\n\
+        pubsubshard_channels:{}\r\n\
+        tracking_total_keys:{}\r\n\
+        tracking_total_prefixes:{}\r\n\
+        client_query_buffer_limit_disconnections:{}\r\n\
+        client_commands_cap_hits:{}\r\n",
+        server.stat_num_connections,
+        server.stat_num_commands,
+        expired,
+        server.stat_evicted_keys,
+        hits,
+        misses,
+        server.pubsub_channels.len(),
+        server.pubsub_patterns.len(),
+        server.pubsub_shard_channels.len(),
+        server.tracking_table.len(),
+        server.tracking_prefixes.len(),
+        server.stat_client_qbuf_limit_disconnections,
+        server.stat_client_commands_cap_hits,
+    );
+}
+
+fn info_replication(server: &Server, info: &mut String) {
+    info.push_str("# Replication\r\n");
+    match &server.master_host {
+        None => info.push_str("role:master\r\n"),
+        Some(host) => {
+            let link = if server.reply_state == ReplyState::Connected {
+                "up"
+            } else {
+                "down"
+            };
+            let _ = write!(
+                info,
+                "role:slave\r\n\
+                master_host:{}\r\n\
+                master_port:{}\r\n\
+                master_link_status:{}\r\n",
+                host, server.master_port, link,
+            );
+        }
+    }
+    let _ = write!(info, "connected_slaves:{}\r\n", server.slaves.len());
+    let _ = write!(info, "master_replid:{}\r\n", server.replid);
+    for (i, slave) in server.slaves.iter().enumerate() {
+        let slave = slave.borrow();
+        let state = match slave.reply_state {
+            ReplyState::WaitBgSaveStart | ReplyState::WaitBgSaveEnd => "wait_bgsave",
+            ReplyState::SendBulk => "send_bulk",
+            ReplyState::Online => "online",
+            _ => "none",
+        };
+        let (ip, port) = split_addr(&slave.addr);
+        let _ = write!(info, "slave{}:ip={},port={},state={}\r\n", i, ip, port, state);
+    }
+}
+
+fn rusage(who: libc::c_int) -> (f64, f64) {
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    if unsafe { libc::getrusage(who, &mut usage) } != 0 {
+        return (0.0, 0.0);
+    }
+    let secs = |t: libc::timeval| t.tv_sec as f64 + t.tv_usec as f64 / 1_000_000.0;
+    (secs(usage.ru_stime), secs(usage.ru_utime))
+}
+
+fn info_cpu(_server: &Server, info: &mut String) {
+    let (sys, user) = rusage(libc::RUSAGE_SELF);
+    let (sys_children, user_children) = rusage(libc::RUSAGE_CHILDREN);
+    let _ = write!(
+        info,
+        "# CPU\r\n\
+        used_cpu_sys:{:.6}\r\n\
+        used_cpu_user:{:.6}\r\n\
+        used_cpu_sys_children:{:.6}\r\n\
+        used_cpu_user_children:{:.6}\r\n",
+        sys, user, sys_children, user_children,
+    );
+}
+
+fn info_keyspace(server: &Server, info: &mut String) {
+    info.push_str("# Keyspace\r\n");
+    for db in server.db.iter() {
+        let keys = db.dict.len();
+        if keys == 0 {
+            continue;
+        }
+        let _ = write!(info, "db{}:keys={},expires={}\r\n", db.id, keys, db.expires.len());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn select_sections() {
+        let none: Vec<String> = vec![];
+        assert!(section_selected(&none, "keyspace"));
+        let stats = vec!["stats".to_string()];
+        assert!(section_selected(&stats, "stats"));
+        assert!(!section_selected(&stats, "keyspace"));
+        let all = vec!["everything".to_string()];
+        assert!(section_selected(&all, "cpu"));
+    }
+}
@@ -0,0 +1,317 @@
+use crate::client::Client;
+use crate::server::Server;
+use crate::ae::AeEventLoop;
+use crate::util::*;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write;
+use std::time::{Duration, SystemTime};
+
+// samples kept for every event, one per second at most
+pub const LATENCY_TS_LEN: usize = 160;
+const GRAPH_ROWS: usize = 4;
+const GRAPH_COLUMNS: usize = 80;
+
+#[derive(Copy, Clone)]
+pub struct LatencySample {
+    // unix time in seconds
+    pub time: u64,
+    // latency in milliseconds
+    pub latency: u64,
+}
+
+#[derive(Default)]
+pub struct LatencyTimeSeries {
+    pub samples: VecDeque<LatencySample>,
+    // all time max, kept when the samples are rotated out
+    pub max: u64,
+}
+
+impl LatencyTimeSeries {
+    fn add_sample(&mut self, time: u64, latency: u64) {
+        if latency > self.max {
+            self.max = latency;
+        }
+        // the samples of the same second are merged into the worst one
+        if let Some(last) = self.samples.back_mut() {
+            if last.time == time {
+                if latency > last.latency {
+                    last.latency = latency;
+                }
+                return;
+            }
+        }
+        if self.samples.len() == LATENCY_TS_LEN {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(LatencySample { time, latency });
+    }
+}
+
+pub type LatencyEvents = HashMap<&'static str, LatencyTimeSeries>;
+
+// Records a latency spike of `event` when it reaches the threshold. The
+// events are "fork", "expire-cycle", "eviction-cycle" and "fsync".
+pub fn latency_add_sample_if_needed(server: &mut Server, event: &'static str, duration: Duration) {
+    let threshold = server.latency_monitor_threshold;
+    let latency = duration.as_millis() as u64;
+    if threshold == 0 || latency < threshold as u64 {
+        return;
+    }
+    let now = unix_timestamp(&SystemTime::now()) / 1000;
+    server.latency_events
+        .entry(event)
+        .or_default()
+        .add_sample(now, latency);
+}
+
+pub fn latency_command(
+    client: &mut Client,
+    server: &mut Server,
+    _el: &mut AeEventLoop,
+) {
+    let sub = client.argv[1].borrow().string_to_vec();
+    let event = client.argv.get(2)
+        .map(|e| String::from_utf8_lossy(e.borrow().string()).to_string());
+    if case_eq(b"latest", &sub) && client.argc() == 2 {
+        latency_latest_reply(client, server);
+    } else if case_eq(b"history", &sub) && client.argc() == 3 {
+        let samples: Vec<LatencySample> = server.latency_events
+            .get(&event.unwrap()[..])
+            .map(|ts| ts.samples.iter().cloned().collect())
+            .unwrap_or_default();
+        client.add_reply_array_len(samples.len());
+        for s in samples {
+            client.add_reply_array_len(2);
+            client.add_reply_integer(s.time as i64);
+            client.add_reply_integer(s.latency as i64);
+        }
+    } else if case_eq(b"reset", &sub) {
+        let resets = if client.argc() == 2 {
+            let n = server.latency_events.len();
+            server.latency_events.clear();
+            n
+        } else {
+            client.argv[2..].iter()
+                .filter(|e| {
+                    let e = String::from_utf8_lossy(e.borrow().string()).to_string();
+                    server.latency_events.remove(&e[..]).is_some()
+                })
+                .count()
+        };
+        client.add_reply_integer(resets as i64);
+    } else if case_eq(b"graph", &sub) && client.argc() == 3 {
+        let event = event.unwrap();
+        match server.latency_events.get(&event[..]) {
+            None => client.add_reply_error(&format!(
+                "No samples available for event '{}'", event,
+            )),
+            Some(ts) => {
+                let now = unix_timestamp(&SystemTime::now()) / 1000;
+                let graph = latency_graph(&event, ts, now);
+                client.add_reply_verbatim(&graph, "txt");
+            }
+        }
+    } else if case_eq(b"doctor", &sub) && client.argc() == 2 {
+        let report = latency_doctor_report(server);
+        client.add_reply_verbatim(&report, "txt");
+    } else {
+        client.add_reply_error(&format!(
+            "Unknown LATENCY subcommand or wrong number of arguments for '{}'",
+            String::from_utf8_lossy(&sub),
+        ));
+    }
+}
+
+fn sorted_events(events: &LatencyEvents) -> Vec<(&&'static str, &LatencyTimeSeries)> {
+    let mut events: Vec<(&&'static str, &LatencyTimeSeries)> = events.iter()
+        .filter(|(_, ts)| !ts.samples.is_empty())
+        .collect();
+    events.sort_by_key(|(name, _)| **name);
+    events
+}
+
+fn latency_latest_reply(client: &mut Client, server: &Server) {
+    let events = sorted_events(&server.latency_events);
+    client.add_reply_array_len(events.len());
+    for (name, ts) in events {
+        let last = ts.samples.back().unwrap();
+        client.add_reply_array_len(4);
+        client.add_reply_bulk_bytes(name.as_bytes());
+        client.add_reply_integer(last.time as i64);
+        client.add_reply_integer(last.latency as i64);
+        client.add_reply_integer(ts.max as i64);
+    }
+}
+
+// "15s", "3m", "2h" or "1d"
+fn time_ago_label(secs: u64) -> String {
+    if secs < 60 {
+        format!("{}s", secs)
+    } else if secs < 3600 {
+        format!("{}m", secs / 60)
+    } else if secs < 3600 * 24 {
+        format!("{}h", secs / 3600)
+    } else {
+        format!("{}d", secs / (3600 * 24))
+    }
+}
+
+// An ASCII chart of the last samples, one column per sample with the
+// time elapsed since it printed vertically below.
+fn latency_graph(event: &str, ts: &LatencyTimeSeries, now: u64) -> String {
+    let skip = ts.samples.len().saturating_sub(GRAPH_COLUMNS);
+    let samples: Vec<&LatencySample> = ts.samples.iter().skip(skip).collect();
+    let max = samples.iter().map(|s| s.latency).max().unwrap_or(0);
+    let min = samples.iter().map(|s| s.latency).min().unwrap_or(0);
+
+    let mut graph = String::new();
+    let _ = writeln!(
+        graph, "{} - high {} ms, low {} ms (all time high {} ms)", event, max, min, ts.max,
+    );
+    graph.push_str(&"-".repeat(GRAPH_COLUMNS));
+    graph.push('\n');
+
+    // the height of a column in half rows, a '_' is half of a '|'
+    let heights: Vec<usize> = samples.iter()
+        .map(|s| {
+            if max == min {
+                GRAPH_ROWS * 2
+            } else {
+                1 + ((s.latency - min) * (GRAPH_ROWS as u64 * 2 - 1) / (max - min)) as usize
+            }
+        })
+        .collect();
+    for row in (0..GRAPH_ROWS).rev() {
+        let line: String = heights.iter()
+            .map(|&h| {
+                if h > row * 2 + 1 {
+                    '|'
+                } else if h == row * 2 + 1 {
+                    '_'
+                } else {
+                    ' '
+                }
+            })
+            .collect();
+        graph.push_str(line.trim_end());
+        graph.push('\n');
+    }
+    graph.push('\n');
+
+    let labels: Vec<Vec<char>> = samples.iter()
+        .map(|s| time_ago_label(now.saturating_sub(s.time)).chars().collect())
+        .collect();
+    let label_len = labels.iter().map(|l| l.len()).max().unwrap_or(0);
+    for i in 0..label_len {
+        let line: String = labels.iter()
+            .map(|l| l.get(i).cloned().unwrap_or(' '))
+            .collect();
+        graph.push_str(line.trim_end());
+        graph.push('\n');
+    }
+    graph
+}
+
+fn event_advice(event: &str) -> &'static str {
+    match event {
+        "fork" => "the background saving forks the server and fork gets slower as the \
+            dataset grows. Consider saving less often, a smaller dataset or a system \
+            where fork is faster.",
+        "expire-cycle" => "many keys expire at the same time. Consider spreading the \
+            expire times of the keys.",
+        "eviction-cycle" => "the server is evicting keys to stay under maxmemory. \
+            Consider a higher maxmemory or using less memory.",
+        "fsync" => "the disk is slow to flush the dump file. Check the disk and the \
+            other processes using it.",
+        _ => "no advice available for this event.",
+    }
+}
+
+fn latency_doctor_report(server: &Server) -> String {
+    let events = sorted_events(&server.latency_events);
+    let mut report = String::new();
+    if events.is_empty() {
+        if server.latency_monitor_threshold == 0 {
+            report.push_str(
+                "I'm sorry, Dave, I can't do that. Latency monitoring is disabled in this \
+                server. You may use \"CONFIG SET latency-monitor-threshold <milliseconds>.\" \
+                in order to enable it.\n"
+            );
+        } else {
+            report.push_str(
+                "Dave, no latency spike was observed during the lifetime of this server, \
+                not in the slightest bit. I honestly think you ought to sleep better.\n"
+            );
+        }
+        return report;
+    }
+
+    report.push_str(
+        "Dave, I have observed latency spikes in this server. \
+        You don't mind talking about it, do you Dave?\n\n"
+    );
+    for (i, (name, ts)) in events.iter().enumerate() {
+        let n = ts.samples.len() as u64;
+        let avg = ts.samples.iter().map(|s| s.latency).sum::<u64>() / n;
+        let mad = ts.samples.iter()
+            .map(|s| (s.latency as i64 - avg as i64).unsigned_abs())
+            .sum::<u64>() / n;
+        let period = if n > 1 {
+            (ts.samples.back().unwrap().time - ts.samples.front().unwrap().time) / (n - 1)
+        } else {
+            0
+        };
+        let _ = writeln!(
+            report,
+            "{}. {}: {} latency spikes (average {}ms, mean deviation {}ms, period {} sec). \
+            Worst all time event {}ms.",
+            i + 1, name, n, avg, mad, period, ts.max,
+        );
+    }
+
+    report.push_str("\nI have a few advices for you:\n\n");
+    for (name, _) in events.iter() {
+        let _ = writeln!(report, "- {}: {}", name, event_advice(name));
+    }
+    report
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn merge_samples_of_the_same_second() {
+        let mut ts = LatencyTimeSeries::default();
+        ts.add_sample(10, 5);
+        ts.add_sample(10, 7);
+        ts.add_sample(10, 3);
+        ts.add_sample(11, 2);
+        assert_eq!(ts.samples.len(), 2);
+        assert_eq!(ts.samples[0].latency, 7);
+        assert_eq!(ts.max, 7);
+
+        for i in 0..LATENCY_TS_LEN as u64 {
+            ts.add_sample(100 + i, 1);
+        }
+        assert_eq!(ts.samples.len(), LATENCY_TS_LEN);
+        assert_eq!(ts.samples[0].time, 100);
+        assert_eq!(ts.max, 7);
+    }
+
+    #[test]
+    fn graph() {
+        let mut ts = LatencyTimeSeries::default();
+        ts.add_sample(100, 10);
+        ts.add_sample(130, 20);
+        ts.add_sample(170, 80);
+        let graph = latency_graph("fork", &ts, 200);
+        let lines: Vec<&str> = graph.lines().collect();
+        assert_eq!(lines[0], "fork - high 80 ms, low 10 ms (all time high 80 ms)");
+        assert_eq!(lines[2], "  |");
+        assert_eq!(lines[5], "_||");
+        assert_eq!(lines[7], "113");
+        assert_eq!(lines[8], "mm0");
+        assert_eq!(lines[9], "  s");
+    }
+}
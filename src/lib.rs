@@ -23,3 +23,15 @@ pub mod sort;
 pub mod rdb;
 pub mod replicate;
 pub mod lua;
+pub mod multi;
+pub mod pubsub;
+pub mod notify;
+pub mod tracking;
+pub mod config;
+pub mod info;
+pub mod slowlog;
+pub mod latency;
+pub mod memory;
+pub mod debug;
+pub mod acl;
+pub mod reply;
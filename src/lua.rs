@@ -1,8 +1,7 @@
 use crate::object::{RobjPtr, Robj};
+use crate::client::Client;
 use rlua::{ToLua, Context, Value, Error, FromLua};
 use std::ffi::CString;
-use std::collections::HashMap;
-use crate::util::{int_reply_to_int, bulk_reply_to_int, multi_bulk_reply_to_int};
 
 #[derive(Clone)]
 pub struct LuaRobj(RobjPtr);
@@ -68,49 +67,75 @@ impl<'lua> FromLua<'lua> for RobjFromLua {
 
 pub enum LuaRedis {
     Integer(i64),
-    Bulk(CString),
+    Bulk(Vec<u8>),
     MultiBulk(Vec<LuaRedis>),
-    Status(CString),
-    Error(CString),
+    Status(Vec<u8>),
+    Error(Vec<u8>),
     Nil,
 }
 
-impl LuaRedis {
-    pub fn new(reply: &[RobjPtr]) -> Self {
-        let first = reply[0].borrow().string().to_vec();
-        if first[0] == b'+' {
-            return Self::Status(CString::new(first[1..first.len() - 2].to_vec()).unwrap());
-        }
-        if first[0] == b'-' {
-            return Self::Error(CString::new(first[1..first.len() - 2].to_vec()).unwrap());
-        }
-        if first[0] == b'$' {
-            assert!(reply.len() > 1);
-            if bulk_reply_to_int(&first) == -1 {
-                return Self::Nil;
+// The replies of the commands called by a script, collected as values
+// instead of being encoded. Maps, sets and doubles arrive in their RESP2
+// shape, as the script client never negotiates RESP3.
+#[derive(Default)]
+pub struct LuaReplyBuilder {
+    done: Vec<LuaRedis>,
+    // the arrays being filled, with their length unless it is deferred
+    open: Vec<(Option<usize>, Vec<LuaRedis>)>,
+}
+
+impl LuaReplyBuilder {
+    pub fn push(&mut self, mut value: LuaRedis) {
+        loop {
+            match self.open.last_mut() {
+                None => {
+                    self.done.push(value);
+                    return;
+                }
+                Some((len, items)) => {
+                    items.push(value);
+                    if *len != Some(items.len()) {
+                        return;
+                    }
+                }
             }
-            return Self::Bulk(CString::new(reply[1].borrow().string().to_vec()).unwrap());
+            let (_, items) = self.open.pop().unwrap();
+            value = LuaRedis::MultiBulk(items);
         }
-        if first[0] == b':' {
-            return Self::Integer(int_reply_to_int(&first));
+    }
+
+    pub fn open_array(&mut self, len: usize) {
+        if len == 0 {
+            self.push(LuaRedis::MultiBulk(vec![]));
+        } else {
+            self.open.push((Some(len), vec![]));
         }
-        if first[0] == b'*' {
-            if multi_bulk_reply_to_int(&first) == -1 {
-                return Self::Nil;
-            }
-            let mut v = vec![];
-            let mut i = 1;
-            while i < reply.len() {
-                let o = LuaRedis::new(&reply[i..]);
-                v.push(o);
-                if reply[i].borrow().string()[0] == b'$' {
-                    i += 2;
-                }
-                i += 1;
-            }
-            return LuaRedis::MultiBulk(v);
+    }
+
+    // returns the depth of the array, to set its length later
+    pub fn open_deferred(&mut self) -> usize {
+        self.open.push((None, vec![]));
+        self.open.len() - 1
+    }
+
+    pub fn set_deferred_len(&mut self, depth: usize, len: usize) {
+        if depth >= self.open.len() {
+            return;
         }
-        LuaRedis::Nil
+        self.open[depth].0 = Some(len);
+        // the elements are usually all there once the length is known
+        if depth == self.open.len() - 1 && self.open[depth].1.len() == len {
+            let (_, items) = self.open.pop().unwrap();
+            self.push(LuaRedis::MultiBulk(items));
+        }
+    }
+
+    // the reply of the last command, a command replies exactly once
+    pub fn take(&mut self) -> LuaRedis {
+        self.open.clear();
+        let reply = self.done.pop().unwrap_or(LuaRedis::Nil);
+        self.done.clear();
+        reply
     }
 }
 
@@ -118,17 +143,17 @@ impl<'lua> ToLua<'lua> for LuaRedis {
     fn to_lua(self, lua: Context<'lua>) -> Result<Value<'lua>, Error> {
         match self {
             Self::Integer(i) => i.to_lua(lua),
-            Self::Bulk(s) => s.to_lua(lua),
+            Self::Bulk(s) => lua.create_string(&s)?.to_lua(lua),
             Self::MultiBulk(v) => v.to_lua(lua),
             Self::Status(s) => {
-                let mut map: HashMap<String, CString> = HashMap::new();
-                map.insert("ok".to_string(), s).unwrap();
-                map.to_lua(lua)
+                let table = lua.create_table()?;
+                table.set("ok", lua.create_string(&s)?)?;
+                table.to_lua(lua)
             }
             Self::Error(s) => {
-                let mut map: HashMap<String, CString> = HashMap::new();
-                map.insert("ok".to_string(), s).unwrap();
-                map.to_lua(lua)
+                let table = lua.create_table()?;
+                table.set("err", lua.create_string(&s)?)?;
+                table.to_lua(lua)
             }
             Self::Nil => {
                 false.to_lua(lua)
@@ -142,13 +167,14 @@ impl<'lua> FromLua<'lua> for LuaRedis {
         let r = match lua_value {
             Value::Integer(i) => LuaRedis::Integer(i),
             Value::Number(n) => LuaRedis::Integer(n as i64),
+            Value::String(s) => LuaRedis::Bulk(s.as_bytes().to_vec()),
             Value::Table(t) => {
                 if t.contains_key("ok")? {
-                    let status: CString = t.get("ok")?;
-                    LuaRedis::Status(status)
+                    let status: rlua::String = t.get("ok")?;
+                    LuaRedis::Status(status.as_bytes().to_vec())
                 } else if t.contains_key("err")? {
-                    let error: CString = t.get("err")?;
-                    LuaRedis::Error(error)
+                    let error: rlua::String = t.get("err")?;
+                    LuaRedis::Error(error.as_bytes().to_vec())
                 } else {
                     let len = t.len()?;
                     let mut v = Vec::with_capacity(len as usize);
@@ -198,9 +224,83 @@ end
 
 -- running code begin
 BACK_TO_RUST = eval_outer()
-if BACK_TO_RUST ~= nil then
-    print(BACK_TO_RUST)
-end
--- running code en
+-- running code end
 "#;
 
+// the script runs in a function, so its return value can be sent back
+pub fn wrap_script(body: &[u8]) -> Vec<u8> {
+    let mut script = BOILERPLATE_BEGIN.to_vec();
+    script.extend_from_slice(body);
+    script.extend_from_slice(BOILERPLATE_END);
+    script
+}
+
+// Replies with the value returned by a script
+pub fn add_lua_reply(client: &mut Client, reply: LuaRedis) {
+    match reply {
+        LuaRedis::Integer(i) => client.add_reply_integer(i),
+        LuaRedis::Bulk(b) => client.add_reply_bulk_bytes(&b),
+        LuaRedis::MultiBulk(v) => {
+            client.add_reply_array_len(v.len());
+            for r in v {
+                add_lua_reply(client, r);
+            }
+        }
+        LuaRedis::Status(s) => client.add_reply_status(&String::from_utf8_lossy(&s)),
+        LuaRedis::Error(e) => {
+            let e = String::from_utf8_lossy(&e);
+            match e.split_once(' ') {
+                Some((code, msg)) if code.bytes().all(|b| b.is_ascii_uppercase()) =>
+                    client.add_reply_error_code(code, msg),
+                _ => client.add_reply_error(&e),
+            }
+        }
+        LuaRedis::Nil => client.add_reply_null(),
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn integers(reply: &LuaRedis) -> Vec<i64> {
+        match reply {
+            LuaRedis::MultiBulk(v) => v.iter().flat_map(integers).collect(),
+            LuaRedis::Integer(i) => vec![*i],
+            _ => vec![],
+        }
+    }
+
+    #[test]
+    fn build_nested_replies() {
+        let mut builder = LuaReplyBuilder::default();
+        builder.open_array(2);
+        builder.open_array(2);
+        builder.push(LuaRedis::Integer(1));
+        builder.push(LuaRedis::Integer(2));
+        builder.open_array(0);
+        let reply = builder.take();
+        assert_eq!(integers(&reply), vec![1, 2]);
+        match reply {
+            LuaRedis::MultiBulk(v) => assert_eq!(v.len(), 2),
+            _ => panic!("not an array"),
+        }
+    }
+
+    #[test]
+    fn build_deferred_replies() {
+        let mut builder = LuaReplyBuilder::default();
+        let outer = builder.open_deferred();
+        for i in 0..3 {
+            builder.open_array(1);
+            builder.push(LuaRedis::Integer(i));
+        }
+        builder.set_deferred_len(outer, 3);
+        let reply = builder.take();
+        assert_eq!(integers(&reply), vec![0, 1, 2]);
+
+        builder.push(LuaRedis::Nil);
+        assert!(matches!(builder.take(), LuaRedis::Nil));
+    }
+}
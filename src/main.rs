@@ -24,9 +24,10 @@ fn main() {
             /path/to/reredis.conf'");
     }
 
+    // the logger is up before binding, which may warn about the addresses
+    init_logger(config.log_level);
     let mut env = Env::new(&config);
     env.init_server();
-    init_logger(env.server.verbosity);
 
     if env.server.daemonize {
         env.daemonize();
@@ -42,7 +43,12 @@ fn main() {
         oom("creating file event");
     }
 
-    info!("The server is now ready to accept connections on port {}", env.server.port);
+    if !env.server.ipfd.is_empty() {
+        info!("The server is now ready to accept connections on port {}", env.server.port);
+    }
+    if let Some(path) = &env.server.unix_socket {
+        info!("The server is now ready to accept connections at {}", path);
+    }
 
     env.ae_main();
 }
\ No newline at end of file
@@ -0,0 +1,298 @@
+use crate::client::{Client, CLIENT_SLAVE};
+use crate::server::Server;
+use crate::ae::AeEventLoop;
+use crate::zalloc;
+use crate::reply::REPLY_BUF_LEN;
+use crate::util::*;
+
+// elements of an aggregated value measured by MEMORY USAGE by default
+const MEMORY_USAGE_DEFAULT_SAMPLES: usize = 5;
+
+// Where the memory goes. The overhead is what is used by the server
+// itself, the rest of the allocated memory is the dataset.
+pub struct MemoryOverhead {
+    pub peak_allocated: usize,
+    pub total_allocated: usize,
+    pub startup_allocated: usize,
+    pub repl_backlog: usize,
+    pub clients_slaves: usize,
+    pub clients_normal: usize,
+    pub lua_caches: usize,
+    // id, main dict and expires dict of the databases with keys
+    pub db: Vec<(usize, usize, usize)>,
+    pub overhead_total: usize,
+    pub dataset: usize,
+    pub total_keys: usize,
+    pub bytes_per_key: usize,
+    pub dataset_perc: f64,
+    pub peak_perc: f64,
+    pub num_slaves: usize,
+    pub num_normal_clients: usize,
+}
+
+// the query buffer and the output buffer of a client
+pub fn client_buffers_size(c: &Client) -> usize {
+    c.query_buf.capacity() + REPLY_BUF_LEN + c.reply.memory()
+}
+
+pub fn get_memory_overhead_data(server: &mut Server, current: &Client) -> MemoryOverhead {
+    server.update_memory_stats();
+    let total_allocated = zalloc::allocated_memory();
+    let startup_allocated = server.initial_memory_usage;
+
+    let mut clients_slaves = 0;
+    let mut clients_normal = 0;
+    let mut num_slaves = 0;
+    let mut num_normal_clients = 0;
+    for c in server.clients.iter() {
+        // the client running the command is borrowed already
+        let (flags, size) = match c.try_borrow() {
+            Ok(c) => (c.flags, client_buffers_size(&c)),
+            Err(_) => (current.flags, client_buffers_size(current)),
+        };
+        if flags & CLIENT_SLAVE != 0 {
+            clients_slaves += size;
+            num_slaves += 1;
+        } else {
+            clients_normal += size;
+            num_normal_clients += 1;
+        }
+    }
+
+    let lua_caches = server.lua.try_borrow().map_or(0, |lua| lua.used_memory());
+
+    let mut db = vec![];
+    let mut total_keys = 0;
+    let mut overhead_total = startup_allocated + clients_slaves + clients_normal + lua_caches;
+    for d in server.db.iter() {
+        let keys = d.dict.len();
+        if keys == 0 {
+            continue;
+        }
+        let main = d.dict.mem_usage();
+        let expires = d.expires.mem_usage();
+        overhead_total += main + expires;
+        total_keys += keys;
+        db.push((d.id, main, expires));
+    }
+
+    let dataset = total_allocated.saturating_sub(overhead_total);
+    let net_usage = total_allocated.saturating_sub(startup_allocated);
+    let bytes_per_key = net_usage.checked_div(total_keys).unwrap_or(0);
+    let dataset_perc = if net_usage > 0 {
+        dataset as f64 * 100.0 / net_usage as f64
+    } else {
+        0.0
+    };
+    let peak_perc = if server.stat_peak_memory > 0 {
+        total_allocated as f64 * 100.0 / server.stat_peak_memory as f64
+    } else {
+        0.0
+    };
+
+    MemoryOverhead {
+        peak_allocated: server.stat_peak_memory,
+        total_allocated,
+        startup_allocated,
+        // there is no replication backlog, slaves always resync from a dump
+        repl_backlog: 0,
+        clients_slaves,
+        clients_normal,
+        lua_caches,
+        db,
+        overhead_total,
+        dataset,
+        total_keys,
+        bytes_per_key,
+        dataset_perc,
+        peak_perc,
+        num_slaves,
+        num_normal_clients,
+    }
+}
+
+pub fn memory_command(
+    client: &mut Client,
+    server: &mut Server,
+    _el: &mut AeEventLoop,
+) {
+    let sub = client.argv[1].borrow().string_to_vec();
+    if case_eq(b"usage", &sub) && client.argc() >= 3 {
+        memory_usage_command(client, server);
+    } else if case_eq(b"stats", &sub) && client.argc() == 2 {
+        let mh = get_memory_overhead_data(server, client);
+        memory_stats_reply(client, &mh);
+    } else if case_eq(b"doctor", &sub) && client.argc() == 2 {
+        let mh = get_memory_overhead_data(server, client);
+        let report = memory_doctor_report(&mh);
+        client.add_reply_verbatim(&report, "txt");
+    } else {
+        client.add_reply_error(&format!(
+            "Unknown MEMORY subcommand or wrong number of arguments for '{}'",
+            String::from_utf8_lossy(&sub),
+        ));
+    }
+}
+
+// MEMORY USAGE key [SAMPLES count]
+fn memory_usage_command(client: &mut Client, server: &Server) {
+    let mut samples = MEMORY_USAGE_DEFAULT_SAMPLES;
+    let mut i = 3;
+    while i < client.argc() {
+        let arg = client.argv[i].borrow().string_to_vec();
+        if case_eq(b"samples", &arg) && i + 1 < client.argc() {
+            let r = bytes_to_i64(client.argv[i + 1].borrow().string());
+            samples = match r {
+                Ok(n) if n >= 0 => n as usize,
+                _ => {
+                    client.add_reply_error("value is out of range, must be positive");
+                    return;
+                }
+            };
+            i += 2;
+        } else {
+            client.add_reply_error("syntax error");
+            return;
+        }
+    }
+
+    let size = server.db[client.db_idx].dict.find(&client.argv[2])
+        .map(|(k, v)| k.borrow().compute_size(0) + v.borrow().compute_size(samples));
+    match size {
+        Some(size) => client.add_reply_integer(size as i64),
+        None => client.add_reply_null(),
+    }
+}
+
+fn memory_stats_reply(client: &mut Client, mh: &MemoryOverhead) {
+    let len = client.add_reply_deferred_len();
+    let mut fields = 0;
+    let add_int_fields = |client: &mut Client, ints: &[(&str, usize)]| {
+        for (name, n) in ints {
+            client.add_reply_bulk_bytes(name.as_bytes());
+            client.add_reply_integer(*n as i64);
+        }
+        ints.len()
+    };
+
+    fields += add_int_fields(client, &[
+        ("peak.allocated", mh.peak_allocated),
+        ("total.allocated", mh.total_allocated),
+        ("startup.allocated", mh.startup_allocated),
+        ("replication.backlog", mh.repl_backlog),
+        ("clients.slaves", mh.clients_slaves),
+        ("clients.normal", mh.clients_normal),
+        ("lua.caches", mh.lua_caches),
+    ]);
+    for (id, main, expires) in mh.db.iter() {
+        client.add_reply_bulk_bytes(format!("db.{}", id).as_bytes());
+        client.add_reply_map_len(2);
+        client.add_reply_bulk_bytes(b"overhead.hashtable.main");
+        client.add_reply_integer(*main as i64);
+        client.add_reply_bulk_bytes(b"overhead.hashtable.expires");
+        client.add_reply_integer(*expires as i64);
+        fields += 1;
+    }
+    fields += add_int_fields(client, &[
+        ("overhead.total", mh.overhead_total),
+        ("keys.count", mh.total_keys),
+        ("keys.bytes-per-key", mh.bytes_per_key),
+        ("dataset.bytes", mh.dataset),
+    ]);
+    // percentages, with two decimals
+    for (name, f) in [("dataset.percentage", mh.dataset_perc), ("peak.percentage", mh.peak_perc)] {
+        client.add_reply_bulk_bytes(name.as_bytes());
+        client.add_reply_double((f * 100.0).round() / 100.0);
+        fields += 1;
+    }
+
+    client.set_deferred_map_len(len, fields);
+}
+
+pub fn memory_doctor_report(mh: &MemoryOverhead) -> String {
+    // below this there is too little data to tell anything
+    if mh.total_allocated < 1024 * 1024 * 5 {
+        return "Hi Sam, this instance is empty or is using very little memory, \
+            my issues detector can't be used in these conditions. \
+            Please, leave for your mission on Earth and fill it with some data. \
+            The new Sam and I will be back to our programming as soon as I \
+            finished rebooting.\n".to_string();
+    }
+
+    let mut issues = vec![];
+    if mh.peak_allocated > mh.total_allocated &&
+        mh.peak_allocated as f64 / mh.total_allocated as f64 > 1.5 {
+        issues.push(
+            " * Peak memory: In the past this instance used more than 150% the memory \
+            that is currently using. The allocator is normally not able to release \
+            memory after a peak, so you can expect to see a big fragmentation ratio, \
+            however this is actually harmless and is only due to the memory peak, and \
+            if the server will use again the same memory it will reuse what it did \
+            not release.\n"
+        );
+    }
+    if mh.num_normal_clients > 0 &&
+        mh.clients_normal / mh.num_normal_clients > 1024 * 200 {
+        issues.push(
+            " * Big client buffers: The clients output buffers are in general very big, \
+            that's probably because some client is reading the replies of big commands, \
+            like KEYS, LRANGE or SMEMBERS on big values, more slowly than the server \
+            produces them.\n"
+        );
+    }
+    if mh.num_slaves > 0 &&
+        mh.clients_slaves / mh.num_slaves > 1024 * 1024 * 10 {
+        issues.push(
+            " * Big slave buffers: The slave output buffers in this instance are greater \
+            than 10MB for each slave (on average). This likely means that there is some \
+            slave instance that is struggling receiving data, either because it is too \
+            slow or because of networking issues.\n"
+        );
+    }
+
+    if issues.is_empty() {
+        return "Hi Sam, I can't find any memory issue in your instance. \
+            I can only account for what occurs on this base.\n".to_string();
+    }
+    let mut report = "Sam, I detected a few issues in this instance memory implants:\n\n".to_string();
+    for issue in issues {
+        report.push_str(issue);
+        report.push('\n');
+    }
+    report.push_str("I'm here to keep you safe, Sam. I want to help you.\n");
+    report
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn overhead(total: usize, peak: usize) -> MemoryOverhead {
+        MemoryOverhead {
+            peak_allocated: peak,
+            total_allocated: total,
+            startup_allocated: 0,
+            repl_backlog: 0,
+            clients_slaves: 0,
+            clients_normal: 0,
+            lua_caches: 0,
+            db: vec![],
+            overhead_total: 0,
+            dataset: total,
+            total_keys: 0,
+            bytes_per_key: 0,
+            dataset_perc: 100.0,
+            peak_perc: 100.0,
+            num_slaves: 0,
+            num_normal_clients: 0,
+        }
+    }
+
+    #[test]
+    fn doctor() {
+        assert!(memory_doctor_report(&overhead(1024, 1024)).contains("empty"));
+        let mb = 1024 * 1024;
+        assert!(memory_doctor_report(&overhead(10 * mb, 10 * mb)).contains("can't find any"));
+        assert!(memory_doctor_report(&overhead(10 * mb, 20 * mb)).contains("Peak memory"));
+    }
+}
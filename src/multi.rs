@@ -0,0 +1,179 @@
+use crate::client::{Client, CLIENT_MULTI, CLIENT_DIRTY_EXEC};
+use crate::server::Server;
+use crate::ae::AeEventLoop;
+use crate::command::Command;
+use crate::db::DB;
+use crate::object::{Robj, RobjPtr};
+use crate::replicate;
+use crate::tracking::tracking_remember_keys;
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::mem::take;
+
+pub struct MultiCmd {
+    pub argv: Vec<RobjPtr>,
+    pub cmd: &'static Command,
+}
+
+pub struct WatchedKey {
+    pub db_idx: usize,
+    pub key: RobjPtr,
+    pub version: usize,
+}
+
+pub fn queue_multi_command(client: &mut Client, cmd: &'static Command) {
+    let argv = take(&mut client.argv);
+    client.mstate.push(MultiCmd { argv, cmd });
+    client.add_reply_status("QUEUED");
+}
+
+// Errors found while queueing make the following EXEC fail
+pub fn flag_transaction(client: &mut Client) {
+    if client.flags & CLIENT_MULTI != 0 {
+        client.flags |= CLIENT_DIRTY_EXEC;
+    }
+}
+
+pub fn unwatch_all_keys(dbs: &mut [DB], client: &Client) {
+    for w in client.watched_keys.iter() {
+        dbs[w.db_idx].unwatch_key(&w.key);
+    }
+}
+
+fn discard_transaction(client: &mut Client, server: &mut Server) {
+    client.mstate.clear();
+    client.flags &= !(CLIENT_MULTI | CLIENT_DIRTY_EXEC);
+    unwatch_all_keys(&mut server.db, client);
+    client.watched_keys.clear();
+}
+
+fn is_watched_key_touched(client: &Client, server: &mut Server) -> bool {
+    client.watched_keys.iter().any(|w| {
+        let db = &mut server.db[w.db_idx];
+        // a watched key expiring before EXEC aborts the transaction as well
+        let _ = db.expire_if_needed(&w.key);
+        db.watched_key_version(&w.key) != Some(w.version)
+    })
+}
+
+pub fn multi_command(
+    client: &mut Client,
+    _server: &mut Server,
+    _el: &mut AeEventLoop,
+) {
+    if client.flags & CLIENT_MULTI != 0 {
+        client.add_reply_error("MULTI calls can not be nested");
+        return;
+    }
+    client.flags |= CLIENT_MULTI;
+    client.add_reply_ok();
+}
+
+pub fn discard_command(
+    client: &mut Client,
+    server: &mut Server,
+    _el: &mut AeEventLoop,
+) {
+    if client.flags & CLIENT_MULTI == 0 {
+        client.add_reply_error("DISCARD without MULTI");
+        return;
+    }
+    discard_transaction(client, server);
+    client.add_reply_ok();
+}
+
+pub fn exec_command(
+    client: &mut Client,
+    server: &mut Server,
+    el: &mut AeEventLoop,
+) {
+    if client.flags & CLIENT_MULTI == 0 {
+        client.add_reply_error("EXEC without MULTI");
+        return;
+    }
+
+    if client.flags & CLIENT_DIRTY_EXEC != 0 {
+        client.add_reply_error_code("EXECABORT", "Transaction discarded \
+                because of previous errors.");
+        discard_transaction(client, server);
+        return;
+    }
+
+    if is_watched_key_touched(client, server) {
+        client.add_reply_null_array();
+        discard_transaction(client, server);
+        return;
+    }
+
+    let commands = take(&mut client.mstate);
+    let exec_argv = take(&mut client.argv);
+    discard_transaction(client, server);
+
+    client.add_reply_array_len(commands.len());
+
+    // slaves receive MULTI right before the first command that changes
+    // the dataset, the EXEC is fed by process_command as the dirty
+    // counter grew during the transaction
+    let mut multi_propagated = false;
+    for MultiCmd { argv, cmd } in commands {
+        client.argv = argv;
+        let dirty = server.dirty;
+        (cmd.proc)(client, server, el);
+        tracking_remember_keys(server, client, cmd);
+        if !multi_propagated && dirty < server.dirty {
+            propagate_multi(client, server, el);
+            multi_propagated = true;
+        }
+        client.propagate(server, el, dirty);
+    }
+
+    client.argv = exec_argv;
+}
+
+fn propagate_multi(client: &mut Client, server: &mut Server, el: &mut AeEventLoop) {
+    if server.slaves.is_empty() {
+        return;
+    }
+    let argv = take(&mut client.argv);
+    client.argv.push(Robj::create_string_object("MULTI"));
+    replicate::feed_slaves(el, client, &server.slaves, client.db_idx);
+    let slaves: Vec<Rc<RefCell<Client>>> = server.slaves.iter().map(Rc::clone).collect();
+    server.close_clients_on_output_buffer_limit_reached(client, &slaves);
+    client.argv = argv;
+}
+
+pub fn watch_command(
+    client: &mut Client,
+    server: &mut Server,
+    _el: &mut AeEventLoop,
+) {
+    if client.flags & CLIENT_MULTI != 0 {
+        client.add_reply_error("WATCH inside MULTI is not allowed");
+        return;
+    }
+
+    let db_idx = client.db_idx;
+    let db = &mut server.db[db_idx];
+    for key in client.argv.iter().skip(1) {
+        let watched = client.watched_keys.iter().any(|w| {
+            w.db_idx == db_idx && w.key.borrow().string_cmp(key) == std::cmp::Ordering::Equal
+        });
+        if watched {
+            continue;
+        }
+        let _ = db.expire_if_needed(key);
+        let version = db.watch_key(key);
+        client.watched_keys.push(WatchedKey { db_idx, key: Rc::clone(key), version });
+    }
+    client.add_reply_ok();
+}
+
+pub fn unwatch_command(
+    client: &mut Client,
+    server: &mut Server,
+    _el: &mut AeEventLoop,
+) {
+    unwatch_all_keys(&mut server.db, client);
+    client.watched_keys.clear();
+    client.add_reply_ok();
+}
@@ -0,0 +1,147 @@
+use crate::client::Client;
+use crate::server::Server;
+use crate::ae::AeEventLoop;
+use crate::object::{Robj, RobjPtr};
+use crate::pubsub::publish_message;
+use std::mem::take;
+
+// Keyspace event classes, see `notify-keyspace-events` in example.conf
+pub const NOTIFY_KEYSPACE: i32 = 1 << 0;
+pub const NOTIFY_KEYEVENT: i32 = 1 << 1;
+pub const NOTIFY_GENERIC: i32 = 1 << 2;
+pub const NOTIFY_STRING: i32 = 1 << 3;
+pub const NOTIFY_LIST: i32 = 1 << 4;
+pub const NOTIFY_SET: i32 = 1 << 5;
+pub const NOTIFY_HASH: i32 = 1 << 6;
+pub const NOTIFY_ZSET: i32 = 1 << 7;
+pub const NOTIFY_EXPIRED: i32 = 1 << 8;
+pub const NOTIFY_EVICTED: i32 = 1 << 9;
+pub const NOTIFY_STREAM: i32 = 1 << 10;
+pub const NOTIFY_KEY_MISS: i32 = 1 << 11;
+pub const NOTIFY_MODULE: i32 = 1 << 12;
+pub const NOTIFY_NEW: i32 = 1 << 13;
+pub const NOTIFY_ALL: i32 = NOTIFY_GENERIC | NOTIFY_STRING | NOTIFY_LIST |
+    NOTIFY_SET | NOTIFY_HASH | NOTIFY_ZSET | NOTIFY_EXPIRED | NOTIFY_EVICTED |
+    NOTIFY_STREAM | NOTIFY_MODULE;
+
+const CLASS_CHARS: [(char, i32); 13] = [
+    ('g', NOTIFY_GENERIC),
+    ('$', NOTIFY_STRING),
+    ('l', NOTIFY_LIST),
+    ('s', NOTIFY_SET),
+    ('h', NOTIFY_HASH),
+    ('z', NOTIFY_ZSET),
+    ('x', NOTIFY_EXPIRED),
+    ('e', NOTIFY_EVICTED),
+    ('t', NOTIFY_STREAM),
+    ('m', NOTIFY_KEY_MISS),
+    ('d', NOTIFY_MODULE),
+    ('n', NOTIFY_NEW),
+    ('K', NOTIFY_KEYSPACE),
+];
+
+pub struct KeyspaceEvent {
+    pub class: i32,
+    pub event: &'static str,
+    pub key: RobjPtr,
+    pub db: usize,
+}
+
+pub fn keyspace_events_from_str(s: &str) -> Option<i32> {
+    let mut flags: i32 = 0;
+    for ch in s.chars() {
+        flags |= match ch {
+            'A' => NOTIFY_ALL,
+            'E' => NOTIFY_KEYEVENT,
+            _ => CLASS_CHARS.iter()
+                .find(|(c, _)| *c == ch)
+                .map(|(_, f)| *f)?,
+        };
+    }
+    Some(flags)
+}
+
+pub fn keyspace_events_to_string(flags: i32) -> String {
+    let mut s = String::new();
+    if flags & NOTIFY_ALL == NOTIFY_ALL {
+        s.push('A');
+    }
+    for (ch, f) in CLASS_CHARS.iter() {
+        if *f == NOTIFY_KEYSPACE || (flags & NOTIFY_ALL == NOTIFY_ALL && f & NOTIFY_ALL != 0) {
+            continue;
+        }
+        if flags & f != 0 {
+            s.push(*ch);
+        }
+    }
+    if flags & NOTIFY_KEYSPACE != 0 {
+        s.push('K');
+    }
+    if flags & NOTIFY_KEYEVENT != 0 {
+        s.push('E');
+    }
+    s
+}
+
+// Publishes the events queued by commands, by key expiration and by
+// eviction. `current` is the client running a command, if any.
+pub fn flush_keyspace_events(
+    server: &mut Server,
+    el: &mut AeEventLoop,
+    mut current: Option<&mut Client>,
+) {
+    let flags = server.notify_keyspace_events;
+    for db in server.db.iter_mut() {
+        if db.expired_keys.is_empty() {
+            continue;
+        }
+        let expired = take(&mut db.expired_keys);
+        if flags & NOTIFY_EXPIRED == 0 {
+            continue;
+        }
+        for key in expired {
+            server.keyspace_events.push(KeyspaceEvent {
+                class: NOTIFY_EXPIRED,
+                event: "expired",
+                key,
+                db: db.id,
+            });
+        }
+    }
+
+    if server.keyspace_events.is_empty() {
+        return;
+    }
+
+    for e in take(&mut server.keyspace_events) {
+        let event = Robj::create_string_object(e.event);
+        if flags & NOTIFY_KEYSPACE != 0 {
+            let mut channel = format!("__keyspace@{}__:", e.db).into_bytes();
+            channel.extend_from_slice(&e.key.borrow().string_to_vec());
+            let channel = Robj::from_bytes(channel);
+            publish_message(server, el, current.as_deref_mut(), &channel, &event);
+        }
+        if flags & NOTIFY_KEYEVENT != 0 {
+            let channel = Robj::from_bytes(
+                format!("__keyevent@{}__:{}", e.db, e.event).into_bytes()
+            );
+            publish_message(server, el, current.as_deref_mut(), &channel, &e.key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_keyspace_events() {
+        assert_eq!(keyspace_events_from_str("").unwrap(), 0);
+        assert_eq!(keyspace_events_from_str("Ex").unwrap(), NOTIFY_KEYEVENT | NOTIFY_EXPIRED);
+        assert_eq!(keyspace_events_from_str("KA").unwrap(), NOTIFY_KEYSPACE | NOTIFY_ALL);
+        assert!(keyspace_events_from_str("Kw").is_none());
+
+        assert_eq!(keyspace_events_to_string(keyspace_events_from_str("lKg$").unwrap()), "g$lK");
+        assert_eq!(keyspace_events_to_string(keyspace_events_from_str("AKEm").unwrap()), "AmKE");
+    }
+}
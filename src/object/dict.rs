@@ -153,6 +153,14 @@ impl<K, V> Dict<K, V>
         self.ht[0].size + self.ht[1].size
     }
 
+    // the memory used by the tables and the entries, not counting
+    // what the keys and the values point to
+    pub fn mem_usage(&self) -> usize {
+        std::mem::size_of::<Self>() +
+            self.slot() * std::mem::size_of::<Option<Box<DictEntry<K, V>>>>() +
+            self.len() * std::mem::size_of::<DictEntry<K, V>>()
+    }
+
     pub fn find(&self, key: &K) -> Option<(&K, &V)> {
         if self.ht[0].size == 0 {
             return None;
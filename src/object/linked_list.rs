@@ -188,6 +188,11 @@ impl<T> LinkedList<T> {
         self.len
     }
 
+    // the memory used by the nodes, not counting what the elements point to
+    pub fn mem_usage(&self) -> usize {
+        mem::size_of::<Self>() + self.len * mem::size_of::<Node<T>>()
+    }
+
     pub fn clear(&mut self) {
         *self = Self::new()
     }
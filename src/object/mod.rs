@@ -47,6 +47,22 @@ pub enum RobjEncoding {
     EmbStr,
 }
 
+impl RobjEncoding {
+    pub fn name(self) -> &'static str {
+        match self {
+            RobjEncoding::LinkedList => "linkedlist",
+            RobjEncoding::Raw => "raw",
+            RobjEncoding::Int => "int",
+            RobjEncoding::Ht => "hashtable",
+            RobjEncoding::ZipMap => "ziplist",
+            RobjEncoding::ZipList => "ziplist",
+            RobjEncoding::IntSet => "intset",
+            RobjEncoding::SkipList => "skiplist",
+            RobjEncoding::EmbStr => "embstr",
+        }
+    }
+}
+
 pub trait ObjectData {
     fn bytes_ref(&self) -> &[u8] { panic!("This is not a byte slice") }
     fn sds_ref(&self) -> &str { panic!("This is not an Sds string") }
@@ -64,6 +80,7 @@ pub trait ObjectData {
     fn set_wrapper_ref(&self) -> &dyn SetWrapper { panic!("This is not as SetWrapper") }
     fn set_wrapper_mut(&mut self) -> &mut dyn SetWrapper { panic!("This is not as SetWrapper") }
     fn zset_ref(&self) -> &Zset { panic!("This is not a Zset") }
+    fn zset_mut(&mut self) -> &mut Zset { panic!("This is not a Zset") }
     fn encoding(&self) -> RobjEncoding;
 }
 
@@ -104,6 +121,13 @@ impl Robj {
         }
     }
 
+    pub fn string_to_vec(&self) -> Vec<u8> {
+        match self.encoding() {
+            RobjEncoding::Int => self.integer().to_string().into_bytes(),
+            _ => self.string().to_vec(),
+        }
+    }
+
     pub fn raw_data(&self) -> &[u8] {
         self.ptr.raw_bytes()
     }
@@ -400,10 +424,67 @@ impl Robj {
         self.obj_type
     }
 
+    pub fn lru(&self) -> SystemTime {
+        self.lru
+    }
+
+    // Estimates the memory used by the object. For the aggregated types
+    // the first `samples` elements (all of them with 0) are measured and
+    // their average size is taken for all the elements.
+    pub fn compute_size(&self, samples: usize) -> usize {
+        let header = std::mem::size_of::<RefCell<Robj>>() + 2 * std::mem::size_of::<usize>();
+        let data = match self.encoding() {
+            RobjEncoding::Raw | RobjEncoding::EmbStr => {
+                std::mem::size_of::<Vec<u8>>() + self.string().len()
+            }
+            RobjEncoding::Int => std::mem::size_of::<i64>(),
+            RobjEncoding::ZipList | RobjEncoding::ZipMap => {
+                std::mem::size_of::<ZipList>() + self.ptr.zip_list_ref().blob_len()
+            }
+            RobjEncoding::IntSet => {
+                std::mem::size_of::<IntSet>() + self.ptr.int_set_ref().blob_len()
+            }
+            RobjEncoding::LinkedList => {
+                let list = self.ptr.linked_list_ref();
+                list.mem_usage() + sampled_size(
+                    list.iter().map(|e| e.borrow().compute_size(0)), list.len(), samples,
+                )
+            }
+            RobjEncoding::Ht if self.obj_type == RobjType::Set => {
+                let set = self.ptr.set_ref();
+                set.mem_usage() + sampled_size(
+                    set.iter().map(|(e, _)| e.borrow().compute_size(0)), set.len(), samples,
+                )
+            }
+            RobjEncoding::Ht => {
+                let ht = self.ptr.hash_table_ref();
+                ht.mem_usage() + sampled_size(
+                    ht.iter().map(|(k, v)| k.borrow().compute_size(0) + v.borrow().compute_size(0)),
+                    ht.len(),
+                    samples,
+                )
+            }
+            RobjEncoding::SkipList => {
+                let zset = self.ptr.zset_ref();
+                // the scores are kept in the dict as string objects too
+                zset.mem_usage() + sampled_size(
+                    zset.iter().map(|(e, score)| {
+                        e.borrow().compute_size(0) +
+                            Self::create_string_object_from_double(score).borrow().compute_size(0)
+                    }),
+                    zset.len(),
+                    samples,
+                )
+            }
+        };
+        header + data
+    }
+
     pub fn linear_iter<'a>(&'a self) -> Box<dyn Iterator<Item=RobjPtr> + 'a> {
         match self.obj_type {
             RobjType::Set => self.set_iter(),
             RobjType::List => self.list_iter(),
+            RobjType::Zset => Box::new(self.zset_iter().map(|x| x.0)),
             _ => unreachable!()
         }
     }
@@ -412,10 +493,29 @@ impl Robj {
         match self.obj_type {
             RobjType::Set => self.set_len(),
             RobjType::List => self.list_len(),
+            RobjType::Zset => self.zset_len(),
             _ => unreachable!()
         }
     }
 
+    pub fn zset_len(&self) -> usize {
+        self.ptr.zset_ref().len()
+    }
+
+    pub fn zset_add(&mut self, score: f64, member: RobjPtr) -> bool {
+        self.ptr.zset_mut().add(score, member)
+    }
+
+    pub fn zset_iter(&self) -> Box<dyn Iterator<Item=(RobjPtr, f64)>> {
+        self.ptr.zset_ref().iter()
+    }
+
+    pub fn hash_get(&self, field: &RobjPtr) -> Option<RobjPtr> {
+        self.ptr.hash_table_ref()
+            .find(field)
+            .map(|(_, v)| Rc::clone(v))
+    }
+
     pub fn list_push(&mut self, o: RobjPtr, w: ListWhere) {
         match self.encoding() {
             RobjEncoding::ZipList => {
@@ -763,6 +863,18 @@ impl Robj {
     }
 }
 
+// the sum of the sizes of all the elements after the average of the
+// first `samples` ones
+fn sampled_size<I: Iterator<Item=usize>>(sizes: I, len: usize, samples: usize) -> usize {
+    let samples = if samples == 0 { len } else { samples };
+    let (sum, n) = sizes.take(samples)
+        .fold((0, 0), |(sum, n), size| (sum + size, n + 1));
+    if n == 0 {
+        return 0;
+    }
+    (sum as f64 / n as f64 * len as f64) as usize
+}
+
 impl DictPartialEq for RobjPtr {
     fn eq(&self, other: &Self) -> bool {
         self.borrow().string() == other.borrow().string()
@@ -867,9 +979,12 @@ impl ObjectData for Zset {
     fn zset_ref(&self) -> &Zset {
         self
     }
+    fn zset_mut(&mut self) -> &mut Zset {
+        self
+    }
 
     fn encoding(&self) -> RobjEncoding {
-        unimplemented!()
+        RobjEncoding::SkipList
     }
 }
 
@@ -1018,4 +1133,19 @@ mod test {
         let objp = Robj::create_string_object_from_double(0.0);
         assert_eq!(objp.borrow().string(), b"0");
     }
+
+    #[test]
+    fn zset_linear_iter() {
+        let objp = Robj::create_zset_object();
+        assert!(objp.borrow_mut().zset_add(2.0, Robj::create_string_object("b")));
+        assert!(objp.borrow_mut().zset_add(1.0, Robj::create_string_object("a")));
+        assert!(!objp.borrow_mut().zset_add(3.0, Robj::create_string_object("a")));
+
+        let obj = objp.borrow();
+        let members: Vec<Vec<u8>> = obj.linear_iter()
+            .map(|x| x.borrow().string().to_vec())
+            .collect();
+        assert_eq!(members, vec![b"b".to_vec(), b"a".to_vec()]);
+        assert_eq!(obj.linear_len(), 2);
+    }
 }
\ No newline at end of file
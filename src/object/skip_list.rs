@@ -115,6 +115,31 @@ impl SkipList {
         }
     }
 
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    // the memory used by the nodes, a node has 4/3 levels on average
+    pub fn mem_usage(&self) -> usize {
+        let node = std::mem::size_of::<RefCell<SkipListNode>>() + 2 * std::mem::size_of::<usize>();
+        let level = std::mem::size_of::<SkipListLevel>();
+        std::mem::size_of::<Self>() +
+            node + SKIP_LIST_MAX_LEVEL * level +
+            self.length * (node + level * 4 / 3)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    pub fn iter(&self) -> Box<dyn Iterator<Item=(RobjPtr, f64)>> {
+        Box::new(self.header.borrow().iter(0)
+            .map(|node| {
+                let node = node.borrow();
+                (Rc::clone(node.obj_ref()), node.score)
+            }))
+    }
+
     fn random_level() -> usize {
         let mut level = 1usize;
         let mut rng = rand::thread_rng();
@@ -552,6 +577,18 @@ mod test {
         let node = list.first_in_range(&range).unwrap();
         assert_eq!(node.borrow().score, 2.1);
     }
+
+    #[test]
+    fn iterate_in_score_order() {
+        let mut list = SkipList::new();
+        list.insert(3.2, Robj::create_string_object("foo"));
+        list.insert(0.2, Robj::create_string_object("bar"));
+        list.insert(2.1, Robj::create_string_object("haha"));
+
+        let scores: Vec<f64> = list.iter().map(|x| x.1).collect();
+        assert_eq!(scores, vec![0.2, 2.1, 3.2]);
+        assert_eq!(list.len(), 3);
+    }
 }
 
 
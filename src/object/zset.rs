@@ -1,8 +1,9 @@
-use crate::object::RobjPtr;
+use crate::object::{RobjPtr, Robj};
 use crate::object::skip_list::SkipList;
 use crate::object::dict::Dict;
 use crate::hash;
 use rand::prelude::*;
+use std::rc::Rc;
 
 pub struct Zset {
     dict: Dict<RobjPtr, RobjPtr>,
@@ -16,4 +17,33 @@ impl Zset {
             list: SkipList::new(),
         }
     }
+
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    // the memory used by the dict and the skip list, not counting the
+    // members and the scores
+    pub fn mem_usage(&self) -> usize {
+        self.dict.mem_usage() + self.list.mem_usage()
+    }
+
+    pub fn add(&mut self, score: f64, member: RobjPtr) -> bool {
+        let old = self.dict.find(&member)
+            .map(|(_, s)| s.borrow().float());
+        if let Some(old) = old {
+            self.list.delete(old, &member);
+        }
+        self.list.insert(score, Rc::clone(&member));
+        self.dict.replace(member, Robj::create_string_object_from_double(score));
+        old.is_none()
+    }
+
+    pub fn iter(&self) -> Box<dyn Iterator<Item=(RobjPtr, f64)>> {
+        self.list.iter()
+    }
 }
\ No newline at end of file
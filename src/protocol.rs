@@ -103,6 +103,70 @@ pub fn decode(raw: &[u8]) -> Result<DecodeIter, ()> {
     Ok(iter)
 }
 
+// The protocol versions a client can negotiate with HELLO. RESP3 has
+// native types for the replies RESP2 encodes as arrays or bulk strings,
+// the functions below encode a reply in the version of the client.
+pub const RESP2: u8 = 2;
+pub const RESP3: u8 = 3;
+
+pub fn null(resp: u8) -> &'static str {
+    if resp == RESP3 { "_\r\n" } else { "$-1\r\n" }
+}
+
+pub fn null_array(resp: u8) -> &'static str {
+    if resp == RESP3 { "_\r\n" } else { "*-1\r\n" }
+}
+
+// a RESP2 map is a flat array of the keys and the values
+pub fn map_len(resp: u8, n: usize) -> String {
+    if resp == RESP3 { format!("%{}\r\n", n) } else { format!("*{}\r\n", n * 2) }
+}
+
+pub fn set_len(resp: u8, n: usize) -> String {
+    if resp == RESP3 { format!("~{}\r\n", n) } else { format!("*{}\r\n", n) }
+}
+
+// out of band data like pub/sub messages and invalidations
+pub fn push_len(resp: u8, n: usize) -> String {
+    if resp == RESP3 { format!(">{}\r\n", n) } else { format!("*{}\r\n", n) }
+}
+
+pub fn double_to_string(d: f64) -> String {
+    if d.is_infinite() {
+        if d > 0.0 { "inf".to_string() } else { "-inf".to_string() }
+    } else {
+        format!("{}", d)
+    }
+}
+
+pub fn double(resp: u8, d: f64) -> String {
+    let s = double_to_string(d);
+    if resp == RESP3 { format!(",{}\r\n", s) } else { format!("${}\r\n{}\r\n", s.len(), s) }
+}
+
+pub fn boolean(resp: u8, b: bool) -> &'static str {
+    match (resp == RESP3, b) {
+        (true, true) => "#t\r\n",
+        (true, false) => "#f\r\n",
+        (false, true) => ":1\r\n",
+        (false, false) => ":0\r\n",
+    }
+}
+
+// an integer too big for a 64 bit integer, in decimal
+pub fn big_number(resp: u8, n: &str) -> String {
+    if resp == RESP3 { format!("({}\r\n", n) } else { format!("${}\r\n{}\r\n", n.len(), n) }
+}
+
+// text meant to be shown as is, `format` is "txt" or "mkd"
+pub fn verbatim(resp: u8, s: &str, format: &str) -> String {
+    if resp == RESP3 {
+        format!("={}\r\n{}:{}\r\n", s.len() + 4, format, s)
+    } else {
+        format!("${}\r\n{}\r\n", s.len(), s)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -188,4 +252,15 @@ mod test {
             1,
         );
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn encode_resp3() {
+        assert_eq!(map_len(RESP2, 2), "*4\r\n");
+        assert_eq!(map_len(RESP3, 2), "%2\r\n");
+        assert_eq!(double(RESP3, 1.5), ",1.5\r\n");
+        assert_eq!(double(RESP2, f64::INFINITY), "$3\r\ninf\r\n");
+        assert_eq!(verbatim(RESP3, "ok", "txt"), "=6\r\ntxt:ok\r\n");
+        assert_eq!(null(RESP2), "$-1\r\n");
+        assert_eq!(null_array(RESP3), "_\r\n");
+    }
+}
@@ -0,0 +1,472 @@
+use crate::client::{Client, ClientData};
+use crate::server::Server;
+use crate::ae::{AeEventLoop, AE_WRITABLE};
+use crate::object::RobjPtr;
+use crate::env::send_reply_to_client;
+use crate::glob::glob_match;
+use crate::util::case_eq;
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::mem::take;
+
+// Commands a client subscribed to something is still allowed to call
+pub const SUBSCRIBE_CONTEXT_COMMANDS: [&str; 7] = [
+    "subscribe", "unsubscribe", "psubscribe", "punsubscribe",
+    "ssubscribe", "sunsubscribe", "ping",
+];
+
+// Adds a reply to `target`, which may be the client running the command.
+// That client is already borrowed and its reply is sent after the command,
+// the others get a writable event unless they went over their output
+// buffer limit.
+pub fn add_reply_to_client<F>(
+    server: &mut Server,
+    el: &mut AeEventLoop,
+    current: Option<&mut Client>,
+    target: &Rc<RefCell<Client>>,
+    f: F,
+) where F: FnOnce(&mut Client) {
+    if let Some(current) = current {
+        if std::ptr::eq(target.as_ptr(), current) {
+            f(current);
+            server.close_client_on_output_buffer_limit_reached(current);
+            return;
+        }
+    }
+    let mut target_ref = target.borrow_mut();
+    f(&mut target_ref);
+    if server.close_client_on_output_buffer_limit_reached(&mut target_ref) {
+        return;
+    }
+    let _ = el.create_file_event(
+        Rc::clone(&target_ref.fd),
+        AE_WRITABLE,
+        send_reply_to_client,
+        ClientData::Client(Rc::clone(target)),
+    );
+}
+
+// Shard channels are scoped to the node owning the slot of the channel,
+// a standalone server owns all slots so they act as node-local channels.
+#[derive(Copy, Clone, PartialEq)]
+enum PubSubType {
+    Global,
+    Shard,
+}
+
+impl PubSubType {
+    fn subscribe_msg(self) -> &'static str {
+        match self {
+            PubSubType::Global => "subscribe",
+            PubSubType::Shard => "ssubscribe",
+        }
+    }
+
+    fn unsubscribe_msg(self) -> &'static str {
+        match self {
+            PubSubType::Global => "unsubscribe",
+            PubSubType::Shard => "sunsubscribe",
+        }
+    }
+
+    fn message_msg(self) -> &'static str {
+        match self {
+            PubSubType::Global => "message",
+            PubSubType::Shard => "smessage",
+        }
+    }
+
+    // the count in (un)subscribe replies, shard channels are counted apart
+    fn count(self, client: &Client) -> usize {
+        match self {
+            PubSubType::Global => client.pubsub_channels.len() + client.pubsub_patterns.len(),
+            PubSubType::Shard => client.pubsub_shard_channels.len(),
+        }
+    }
+
+    fn client_channels(self, client: &mut Client) -> &mut Vec<Vec<u8>> {
+        match self {
+            PubSubType::Global => &mut client.pubsub_channels,
+            PubSubType::Shard => &mut client.pubsub_shard_channels,
+        }
+    }
+
+    fn server_channels(self, server: &Server) -> &HashMap<Vec<u8>, Vec<Rc<RefCell<Client>>>> {
+        match self {
+            PubSubType::Global => &server.pubsub_channels,
+            PubSubType::Shard => &server.pubsub_shard_channels,
+        }
+    }
+
+    fn server_channels_mut(self, server: &mut Server) -> &mut HashMap<Vec<u8>, Vec<Rc<RefCell<Client>>>> {
+        match self {
+            PubSubType::Global => &mut server.pubsub_channels,
+            PubSubType::Shard => &mut server.pubsub_shard_channels,
+        }
+    }
+}
+
+fn add_pubsub_reply(client: &mut Client, kind: &str, target: Option<&[u8]>, count: usize) {
+    client.add_reply_push_len(3);
+    client.add_reply_bulk_bytes(kind.as_bytes());
+    match target {
+        Some(t) => client.add_reply_bulk_bytes(t),
+        None => client.add_reply_null(),
+    }
+    client.add_reply_integer(count as i64);
+}
+
+fn subscribe_channel(client: &mut Client, server: &mut Server, channel: &[u8], t: PubSubType) {
+    if !t.client_channels(client).iter().any(|c| c.as_slice() == channel) {
+        let c = server.find_client(client);
+        t.client_channels(client).push(channel.to_vec());
+        t.server_channels_mut(server)
+            .entry(channel.to_vec())
+            .or_default()
+            .push(c);
+    }
+    let count = t.count(client);
+    add_pubsub_reply(client, t.subscribe_msg(), Some(channel), count);
+}
+
+fn unsubscribe_channel(client: &mut Client, server: &mut Server, channel: &[u8], t: PubSubType) {
+    let channels = t.client_channels(client);
+    if let Some(pos) = channels.iter().position(|c| c.as_slice() == channel) {
+        channels.remove(pos);
+        remove_channel_subscriber(server, channel, client, t);
+    }
+    let count = t.count(client);
+    add_pubsub_reply(client, t.unsubscribe_msg(), Some(channel), count);
+}
+
+fn remove_channel_subscriber(server: &mut Server, channel: &[u8], client: &Client, t: PubSubType) {
+    let ptr = client as *const Client;
+    let server_channels = t.server_channels_mut(server);
+    let empty = match server_channels.get_mut(channel) {
+        None => return,
+        Some(subscribers) => {
+            subscribers.retain(|x| !std::ptr::eq(x.as_ptr(), ptr));
+            subscribers.is_empty()
+        }
+    };
+    if empty {
+        server_channels.remove(channel);
+    }
+}
+
+fn subscribe_pattern(client: &mut Client, server: &mut Server, pattern: &[u8]) {
+    if !client.pubsub_patterns.iter().any(|p| p.as_slice() == pattern) {
+        let c = server.find_client(client);
+        client.pubsub_patterns.push(pattern.to_vec());
+        server.pubsub_patterns.push((pattern.to_vec(), c));
+    }
+    let count = PubSubType::Global.count(client);
+    add_pubsub_reply(client, "psubscribe", Some(pattern), count);
+}
+
+fn unsubscribe_pattern(client: &mut Client, server: &mut Server, pattern: &[u8]) {
+    if let Some(pos) = client.pubsub_patterns.iter().position(|p| p.as_slice() == pattern) {
+        client.pubsub_patterns.remove(pos);
+        let ptr = client as *const Client;
+        server.pubsub_patterns.retain(|(p, x)| {
+            p.as_slice() != pattern || !std::ptr::eq(x.as_ptr(), ptr)
+        });
+    }
+    let count = PubSubType::Global.count(client);
+    add_pubsub_reply(client, "punsubscribe", Some(pattern), count);
+}
+
+// Drops every subscription of a client which is being freed
+pub fn pubsub_unsubscribe_all(server: &mut Server, client: &Client) {
+    for channel in client.pubsub_channels.iter() {
+        remove_channel_subscriber(server, channel, client, PubSubType::Global);
+    }
+    for channel in client.pubsub_shard_channels.iter() {
+        remove_channel_subscriber(server, channel, client, PubSubType::Shard);
+    }
+    if !client.pubsub_patterns.is_empty() {
+        let ptr = client as *const Client;
+        server.pubsub_patterns.retain(|(_, x)| !std::ptr::eq(x.as_ptr(), ptr));
+    }
+}
+
+// A message for a subscriber borrowed further up the stack, which is the
+// caller of the script that published it. It is sent once the script is
+// done, see `flush_pending_messages`.
+pub struct PendingMessage {
+    target: Rc<RefCell<Client>>,
+    pattern: Option<Vec<u8>>,
+    kind: &'static str,
+    channel: RobjPtr,
+    message: RobjPtr,
+}
+
+fn add_message_reply(c: &mut Client, m: &PendingMessage) {
+    match m.pattern.as_ref() {
+        None => {
+            c.add_reply_push_len(3);
+            c.add_reply_bulk_bytes(m.kind.as_bytes());
+        }
+        Some(pattern) => {
+            c.add_reply_push_len(4);
+            c.add_reply_bulk_bytes(m.kind.as_bytes());
+            c.add_reply_bulk_bytes(pattern);
+        }
+    }
+    c.add_reply_bulk(Rc::clone(&m.channel));
+    c.add_reply_bulk(Rc::clone(&m.message));
+}
+
+fn send_message(
+    server: &mut Server,
+    el: &mut AeEventLoop,
+    current: Option<&mut Client>,
+    m: PendingMessage,
+) {
+    let borrowed = match current.as_deref() {
+        Some(c) if std::ptr::eq(m.target.as_ptr(), c) => false,
+        _ => m.target.try_borrow_mut().is_err(),
+    };
+    if borrowed {
+        server.pubsub_pending.push(m);
+        return;
+    }
+    add_reply_to_client(server, el, current, &m.target, |c| add_message_reply(c, &m));
+}
+
+pub fn flush_pending_messages(
+    server: &mut Server,
+    el: &mut AeEventLoop,
+    mut current: Option<&mut Client>,
+) {
+    for m in take(&mut server.pubsub_pending) {
+        add_reply_to_client(server, el, current.as_deref_mut(), &m.target, |c| add_message_reply(c, &m));
+    }
+}
+
+fn publish_to_channel(
+    server: &mut Server,
+    el: &mut AeEventLoop,
+    mut current: Option<&mut Client>,
+    channel: &RobjPtr,
+    message: &RobjPtr,
+    t: PubSubType,
+) -> usize {
+    let channel_bytes = channel.borrow().string_to_vec();
+    let subscribers: Vec<Rc<RefCell<Client>>> = match t.server_channels(server).get(&channel_bytes) {
+        None => return 0,
+        Some(s) => s.iter().map(Rc::clone).collect(),
+    };
+    let receivers = subscribers.len();
+    for target in subscribers {
+        send_message(server, el, current.as_deref_mut(), PendingMessage {
+            target,
+            pattern: None,
+            kind: t.message_msg(),
+            channel: Rc::clone(channel),
+            message: Rc::clone(message),
+        });
+    }
+    receivers
+}
+
+// Returns the number of clients that received the message
+pub fn publish_message(
+    server: &mut Server,
+    el: &mut AeEventLoop,
+    mut current: Option<&mut Client>,
+    channel: &RobjPtr,
+    message: &RobjPtr,
+) -> usize {
+    let mut receivers = publish_to_channel(
+        server, el, current.as_deref_mut(), channel, message, PubSubType::Global,
+    );
+    let channel_bytes = channel.borrow().string_to_vec();
+
+    let patterns: Vec<(Vec<u8>, Rc<RefCell<Client>>)> = server.pubsub_patterns.iter()
+        .filter(|(pattern, _)| glob_match(pattern, &channel_bytes, false))
+        .map(|(pattern, s)| (pattern.clone(), Rc::clone(s)))
+        .collect();
+    for (pattern, target) in patterns {
+        send_message(server, el, current.as_deref_mut(), PendingMessage {
+            target,
+            pattern: Some(pattern),
+            kind: "pmessage",
+            channel: Rc::clone(channel),
+            message: Rc::clone(message),
+        });
+        receivers += 1;
+    }
+
+    receivers
+}
+
+fn subscribe_generic_command(client: &mut Client, server: &mut Server, t: PubSubType) {
+    let channels: Vec<Vec<u8>> = client.argv[1..].iter()
+        .map(|x| x.borrow().string_to_vec())
+        .collect();
+    for channel in channels.iter() {
+        subscribe_channel(client, server, channel, t);
+    }
+}
+
+fn unsubscribe_generic_command(client: &mut Client, server: &mut Server, t: PubSubType) {
+    let channels: Vec<Vec<u8>> = if client.argc() == 1 {
+        t.client_channels(client).clone()
+    } else {
+        client.argv[1..].iter()
+            .map(|x| x.borrow().string_to_vec())
+            .collect()
+    };
+    if channels.is_empty() {
+        let count = t.count(client);
+        add_pubsub_reply(client, t.unsubscribe_msg(), None, count);
+    }
+    for channel in channels.iter() {
+        unsubscribe_channel(client, server, channel, t);
+    }
+}
+
+pub fn subscribe_command(
+    client: &mut Client,
+    server: &mut Server,
+    _el: &mut AeEventLoop,
+) {
+    subscribe_generic_command(client, server, PubSubType::Global);
+}
+
+pub fn unsubscribe_command(
+    client: &mut Client,
+    server: &mut Server,
+    _el: &mut AeEventLoop,
+) {
+    unsubscribe_generic_command(client, server, PubSubType::Global);
+}
+
+pub fn ssubscribe_command(
+    client: &mut Client,
+    server: &mut Server,
+    _el: &mut AeEventLoop,
+) {
+    subscribe_generic_command(client, server, PubSubType::Shard);
+}
+
+pub fn sunsubscribe_command(
+    client: &mut Client,
+    server: &mut Server,
+    _el: &mut AeEventLoop,
+) {
+    unsubscribe_generic_command(client, server, PubSubType::Shard);
+}
+
+pub fn psubscribe_command(
+    client: &mut Client,
+    server: &mut Server,
+    _el: &mut AeEventLoop,
+) {
+    let patterns: Vec<Vec<u8>> = client.argv[1..].iter()
+        .map(|x| x.borrow().string_to_vec())
+        .collect();
+    for pattern in patterns.iter() {
+        subscribe_pattern(client, server, pattern);
+    }
+}
+
+pub fn punsubscribe_command(
+    client: &mut Client,
+    server: &mut Server,
+    _el: &mut AeEventLoop,
+) {
+    let patterns: Vec<Vec<u8>> = if client.argc() == 1 {
+        client.pubsub_patterns.clone()
+    } else {
+        client.argv[1..].iter()
+            .map(|x| x.borrow().string_to_vec())
+            .collect()
+    };
+    if patterns.is_empty() {
+        let count = PubSubType::Global.count(client);
+        add_pubsub_reply(client, "punsubscribe", None, count);
+    }
+    for pattern in patterns.iter() {
+        unsubscribe_pattern(client, server, pattern);
+    }
+}
+
+pub fn publish_command(
+    client: &mut Client,
+    server: &mut Server,
+    el: &mut AeEventLoop,
+) {
+    let channel = Rc::clone(&client.argv[1]);
+    let message = Rc::clone(&client.argv[2]);
+    let receivers = publish_message(server, el, Some(client), &channel, &message);
+    client.add_reply_integer(receivers as i64);
+}
+
+pub fn spublish_command(
+    client: &mut Client,
+    server: &mut Server,
+    el: &mut AeEventLoop,
+) {
+    let channel = Rc::clone(&client.argv[1]);
+    let message = Rc::clone(&client.argv[2]);
+    let receivers = publish_to_channel(server, el, Some(client), &channel, &message, PubSubType::Shard);
+    client.add_reply_integer(receivers as i64);
+}
+
+fn pubsub_channels_reply(client: &mut Client, server: &Server, t: PubSubType) {
+    let pattern = client.argv.get(2).map(|x| x.borrow().string_to_vec());
+    let channels: Vec<&Vec<u8>> = t.server_channels(server).keys()
+        .filter(|c| match pattern.as_ref() {
+            None => true,
+            Some(p) => glob_match(p, c, false),
+        })
+        .collect();
+    client.add_reply_array_len(channels.len());
+    for c in channels {
+        client.add_reply_bulk_bytes(c);
+    }
+}
+
+fn pubsub_numsub_reply(client: &mut Client, server: &Server, t: PubSubType) {
+    client.add_reply_map_len(client.argc() - 2);
+    for j in 2..client.argc() {
+        let channel = Rc::clone(&client.argv[j]);
+        let count = t.server_channels(server)
+            .get(&channel.borrow().string_to_vec())
+            .map_or(0, |s| s.len());
+        client.add_reply_bulk(channel);
+        client.add_reply_integer(count as i64);
+    }
+}
+
+pub fn pubsub_command(
+    client: &mut Client,
+    server: &mut Server,
+    _el: &mut AeEventLoop,
+) {
+    let sub = client.argv[1].borrow().string_to_vec();
+    if case_eq(b"channels", &sub) && client.argc() <= 3 {
+        pubsub_channels_reply(client, server, PubSubType::Global);
+    } else if case_eq(b"shardchannels", &sub) && client.argc() <= 3 {
+        pubsub_channels_reply(client, server, PubSubType::Shard);
+    } else if case_eq(b"numsub", &sub) {
+        pubsub_numsub_reply(client, server, PubSubType::Global);
+    } else if case_eq(b"shardnumsub", &sub) {
+        pubsub_numsub_reply(client, server, PubSubType::Shard);
+    } else if case_eq(b"numpat", &sub) && client.argc() == 2 {
+        let mut patterns: Vec<&Vec<u8>> = server.pubsub_patterns.iter()
+            .map(|(p, _)| p)
+            .collect();
+        patterns.sort();
+        patterns.dedup();
+        client.add_reply_integer(patterns.len() as i64);
+    } else {
+        client.add_reply_error(&format!(
+            "Unknown PUBSUB subcommand or wrong number of arguments for '{}'",
+            String::from_utf8_lossy(&sub),
+        ));
+    }
+}
@@ -4,7 +4,7 @@ use std::io::{Write, BufWriter, BufReader, Read};
 use std::fs::{File, OpenOptions, rename};
 use crate::db::DB;
 use crate::object::{RobjPtr, RobjEncoding, RobjType, Robj};
-use std::time::SystemTime;
+use std::time::{SystemTime, Instant};
 use crate::util::{unix_timestamp, to_system_time};
 use std::rc::Rc;
 use crate::object::linked_list::LinkedList;
@@ -15,6 +15,7 @@ use std::os::unix::io::AsRawFd;
 use nix::unistd::{fork, ForkResult, Pid};
 use nix::sys::ptrace::kill;
 use std::process::exit;
+use crate::latency::latency_add_sample_if_needed;
 
 const RDB_DB_SELECT_FLAG: u8 = 0xFE;
 const RDB_DB_END_FLAG: u8 = 0xFF;
@@ -49,16 +50,19 @@ pub fn rdb_save_in_background(server: &mut Server) -> Result<(), ()> {
     if server.bg_save_in_progress {
         return Err(());
     }
+    let start = Instant::now();
     match fork() {
         Ok(ForkResult::Parent { child, .. }) => {
+            latency_add_sample_if_needed(server, "fork", start.elapsed());
             info!("Background saving started by pid {}", child);
             server.bg_save_in_progress = true;
             server.bg_save_child_pid = child.as_raw();
             return Ok(());
         }
         Ok(ForkResult::Child) => {
-            let fd = server.fd.borrow().unwrap_listener().as_raw_fd();
-            let _ = nix::unistd::close(fd);
+            for fd in server.ipfd.iter().chain(server.unix_fd.iter()) {
+                let _ = nix::unistd::close(fd.borrow().unwrap_listener().as_raw_fd());
+            }
             if let Ok(()) = rdb_save(server) {
                 exit(0);
             } else {
@@ -76,7 +80,7 @@ pub fn rdb_kill_background_saving(server: &Server) {
     let _ = kill(Pid::from_raw(server.bg_save_child_pid));
 }
 
-pub fn rdb_save(server: &Server) -> io::Result<()> {
+pub fn rdb_save(server: &mut Server) -> io::Result<()> {
     let temp_file_name = format!("temp-{}.rdb", rand::thread_rng().gen::<usize>());
     let file: File = OpenOptions::new()
         .write(true)
@@ -97,6 +101,9 @@ pub fn rdb_save(server: &Server) -> io::Result<()> {
     writer.write_all(RDB_END_BUF)?;
     writer.write_all(RDB_NO_CHECKSUM)?;
     writer.flush()?;
+    let start = Instant::now();
+    writer.get_ref().sync_all()?;
+    latency_add_sample_if_needed(server, "fsync", start.elapsed());
 
     rename(&temp_file_name, &server.db_filename)?;
     Ok(())
@@ -264,6 +271,15 @@ trait RdbWriter: io::Write {
 
 impl RdbWriter for BufWriter<File> {}
 
+impl RdbWriter for Vec<u8> {}
+
+// the length of the object once serialized in the dump
+pub fn rdb_object_len(obj: &RobjPtr) -> usize {
+    let mut buf: Vec<u8> = vec![];
+    let _ = buf.dump_object(obj);
+    buf.len()
+}
+
 fn value_type_flag(o: &RobjPtr) -> u8 {
     use RobjEncoding::*;
     use RobjType::*;
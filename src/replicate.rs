@@ -1,4 +1,4 @@
-use crate::client::{Client, ReplyState, ClientData};
+use crate::client::{Client, ReplyState, ClientData, CLIENT_CLOSE_ASAP};
 use crate::object::linked_list::LinkedList;
 use std::rc::Rc;
 use std::cell::RefCell;
@@ -51,26 +51,30 @@ fn feed_one_slave(
     db_idx: usize,
     outv: &Vec<RobjPtr>,
 ) {
+    if slave.flags & CLIENT_CLOSE_ASAP != 0 {
+        return;
+    }
     if slave.slave_select_db != db_idx {
         slave.slave_select_db = db_idx;
         feed_select_db_command(slave);
     }
-    slave.reply.reserve(outv.len());
-    slave.reply.extend_from_slice(&outv[..]);
+    for o in outv.iter() {
+        slave.reply.add_object(Rc::clone(o));
+    }
 }
 
 fn feed_select_db_command(slave: &mut Client) {
     let idx = slave.slave_select_db;
-    slave.add_reply(
+    slave.add_reply_raw(
         Robj::from_bytes(format!("*{}\r\n", 2).into_bytes())
     );
-    slave.add_reply(Robj::create_string_object("$6\r\nselect\r\n"));
+    slave.add_reply_raw(Robj::create_string_object("$6\r\nselect\r\n"));
     if idx < 10 {
-        slave.add_reply(
+        slave.add_reply_raw(
             Robj::from_bytes(format!("${}\r\n{}\r\n", 1, idx).into_bytes())
         )
     } else if idx < 100 {
-        slave.add_reply(
+        slave.add_reply_raw(
             Robj::from_bytes(format!("${}\r\n{}\r\n", 2, idx).into_bytes())
         )
     } else {
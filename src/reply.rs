@@ -0,0 +1,482 @@
+use std::cell::Ref;
+use std::collections::VecDeque;
+use std::io;
+use std::num::ParseIntError;
+use std::os::unix::io::RawFd;
+use nix::errno::Errno;
+use nix::sys::uio::{writev, IoVec};
+use crate::object::{Robj, RobjPtr, RobjEncoding};
+use crate::util::{human_size, parse_usize};
+
+// size of the buffer every client owns for its small replies
+pub const REPLY_BUF_LEN: usize = 16 * 1024;
+// size of the blocks the replies go to when the buffer is full
+pub const REPLY_CHUNK_BYTES: usize = 16 * 1024;
+// strings at least this long are queued by reference instead of copied
+pub const REPLY_SHARED_MIN_LEN: usize = 4 * 1024;
+// iovecs handed to a single writev
+const REPLY_MAX_IOV: usize = 64;
+
+// The classes of clients with their own client-output-buffer-limit, the
+// value is the index of the class in the limits of the server
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ClientClass {
+    Normal = 0,
+    Replica = 1,
+    PubSub = 2,
+}
+
+const CLIENT_CLASSES: [ClientClass; 3] = [ClientClass::Normal, ClientClass::Replica, ClientClass::PubSub];
+
+impl ClientClass {
+    pub fn name(self) -> &'static str {
+        match self {
+            ClientClass::Normal => "normal",
+            ClientClass::Replica => "slave",
+            ClientClass::PubSub => "pubsub",
+        }
+    }
+
+    // "replica" is accepted as an alias of "slave"
+    pub fn from_name(name: &str) -> Option<ClientClass> {
+        match name.to_ascii_lowercase().as_str() {
+            "normal" => Some(ClientClass::Normal),
+            "slave" | "replica" => Some(ClientClass::Replica),
+            "pubsub" => Some(ClientClass::PubSub),
+            _ => None,
+        }
+    }
+}
+
+// A client is closed when its output buffer reaches the hard limit, or
+// stays over the soft limit for more than soft_seconds. 0 disables a limit.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct OutputBufferLimit {
+    pub hard: usize,
+    pub soft: usize,
+    pub soft_seconds: u64,
+}
+
+pub const DEFAULT_OUTPUT_BUFFER_LIMITS: [OutputBufferLimit; 3] = [
+    OutputBufferLimit { hard: 0, soft: 0, soft_seconds: 0 },
+    OutputBufferLimit { hard: 256 * 1024 * 1024, soft: 64 * 1024 * 1024, soft_seconds: 60 },
+    OutputBufferLimit { hard: 32 * 1024 * 1024, soft: 8 * 1024 * 1024, soft_seconds: 60 },
+];
+
+// "<class> <hard> <soft> <soft seconds> [<class> ...]", the limits are only
+// changed when all of them are valid
+pub fn parse_output_buffer_limits(
+    value: &str,
+    limits: &mut [OutputBufferLimit; 3],
+) -> Result<(), String> {
+    let args: Vec<&str> = value.split_ascii_whitespace().collect();
+    if args.is_empty() || !args.len().is_multiple_of(4) {
+        return Err("Wrong number of arguments in buffer limit configuration.".to_string());
+    }
+    let mut parsed = *limits;
+    for group in args.chunks(4) {
+        let class = ClientClass::from_name(group[0]).ok_or_else(|| {
+            format!("Invalid client class specified in buffer limit configuration: {}", group[0])
+        })?;
+        let limit = || -> Result<OutputBufferLimit, ParseIntError> {
+            Ok(OutputBufferLimit {
+                hard: human_size(group[1])?,
+                soft: human_size(group[2])?,
+                soft_seconds: parse_usize(group[3])? as u64,
+            })
+        };
+        parsed[class as usize] = limit().map_err(|_| {
+            "Error in hard, soft or soft_seconds setting in buffer limit configuration.".to_string()
+        })?;
+    }
+    *limits = parsed;
+    Ok(())
+}
+
+pub fn output_buffer_limits_string(limits: &[OutputBufferLimit; 3]) -> String {
+    CLIENT_CLASSES.iter()
+        .map(|class| {
+            let l = limits[*class as usize];
+            format!("{} {} {} {}", class.name(), l.hard, l.soft, l.soft_seconds)
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+enum ReplyBlock {
+    // small replies copied one after another, never grown past its capacity
+    Chunk(Vec<u8>),
+    // a string value sent without copying it, strings are never modified
+    // in place so it can be shared with the keyspace
+    Object(RobjPtr),
+    // the header of an aggregate, filled in when its length is known
+    Deferred(Vec<u8>),
+}
+
+impl ReplyBlock {
+    fn size(&self) -> usize {
+        match self {
+            ReplyBlock::Chunk(v) => v.capacity(),
+            ReplyBlock::Object(o) => o.borrow().string().len(),
+            ReplyBlock::Deferred(v) => v.len(),
+        }
+    }
+}
+
+// The pending replies of a client: the static buffer comes first and the
+// blocks after it, so the buffer is only appended to while there are no
+// blocks.
+pub struct ReplyBuffer {
+    buf: Box<[u8]>,
+    buf_pos: usize,
+    blocks: VecDeque<ReplyBlock>,
+    // bytes of the first pending part already written to the socket
+    sent: usize,
+    // bytes not written yet
+    pending: usize,
+    // memory held by the blocks
+    block_bytes: usize,
+}
+
+impl ReplyBuffer {
+    pub fn new() -> ReplyBuffer {
+        ReplyBuffer {
+            buf: vec![0; REPLY_BUF_LEN].into_boxed_slice(),
+            buf_pos: 0,
+            blocks: VecDeque::new(),
+            sent: 0,
+            pending: 0,
+            block_bytes: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending == 0
+    }
+
+    // bytes waiting to be written
+    pub fn len(&self) -> usize {
+        self.pending
+    }
+
+    // bytes used in the static buffer
+    pub fn buf_len(&self) -> usize {
+        self.buf_pos
+    }
+
+    pub fn blocks_len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    // memory of the output buffer beyond the static buffer
+    pub fn memory(&self) -> usize {
+        self.block_bytes
+    }
+
+    pub fn clear(&mut self) {
+        self.buf_pos = 0;
+        self.blocks.clear();
+        self.sent = 0;
+        self.pending = 0;
+        self.block_bytes = 0;
+    }
+
+    pub fn add_bytes(&mut self, mut b: &[u8]) {
+        if b.is_empty() {
+            return;
+        }
+        self.pending += b.len();
+
+        if self.blocks.is_empty() {
+            let n = b.len().min(REPLY_BUF_LEN - self.buf_pos);
+            self.buf[self.buf_pos..self.buf_pos + n].copy_from_slice(&b[..n]);
+            self.buf_pos += n;
+            b = &b[n..];
+            if b.is_empty() {
+                return;
+            }
+        }
+
+        if let Some(ReplyBlock::Chunk(tail)) = self.blocks.back_mut() {
+            let n = b.len().min(tail.capacity() - tail.len());
+            tail.extend_from_slice(&b[..n]);
+            b = &b[n..];
+            if b.is_empty() {
+                return;
+            }
+        }
+
+        let mut chunk = Vec::with_capacity(b.len().max(REPLY_CHUNK_BYTES));
+        chunk.extend_from_slice(b);
+        self.block_bytes += chunk.capacity();
+        self.blocks.push_back(ReplyBlock::Chunk(chunk));
+    }
+
+    // appends the bytes of a string object, long ones by reference
+    pub fn add_object(&mut self, o: RobjPtr) {
+        let shared = {
+            let r = o.borrow();
+            match r.encoding() {
+                RobjEncoding::Int => {
+                    self.add_bytes(r.integer().to_string().as_bytes());
+                    return;
+                }
+                _ if r.string().len() < REPLY_SHARED_MIN_LEN => {
+                    self.add_bytes(r.string());
+                    return;
+                }
+                _ => r.string().len(),
+            }
+        };
+        self.pending += shared;
+        self.block_bytes += shared;
+        self.blocks.push_back(ReplyBlock::Object(o));
+    }
+
+    // Reserves a place for a header whose content is not known yet. It
+    // must be filled by set_deferred before the client gets back to the
+    // event loop. The chunk before it is never appended to again, so most
+    // of its unused space is given back.
+    pub fn add_deferred(&mut self) -> usize {
+        if let Some(ReplyBlock::Chunk(tail)) = self.blocks.back_mut() {
+            let capacity = tail.capacity();
+            if capacity - tail.len() > capacity / 4 {
+                tail.shrink_to_fit();
+                self.block_bytes -= capacity - tail.capacity();
+            }
+        }
+        self.blocks.push_back(ReplyBlock::Deferred(vec![]));
+        self.blocks.len() - 1
+    }
+
+    // The header goes to the head of the next chunk when it fits in its
+    // unused space, the deferred block is left empty then.
+    pub fn set_deferred(&mut self, at: usize, header: &[u8]) {
+        match self.blocks.get_mut(at) {
+            Some(ReplyBlock::Deferred(v)) => {
+                self.pending -= v.len();
+                self.block_bytes -= v.len();
+                v.clear();
+            }
+            _ => return,
+        }
+        self.pending += header.len();
+
+        if let Some(ReplyBlock::Chunk(next)) = self.blocks.get_mut(at + 1) {
+            if next.capacity() - next.len() >= header.len() {
+                next.extend_from_slice(header);
+                next.rotate_right(header.len());
+                return;
+            }
+        }
+        if let Some(ReplyBlock::Deferred(v)) = self.blocks.get_mut(at) {
+            v.extend_from_slice(header);
+            self.block_bytes += v.len();
+        }
+    }
+
+    // Writes the pending replies with writev until `limit` bytes are
+    // written or the socket would block, a WouldBlock error is only
+    // returned when nothing could be written.
+    pub fn write_to(&mut self, fd: RawFd, limit: usize) -> io::Result<usize> {
+        let mut total = 0;
+        while self.pending > 0 && total < limit {
+            match self.writev_once(fd, limit - total) {
+                Ok(n) => {
+                    self.consume(n);
+                    total += n;
+                }
+                Err(nix::Error::Sys(Errno::EINTR)) => continue,
+                Err(nix::Error::Sys(Errno::EAGAIN)) if total > 0 => break,
+                Err(nix::Error::Sys(errno)) => {
+                    return Err(io::Error::from_raw_os_error(errno as i32));
+                }
+                Err(e) => return Err(io::Error::other(e)),
+            }
+        }
+        Ok(total)
+    }
+
+    fn writev_once(&self, fd: RawFd, limit: usize) -> nix::Result<usize> {
+        let objects: Vec<Ref<Robj>> = self.blocks.iter()
+            .take(REPLY_MAX_IOV)
+            .filter_map(|b| match b {
+                ReplyBlock::Object(o) => Some(o.borrow()),
+                _ => None,
+            })
+            .collect();
+        let mut objects = objects.iter();
+
+        let mut iov: Vec<IoVec<&[u8]>> = Vec::with_capacity(REPLY_MAX_IOV + 1);
+        let mut skip = self.sent;
+        let mut queued = 0;
+        if self.buf_pos > 0 {
+            iov.push(IoVec::from_slice(&self.buf[skip..self.buf_pos]));
+            queued += self.buf_pos - skip;
+            skip = 0;
+        }
+        for b in self.blocks.iter().take(REPLY_MAX_IOV) {
+            if queued >= limit {
+                break;
+            }
+            let bytes = match b {
+                ReplyBlock::Chunk(v) | ReplyBlock::Deferred(v) => &v[..],
+                ReplyBlock::Object(_) => objects.next().unwrap().string(),
+            };
+            if bytes.len() > skip {
+                iov.push(IoVec::from_slice(&bytes[skip..]));
+                queued += bytes.len() - skip;
+            }
+            skip = 0;
+        }
+
+        writev(fd, &iov)
+    }
+
+    // drops the `n` bytes just written from the head of the buffer
+    fn consume(&mut self, mut n: usize) {
+        self.pending -= n;
+        if self.buf_pos > 0 {
+            let left = self.buf_pos - self.sent;
+            if n < left {
+                self.sent += n;
+                return;
+            }
+            n -= left;
+            self.buf_pos = 0;
+            self.sent = 0;
+        }
+        while n > 0 {
+            let len = match self.blocks.front() {
+                Some(ReplyBlock::Chunk(v)) | Some(ReplyBlock::Deferred(v)) => v.len(),
+                Some(ReplyBlock::Object(o)) => o.borrow().string().len(),
+                None => return,
+            };
+            let left = len - self.sent;
+            if n < left {
+                self.sent += n;
+                return;
+            }
+            n -= left;
+            self.sent = 0;
+            let block = self.blocks.pop_front().unwrap();
+            self.block_bytes -= block.size();
+        }
+        // empty blocks left at the head by a write that ended on them
+        while let Some(b) = self.blocks.front() {
+            let empty = match b {
+                ReplyBlock::Chunk(v) | ReplyBlock::Deferred(v) => v.is_empty(),
+                ReplyBlock::Object(_) => false,
+            };
+            if !empty || self.sent != 0 {
+                break;
+            }
+            let block = self.blocks.pop_front().unwrap();
+            self.block_bytes -= block.size();
+        }
+    }
+}
+
+impl Default for ReplyBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::net::UnixStream;
+    use std::io::Read;
+
+    fn drain(b: &mut ReplyBuffer, limit: usize) -> Vec<u8> {
+        let (w, mut r) = UnixStream::pair().unwrap();
+        let mut written = 0;
+        while !b.is_empty() {
+            written += b.write_to(w.as_raw_fd(), limit).unwrap();
+        }
+        drop(w);
+        let mut out = vec![];
+        r.read_to_end(&mut out).unwrap();
+        assert_eq!(out.len(), written);
+        out
+    }
+
+    #[test]
+    fn parse_limits() {
+        let mut limits = DEFAULT_OUTPUT_BUFFER_LIMITS;
+        parse_output_buffer_limits("replica 1mb 512kb 10 normal 100 0 0", &mut limits).unwrap();
+        assert_eq!(limits[ClientClass::Replica as usize], OutputBufferLimit {
+            hard: 1024 * 1024, soft: 512 * 1024, soft_seconds: 10,
+        });
+        assert_eq!(
+            output_buffer_limits_string(&limits),
+            "normal 100 0 0 slave 1048576 524288 10 pubsub 33554432 8388608 60",
+        );
+
+        assert!(parse_output_buffer_limits("pubsub 1 2", &mut limits).is_err());
+        assert!(parse_output_buffer_limits("normal 1 1 1 master 1 1 1", &mut limits).is_err());
+        assert!(parse_output_buffer_limits("normal 1 1 1 pubsub x 1 1", &mut limits).is_err());
+        assert_eq!(limits[ClientClass::Normal as usize].hard, 100);
+    }
+
+    #[test]
+    fn spill_to_blocks() {
+        let mut b = ReplyBuffer::new();
+        let mut expected = vec![];
+        for i in 0..5000 {
+            let s = format!(":{}\r\n", i);
+            b.add_bytes(s.as_bytes());
+            expected.extend_from_slice(s.as_bytes());
+        }
+        assert_eq!(b.buf_len(), REPLY_BUF_LEN);
+        assert!(b.blocks_len() > 0);
+        assert_eq!(b.len(), expected.len());
+        assert_eq!(drain(&mut b, 1000), expected);
+        assert_eq!(b.memory(), 0);
+    }
+
+    #[test]
+    fn shared_objects_and_deferred() {
+        let mut b = ReplyBuffer::new();
+        let big = vec![b'x'; REPLY_SHARED_MIN_LEN * 2];
+        let at = b.add_deferred();
+        b.add_bytes(b"$8192\r\n");
+        b.add_object(Robj::from_bytes(big.clone()));
+        b.add_bytes(b"\r\n");
+        b.add_object(Robj::create_string_object("small"));
+        b.set_deferred(at, b"*2\r\n");
+        assert_eq!(b.buf_len(), 0);
+        assert_eq!(b.blocks_len(), 4);
+
+        let mut expected = b"*2\r\n$8192\r\n".to_vec();
+        expected.extend_from_slice(&big);
+        expected.extend_from_slice(b"\r\nsmall");
+        assert_eq!(b.len(), expected.len());
+        assert_eq!(drain(&mut b, 7), expected);
+        assert_eq!(b.blocks_len(), 0);
+        assert_eq!(b.memory(), 0);
+    }
+
+    #[test]
+    fn many_deferred_headers() {
+        let mut b = ReplyBuffer::new();
+        let mut expected = vec![];
+        b.add_bytes(&vec![b'x'; REPLY_BUF_LEN]);
+        expected.extend_from_slice(&vec![b'x'; REPLY_BUF_LEN]);
+        for i in 0..1000 {
+            let at = b.add_deferred();
+            let s = format!(":{}\r\n", i);
+            b.add_bytes(s.as_bytes());
+            b.set_deferred(at, b"*1\r\n");
+            expected.extend_from_slice(b"*1\r\n");
+            expected.extend_from_slice(s.as_bytes());
+        }
+        // only the last chunk keeps its unused space
+        assert!(b.memory() < expected.len() - REPLY_BUF_LEN + REPLY_CHUNK_BYTES);
+        assert_eq!(b.len(), expected.len());
+        assert_eq!(drain(&mut b, 100), expected);
+        assert_eq!(b.memory(), 0);
+    }
+}
@@ -2,14 +2,17 @@ use log::LevelFilter;
 use std::fs::{File, OpenOptions};
 use crate::ae::*;
 use mio::net::TcpListener;
+use mio_uds::UnixListener;
 use mio::net;
 use std::rc::Rc;
 use std::cell::RefCell;
-use crate::client::{Client, ReplyState, CLIENT_MASTER, CLIENT_SLAVE, CLIENT_MONITOR, CLIENT_CLOSE_ASAP};
+use crate::client::{Client, ClientData, ReplyState, CLIENT_MASTER, CLIENT_SLAVE, CLIENT_MONITOR, CLIENT_CLOSE_ASAP,
+                    CLIENT_PAUSED, CLIENT_PENDING_INPUT, CLIENT_CLOSE_AFTER_REPLY};
+use crate::command::{Command, CMD_WRITE, client_info_string};
 use crate::db::DB;
-use crate::env::Config;
-use std::net::SocketAddr;
-use std::time::SystemTime;
+use crate::env::{Config, send_reply_to_client, accept_handler};
+use std::net::{SocketAddr, IpAddr, Ipv4Addr, Ipv6Addr, ToSocketAddrs};
+use std::time::{SystemTime, Duration, Instant};
 use crate::object::linked_list::LinkedList;
 use crate::{zalloc, rdb};
 use crate::object::RobjPtr;
@@ -20,20 +23,61 @@ use std::io::{Write, BufReader, BufRead, Read};
 use crate::util::*;
 use rand::prelude::*;
 use std::fs;
+use std::os::unix::fs::PermissionsExt;
 use std::process::exit;
 use rlua::Lua;
-
+use crate::multi::unwatch_all_keys;
+use crate::pubsub::{pubsub_unsubscribe_all, PendingMessage};
+use crate::tracking::tracking_unlink_client;
+use crate::slowlog::Slowlog;
+use crate::reply::OutputBufferLimit;
+use crate::latency::{LatencyEvents, latency_add_sample_if_needed};
+use crate::acl::{Acl, parse_acl_file};
+use crate::debug::EnableDebugCommand;
+use std::collections::{HashMap, HashSet};
+use crate::notify::{KeyspaceEvent, NOTIFY_EVICTED};
+use crate::protocol::RESP2;
+
+
+// CLIENT PAUSE either holds the commands that may change the dataset or
+// every command
+#[derive(Copy, Clone, PartialEq, PartialOrd)]
+pub enum ClientPauseType {
+    Off,
+    Write,
+    All,
+}
 
 pub struct Server {
     pub port: u16,
-    pub fd: Fd,
+    pub ipfd: Vec<Fd>,
+    pub unix_fd: Option<Fd>,
     pub db: Vec<DB>,
     // TODO: sharing pool
     pub dirty: usize,
     pub clients: LinkedList<Rc<RefCell<Client>>>,
+    // the same clients by id, so that one can be found without borrowing the others
+    clients_index: HashMap<usize, Rc<RefCell<Client>>>,
     pub clients_to_closed: LinkedList<*const Client>,
+    pub clients_pending_input: Vec<Rc<RefCell<Client>>>,
     pub slaves: LinkedList<Rc<RefCell<Client>>>,
     pub monitors: LinkedList<Rc<RefCell<Client>>>,
+    pub pubsub_channels: HashMap<Vec<u8>, Vec<Rc<RefCell<Client>>>>,
+    pub pubsub_patterns: Vec<(Vec<u8>, Rc<RefCell<Client>>)>,
+    pub pubsub_shard_channels: HashMap<Vec<u8>, Vec<Rc<RefCell<Client>>>>,
+    pub pubsub_pending: Vec<PendingMessage>,
+    pub keyspace_events: Vec<KeyspaceEvent>,
+    pub slowlog: Slowlog,
+    pub latency_events: LatencyEvents,
+    pub next_client_id: usize,
+    // keys read by clients in default tracking mode, mapped to client ids
+    pub tracking_table: HashMap<Vec<u8>, HashSet<usize>>,
+    // prefixes registered by clients in BCAST mode, mapped to client ids
+    pub tracking_prefixes: HashMap<Vec<u8>, HashSet<usize>>,
+    pub tracking_clients: usize,
+    pub tracking_flushed: bool,
+    pub client_pause_type: ClientPauseType,
+    pub client_pause_end: SystemTime,
     pub cron_loops: usize,
     pub last_save: SystemTime,
     pub used_memory: usize,
@@ -45,8 +89,13 @@ pub struct Server {
     pub stat_start_time: SystemTime,
     pub stat_num_commands: usize,
     pub stat_num_connections: usize,
+    pub stat_evicted_keys: usize,
+    pub stat_peak_memory: usize,
+    pub stat_client_qbuf_limit_disconnections: usize,
+    pub stat_client_commands_cap_hits: usize,
 
     // configuration
+    pub config_file: Option<String>,
     pub verbosity: LevelFilter,
     pub glue_output: bool,
     pub max_idle_time: usize,
@@ -54,10 +103,31 @@ pub struct Server {
     pub bg_save_in_progress: bool,
     pub bg_save_child_pid: i32,
     pub save_params: Vec<(usize, usize)>,
+    pub notify_keyspace_events: i32,
+    pub tracking_table_max_keys: usize,
+    pub slowlog_log_slower_than: i64,
+    pub slowlog_max_len: usize,
+    pub latency_monitor_threshold: usize,
+    // client-output-buffer-limit, indexed by ClientClass
+    pub client_obuf_limits: [OutputBufferLimit; 3],
+    pub client_max_querybuf_len: usize,
+    pub proto_max_bulk_len: usize,
+    // allocated memory once the server is initialized, before loading data
+    pub initial_memory_usage: usize,
+    pub enable_debug_command: EnableDebugCommand,
+    // DEBUG SET-ACTIVE-EXPIRE 0 stops the cron from expiring keys
+    pub active_expire_enabled: bool,
+    // the replication id, changed by DEBUG CHANGE-REPL-ID
+    pub replid: String,
     pub log_file: Option<File>,
-    pub bind_addr: String,
+    pub bind_addrs: Vec<String>,
+    pub unix_socket: Option<String>,
+    pub unix_socket_perm: u32,
     pub db_filename: String,
     pub require_pass: Option<String>,
+    pub acl: Acl,
+    pub acl_file: Option<String>,
+    pub acllog_max_len: usize,
 
     pub is_slave: bool,
     pub master_host: Option<String>,
@@ -72,12 +142,19 @@ pub struct Server {
 
 impl Server {
     pub fn new(config: &Config) -> Server {
-        let addr: SocketAddr = format!("{}:{}", config.bind_addr, config.port).parse().unwrap();
-        let server = TcpListener::bind(&addr).unwrap_or_else(|e| {
-            eprintln!("Error bind address {:?}: {}", addr, e);
+        let ipfd = listen_to_port(config.port, &config.bind_addrs).unwrap_or_else(|e| {
+            eprintln!("{}", e);
             exit(1);
         });
-        let fd = Rc::new(RefCell::new(Fdp::Listener(server)));
+        let unix_fd = config.unix_socket.as_ref().map(|path| {
+            Rc::new(RefCell::new(Fdp::Listener(Listener::Unix(
+                listen_to_unix_socket(path, config.unix_socket_perm)
+            ))))
+        });
+        if ipfd.is_empty() && unix_fd.is_none() {
+            eprintln!("Configured to not listen anywhere, exiting.");
+            exit(1);
+        }
 
         let mut db: Vec<DB> = Vec::with_capacity(config.db_num);
         for i in 0..config.db_num {
@@ -92,6 +169,21 @@ impl Server {
         let shutdown_asap = Arc::new(AtomicBool::new(false));
         set_up_signal_handling(&shutdown_asap);
 
+        let mut acl = Acl::default();
+        if let Some(filename) = &config.acl_file {
+            let r = std::fs::read_to_string(filename)
+                .map_err(|e| format!("opening file '{}': {}", filename, e))
+                .and_then(|content| parse_acl_file(&content, filename));
+            acl.users = r.unwrap_or_else(|e| {
+                eprintln!("Error loading ACLs, {}", e);
+                exit(1);
+            });
+        }
+        // requirepass is the password of the default user
+        if config.require_pass.is_some() {
+            acl.set_default_password(config.require_pass.as_deref());
+        }
+
         let reply_state = match config.master_host {
             Some(_) => ReplyState::Connect,
             _ => ReplyState::None,
@@ -99,13 +191,30 @@ impl Server {
 
         Server {
             port: config.port,
-            fd,
+            ipfd,
+            unix_fd,
             db,
             dirty: 0,
             clients: LinkedList::new(),
+            clients_index: HashMap::new(),
             clients_to_closed: LinkedList::new(),
+            clients_pending_input: vec![],
             slaves: LinkedList::new(),
             monitors: LinkedList::new(),
+            pubsub_channels: HashMap::new(),
+            pubsub_patterns: vec![],
+            pubsub_shard_channels: HashMap::new(),
+            pubsub_pending: vec![],
+            keyspace_events: vec![],
+            slowlog: Slowlog::default(),
+            latency_events: HashMap::new(),
+            next_client_id: 1,
+            tracking_table: HashMap::new(),
+            tracking_prefixes: HashMap::new(),
+            tracking_clients: 0,
+            tracking_flushed: false,
+            client_pause_type: ClientPauseType::Off,
+            client_pause_end: SystemTime::now(),
 
             cron_loops: 0,
             last_save: SystemTime::now(),
@@ -117,7 +226,12 @@ impl Server {
             stat_start_time: SystemTime::now(),
             stat_num_commands: 0,
             stat_num_connections: 0,
+            stat_evicted_keys: 0,
+            stat_peak_memory: 0,
+            stat_client_qbuf_limit_disconnections: 0,
+            stat_client_commands_cap_hits: 0,
 
+            config_file: config.config_file.clone(),
             verbosity: config.log_level,
             glue_output: config.glue_output,
             max_idle_time: config.max_idle_time,
@@ -125,10 +239,27 @@ impl Server {
             bg_save_in_progress: false,
             bg_save_child_pid: -1,
             save_params: config.save_params.clone(),
+            notify_keyspace_events: config.notify_keyspace_events,
+            tracking_table_max_keys: config.tracking_table_max_keys,
+            slowlog_log_slower_than: config.slowlog_log_slower_than,
+            slowlog_max_len: config.slowlog_max_len,
+            latency_monitor_threshold: config.latency_monitor_threshold,
+            client_obuf_limits: config.client_obuf_limits,
+            client_max_querybuf_len: config.client_max_querybuf_len,
+            proto_max_bulk_len: config.proto_max_bulk_len,
+            initial_memory_usage: 0,
+            enable_debug_command: config.enable_debug_command,
+            active_expire_enabled: true,
+            replid: gen_replid(),
             log_file,
-            bind_addr: config.bind_addr.clone(),
+            bind_addrs: config.bind_addrs.clone(),
+            unix_socket: config.unix_socket.clone(),
+            unix_socket_perm: config.unix_socket_perm,
             db_filename: config.db_filename.clone(),
             require_pass: config.require_pass.clone(),
+            acl,
+            acl_file: config.acl_file.clone(),
+            acllog_max_len: config.acllog_max_len,
 
             is_slave: false,
             master_host: config.master_host.clone(),
@@ -142,16 +273,78 @@ impl Server {
         }
     }
 
+    pub fn change_replication_id(&mut self) {
+        self.replid = gen_replid();
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stat_num_commands = 0;
+        self.stat_num_connections = 0;
+        self.stat_evicted_keys = 0;
+        self.stat_peak_memory = self.used_memory;
+        for db in self.db.iter_mut() {
+            db.stat_keyspace_hits = 0;
+            db.stat_keyspace_misses = 0;
+            db.stat_expired_keys = 0;
+        }
+    }
+
+    pub fn update_memory_stats(&mut self) {
+        self.used_memory = zalloc::allocated_memory();
+        if self.used_memory > self.stat_peak_memory {
+            self.stat_peak_memory = self.used_memory;
+        }
+    }
+
+    pub fn create_client_id(&mut self) -> usize {
+        let id = self.next_client_id;
+        self.next_client_id += 1;
+        id
+    }
+
+    pub fn link_client(&mut self, c: &Rc<RefCell<Client>>) {
+        let id = c.borrow().id;
+        self.clients.push_back(Rc::clone(c));
+        self.clients_index.insert(id, Rc::clone(c));
+    }
+
+    pub fn lookup_client_by_id(&self, id: usize) -> Option<Rc<RefCell<Client>>> {
+        self.clients_index.get(&id).map(Rc::clone)
+    }
+
     pub fn free_client(&mut self, c: &Rc<RefCell<Client>>) {
         self.clients.delete_first_n_filter(self.clients.len(), |x| {
             Rc::ptr_eq(&c, x)
         });
+        self.free_client_resources(&c.borrow());
+    }
+
+    // releases what the rest of the server holds on behalf of the client
+    fn free_client_resources(&mut self, c: &Client) {
+        self.clients_index.remove(&c.id);
+        unwatch_all_keys(&mut self.db, c);
+        pubsub_unsubscribe_all(self, c);
+        tracking_unlink_client(self, c);
+    }
+
+    // a client closed while scheduled for an async free leaves the queue,
+    // a new client could be allocated at the same address
+    fn remove_from_async_free_queue(&mut self, ptr: *const Client) {
+        self.clients_to_closed.delete_first_n_filter(1, |x| *x == ptr);
     }
 
     pub fn free_client_with_flags(&mut self, c: &Rc<RefCell<Client>>, flags: i32) {
         self.clients.delete_first_n_filter(self.clients.len(), |x| {
             Rc::ptr_eq(&c, x)
         });
+        // the client is borrowed by the caller, so it is matched by address
+        self.clients_index.retain(|_, x| !Rc::ptr_eq(c, x));
+        if flags & CLIENT_CLOSE_ASAP != 0 {
+            self.remove_from_async_free_queue(c.as_ptr());
+        }
+        if flags & CLIENT_PENDING_INPUT != 0 {
+            self.clients_pending_input.retain(|x| !Rc::ptr_eq(c, x));
+        }
         if flags & CLIENT_SLAVE != 0 {
             let list = if c.borrow().flags & CLIENT_MONITOR != 0 {
                 &mut self.monitors
@@ -173,6 +366,13 @@ impl Server {
         self.clients.delete_first_n_filter(1, |x| {
             ptr == x.as_ptr()
         });
+        if c.flags & CLIENT_CLOSE_ASAP != 0 {
+            self.remove_from_async_free_queue(ptr);
+        }
+        if c.flags & CLIENT_PENDING_INPUT != 0 {
+            self.clients_pending_input.retain(|x| !std::ptr::eq(x.as_ptr(), ptr));
+        }
+        self.free_client_resources(c);
         if c.flags & CLIENT_SLAVE != 0 {
             let list = if c.flags & CLIENT_MONITOR != 0 {
                 &mut self.monitors
@@ -189,13 +389,12 @@ impl Server {
         }
     }
 
-    fn free_client_by_ptr(&mut self, ptr: *const Client) -> Rc<RefCell<Client>> {
+    fn free_client_by_ptr(&mut self, ptr: *const Client) -> Option<Rc<RefCell<Client>>> {
         // only used in server_cron
         // TODO: this can be improved
         let client_rc = self.clients.iter()
             .find(|x| ptr == x.as_ptr())
-            .map(|x| Rc::clone(x))
-            .unwrap();
+            .map(|x| Rc::clone(x))?;
 
         self.clients.delete_first_n_filter(1, |x| {
             Rc::ptr_eq(&client_rc, x)
@@ -203,6 +402,7 @@ impl Server {
 
         {
             let c = client_rc.borrow();
+            self.free_client_resources(&c);
             if c.flags & CLIENT_SLAVE != 0 {
                 let list = if c.flags & CLIENT_MONITOR != 0 {
                     &mut self.monitors
@@ -219,12 +419,16 @@ impl Server {
             }
         }
 
-        client_rc
+        Some(client_rc)
     }
 
     pub fn free_clients_in_async_free_queue(&mut self, el: &mut AeEventLoop) {
         while let Some(client) = self.clients_to_closed.pop_front() {
-            let client = self.free_client_by_ptr(client);
+            // the client may have been freed since, e.g. when it closed the connection
+            let client = match self.free_client_by_ptr(client) {
+                Some(c) => c,
+                None => continue,
+            };
             let client_ref = client.borrow();
             el.delete_file_event(&client_ref.fd, AE_WRITABLE);
             el.delete_file_event(&client_ref.fd, AE_WRITABLE | AE_READABLE);
@@ -232,11 +436,61 @@ impl Server {
         }
     }
 
+    // Queues a client that ran the max commands in one go, the rest of its
+    // queries are run by the next iteration of the event loop.
+    pub fn defer_client_input(&mut self, c: &mut Client, el: &mut AeEventLoop) {
+        self.stat_client_commands_cap_hits += 1;
+        if c.flags & CLIENT_PENDING_INPUT != 0 {
+            return;
+        }
+        c.flags |= CLIENT_PENDING_INPUT;
+        if self.clients_pending_input.is_empty() {
+            el.create_time_event(Duration::from_millis(0), process_clients_with_pending_input,
+                                 ClientData::Nil(), default_ae_event_finalizer_proc);
+        }
+        let c = self.find_client(c);
+        self.clients_pending_input.push(c);
+    }
+
     pub fn async_free_client(&mut self, c: &mut Client) {
+        if c.flags & CLIENT_CLOSE_ASAP != 0 {
+            return;
+        }
         c.flags |= CLIENT_CLOSE_ASAP;
         self.clients_to_closed.push_back(c as *const Client);
     }
 
+    // Schedules the close of a client whose output buffer went over the
+    // limits of its class, its pending replies are dropped right away.
+    pub fn close_client_on_output_buffer_limit_reached(&mut self, c: &mut Client) -> bool {
+        if c.flags & (CLIENT_MASTER | CLIENT_CLOSE_ASAP) != 0 {
+            return false;
+        }
+        if !c.output_buffer_limit_reached(&self.client_obuf_limits) {
+            return false;
+        }
+        warn!("Client {} scheduled to be closed ASAP for overcoming of output buffer limits.",
+              client_info_string(c));
+        c.reply.clear();
+        self.async_free_client(c);
+        true
+    }
+
+    // the same for clients fed by `current`, which is borrowed already
+    pub fn close_clients_on_output_buffer_limit_reached(
+        &mut self,
+        current: &mut Client,
+        clients: &[Rc<RefCell<Client>>],
+    ) {
+        for c in clients.iter() {
+            if std::ptr::eq(c.as_ptr(), current) {
+                self.close_client_on_output_buffer_limit_reached(current);
+            } else {
+                self.close_client_on_output_buffer_limit_reached(&mut c.borrow_mut());
+            }
+        }
+    }
+
     pub fn find_client(&self, c: &Client) -> Rc<RefCell<Client>> {
         let ptr1 = c as *const Client;
         for client in self.clients.iter() {
@@ -248,6 +502,56 @@ impl Server {
         unreachable!()
     }
 
+    pub fn client_pause_type(&self) -> ClientPauseType {
+        if SystemTime::now() >= self.client_pause_end {
+            ClientPauseType::Off
+        } else {
+            self.client_pause_type
+        }
+    }
+
+    // A longer or stricter pause wins over the one in progress. The paused
+    // clients are resumed by a time event fired when the pause ends.
+    pub fn pause_clients(&mut self, el: &mut AeEventLoop, end: SystemTime, t: ClientPauseType) {
+        let current = self.client_pause_type();
+        if t > current {
+            self.client_pause_type = t;
+        } else {
+            self.client_pause_type = current;
+        }
+        if end > self.client_pause_end {
+            self.client_pause_end = end;
+        }
+        let after = self.client_pause_end
+            .duration_since(SystemTime::now())
+            .unwrap_or_default();
+        el.create_time_event(after, resume_paused_clients, ClientData::Nil(),
+                             default_ae_event_finalizer_proc);
+    }
+
+    pub fn unpause_clients(&mut self, el: &mut AeEventLoop) {
+        self.client_pause_type = ClientPauseType::Off;
+        self.client_pause_end = SystemTime::now();
+        el.create_time_event(Duration::from_millis(0), resume_paused_clients,
+                             ClientData::Nil(), default_ae_event_finalizer_proc);
+    }
+
+    // masters and slaves are never paused
+    pub fn is_command_paused(&self, c: &Client, cmd: &Command) -> bool {
+        if c.flags & (CLIENT_SLAVE | CLIENT_MASTER) != 0 {
+            return false;
+        }
+        match self.client_pause_type() {
+            ClientPauseType::Off => false,
+            ClientPauseType::All => true,
+            ClientPauseType::Write => {
+                cmd.flags & CMD_WRITE != 0 ||
+                    ["eval", "publish", "spublish"].contains(&cmd.name) ||
+                    (cmd.name == "exec" && c.mstate.iter().any(|m| m.cmd.flags & CMD_WRITE != 0))
+            }
+        }
+    }
+
     pub fn transfer_client_to_slaves(&mut self, c: &Client, monitor: bool) {
         let c = self.find_client(c);
         if monitor {
@@ -262,20 +566,33 @@ impl Server {
         let now = SystemTime::now();
         let len = self.clients.len();
         let max_idle_time = self.max_idle_time;
-        self.clients.delete_first_n_filter(len, |x| {
+        let is_idle = |x: &Rc<RefCell<Client>>| {
+            let c = x.borrow();
+            // replicas, the master and RESP2 subscribers are quiet by design,
+            // a paused client is waiting for the server
+            if c.flags & (CLIENT_SLAVE | CLIENT_MONITOR | CLIENT_MASTER | CLIENT_PAUSED) != 0 ||
+                (c.resp == RESP2 && c.subscription_count() > 0) {
+                return false;
+            }
             let elapsed =
-                now.duration_since(x.borrow().last_interaction)
+                now.duration_since(c.last_interaction)
                     .unwrap()
                     .as_secs() as usize;
-            if elapsed > max_idle_time {
-                true
-            } else {
-                false
-            }
-        })
+            elapsed > max_idle_time
+        };
+        let idle_clients: Vec<Rc<RefCell<Client>>> = self.clients.iter()
+            .filter(|x| is_idle(x))
+            .map(Rc::clone)
+            .collect();
+        for c in idle_clients.iter() {
+            self.free_client_resources(&c.borrow());
+        }
+        self.clients.delete_first_n_filter(len, is_idle)
     }
 
     pub fn free_memory_if_needed(&mut self) {
+        let start = Instant::now();
+        let mut evicted = false;
         while self.max_memory > 0 && zalloc::allocated_memory() > self.max_memory {
             // for now only keys in expires table will be freed
             let mut freed: usize = 0;
@@ -305,22 +622,48 @@ impl Server {
                 }
                 if let Some(key) = min_key {
                     let _ = db.delete_key(&key);
+                    db.signal_modified_key(&key);
+                    if self.notify_keyspace_events & NOTIFY_EVICTED != 0 {
+                        self.keyspace_events.push(KeyspaceEvent {
+                            class: NOTIFY_EVICTED,
+                            event: "evicted",
+                            key,
+                            db: db.id,
+                        });
+                    }
                     freed += 1;
+                    self.stat_evicted_keys += 1;
                 }
             }
             if freed == 0 {
-                return;
+                break;
             }
+            evicted = true;
+        }
+        if evicted {
+            latency_add_sample_if_needed(self, "eviction-cycle", start.elapsed());
+        }
+    }
+
+    // queues a keyspace event, it's published once the command returns
+    pub fn notify_keyspace_event(&mut self, class: i32, event: &'static str, key: &RobjPtr, db: usize) {
+        if self.notify_keyspace_events & class == 0 {
+            return;
         }
+        self.keyspace_events.push(KeyspaceEvent { class, event, key: Rc::clone(key), db });
     }
 
     pub fn flush_db(&mut self, idx: usize) {
+        self.db[idx].signal_flushed_db();
+        self.tracking_flushed = true;
+        let watched_keys = std::mem::take(&mut self.db[idx].watched_keys);
         self.db[idx] = DB::new(idx);
+        self.db[idx].watched_keys = watched_keys;
     }
 
     pub fn flush_all(&mut self) {
         for i in 0..self.db.len() {
-            self.db[i] = DB::new(i);
+            self.flush_db(i);
         }
     }
 
@@ -332,6 +675,7 @@ impl Server {
 
         match rdb::rdb_save(self) {
             Ok(_) => {
+                self.remove_unix_socket();
                 warn!("{} bytes used at exit", crate::zalloc::allocated_memory());
                 warn!("Server exit now, bye bye...");
             }
@@ -341,9 +685,33 @@ impl Server {
         }
     }
 
+    // The listeners of the old addresses are closed before binding the new
+    // ones, which may be the same. On error no TCP listener is left, the
+    // caller binds the old addresses again.
+    pub fn change_bind_addrs(&mut self, el: &mut AeEventLoop) -> Result<(), String> {
+        for fd in self.ipfd.drain(..) {
+            el.delete_listener_event(&fd);
+        }
+        self.ipfd = listen_to_port(self.port, &self.bind_addrs)?;
+        for fd in self.ipfd.iter() {
+            el.create_file_event(Rc::clone(fd), AE_READABLE, accept_handler, ClientData::Nil())
+                .map_err(|()| "Failed to create the accept handler".to_string())?;
+        }
+        Ok(())
+    }
+
+    pub fn remove_unix_socket(&self) {
+        if let Some(path) = &self.unix_socket {
+            info!("Removing the unix socket file");
+            let _ = fs::remove_file(path);
+        }
+    }
+
     pub fn sync_with_master(&mut self, el: &mut AeEventLoop) -> Result<(), Box<dyn Error>> {
-        let addr = self.master_host.as_ref().unwrap();
-        let addr: SocketAddr = format!("{}:{}", addr, self.master_port).parse()?;
+        let host = self.master_host.as_ref().unwrap();
+        let addr: SocketAddr = (host.as_str(), self.master_port).to_socket_addrs()?
+            .next()
+            .ok_or_else(|| format!("Unable to resolve MASTER: {}", format_addr(host, self.master_port)))?;
         let mut buf: [u8; 1024] = [0; 1024];
         let mut line_buf = String::from("");
         let mut socket = std::net::TcpStream::connect(&addr).map_err(|e| {
@@ -423,13 +791,15 @@ impl Server {
             warn!("Error reconnecting to master: {}", e);
             e
         })?;
-        let fd = Rc::new(RefCell::new(Fdp::Stream(socket)));
+        let fd = Rc::new(RefCell::new(Fdp::Stream(Stream::Tcp(socket))));
         let master = Client::with_fd_and_el(fd, el).map_err(|_| {
             warn!("Failed trying to create client with master");
             std::io::Error::new(std::io::ErrorKind::Other, "error creating client")
         })?;
         master.borrow_mut().flags |= CLIENT_MASTER;
-        self.clients.push_back(Rc::clone(&master));
+        master.borrow_mut().id = self.create_client_id();
+        master.borrow_mut().addr = addr.to_string();
+        self.link_client(&master);
         self.master = Some(master);
         self.reply_state = ReplyState::Connected;
 
@@ -441,6 +811,140 @@ impl Server {
     }
 }
 
+// Binds a listener per address, none when the port is 0. The addresses
+// prefixed with '-' are skipped when they are not available on the host,
+// "*" and "::*" are all the IPv4 and all the IPv6 interfaces.
+pub fn listen_to_port(port: u16, addrs: &[String]) -> Result<Vec<Fd>, String> {
+    let mut fds = vec![];
+    if port == 0 {
+        return Ok(fds);
+    }
+    for addr in addrs {
+        let (optional, ip) = parse_bind_addr(addr)?;
+        let sock_addr = SocketAddr::new(ip, port);
+        match bind_tcp_listener(&sock_addr) {
+            Ok(l) => fds.push(Rc::new(RefCell::new(Fdp::Listener(Listener::Tcp(l))))),
+            Err(ref e) if optional && is_addr_unavailable(e) => {
+                warn!("Skipping the unavailable bind address {}: {}", sock_addr, e);
+            }
+            Err(e) => {
+                return Err(format!("Could not create server TCP listening socket {}: {}", sock_addr, e));
+            }
+        }
+    }
+    Ok(fds)
+}
+
+// whether the address is optional, and its IP
+pub fn parse_bind_addr(addr: &str) -> Result<(bool, IpAddr), String> {
+    let (optional, addr) = match addr.strip_prefix('-') {
+        Some(addr) => (true, addr),
+        None => (false, addr),
+    };
+    let ip: IpAddr = match addr {
+        "*" => Ipv4Addr::UNSPECIFIED.into(),
+        "::*" => Ipv6Addr::UNSPECIFIED.into(),
+        _ => addr.parse().map_err(|_| format!("Invalid bind address '{}'", addr))?,
+    };
+    Ok((optional, ip))
+}
+
+// the IPv6 listeners only take IPv6 connections, so that "*" and "::*"
+// can be bound together
+fn bind_tcp_listener(addr: &SocketAddr) -> std::io::Result<TcpListener> {
+    let builder = match addr {
+        SocketAddr::V4(_) => net2::TcpBuilder::new_v4()?,
+        SocketAddr::V6(_) => {
+            let builder = net2::TcpBuilder::new_v6()?;
+            builder.only_v6(true)?;
+            builder
+        }
+    };
+    builder.reuse_address(true)?;
+    builder.bind(addr)?;
+    TcpListener::from_std(builder.listen(511)?)
+}
+
+fn is_addr_unavailable(e: &std::io::Error) -> bool {
+    e.kind() == std::io::ErrorKind::AddrNotAvailable ||
+        [nix::libc::EAFNOSUPPORT, nix::libc::EPROTONOSUPPORT].contains(&e.raw_os_error().unwrap_or(0))
+}
+
+// a stale socket file left by a previous run is replaced
+fn listen_to_unix_socket(path: &str, perm: u32) -> UnixListener {
+    let _ = fs::remove_file(path);
+    let listener = UnixListener::bind(path).unwrap_or_else(|e| {
+        eprintln!("Error opening Unix socket {}: {}", path, e);
+        exit(1);
+    });
+    if perm != 0 {
+        if let Err(e) = fs::set_permissions(path, fs::Permissions::from_mode(perm)) {
+            eprintln!("Error setting the permissions of Unix socket {}: {}", path, e);
+            exit(1);
+        }
+    }
+    listener
+}
+
+// time event resuming the clients paused by CLIENT PAUSE
+pub fn resume_paused_clients(
+    server: &mut Server,
+    el: &mut AeEventLoop,
+    _id: i64,
+    _data: &ClientData,
+) -> i32 {
+    // a longer pause has its own event
+    if server.client_pause_type() != ClientPauseType::Off {
+        return -1;
+    }
+    let paused: Vec<Rc<RefCell<Client>>> = server.clients.iter()
+        .filter(|c| c.borrow().flags & CLIENT_PAUSED != 0)
+        .map(Rc::clone)
+        .collect();
+    for c in paused {
+        c.borrow_mut().resume(server, el);
+        let c_ref = c.borrow();
+        if !c_ref.reply.is_empty() {
+            let _ = el.create_file_event(
+                Rc::clone(&c_ref.fd),
+                AE_WRITABLE,
+                send_reply_to_client,
+                ClientData::Client(Rc::clone(&c)),
+            );
+        }
+    }
+    -1
+}
+
+// time event running the queries left by the clients that hit the commands
+// cap, the ones hitting it again are queued for the next iteration
+pub fn process_clients_with_pending_input(
+    server: &mut Server,
+    el: &mut AeEventLoop,
+    _id: i64,
+    _data: &ClientData,
+) -> i32 {
+    let pending = std::mem::take(&mut server.clients_pending_input);
+    for c in pending {
+        let mut c_ref = c.borrow_mut();
+        c_ref.flags &= !CLIENT_PENDING_INPUT;
+        c_ref.process_input_buffer(server, el);
+        if c_ref.flags & CLIENT_CLOSE_AFTER_REPLY != 0 && c_ref.reply.is_empty() {
+            server.async_free_client(&mut c_ref);
+        } else if c_ref.flags & CLIENT_MASTER != 0 {
+            c_ref.reply.clear();
+        } else if !c_ref.reply.is_empty() {
+            let _ = el.create_file_event(
+                Rc::clone(&c_ref.fd),
+                AE_WRITABLE,
+                send_reply_to_client,
+                ClientData::Client(Rc::clone(&c)),
+            );
+        }
+    }
+    -1
+}
+
 fn set_up_signal_handling(sig_term_sign: &Arc<AtomicBool>) {
     signal_hook::flag::register(
         signal_hook::SIGTERM,
@@ -460,4 +964,10 @@ fn set_up_signal_handling(sig_term_sign: &Arc<AtomicBool>) {
         signal_hook::SIGHUP,
         useless_flag,
     ).unwrap();
-}
\ No newline at end of file
+}
+
+// 40 random hex characters
+fn gen_replid() -> String {
+    let mut rng = rand::thread_rng();
+    (0..40).map(|_| format!("{:x}", rng.gen_range(0, 16))).collect()
+}
@@ -0,0 +1,166 @@
+use crate::client::Client;
+use crate::server::Server;
+use crate::ae::AeEventLoop;
+use crate::object::{Robj, RobjPtr};
+use crate::util::*;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::time::{Duration, SystemTime};
+
+// an entry keeps at most this many arguments and this many bytes of each
+pub const SLOWLOG_ENTRY_MAX_ARGC: usize = 32;
+pub const SLOWLOG_ENTRY_MAX_STRING: usize = 128;
+
+pub struct SlowlogEntry {
+    pub id: usize,
+    pub time: SystemTime,
+    pub duration: Duration,
+    pub argv: Vec<RobjPtr>,
+    pub peer_id: String,
+    pub client_name: String,
+}
+
+#[derive(Default)]
+pub struct Slowlog {
+    pub entries: VecDeque<SlowlogEntry>,
+    pub next_id: usize,
+}
+
+impl Slowlog {
+    // the newest entries are at the front
+    pub fn push(&mut self, client: &Client, duration: Duration, max_len: usize) {
+        let entry = SlowlogEntry {
+            id: self.next_id,
+            time: SystemTime::now(),
+            duration,
+            argv: slowlog_argv(&client.argv),
+            peer_id: client.addr.clone(),
+            client_name: client.name.as_ref()
+                .map(|n| String::from_utf8_lossy(n.borrow().string()).to_string())
+                .unwrap_or_default(),
+        };
+        self.next_id += 1;
+        self.entries.push_front(entry);
+        self.trim(max_len);
+    }
+
+    pub fn trim(&mut self, max_len: usize) {
+        self.entries.truncate(max_len);
+    }
+
+    pub fn reset(&mut self) {
+        self.entries.clear();
+    }
+}
+
+// Copies the arguments of a command, the ones past the limits are replaced
+// by a note of how much was left out, so a huge command can't fill the memory.
+fn slowlog_argv(argv: &[RobjPtr]) -> Vec<RobjPtr> {
+    let argc = std::cmp::min(argv.len(), SLOWLOG_ENTRY_MAX_ARGC);
+    let mut ret = Vec::with_capacity(argc);
+    for (i, arg) in argv.iter().take(argc).enumerate() {
+        if argc != argv.len() && i == argc - 1 {
+            ret.push(Robj::create_string_object(&format!(
+                "... ({} more arguments)", argv.len() - argc + 1,
+            )));
+            break;
+        }
+        let arg = arg.borrow();
+        let s = arg.string();
+        if s.len() > SLOWLOG_ENTRY_MAX_STRING {
+            let mut truncated = s[..SLOWLOG_ENTRY_MAX_STRING].to_vec();
+            truncated.extend_from_slice(
+                format!("... ({} more bytes)", s.len() - SLOWLOG_ENTRY_MAX_STRING).as_bytes()
+            );
+            ret.push(Robj::from_bytes(truncated));
+        } else {
+            ret.push(Robj::from_bytes(s.to_vec()));
+        }
+    }
+    ret
+}
+
+// called after every command with the time spent in it
+pub fn slowlog_push_entry_if_needed(server: &mut Server, client: &Client, duration: Duration) {
+    if server.slowlog_log_slower_than < 0 {
+        return;
+    }
+    if duration.as_micros() >= server.slowlog_log_slower_than as u128 {
+        let max_len = server.slowlog_max_len;
+        server.slowlog.push(client, duration, max_len);
+    }
+}
+
+pub fn slowlog_command(
+    client: &mut Client,
+    server: &mut Server,
+    _el: &mut AeEventLoop,
+) {
+    let sub = client.argv[1].borrow().string_to_vec();
+    if case_eq(b"reset", &sub) && client.argc() == 2 {
+        server.slowlog.reset();
+        client.add_reply_ok();
+    } else if case_eq(b"len", &sub) && client.argc() == 2 {
+        client.add_reply_integer(server.slowlog.entries.len() as i64);
+    } else if case_eq(b"get", &sub) && (client.argc() == 2 || client.argc() == 3) {
+        let mut count: usize = 10;
+        if client.argc() == 3 {
+            let r = bytes_to_i64(client.argv[2].borrow().string());
+            count = match r {
+                Ok(-1) => server.slowlog.entries.len(),
+                Ok(n) if n >= 0 => n as usize,
+                _ => {
+                    client.add_reply_error("count should be greater than or equal to -1");
+                    return;
+                }
+            };
+        }
+        slowlog_get_reply(client, server, count);
+    } else {
+        client.add_reply_error(&format!(
+            "Unknown SLOWLOG subcommand or wrong number of arguments for '{}'",
+            String::from_utf8_lossy(&sub),
+        ));
+    }
+}
+
+fn slowlog_get_reply(client: &mut Client, server: &Server, count: usize) {
+    let entries: Vec<&SlowlogEntry> = server.slowlog.entries.iter()
+        .take(count)
+        .collect();
+    client.add_reply_array_len(entries.len());
+    for entry in entries {
+        client.add_reply_array_len(6);
+        client.add_reply_integer(entry.id as i64);
+        client.add_reply_integer((unix_timestamp(&entry.time) / 1000) as i64);
+        client.add_reply_integer(entry.duration.as_micros() as i64);
+        client.add_reply_array_len(entry.argv.len());
+        for arg in entry.argv.iter() {
+            client.add_reply_bulk(Rc::clone(arg));
+        }
+        client.add_reply_bulk_bytes(entry.peer_id.as_bytes());
+        client.add_reply_bulk_bytes(entry.client_name.as_bytes());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn truncate_argv() {
+        let argv: Vec<RobjPtr> = (0..40)
+            .map(|i| Robj::create_string_object(&i.to_string()))
+            .collect();
+        let ret = slowlog_argv(&argv);
+        assert_eq!(ret.len(), SLOWLOG_ENTRY_MAX_ARGC);
+        assert_eq!(ret[30].borrow().string(), b"30");
+        assert_eq!(ret[31].borrow().string(), b"... (9 more arguments)");
+
+        let long = vec![Robj::from_bytes(vec![b'a'; 130])];
+        let ret = slowlog_argv(&long);
+        let mut expected = vec![b'a'; 128];
+        expected.extend_from_slice(b"... (2 more bytes)");
+        assert_eq!(ret[0].borrow().string(), &expected[..]);
+    }
+}
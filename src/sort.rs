@@ -1,7 +1,13 @@
-use crate::object::RobjPtr;
+use crate::object::{RobjPtr, RobjType, Robj};
+use crate::db::DB;
+use crate::acl::User;
 use std::cmp::Ordering;
 use std::ops::Range;
 use crate::util::bytes_to_i64;
+use std::rc::Rc;
+
+pub const SORT_BY_DENIED_ERR: &str = "BY option of SORT denied due to insufficient ACL permissions.";
+pub const SORT_GET_DENIED_ERR: &str = "GET option of SORT denied due to insufficient ACL permissions.";
 
 pub fn parse_sort_command(cmd: &[RobjPtr]) -> Result<SortInfo, SortSyntaxError> {
     let mut info = SortInfo {
@@ -62,7 +68,7 @@ pub fn parse_sort_command(cmd: &[RobjPtr]) -> Result<SortInfo, SortSyntaxError>
                         if i < 0 {
                             std::usize::MAX
                         } else {
-                            i as usize
+                            left.saturating_add(i as usize)
                         }
                     },
                     Err(_) => return Err(SortSyntaxError::LimitInvalid),
@@ -78,6 +84,87 @@ pub fn parse_sort_command(cmd: &[RobjPtr]) -> Result<SortInfo, SortSyntaxError>
     Ok(info)
 }
 
+// The position in argv of the STORE destination of SORT, the last one
+// given is used like in `parse_sort_command`.
+pub fn sort_store_index(argv: &[RobjPtr]) -> Option<usize> {
+    let mut store = None;
+    let mut idx: usize = 2;
+    while idx < argv.len() {
+        let s = argv[idx].borrow().string().to_ascii_lowercase();
+        match &s[..] {
+            b"by" | b"get" => idx += 1,
+            b"limit" => idx += 2,
+            b"store" => {
+                if idx + 1 < argv.len() {
+                    store = Some(idx + 1);
+                }
+                idx += 1;
+            }
+            _ => {}
+        }
+        idx += 1;
+    }
+    store
+}
+
+// Looks up the key built from `pat` by substituting its first '*' with
+// `subst`. A pattern like "weight_*->field" reads the field of a hash.
+// The pattern "#" stands for the element itself. The key must be readable
+// by `user`, if any.
+pub fn lookup_key_by_pattern(
+    db: &mut DB,
+    user: Option<&User>,
+    pat: &[u8],
+    subst: &RobjPtr,
+) -> Result<Option<RobjPtr>, SortError> {
+    if pat == b"#" {
+        return Ok(Some(Rc::clone(subst)));
+    }
+
+    let star = match pat.iter().position(|c| *c == b'*') {
+        None => return Ok(None),
+        Some(i) => i,
+    };
+    let field = pat[star + 1..]
+        .windows(2)
+        .position(|w| w == b"->")
+        .map(|i| star + 1 + i)
+        .filter(|i| i + 2 < pat.len());
+
+    let key_end = field.unwrap_or(pat.len());
+    let mut key: Vec<u8> = Vec::with_capacity(key_end + subst.borrow().string_len());
+    key.extend_from_slice(&pat[..star]);
+    key.extend_from_slice(&subst.borrow().string_to_vec());
+    key.extend_from_slice(&pat[star + 1..key_end]);
+    if let Some(user) = user {
+        if !user.can_read_key(&key) {
+            return Err(SortError::KeyNotAllowed);
+        }
+    }
+
+    let o = match db.look_up_key_read(&Robj::from_bytes(key)) {
+        None => return Ok(None),
+        Some(o) => o,
+    };
+    let t = o.borrow().object_type();
+    match field {
+        Some(i) => {
+            if t != RobjType::Hash {
+                return Ok(None);
+            }
+            let field = Robj::create_bytes_object(&pat[i + 2..]);
+            let value = o.borrow().hash_get(&field);
+            Ok(value)
+        }
+        None => {
+            if t != RobjType::String {
+                return Ok(None);
+            }
+            Ok(Some(o))
+        }
+    }
+}
+
 pub struct SortInfo {
     pub options: SortOptions,
     pub limit: Option<Range<usize>>,
@@ -107,9 +194,11 @@ pub enum SortOrder {
 #[derive(Copy, Clone, PartialEq)]
 pub enum SortError {
     ParseNumericError,
+    // a key read through BY or GET is not readable by the user
+    KeyNotAllowed,
 }
 
-type CmpFn<T> = fn(l: &(RobjPtr, T), r: &(RobjPtr, T)) -> Ordering;
+type CmpFn = fn(l: &(RobjPtr, RobjPtr), r: &(RobjPtr, RobjPtr)) -> Ordering;
 
 pub struct SortOptions {
     pub sort_type: SortType,
@@ -117,8 +206,11 @@ pub struct SortOptions {
 }
 
 
+// Elements with equal weights are compared with each other, so the
+// result doesn't depend on the iteration order of the sorted value.
+// This keeps SORT ... STORE the same on the slaves.
 impl SortOptions {
-    pub fn sort<T>(&self, v: &mut Vec<(RobjPtr, T)>) -> Result<(), SortError> {
+    pub fn sort(&self, v: &mut Vec<(RobjPtr, RobjPtr)>) -> Result<(), SortError> {
         if self.sort_type == SortType::Numeric && !Self::all_numeric(v) {
             return Err(SortError::ParseNumericError);
         }
@@ -126,7 +218,7 @@ impl SortOptions {
         Ok(())
     }
 
-    fn all_numeric<T>(v: &mut Vec<(RobjPtr, T)>) -> bool {
+    fn all_numeric(v: &mut Vec<(RobjPtr, RobjPtr)>) -> bool {
         for o in v.iter().map(|t| &t.0) {
             if let Err(_) = o.borrow().parse_to_float() {
                 return false;
@@ -135,23 +227,29 @@ impl SortOptions {
         true
     }
 
-    fn numeric_lt<T>(l: &(RobjPtr, T), r: &(RobjPtr, T)) -> Ordering {
+    fn tie_break(l: &(RobjPtr, RobjPtr), r: &(RobjPtr, RobjPtr)) -> Ordering {
+        l.1.borrow().string_cmp(&(r.1))
+    }
+
+    fn numeric_lt(l: &(RobjPtr, RobjPtr), r: &(RobjPtr, RobjPtr)) -> Ordering {
         l.0.borrow().float().partial_cmp(&r.0.borrow().float()).unwrap()
+            .then_with(|| Self::tie_break(l, r))
     }
 
-    fn numeric_gt<T>(l: &(RobjPtr, T), r: &(RobjPtr, T)) -> Ordering {
-        l.0.borrow().float().partial_cmp(&r.0.borrow().float()).unwrap().reverse()
+    fn numeric_gt(l: &(RobjPtr, RobjPtr), r: &(RobjPtr, RobjPtr)) -> Ordering {
+        Self::numeric_lt(l, r).reverse()
     }
 
-    fn alphabetic_lt<T>(l: &(RobjPtr, T), r: &(RobjPtr, T)) -> Ordering {
+    fn alphabetic_lt(l: &(RobjPtr, RobjPtr), r: &(RobjPtr, RobjPtr)) -> Ordering {
         l.0.borrow().string_cmp(&(r.0))
+            .then_with(|| Self::tie_break(l, r))
     }
 
-    fn alphabetic_gt<T>(l: &(RobjPtr, T), r: &(RobjPtr, T)) -> Ordering {
-        l.0.borrow().string_cmp(&(r.0)).reverse()
+    fn alphabetic_gt(l: &(RobjPtr, RobjPtr), r: &(RobjPtr, RobjPtr)) -> Ordering {
+        Self::alphabetic_lt(l, r).reverse()
     }
 
-    fn get_cmp_func<T>(&self) -> CmpFn<T> {
+    fn get_cmp_func(&self) -> CmpFn {
         use SortType::*;
         use SortOrder::*;
         match (self.sort_type, self.sort_order) {
@@ -0,0 +1,500 @@
+use crate::client::*;
+use crate::server::Server;
+use crate::ae::AeEventLoop;
+use crate::object::RobjPtr;
+use crate::command::{Command, CMD_READONLY, get_keys_from_command};
+use crate::pubsub::add_reply_to_client;
+use crate::util::{case_eq, bytes_to_usize};
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::mem::take;
+use crate::protocol::RESP3;
+
+// Tracking modes a client can not switch to while tracking is on
+const TRACKING_OPTIONS: i32 = CLIENT_TRACKING_BCAST | CLIENT_TRACKING_OPTIN |
+    CLIENT_TRACKING_OPTOUT | CLIENT_TRACKING_NOLOOP;
+
+// keys evicted from the tracking table in a single cron run
+const TRACKING_LIMIT_EFFORT: usize = 100;
+
+// Runs `f` on `c`, which may be the client running the command and thus
+// already borrowed.
+fn with_client<F, R>(current: Option<&mut Client>, c: &Rc<RefCell<Client>>, f: F) -> R
+    where F: FnOnce(&mut Client) -> R {
+    match current {
+        Some(current) if std::ptr::eq(c.as_ptr(), current) => f(current),
+        _ => f(&mut c.borrow_mut()),
+    }
+}
+
+fn enable_tracking(
+    server: &mut Server,
+    client: &mut Client,
+    redirection: usize,
+    options: i32,
+    prefixes: Vec<Vec<u8>>,
+) {
+    if client.flags & CLIENT_TRACKING == 0 {
+        server.tracking_clients += 1;
+    }
+    client.flags &= !(TRACKING_OPTIONS | CLIENT_TRACKING_BROKEN_REDIR);
+    client.flags |= CLIENT_TRACKING | options;
+    client.tracking_redirection = redirection;
+
+    if options & CLIENT_TRACKING_BCAST == 0 {
+        return;
+    }
+    // without any prefix every key is broadcast
+    let prefixes = if prefixes.is_empty() { vec![vec![]] } else { prefixes };
+    for prefix in prefixes {
+        server.tracking_prefixes
+            .entry(prefix.clone())
+            .or_default()
+            .insert(client.id);
+        if !client.tracking_prefixes.contains(&prefix) {
+            client.tracking_prefixes.push(prefix);
+        }
+    }
+}
+
+fn disable_tracking(server: &mut Server, client: &mut Client) {
+    tracking_unlink_client(server, client);
+    client.flags &= !(CLIENT_TRACKING | CLIENT_TRACKING_CACHING |
+        CLIENT_TRACKING_BROKEN_REDIR | TRACKING_OPTIONS);
+    client.tracking_redirection = 0;
+    client.tracking_prefixes.clear();
+}
+
+// Removes the client from the BCAST prefixes. The keys it read in default
+// mode stay in the tracking table and are skipped once they are invalidated.
+pub fn tracking_unlink_client(server: &mut Server, c: &Client) {
+    if c.flags & CLIENT_TRACKING == 0 {
+        return;
+    }
+    for prefix in c.tracking_prefixes.iter() {
+        let empty = match server.tracking_prefixes.get_mut(prefix) {
+            None => false,
+            Some(ids) => {
+                ids.remove(&c.id);
+                ids.is_empty()
+            }
+        };
+        if empty {
+            server.tracking_prefixes.remove(prefix);
+        }
+    }
+    server.tracking_clients -= 1;
+}
+
+fn prefixes_overlap(a: &[u8], b: &[u8]) -> bool {
+    a.starts_with(b) || b.starts_with(a)
+}
+
+fn check_prefix_collisions(client: &Client, prefixes: &[Vec<u8>]) -> Result<(), String> {
+    for (i, prefix) in prefixes.iter().enumerate() {
+        if let Some(old) = client.tracking_prefixes.iter()
+            .find(|old| prefixes_overlap(old, prefix)) {
+            return Err(format!(
+                "Prefix '{}' overlaps with an existing prefix '{}'. \
+                Prefixes for a single client must not overlap.",
+                String::from_utf8_lossy(prefix),
+                String::from_utf8_lossy(old),
+            ));
+        }
+        if let Some(other) = prefixes[i + 1..].iter()
+            .find(|other| prefixes_overlap(other, prefix)) {
+            return Err(format!(
+                "Prefix '{}' overlaps with another provided prefix '{}'. \
+                Prefixes for a single client must not overlap.",
+                String::from_utf8_lossy(prefix),
+                String::from_utf8_lossy(other),
+            ));
+        }
+    }
+    Ok(())
+}
+
+// CLIENT TRACKING <ON|OFF> [REDIRECT id] [PREFIX prefix ...] [BCAST] [OPTIN] [OPTOUT] [NOLOOP]
+pub fn client_tracking_command(client: &mut Client, server: &mut Server) {
+    let mut options: i32 = 0;
+    let mut redirection: usize = 0;
+    let mut prefixes: Vec<Vec<u8>> = vec![];
+
+    let mut j = 3;
+    while j < client.argc() {
+        let opt = client.argv[j].borrow().string_to_vec();
+        let has_next = j + 1 < client.argc();
+        if case_eq(b"redirect", &opt) && has_next {
+            if redirection != 0 {
+                client.add_reply_error("A client can only redirect to a single other client");
+                return;
+            }
+            let r = bytes_to_usize(client.argv[j + 1].borrow().string());
+            redirection = match r {
+                Ok(id) => id,
+                Err(_) => {
+                    client.add_reply_error("value is not an integer or out of range");
+                    return;
+                }
+            };
+            j += 1;
+        } else if case_eq(b"bcast", &opt) {
+            options |= CLIENT_TRACKING_BCAST;
+        } else if case_eq(b"optin", &opt) {
+            options |= CLIENT_TRACKING_OPTIN;
+        } else if case_eq(b"optout", &opt) {
+            options |= CLIENT_TRACKING_OPTOUT;
+        } else if case_eq(b"noloop", &opt) {
+            options |= CLIENT_TRACKING_NOLOOP;
+        } else if case_eq(b"prefix", &opt) && has_next {
+            prefixes.push(client.argv[j + 1].borrow().string_to_vec());
+            j += 1;
+        } else {
+            client.add_reply_error("syntax error");
+            return;
+        }
+        j += 1;
+    }
+
+    let mode = client.argv[2].borrow().string_to_vec();
+    if case_eq(b"off", &mode) {
+        disable_tracking(server, client);
+        client.add_reply_ok();
+        return;
+    } else if !case_eq(b"on", &mode) {
+        client.add_reply_error("syntax error");
+        return;
+    }
+
+    let bcast = options & CLIENT_TRACKING_BCAST != 0;
+    let optin = options & CLIENT_TRACKING_OPTIN != 0;
+    let optout = options & CLIENT_TRACKING_OPTOUT != 0;
+    let tracking = client.flags & CLIENT_TRACKING != 0;
+
+    if !bcast && !prefixes.is_empty() {
+        client.add_reply_error("PREFIX option requires BCAST mode to be enabled");
+        return;
+    }
+    if tracking && bcast != (client.flags & CLIENT_TRACKING_BCAST != 0) {
+        client.add_reply_error("You can't switch BCAST mode on/off before disabling \
+            tracking for this client, and then re-enabling it with a different mode.");
+        return;
+    }
+    if bcast && (optin || optout) {
+        client.add_reply_error("OPTIN and OPTOUT are not compatible with BCAST");
+        return;
+    }
+    if optin && optout {
+        client.add_reply_error("You can't use OPTIN and OPTOUT at the same time");
+        return;
+    }
+    if tracking && ((optin && client.flags & CLIENT_TRACKING_OPTOUT != 0) ||
+        (optout && client.flags & CLIENT_TRACKING_OPTIN != 0)) {
+        client.add_reply_error("You can't switch OPTIN/OPTOUT mode before disabling \
+            tracking for this client, and then re-enabling it with a different mode.");
+        return;
+    }
+    if bcast {
+        if let Err(e) = check_prefix_collisions(client, &prefixes) {
+            client.add_reply_error(&e);
+            return;
+        }
+    }
+    if redirection != 0 && server.lookup_client_by_id(redirection).is_none() {
+        client.add_reply_error("The client ID you want redirect to does not exist");
+        return;
+    }
+
+    enable_tracking(server, client, redirection, options, prefixes);
+    client.add_reply_ok();
+}
+
+// CLIENT CACHING <YES|NO>
+pub fn client_caching_command(client: &mut Client) {
+    if client.flags & CLIENT_TRACKING == 0 ||
+        client.flags & (CLIENT_TRACKING_OPTIN | CLIENT_TRACKING_OPTOUT) == 0 {
+        client.add_reply_error("CLIENT CACHING can be called only when the client is in \
+            tracking mode with OPTIN or OPTOUT mode enabled");
+        return;
+    }
+
+    let opt = client.argv[2].borrow().string_to_vec();
+    if case_eq(b"yes", &opt) {
+        if client.flags & CLIENT_TRACKING_OPTIN == 0 {
+            client.add_reply_error("CLIENT CACHING YES is only valid when tracking is \
+                enabled in OPTIN mode.");
+            return;
+        }
+    } else if case_eq(b"no", &opt) {
+        if client.flags & CLIENT_TRACKING_OPTOUT == 0 {
+            client.add_reply_error("CLIENT CACHING NO is only valid when tracking is \
+                enabled in OPTOUT mode.");
+            return;
+        }
+    } else {
+        client.add_reply_error("syntax error");
+        return;
+    }
+
+    client.flags |= CLIENT_TRACKING_CACHING;
+    client.add_reply_ok();
+}
+
+fn tracking_redirection(client: &Client) -> i64 {
+    if client.flags & CLIENT_TRACKING == 0 {
+        -1
+    } else {
+        client.tracking_redirection as i64
+    }
+}
+
+// CLIENT GETREDIR
+pub fn client_getredir_command(client: &mut Client) {
+    client.add_reply_integer(tracking_redirection(client));
+}
+
+// CLIENT TRACKINGINFO
+pub fn client_trackinginfo_command(client: &mut Client) {
+    let mut flags: Vec<&str> = vec![];
+    if client.flags & CLIENT_TRACKING == 0 {
+        flags.push("off");
+    } else {
+        flags.push("on");
+        for (flag, name) in [
+            (CLIENT_TRACKING_BCAST, "bcast"),
+            (CLIENT_TRACKING_OPTIN, "optin"),
+            (CLIENT_TRACKING_OPTOUT, "optout"),
+            (CLIENT_TRACKING_NOLOOP, "noloop"),
+            (CLIENT_TRACKING_BROKEN_REDIR, "broken_redirect"),
+        ].iter() {
+            if client.flags & flag != 0 {
+                flags.push(name);
+            }
+        }
+        if client.flags & CLIENT_TRACKING_CACHING != 0 {
+            if client.flags & CLIENT_TRACKING_OPTIN != 0 {
+                flags.push("caching-yes");
+            } else {
+                flags.push("caching-no");
+            }
+        }
+    }
+
+    client.add_reply_map_len(3);
+    client.add_reply_bulk_bytes(b"flags");
+    client.add_reply_set_len(flags.len());
+    for flag in flags {
+        client.add_reply_bulk_bytes(flag.as_bytes());
+    }
+    client.add_reply_bulk_bytes(b"redirect");
+    client.add_reply_integer(tracking_redirection(client));
+    client.add_reply_bulk_bytes(b"prefixes");
+    client.add_reply_array_len(client.tracking_prefixes.len());
+    let prefixes = client.tracking_prefixes.clone();
+    for prefix in prefixes {
+        client.add_reply_bulk_bytes(&prefix);
+    }
+}
+
+// Remembers the keys read by a command of a client in default tracking
+// mode, called after the command is executed.
+pub fn tracking_remember_keys(server: &mut Server, client: &Client, cmd: &Command) {
+    let flags = client.flags;
+    if flags & CLIENT_TRACKING == 0 || flags & CLIENT_TRACKING_BCAST != 0 ||
+        cmd.flags & CMD_READONLY == 0 {
+        return;
+    }
+    let caching = flags & CLIENT_TRACKING_CACHING != 0;
+    if (flags & CLIENT_TRACKING_OPTIN != 0 && !caching) ||
+        (flags & CLIENT_TRACKING_OPTOUT != 0 && caching) {
+        return;
+    }
+    for i in get_keys_from_command(cmd, client.argc()) {
+        server.tracking_table
+            .entry(client.argv[i].borrow().string_to_vec())
+            .or_default()
+            .insert(client.id);
+    }
+}
+
+// CLIENT CACHING only applies to the command right after it
+pub fn tracking_reset_caching(client: &mut Client, cmd: &Command) {
+    if client.flags & CLIENT_TRACKING_CACHING == 0 {
+        return;
+    }
+    let caching_cmd = cmd.name == "client" && client.argc() > 1 &&
+        case_eq(b"caching", client.argv[1].borrow().string());
+    if !caching_cmd {
+        client.flags &= !CLIENT_TRACKING_CACHING;
+    }
+}
+
+// RESP2 clients receive invalidations as messages of the __redis__:invalidate
+// channel, RESP3 clients as invalidate push messages. A null means every key
+// is invalidated.
+fn add_invalidation_message(client: &mut Client, key: Option<&[u8]>) {
+    if client.resp == RESP3 {
+        client.add_reply_push_len(2);
+        client.add_reply_bulk_bytes(b"invalidate");
+    } else {
+        client.add_reply_push_len(3);
+        client.add_reply_bulk_bytes(b"message");
+        client.add_reply_bulk_bytes(b"__redis__:invalidate");
+    }
+    match key {
+        None => client.add_reply_null_array(),
+        Some(key) => {
+            client.add_reply_array_len(1);
+            client.add_reply_bulk_bytes(key);
+        }
+    }
+}
+
+fn send_tracking_message(
+    server: &mut Server,
+    el: &mut AeEventLoop,
+    mut current: Option<&mut Client>,
+    tracker: &Rc<RefCell<Client>>,
+    key: Option<&[u8]>,
+) {
+    let redirection = with_client(current.as_deref_mut(), tracker, |c| c.tracking_redirection);
+    let target = if redirection == 0 {
+        Rc::clone(tracker)
+    } else {
+        match server.lookup_client_by_id(redirection) {
+            Some(target) => target,
+            None => {
+                with_client(current, tracker, |c| c.flags |= CLIENT_TRACKING_BROKEN_REDIR);
+                return;
+            }
+        }
+    };
+
+    // a RESP2 client can only receive the messages in the pub/sub context
+    let (resp, subscriptions) = with_client(
+        current.as_deref_mut(), &target, |c| (c.resp, c.subscription_count()),
+    );
+    if resp != RESP3 && subscriptions == 0 {
+        return;
+    }
+    add_reply_to_client(server, el, current, &target, |c| add_invalidation_message(c, key));
+}
+
+fn tracking_invalidate_key(
+    server: &mut Server,
+    el: &mut AeEventLoop,
+    mut current: Option<&mut Client>,
+    key: &[u8],
+    bcast: bool,
+) {
+    let mut ids: Vec<(usize, bool)> = vec![];
+    if bcast {
+        for (prefix, clients) in server.tracking_prefixes.iter() {
+            if key.starts_with(prefix) {
+                ids.extend(clients.iter().map(|id| (*id, true)));
+            }
+        }
+    }
+    if let Some(clients) = server.tracking_table.remove(key) {
+        ids.extend(clients.into_iter().map(|id| (id, false)));
+    }
+
+    for (id, bcast) in ids {
+        let tracker = match server.lookup_client_by_id(id) {
+            None => continue,
+            Some(c) => c,
+        };
+        let is_current = match current.as_deref() {
+            Some(c) => std::ptr::eq(tracker.as_ptr(), c),
+            None => false,
+        };
+        let flags = with_client(current.as_deref_mut(), &tracker, |c| c.flags);
+        // the client may have switched mode since it read the key
+        if flags & CLIENT_TRACKING == 0 || (flags & CLIENT_TRACKING_BCAST != 0) != bcast {
+            continue;
+        }
+        if flags & CLIENT_TRACKING_NOLOOP != 0 && is_current {
+            continue;
+        }
+        send_tracking_message(server, el, current.as_deref_mut(), &tracker, Some(key));
+    }
+}
+
+fn tracking_invalidate_all(
+    server: &mut Server,
+    el: &mut AeEventLoop,
+    mut current: Option<&mut Client>,
+) {
+    server.tracking_table.clear();
+    let trackers: Vec<Rc<RefCell<Client>>> = server.clients.iter()
+        .filter(|c| with_client(current.as_deref_mut(), c, |c| c.flags) & CLIENT_TRACKING != 0)
+        .map(Rc::clone)
+        .collect();
+    for tracker in trackers.iter() {
+        send_tracking_message(server, el, current.as_deref_mut(), tracker, None);
+    }
+}
+
+// Sends the invalidations of the keys modified by a command, by key
+// expiration or eviction, and of flushed databases. `current` is the
+// client running a command, if any.
+pub fn flush_tracking_invalidations(
+    server: &mut Server,
+    el: &mut AeEventLoop,
+    mut current: Option<&mut Client>,
+) {
+    let mut keys: Vec<RobjPtr> = vec![];
+    for db in server.db.iter_mut() {
+        if !db.modified_keys.is_empty() {
+            keys.append(&mut db.modified_keys);
+        }
+    }
+    let flushed = take(&mut server.tracking_flushed);
+    if server.tracking_clients == 0 {
+        return;
+    }
+
+    if flushed {
+        tracking_invalidate_all(server, el, current.as_deref_mut());
+    }
+    for key in keys {
+        let key = key.borrow().string_to_vec();
+        tracking_invalidate_key(server, el, current.as_deref_mut(), &key, true);
+    }
+}
+
+// Keeps the tracking table within tracking-table-max-keys by invalidating
+// keys, as if they were modified, the clients are then free to read them
+// again.
+pub fn tracking_limit_used_slots(server: &mut Server, el: &mut AeEventLoop) {
+    let max_keys = server.tracking_table_max_keys;
+    if max_keys == 0 {
+        return;
+    }
+    let mut effort = TRACKING_LIMIT_EFFORT;
+    while server.tracking_table.len() > max_keys && effort > 0 {
+        let key = match server.tracking_table.keys().next() {
+            None => break,
+            Some(key) => key.clone(),
+        };
+        tracking_invalidate_key(server, el, None, &key, false);
+        effort -= 1;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ae::Fdp;
+
+    #[test]
+    fn prefix_collisions() {
+        let c = Client::with_fd(Rc::new(RefCell::new(Fdp::Nil)));
+        let mut c = c.borrow_mut();
+        assert!(check_prefix_collisions(&c, &[b"user:".to_vec(), b"obj:".to_vec()]).is_ok());
+        assert!(check_prefix_collisions(&c, &[b"user:".to_vec(), b"user:1".to_vec()]).is_err());
+        c.tracking_prefixes.push(b"obj:".to_vec());
+        assert!(check_prefix_collisions(&c, &[b"ob".to_vec()]).is_err());
+        assert!(check_prefix_collisions(&c, &[b"user:".to_vec()]).is_ok());
+    }
+}
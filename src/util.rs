@@ -55,6 +55,24 @@ pub fn parse_port_from_bytes(b: &[u8]) -> Result<u16, Box<dyn Error>> {
     Ok(port)
 }
 
+// "ip:port", with the IPv6 addresses in brackets
+pub fn format_addr(ip: &str, port: u16) -> String {
+    if ip.contains(':') {
+        format!("[{}]:{}", ip, port)
+    } else {
+        format!("{}:{}", ip, port)
+    }
+}
+
+// the reverse of format_addr, the port is empty when missing
+pub fn split_addr(addr: &str) -> (&str, &str) {
+    let (ip, port) = match addr.rfind(':') {
+        Some(i) => (&addr[..i], &addr[i + 1..]),
+        None => (addr, ""),
+    };
+    (ip.trim_start_matches('[').trim_end_matches(']'), port)
+}
+
 #[inline]
 pub fn parse_usize_pair(s1: &str, s2: &str) -> Result<(usize, usize), ParseIntError> {
     let a: usize = parse_usize(s1)?;
@@ -90,6 +108,21 @@ pub fn human_size(s: &str) -> Result<usize, ParseIntError> {
     err
 }
 
+// the reverse of human_size, with two decimals, e.g. "1.50M"
+pub fn bytes_to_human(n: usize) -> String {
+    let units = ["K", "M", "G", "T", "P"];
+    if n < 1024 {
+        return format!("{}B", n);
+    }
+    let mut size = n as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.2}{}", size, units[unit])
+}
+
 pub fn yes_or_no(s: &str) -> Option<bool> {
     if s.eq_ignore_ascii_case("yes") {
         return Some(true);
@@ -138,6 +171,83 @@ pub fn to_system_time(timestamp: u64) -> SystemTime {
     SystemTime::UNIX_EPOCH + Duration::from_millis(timestamp)
 }
 
+pub struct LcsMatch {
+    pub a: (usize, usize),
+    pub b: (usize, usize),
+    pub len: usize,
+}
+
+// the matched ranges are collected from the end of both strings
+// backwards, which is also the order Redis replies with
+pub fn lcs(a: &[u8], b: &[u8], min_match_len: usize) -> (Vec<u8>, Vec<LcsMatch>) {
+    let (alen, blen) = (a.len(), b.len());
+    let width = blen + 1;
+    let mut table: Vec<u32> = vec![0; (alen + 1) * width];
+
+    for i in 1..=alen {
+        for j in 1..=blen {
+            table[i * width + j] = if a[i - 1] == b[j - 1] {
+                table[(i - 1) * width + j - 1] + 1
+            } else {
+                std::cmp::max(table[(i - 1) * width + j], table[i * width + j - 1])
+            };
+        }
+    }
+
+    let mut idx = table[alen * width + blen] as usize;
+    let mut result: Vec<u8> = vec![0; idx];
+    let mut matches: Vec<LcsMatch> = vec![];
+    let mut range: Option<((usize, usize), (usize, usize))> = None;
+    let (mut i, mut j) = (alen, blen);
+
+    while i > 0 && j > 0 {
+        let mut emit = false;
+        if a[i - 1] == b[j - 1] {
+            result[idx - 1] = a[i - 1];
+            range = match range {
+                None => Some(((i - 1, i - 1), (j - 1, j - 1))),
+                Some(((a_start, a_end), (b_start, b_end))) => {
+                    if a_start == i && b_start == j {
+                        Some(((a_start - 1, a_end), (b_start - 1, b_end)))
+                    } else {
+                        emit = true;
+                        Some(((a_start, a_end), (b_start, b_end)))
+                    }
+                }
+            };
+            if let Some(((0, _), _)) | Some((_, (0, _))) = range {
+                emit = true;
+            }
+            idx -= 1;
+            i -= 1;
+            j -= 1;
+        } else {
+            if table[(i - 1) * width + j] > table[i * width + j - 1] {
+                i -= 1;
+            } else {
+                j -= 1;
+            }
+            if range.is_some() {
+                emit = true;
+            }
+        }
+
+        if emit {
+            let ((a_start, a_end), (b_start, b_end)) = range.take().unwrap();
+            let len = a_end - a_start + 1;
+            if min_match_len == 0 || len >= min_match_len {
+                matches.push(LcsMatch {
+                    a: (a_start, a_end),
+                    b: (b_start, b_end),
+                    len,
+                });
+            }
+        }
+    }
+
+    (result, matches)
+}
+
 fn reply_preceding_to_int(bytes: &[u8]) -> i64 {
     let content = &bytes[1..bytes.len() - 2];
     bytes_to_i64(content).unwrap()
@@ -176,6 +286,14 @@ mod test {
         assert!(human_size("2mib").is_err());
     }
 
+    #[test]
+    fn test_bytes_to_human() {
+        assert_eq!(bytes_to_human(15), "15B");
+        assert_eq!(bytes_to_human(1024), "1.00K");
+        assert_eq!(bytes_to_human(1536 * 1024), "1.50M");
+        assert_eq!(bytes_to_human(5 * (1 << 30)), "5.00G");
+    }
+
     #[test]
     fn test_prefix_of() {
         assert!(is_prefix_of("--", "--good"));
@@ -183,10 +301,35 @@ mod test {
         assert!(!is_prefix_of("--", "dm"));
     }
 
+    #[test]
+    fn test_format_and_split_addr() {
+        assert_eq!(format_addr("127.0.0.1", 6379), "127.0.0.1:6379");
+        assert_eq!(format_addr("::1", 6379), "[::1]:6379");
+        assert_eq!(split_addr("127.0.0.1:6379"), ("127.0.0.1", "6379"));
+        assert_eq!(split_addr("[::1]:6379"), ("::1", "6379"));
+        assert_eq!(split_addr("/tmp/redis.sock:0"), ("/tmp/redis.sock", "0"));
+    }
+
     #[test]
     fn test_integer_reply_to_integer() {
         assert_eq!(int_reply_to_int(b":1\r\n"), 1);
         assert_eq!(int_reply_to_int(b":1000\r\n"), 1000);
         assert_eq!(int_reply_to_int(b":-1000\r\n"), -1000);
     }
+
+    #[test]
+    fn test_lcs() {
+        let (s, matches) = lcs(b"ohmytext", b"mynewtext", 0);
+        assert_eq!(s, b"mytext".to_vec());
+        assert_eq!(matches.len(), 2);
+        assert_eq!((matches[0].a, matches[0].b, matches[0].len), ((4, 7), (5, 8), 4));
+        assert_eq!((matches[1].a, matches[1].b, matches[1].len), ((2, 3), (0, 1), 2));
+
+        let (_, matches) = lcs(b"ohmytext", b"mynewtext", 4);
+        assert_eq!(matches.len(), 1);
+
+        let (s, matches) = lcs(b"", b"abc", 0);
+        assert!(s.is_empty());
+        assert!(matches.is_empty());
+    }
 }
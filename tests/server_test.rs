@@ -1,5 +1,6 @@
 #![allow(unused)]
 #![allow(dead_code)]
+#![allow(dependency_on_unit_never_type_fallback)]
 #[macro_use]
 extern crate log;
 extern crate env_logger;
@@ -11,6 +12,8 @@ static A: Zalloc = Zalloc;
 mod common;
 
 use reredis::env::*;
+use reredis::debug::EnableDebugCommand;
+use reredis::notify::keyspace_events_from_str;
 use reredis::oom::oom;
 use reredis::zalloc::Zalloc;
 use threadpool::ThreadPool;
@@ -26,6 +29,9 @@ use redis::Commands;
 use rand::Rng;
 
 const ADDR: &str = "redis://127.0.0.1/";
+const UNIX_SOCKET: &str = "__temp_reredis_test.sock";
+// 192.0.2.1 is a documentation address no interface has, so it is skipped
+const BIND_ADDRS: &str = "127.0.0.1 -::1 -192.0.2.1";
 
 trait TestInputData {}
 
@@ -66,6 +72,10 @@ fn test_main() {
     let handle: thread::JoinHandle<()> = thread::spawn(|| {
         let mut config = Config::new();
         config.db_filename = "__temp_reredis_test_rdb_file.trdb".to_string();
+        config.notify_keyspace_events = keyspace_events_from_str("KEA").unwrap();
+        config.enable_debug_command = EnableDebugCommand::Local;
+        config.unix_socket = Some(UNIX_SOCKET.to_string());
+        config.bind_addrs = BIND_ADDRS.split(' ').map(|a| a.to_string()).collect();
         let mut env = Env::new(&config);
         env.server.clean_rdb = true;
         env.init_server();
@@ -102,6 +112,12 @@ fn test_main() {
 
 
     pool.join();
+    for test in SERIAL_TEST_CASES.iter() {
+        if let Err(e) = (test.func)(Box::new(())) {
+            panic!("\n{}: \n{}\n", test.name, e.to_string());
+        }
+        error!("{} .. ok", test.name);
+    }
     eprintln!("ADMIN: wait for error for 3 seconds");
     if let Ok(err) = receiver.recv_timeout(Duration::from_secs(3)) {
         panic!("{}", err);
@@ -120,6 +136,41 @@ const TEST_CASES: &'static [TestCase] = &[
     TestCase { name: "simple mget", func: test_simple_mget },
     TestCase { name: "simple list push and pop", func: test_simple_list_push_pop },
     TestCase { name: "simple sort", func: test_simple_sort },
+    TestCase { name: "getex and getdel", func: test_getex_getdel },
+    TestCase { name: "simple lcs", func: test_simple_lcs },
+    TestCase { name: "sort store and nosort", func: test_sort_store_nosort },
+    TestCase { name: "multi exec and watch", func: test_multi_exec_watch },
+    TestCase { name: "publish and subscribe", func: test_publish_subscribe },
+    TestCase { name: "sharded publish and subscribe", func: test_sharded_publish_subscribe },
+    TestCase { name: "keyspace notifications", func: test_keyspace_notifications },
+    TestCase { name: "publish from eval to the caller", func: test_publish_from_eval },
+    TestCase { name: "client tracking", func: test_client_tracking },
+    TestCase { name: "client tracking with eval", func: test_client_tracking_eval },
+    TestCase { name: "client name list and kill", func: test_client_name_list_kill },
+    TestCase { name: "config get and set", func: test_config_get_set },
+    TestCase { name: "info sections", func: test_info_sections },
+    TestCase { name: "slowlog", func: test_slowlog },
+    TestCase { name: "latency monitor", func: test_latency_monitor },
+    TestCase { name: "command introspection", func: test_command_introspection },
+    TestCase { name: "memory usage and stats", func: test_memory_usage_stats },
+    TestCase { name: "debug", func: test_debug },
+    TestCase { name: "acl users", func: test_acl_users },
+    TestCase { name: "acl key permissions", func: test_acl_key_permissions },
+    TestCase { name: "hello", func: test_hello },
+    TestCase { name: "client output buffer limit", func: test_client_output_buffer_limit },
+    TestCase { name: "protocol limits", func: test_protocol_limits },
+    TestCase { name: "big arguments", func: test_big_arguments },
+    TestCase { name: "long pipeline", func: test_long_pipeline },
+    TestCase { name: "unix socket", func: test_unix_socket },
+    TestCase { name: "bind addresses", func: test_bind_addresses },
+];
+
+// these change the listeners the others connect to, so they run once the
+// others are done
+const SERIAL_TEST_CASES: &'static [TestCase] = &[
+    TestCase { name: "config set bind", func: test_config_set_bind },
+    TestCase { name: "idle subscriber", func: test_idle_subscriber },
+    TestCase { name: "keyspace hits and misses", func: test_keyspace_hits_misses },
 ];
 
 // simple tests
@@ -278,6 +329,925 @@ fn test_simple_sort(_input: Box<dyn TestInputData>) -> TestResult {
     Ok(())
 }
 
+fn test_getex_getdel(_input: Box<dyn TestInputData>) -> TestResult {
+    error!("ready to getex and getdel");
+    let mut con = establish()?;
+    let _: () = con.set("_getex_key", "_value")?;
+
+    let ret: String = redis::cmd("GETEX").arg("_getex_key").arg("EX").arg(100)
+        .query(&mut con)?;
+    compare("_value".to_string(), ret)?;
+    let ret: i64 = con.ttl("_getex_key")?;
+    if ret <= 0 || ret > 100 {
+        compare_i64(100, ret)?;
+    }
+
+    let ret: String = redis::cmd("GETEX").arg("_getex_key").arg("PERSIST")
+        .query(&mut con)?;
+    compare("_value".to_string(), ret)?;
+    let ret: i64 = con.ttl("_getex_key")?;
+    compare_i64(-1, ret)?;
+
+    let ret: String = redis::cmd("GETDEL").arg("_getex_key").query(&mut con)?;
+    compare("_value".to_string(), ret)?;
+    let ret: Option<String> = con.get("_getex_key")?;
+    is_nil(ret)?;
+
+    Ok(())
+}
+
+fn test_simple_lcs(_input: Box<dyn TestInputData>) -> TestResult {
+    error!("ready to lcs");
+    let mut con = establish()?;
+    let _: () = con.set("_lcs_key1", "ohmytext")?;
+    let _: () = con.set("_lcs_key2", "mynewtext")?;
+
+    let ret: String = redis::cmd("LCS").arg("_lcs_key1").arg("_lcs_key2")
+        .query(&mut con)?;
+    compare("mytext".to_string(), ret)?;
+
+    let ret: i64 = redis::cmd("LCS").arg("_lcs_key1").arg("_lcs_key2").arg("LEN")
+        .query(&mut con)?;
+    compare_i64(6, ret)?;
+
+    Ok(())
+}
+
+fn test_sort_store_nosort(_input: Box<dyn TestInputData>) -> TestResult {
+    error!("ready to sort store and nosort");
+    let mut con = establish()?;
+    for k in &["3", "1", "2"] {
+        let _: () = con.rpush("_sort_store_src", *k)?;
+    }
+    let _: () = con.set("_sort_store_w_1", "30")?;
+    let _: () = con.set("_sort_store_w_2", "20")?;
+    let _: () = con.set("_sort_store_w_3", "10")?;
+
+    let ret: Vec<String> = redis::cmd("SORT").arg("_sort_store_src")
+        .arg("BY").arg("nosort").query(&mut con)?;
+    compare_vec(vec!["3", "1", "2"], ret)?;
+
+    let ret: Vec<String> = redis::cmd("SORT_RO").arg("_sort_store_src")
+        .arg("BY").arg("_sort_store_w_*").arg("LIMIT").arg(1).arg(2)
+        .query(&mut con)?;
+    compare_vec(vec!["2", "1"], ret)?;
+
+    let ret: i64 = redis::cmd("SORT").arg("_sort_store_src").arg("DESC")
+        .arg("STORE").arg("_sort_store_dst").query(&mut con)?;
+    compare_i64(3, ret)?;
+    let ret: Vec<String> = con.lrange("_sort_store_dst", 0, -1)?;
+    compare_vec(vec!["3", "2", "1"], ret)?;
+
+    let ret: i64 = redis::cmd("SORT").arg("_sort_store_nothing")
+        .arg("STORE").arg("_sort_store_dst").query(&mut con)?;
+    compare_i64(0, ret)?;
+    let ret: i64 = con.exists("_sort_store_dst")?;
+    compare_i64(0, ret)?;
+
+    Ok(())
+}
+
+fn test_multi_exec_watch(_input: Box<dyn TestInputData>) -> TestResult {
+    error!("ready to multi exec and watch");
+    let mut con = establish()?;
+    let mut other = establish()?;
+
+    let (a, b): (i64, i64) = redis::pipe().atomic()
+        .cmd("INCRBY").arg("_multi_key").arg(1)
+        .cmd("INCRBY").arg("_multi_key").arg(2)
+        .query(&mut con)?;
+    compare_i64(1, a)?;
+    compare_i64(3, b)?;
+
+    let _: () = redis::cmd("WATCH").arg("_multi_key").query(&mut con)?;
+    let _: () = other.set("_multi_key", 10)?;
+    let ret: Option<(i64, )> = redis::pipe().atomic()
+        .cmd("INCR").arg("_multi_key")
+        .query(&mut con)?;
+    is_nil(ret)?;
+    let ret: i64 = con.get("_multi_key")?;
+    compare_i64(10, ret)?;
+
+    let _: () = redis::cmd("MULTI").query(&mut con)?;
+    let _: () = redis::cmd("SET").arg("_multi_key").arg(20).query(&mut con)?;
+    let _: () = redis::cmd("DISCARD").query(&mut con)?;
+    let ret: i64 = con.get("_multi_key")?;
+    compare_i64(10, ret)?;
+
+    Ok(())
+}
+
+fn test_publish_subscribe(_input: Box<dyn TestInputData>) -> TestResult {
+    error!("ready to publish and subscribe");
+    let mut con = establish()?;
+    let mut sub_con = establish()?;
+    sub_con.set_read_timeout(Some(std::time::Duration::from_secs(3)))?;
+    let mut sub = sub_con.as_pubsub();
+    sub.subscribe("_pubsub_chan")?;
+    sub.psubscribe("_pubsub_pat*")?;
+
+    let ret: i64 = con.publish("_pubsub_chan", "hello")?;
+    compare_i64(1, ret)?;
+    let msg = sub.get_message()?;
+    compare("_pubsub_chan".to_string(), msg.get_channel_name().to_string())?;
+    compare("hello".to_string(), msg.get_payload()?)?;
+
+    let ret: i64 = con.publish("_pubsub_pattern", "world")?;
+    compare_i64(1, ret)?;
+    let msg = sub.get_message()?;
+    compare("_pubsub_pat*".to_string(), msg.get_pattern()?)?;
+    compare("world".to_string(), msg.get_payload()?)?;
+
+    let ret: i64 = con.publish("_pubsub_nobody", "lost")?;
+    compare_i64(0, ret)?;
+
+    Ok(())
+}
+
+fn test_sharded_publish_subscribe(_input: Box<dyn TestInputData>) -> TestResult {
+    error!("ready to sharded publish and subscribe");
+    let mut con = establish()?;
+    let mut sub_con = establish()?;
+    sub_con.set_read_timeout(Some(std::time::Duration::from_secs(3)))?;
+
+    let _: redis::Value = redis::cmd("SSUBSCRIBE").arg("_spubsub_chan").query(&mut sub_con)?;
+    let ret: Vec<(String, i64)> = redis::cmd("PUBSUB").arg("SHARDNUMSUB")
+        .arg("_spubsub_chan").query(&mut con)?;
+    compare_i64(1, ret[0].1)?;
+
+    let ret: i64 = con.publish("_spubsub_chan", "global")?;
+    compare_i64(0, ret)?;
+    let ret: i64 = redis::cmd("SPUBLISH").arg("_spubsub_chan").arg("shard")
+        .query(&mut con)?;
+    compare_i64(1, ret)?;
+
+    let msg: Vec<String> = redis::from_redis_value(&sub_con.recv_response()?)?;
+    compare_vec(vec!["smessage", "_spubsub_chan", "shard"], msg)?;
+
+    Ok(())
+}
+
+fn test_keyspace_notifications(_input: Box<dyn TestInputData>) -> TestResult {
+    error!("ready to receive keyspace notifications");
+    let mut con = establish()?;
+    let mut sub_con = establish()?;
+    sub_con.set_read_timeout(Some(std::time::Duration::from_secs(3)))?;
+    let mut sub = sub_con.as_pubsub();
+    sub.subscribe("__keyspace@0__:_notify_key")?;
+    sub.subscribe("__keyevent@0__:expired")?;
+
+    let _: () = con.set("_notify_key", 1)?;
+    let msg = sub.get_message()?;
+    compare("set".to_string(), msg.get_payload()?)?;
+
+    let _: () = con.rpush("_notify_key_list", "a")?;
+    let _: i64 = con.del("_notify_key")?;
+    let msg = sub.get_message()?;
+    compare("del".to_string(), msg.get_payload()?)?;
+
+    let _: () = con.set("_notify_key", 1)?;
+    let msg = sub.get_message()?;
+    compare("set".to_string(), msg.get_payload()?)?;
+    let _: i64 = redis::cmd("PEXPIREAT").arg("_notify_key").arg(1).query(&mut con)?;
+    let msg = sub.get_message()?;
+    compare("del".to_string(), msg.get_payload()?)?;
+
+    let _: () = con.set("_notify_key_expired", 1)?;
+    let _: i64 = con.expire("_notify_key_expired", 1)?;
+    let msg = sub.get_message()?;
+    compare("__keyevent@0__:expired".to_string(), msg.get_channel_name().to_string())?;
+    compare("_notify_key_expired".to_string(), msg.get_payload()?)?;
+
+    Ok(())
+}
+
+fn test_publish_from_eval(_input: Box<dyn TestInputData>) -> TestResult {
+    use std::io::Read;
+    error!("ready to publish to the caller of a script");
+
+    // a RESP3 client can run commands while subscribed, so the script
+    // publishes to the client that is running it
+    let mut stream = std::net::TcpStream::connect("127.0.0.1:6379")?;
+    stream.set_read_timeout(Some(Duration::from_secs(3)))?;
+    let mut request = Vec::new();
+    for args in [
+        vec!["HELLO", "3"],
+        vec!["SUBSCRIBE", "_eval_pub:ch", "__keyspace@0__:_eval_pub:k"],
+        vec!["EVAL", "return redis.call('publish', '_eval_pub:ch', 'x')", "0"],
+        vec!["EVAL", "return redis.call('set', '_eval_pub:k', 'v')", "0"],
+        vec!["PING"],
+    ].iter() {
+        request.extend(format!("*{}\r\n", args.len()).into_bytes());
+        for arg in args {
+            request.extend(format!("${}\r\n{}\r\n", arg.len(), arg).into_bytes());
+        }
+    }
+    stream.write_all(&request)?;
+
+    let expected = [
+        ":1\r\n",
+        ">3\r\n$7\r\nmessage\r\n$12\r\n_eval_pub:ch\r\n$1\r\nx\r\n",
+        "+OK\r\n",
+        ">3\r\n$7\r\nmessage\r\n$26\r\n__keyspace@0__:_eval_pub:k\r\n$3\r\nset\r\n",
+        "+PONG\r\n",
+    ];
+    let mut reply = vec![0u8; 4096];
+    let mut n = 0;
+    while !String::from_utf8_lossy(&reply[..n]).ends_with("+PONG\r\n") {
+        let read = stream.read(&mut reply[n..])?;
+        if read == 0 {
+            break;
+        }
+        n += read;
+    }
+    let reply = String::from_utf8_lossy(&reply[..n]).to_string();
+    if expected.iter().any(|e| !reply.contains(e)) {
+        return Err(Box::new(ReturnError { expected: expected.concat(), real: reply }));
+    }
+
+    let mut con = establish()?;
+    let _: () = con.del("_eval_pub:k")?;
+    Ok(())
+}
+
+fn test_client_tracking(_input: Box<dyn TestInputData>) -> TestResult {
+    error!("ready to track keys");
+    let mut con = establish()?;
+    let mut tracking_con = establish()?;
+    let mut sub_con = establish()?;
+    sub_con.set_read_timeout(Some(std::time::Duration::from_secs(3)))?;
+
+    let sub_id: i64 = redis::cmd("CLIENT").arg("ID").query(&mut sub_con)?;
+    let mut sub = sub_con.as_pubsub();
+    sub.subscribe("__redis__:invalidate")?;
+
+    let _: () = redis::cmd("CLIENT").arg("TRACKING").arg("ON")
+        .arg("REDIRECT").arg(sub_id).query(&mut tracking_con)?;
+    let ret: i64 = redis::cmd("CLIENT").arg("GETREDIR").query(&mut tracking_con)?;
+    compare_i64(sub_id, ret)?;
+
+    let _: Option<String> = tracking_con.get("_tracking_key")?;
+    let _: () = con.set("_tracking_untracked_key", 1)?;
+    let _: () = con.set("_tracking_key", 1)?;
+    let msg = sub.get_message()?;
+    compare("__redis__:invalidate".to_string(), msg.get_channel_name().to_string())?;
+    let keys: Vec<String> = msg.get_payload()?;
+    compare_vec(vec!["_tracking_key"], keys)?;
+
+    // tracking survives SELECT, the tracked keys are not bound to a database
+    let _: () = con.set("_tracking_key", 2)?;
+    let _: () = redis::cmd("SELECT").arg(1).query(&mut tracking_con)?;
+    let _: Option<String> = tracking_con.get("_tracking_key")?;
+    let _: () = con.set("_tracking_key", 3)?;
+    let msg = sub.get_message()?;
+    let keys: Vec<String> = msg.get_payload()?;
+    compare_vec(vec!["_tracking_key"], keys)?;
+
+    Ok(())
+}
+
+fn test_client_tracking_eval(_input: Box<dyn TestInputData>) -> TestResult {
+    error!("ready to track a key written by a script");
+    let mut tracking_con = establish()?;
+    let mut sub_con = establish()?;
+    sub_con.set_read_timeout(Some(std::time::Duration::from_secs(3)))?;
+
+    let sub_id: i64 = redis::cmd("CLIENT").arg("ID").query(&mut sub_con)?;
+    let mut sub = sub_con.as_pubsub();
+    sub.subscribe("__redis__:invalidate")?;
+
+    // the script writes the key while the tracking client is running EVAL
+    let _: () = redis::cmd("CLIENT").arg("TRACKING").arg("ON")
+        .arg("REDIRECT").arg(sub_id).query(&mut tracking_con)?;
+    let _: Option<String> = tracking_con.get("_tracking_eval_key")?;
+    let ret: String = redis::cmd("EVAL").arg("return redis.call('set', KEYS[1], 'v')")
+        .arg(1).arg("_tracking_eval_key").query(&mut tracking_con)?;
+    compare("OK", ret)?;
+    let msg = sub.get_message()?;
+    let keys: Vec<String> = msg.get_payload()?;
+    compare_vec(vec!["_tracking_eval_key"], keys)?;
+
+    let _: () = tracking_con.del("_tracking_eval_key")?;
+    Ok(())
+}
+
+fn test_client_name_list_kill(_input: Box<dyn TestInputData>) -> TestResult {
+    error!("ready to name and kill clients");
+    let mut con = establish()?;
+    let mut victim = establish()?;
+
+    let ret: Option<String> = redis::cmd("CLIENT").arg("GETNAME").query(&mut con)?;
+    is_nil(ret)?;
+    let _: () = redis::cmd("CLIENT").arg("SETNAME").arg("_named_client").query(&mut con)?;
+    let ret: String = redis::cmd("CLIENT").arg("GETNAME").query(&mut con)?;
+    compare("_named_client".to_string(), ret)?;
+    // the connection is shut down by the client library on error replies
+    let mut bad_name_con = establish()?;
+    let ret: redis::RedisResult<()> =
+        redis::cmd("CLIENT").arg("SETNAME").arg("bad name").query(&mut bad_name_con);
+    if ret.is_ok() {
+        return Err(Box::new(ReturnError {
+            expected: "error".to_string(),
+            real: "OK".to_string(),
+        }));
+    }
+
+    let id: i64 = redis::cmd("CLIENT").arg("ID").query(&mut con)?;
+    let list: String = redis::cmd("CLIENT").arg("LIST").arg("ID").arg(id).query(&mut con)?;
+    let expected = format!("id={} ", id);
+    if !list.starts_with(&expected) || !list.contains(" name=_named_client ") {
+        return Err(Box::new(ReturnError { expected, real: list }));
+    }
+
+    let victim_id: i64 = redis::cmd("CLIENT").arg("ID").query(&mut victim)?;
+    let ret: i64 = redis::cmd("CLIENT").arg("KILL").arg("ID").arg(victim_id).query(&mut con)?;
+    compare_i64(1, ret)?;
+    let ret: i64 = redis::cmd("CLIENT").arg("KILL").arg("ID").arg(victim_id).query(&mut con)?;
+    compare_i64(0, ret)?;
+
+    Ok(())
+}
+
+fn test_config_get_set(_input: Box<dyn TestInputData>) -> TestResult {
+    error!("ready to get and set config");
+    let mut con = establish()?;
+
+    let ret: Vec<String> = redis::cmd("CONFIG").arg("GET").arg("tracking-*").query(&mut con)?;
+    compare_vec(vec!["tracking-table-max-keys", "1000000"], ret)?;
+
+    let _: () = redis::cmd("CONFIG").arg("SET")
+        .arg("tracking-table-max-keys").arg(2000000).query(&mut con)?;
+    let ret: Vec<String> = redis::cmd("CONFIG").arg("GET")
+        .arg("tracking-table-max-keys").query(&mut con)?;
+    compare_vec(vec!["tracking-table-max-keys", "2000000"], ret)?;
+
+    // nothing is set when one of the values is invalid
+    let mut bad_con = establish()?;
+    let ret: redis::RedisResult<()> = redis::cmd("CONFIG").arg("SET")
+        .arg("tracking-table-max-keys").arg(1000000)
+        .arg("maxmemory").arg("nope").query(&mut bad_con);
+    if ret.is_ok() {
+        return Err(Box::new(ReturnError {
+            expected: "error".to_string(),
+            real: "OK".to_string(),
+        }));
+    }
+    let ret: Vec<String> = redis::cmd("CONFIG").arg("GET")
+        .arg("tracking-table-max-keys").query(&mut con)?;
+    compare_vec(vec!["tracking-table-max-keys", "2000000"], ret)?;
+
+    let _: () = redis::cmd("CONFIG").arg("SET")
+        .arg("tracking-table-max-keys").arg(1000000).query(&mut con)?;
+
+    // an invalid bind address leaves the listeners as they are
+    let mut bad_con = establish()?;
+    let ret: redis::RedisResult<()> = redis::cmd("CONFIG").arg("SET")
+        .arg("bind").arg("127.0.0.1 -not-an-address").query(&mut bad_con);
+    if ret.is_ok() {
+        return Err(Box::new(ReturnError {
+            expected: "error".to_string(),
+            real: "OK".to_string(),
+        }));
+    }
+    let ret: Vec<String> = redis::cmd("CONFIG").arg("GET").arg("bind").query(&mut con)?;
+    compare_vec(vec!["bind", BIND_ADDRS], ret)?;
+
+    Ok(())
+}
+
+fn test_info_sections(_input: Box<dyn TestInputData>) -> TestResult {
+    error!("ready to get info");
+    let mut con = establish()?;
+
+    let info: String = redis::cmd("INFO").query(&mut con)?;
+    for section in ["# Server", "# Clients", "# Memory", "# Stats", "# Keyspace"].iter() {
+        if !info.contains(section) {
+            return Err(Box::new(ReturnError { expected: section.to_string(), real: info }));
+        }
+    }
+
+    let info: String = redis::cmd("INFO").arg("stats").query(&mut con)?;
+    if !info.starts_with("# Stats\r\n") || info.contains("# Server") ||
+        !info.contains("keyspace_hits:") {
+        return Err(Box::new(ReturnError { expected: "# Stats".to_string(), real: info }));
+    }
+
+    Ok(())
+}
+
+fn test_slowlog(_input: Box<dyn TestInputData>) -> TestResult {
+    error!("ready to log slow commands");
+    let mut con = establish()?;
+
+    let _: () = redis::cmd("CONFIG").arg("SET")
+        .arg("slowlog-log-slower-than").arg(0).query(&mut con)?;
+    let _: () = redis::cmd("CLIENT").arg("SETNAME").arg("_slowlog_client").query(&mut con)?;
+    let _: () = con.set("_slowlog_key", "v")?;
+    let _: () = redis::cmd("CONFIG").arg("SET")
+        .arg("slowlog-log-slower-than").arg(10000).query(&mut con)?;
+
+    let len: i64 = redis::cmd("SLOWLOG").arg("LEN").query(&mut con)?;
+    if len < 2 {
+        return Err(Box::new(ReturnError { expected: ">= 2".to_string(), real: len.to_string() }));
+    }
+    let entries: Vec<redis::Value> = redis::cmd("SLOWLOG").arg("GET").arg(-1).query(&mut con)?;
+    let found = entries.iter().any(|entry| match entry {
+        redis::Value::Bulk(fields) if fields.len() == 6 => {
+            let argv: Vec<String> = redis::from_redis_value(&fields[3]).unwrap_or_default();
+            let name: String = redis::from_redis_value(&fields[5]).unwrap_or_default();
+            argv[..] == ["SET", "_slowlog_key", "v"] && name == "_slowlog_client"
+        }
+        _ => false,
+    });
+    if !found {
+        return Err(Box::new(ReturnError {
+            expected: "SET _slowlog_key v".to_string(),
+            real: format!("{:?}", entries),
+        }));
+    }
+
+    Ok(())
+}
+
+fn test_latency_monitor(_input: Box<dyn TestInputData>) -> TestResult {
+    error!("ready to check the latency monitor");
+    let mut con = establish()?;
+
+    let _: () = redis::cmd("CONFIG").arg("SET")
+        .arg("latency-monitor-threshold").arg(100).query(&mut con)?;
+    let ret: Vec<String> = redis::cmd("CONFIG").arg("GET")
+        .arg("latency-monitor-threshold").query(&mut con)?;
+    compare("100".to_string(), ret[1].clone())?;
+
+    let _: Vec<redis::Value> = redis::cmd("LATENCY").arg("LATEST").query(&mut con)?;
+    let _: Vec<redis::Value> = redis::cmd("LATENCY").arg("HISTORY").arg("fork").query(&mut con)?;
+    let report: String = redis::cmd("LATENCY").arg("DOCTOR").query(&mut con)?;
+    if !report.starts_with("Dave") {
+        return Err(Box::new(ReturnError { expected: "Dave...".to_string(), real: report }));
+    }
+    let ret: i64 = redis::cmd("LATENCY").arg("RESET").arg("_no_such_event").query(&mut con)?;
+    compare_i64(0, ret)?;
+
+    let _: () = redis::cmd("CONFIG").arg("SET")
+        .arg("latency-monitor-threshold").arg(0).query(&mut con)?;
+    Ok(())
+}
+
+fn test_command_introspection(_input: Box<dyn TestInputData>) -> TestResult {
+    error!("ready to inspect commands");
+    let mut con = establish()?;
+
+    let count: i64 = redis::cmd("COMMAND").arg("COUNT").query(&mut con)?;
+    let all: Vec<redis::Value> = redis::cmd("COMMAND").query(&mut con)?;
+    compare_i64(count, all.len() as i64)?;
+
+    let info: Vec<redis::Value> = redis::cmd("COMMAND").arg("INFO").arg("smove").query(&mut con)?;
+    let fields = match &info[0] {
+        redis::Value::Bulk(fields) => fields.clone(),
+        v => return Err(Box::new(ReturnError { expected: "smove info".to_string(), real: format!("{:?}", v) })),
+    };
+    let name: String = redis::from_redis_value(&fields[0])?;
+    compare("smove".to_string(), name)?;
+    let positions: Vec<i64> = fields[1..6].iter()
+        .filter_map(|f| redis::from_redis_value(f).ok())
+        .collect();
+    compare(format!("{:?}", [4, 1, 2, 1]), format!("{:?}", positions))?;
+
+    let names: Vec<String> = redis::cmd("COMMAND").arg("LIST")
+        .arg("FILTERBY").arg("ACLCAT").arg("transaction").query(&mut con)?;
+    compare(format!("{:?}", ["multi", "exec", "discard", "watch", "unwatch"]), format!("{:?}", names))?;
+
+    let keys: Vec<String> = redis::cmd("COMMAND").arg("GETKEYS")
+        .arg("del").arg("a").arg("b").query(&mut con)?;
+    compare(format!("{:?}", ["a", "b"]), format!("{:?}", keys))?;
+
+    let docs: Vec<redis::Value> = redis::cmd("COMMAND").arg("DOCS").arg("get").query(&mut con)?;
+    compare_i64(2, docs.len() as i64)?;
+
+    Ok(())
+}
+
+fn test_memory_usage_stats(_input: Box<dyn TestInputData>) -> TestResult {
+    error!("ready to check memory usage");
+    let mut con = establish()?;
+
+    let _: () = con.set("_memory_small", "v")?;
+    let _: () = con.set("_memory_big", "v".repeat(1000))?;
+    let small: i64 = redis::cmd("MEMORY").arg("USAGE").arg("_memory_small").query(&mut con)?;
+    let big: i64 = redis::cmd("MEMORY").arg("USAGE").arg("_memory_big")
+        .arg("SAMPLES").arg(0).query(&mut con)?;
+    if small <= 0 || big < small + 900 {
+        return Err(Box::new(ReturnError {
+            expected: "usage growing with the value".to_string(),
+            real: format!("{} and {}", small, big),
+        }));
+    }
+    let ret: Option<i64> = redis::cmd("MEMORY").arg("USAGE").arg("_memory_none").query(&mut con)?;
+    is_nil(ret)?;
+
+    let stats: Vec<redis::Value> = redis::cmd("MEMORY").arg("STATS").query(&mut con)?;
+    let names: Vec<String> = stats.iter()
+        .step_by(2)
+        .filter_map(|v| redis::from_redis_value(v).ok())
+        .collect();
+    for name in ["total.allocated", "clients.normal", "keys.count", "dataset.bytes"].iter() {
+        if !names.iter().any(|n| n == name) {
+            return Err(Box::new(ReturnError { expected: name.to_string(), real: format!("{:?}", names) }));
+        }
+    }
+    let _: String = redis::cmd("MEMORY").arg("DOCTOR").query(&mut con)?;
+
+    Ok(())
+}
+
+fn test_debug(_input: Box<dyn TestInputData>) -> TestResult {
+    error!("ready to debug");
+    let mut con = establish()?;
+
+    let _: () = redis::cmd("DEBUG").arg("POPULATE").arg(3).arg("_debug_pop").arg(10).query(&mut con)?;
+    let ret: Vec<u8> = con.get("_debug_pop:2")?;
+    compare(format!("{:?}", b"value:2\0\0\0"), format!("{:?}", ret))?;
+
+    let _: () = con.rpush("_debug_list_a", &["x", "y"])?;
+    let _: () = con.rpush("_debug_list_b", &["x", "y"])?;
+    let _: () = con.rpush("_debug_list_c", &["y", "x"])?;
+    let digests: Vec<String> = redis::cmd("DEBUG").arg("DIGEST-VALUE")
+        .arg("_debug_list_a").arg("_debug_list_b").arg("_debug_list_c").query(&mut con)?;
+    compare(digests[0].clone(), digests[1].clone())?;
+    if digests[0] == digests[2] {
+        return Err(Box::new(ReturnError { expected: "different digests".to_string(), real: digests[2].clone() }));
+    }
+
+    let ret: String = redis::cmd("DEBUG").arg("OBJECT").arg("_debug_list_a").query(&mut con)?;
+    if !ret.contains("encoding:ziplist") {
+        return Err(Box::new(ReturnError { expected: "encoding:ziplist".to_string(), real: ret }));
+    }
+    let _: String = redis::cmd("DEBUG").arg("JMAP").query(&mut con)?;
+
+    // a sleep that is no duration is refused, the connection is closed by redis-rs
+    for secs in ["inf", "1e30", "-1"].iter() {
+        let mut con = establish()?;
+        let ret: redis::RedisResult<()> = redis::cmd("DEBUG").arg("SLEEP").arg(*secs).query(&mut con);
+        match ret {
+            Err(e) if e.to_string().contains("value is not a valid float") => {}
+            _ => return Err(Box::new(ReturnError { expected: "invalid float".to_string(), real: format!("{:?}", ret) })),
+        }
+    }
+    let _: () = redis::cmd("DEBUG").arg("SLEEP").arg(0).query(&mut con)?;
+
+    Ok(())
+}
+
+fn test_acl_users(_input: Box<dyn TestInputData>) -> TestResult {
+    error!("ready to acl");
+    let mut con = establish()?;
+
+    let _: () = redis::cmd("ACL").arg("SETUSER").arg("_acl_user")
+        .arg("on").arg(">_acl_pass").arg("~_acl_key:*").arg("+@read").arg("+set").query(&mut con)?;
+    let users: Vec<String> = redis::cmd("ACL").arg("USERS").query(&mut con)?;
+    if !users.iter().any(|u| u == "_acl_user") {
+        return Err(Box::new(ReturnError { expected: "_acl_user".to_string(), real: format!("{:?}", users) }));
+    }
+
+    // the refused commands close the connection of redis-rs
+    let mut user = establish()?;
+    let ret: redis::RedisResult<()> = redis::cmd("AUTH").arg("_acl_user").arg("wrong").query(&mut user);
+    if ret.is_ok() {
+        return Err(Box::new(ReturnError { expected: "WRONGPASS".to_string(), real: "OK".to_string() }));
+    }
+
+    let mut user = establish()?;
+    let _: () = redis::cmd("AUTH").arg("_acl_user").arg("_acl_pass").query(&mut user)?;
+    let ret: String = redis::cmd("ACL").arg("WHOAMI").query(&mut user)
+        .or_else(|_| Ok::<String, redis::RedisError>("refused".to_string()))?;
+    compare("refused", ret)?;
+
+    let mut user = establish()?;
+    let _: () = redis::cmd("AUTH").arg("_acl_user").arg("_acl_pass").query(&mut user)?;
+    let _: () = user.set("_acl_key:1", "v")?;
+    let ret: String = user.get("_acl_key:1")?;
+    compare("v", ret)?;
+    let ret: redis::RedisResult<()> = user.set("_acl_other", "v");
+    if ret.is_ok() {
+        return Err(Box::new(ReturnError { expected: "NOPERM".to_string(), real: "OK".to_string() }));
+    }
+
+    let log: Vec<redis::Value> = redis::cmd("ACL").arg("LOG").query(&mut con)?;
+    if log.is_empty() {
+        return Err(Box::new(ReturnError { expected: "log entries".to_string(), real: "none".to_string() }));
+    }
+    let deleted: i64 = redis::cmd("ACL").arg("DELUSER").arg("_acl_user").query(&mut con)?;
+    compare_i64(1, deleted)?;
+
+    Ok(())
+}
+
+fn test_acl_key_permissions(_input: Box<dyn TestInputData>) -> TestResult {
+    error!("ready to check acl key permissions");
+    let mut con = establish()?;
+
+    let _: () = redis::cmd("ACL").arg("SETUSER").arg("_acl_writer")
+        .arg("on").arg(">_acl_pass").arg("%W~_acl_perm:*").arg("%R~_acl_perm:open:*")
+        .arg("+@all").query(&mut con)?;
+    let _: () = con.set("_acl_perm:s", "secret")?;
+    let _: () = con.rpush("_acl_perm:l", "secret")?;
+    let _: () = con.rpush("_acl_perm:open:l", 1)?;
+
+    // a write only key can not be read by a command that also writes it,
+    // the refused commands close the connection of redis-rs
+    for args in [vec!["GETDEL", "_acl_perm:s"], vec!["LPOP", "_acl_perm:l"]].iter() {
+        let mut user = establish()?;
+        let _: () = redis::cmd("AUTH").arg("_acl_writer").arg("_acl_pass").query(&mut user)?;
+        let ret: redis::RedisResult<String> = redis::cmd(args[0]).arg(args[1]).query(&mut user);
+        match ret {
+            Err(e) if e.to_string().contains("NOPERM") => {}
+            _ => return Err(Box::new(ReturnError { expected: "NOPERM".to_string(), real: format!("{:?}", ret) })),
+        }
+    }
+
+    // the keys read through the patterns of SORT are checked too
+    let mut user = establish()?;
+    let _: () = redis::cmd("AUTH").arg("_acl_writer").arg("_acl_pass").query(&mut user)?;
+    let ret: redis::RedisResult<Vec<String>> = redis::cmd("SORT").arg("_acl_perm:open:l")
+        .arg("BY").arg("nosort").arg("GET").arg("_acl_perm:*").query(&mut user);
+    match ret {
+        Err(e) if e.to_string().contains("GET option of SORT denied") => {}
+        _ => return Err(Box::new(ReturnError { expected: "GET denied".to_string(), real: format!("{:?}", ret) })),
+    }
+
+    let mut user = establish()?;
+    let _: () = redis::cmd("AUTH").arg("_acl_writer").arg("_acl_pass").query(&mut user)?;
+    let ret: Vec<String> = redis::cmd("SORT").arg("_acl_perm:open:l")
+        .arg("BY").arg("nosort").arg("GET").arg("#").query(&mut user)?;
+    compare_vec(vec!["1"], ret)?;
+
+    let deleted: i64 = redis::cmd("ACL").arg("DELUSER").arg("_acl_writer").query(&mut con)?;
+    compare_i64(1, deleted)?;
+    let _: () = con.del(&["_acl_perm:s", "_acl_perm:l", "_acl_perm:open:l"])?;
+    Ok(())
+}
+
+fn test_hello(_input: Box<dyn TestInputData>) -> TestResult {
+    error!("ready to hello");
+    let mut con = establish()?;
+
+    let ret: Vec<redis::Value> = redis::cmd("HELLO").arg(2).arg("SETNAME").arg("_hello_client")
+        .query(&mut con)?;
+    compare_i64(14, ret.len() as i64)?;
+    let proto: i64 = redis::from_redis_value(&ret[5])?;
+    compare_i64(2, proto)?;
+    let name: String = redis::cmd("CLIENT").arg("GETNAME").query(&mut con)?;
+    compare("_hello_client", name)?;
+
+    let mut other = establish()?;
+    let ret: redis::RedisResult<redis::Value> = redis::cmd("HELLO").arg(4).query(&mut other);
+    match ret {
+        Err(e) if e.to_string().contains("NOPROTO") => Ok(()),
+        _ => Err(Box::new(ReturnError { expected: "NOPROTO".to_string(), real: format!("{:?}", ret) })),
+    }
+}
+
+fn test_client_output_buffer_limit(_input: Box<dyn TestInputData>) -> TestResult {
+    error!("ready to overflow the output buffer of a subscriber");
+    let mut con = establish()?;
+
+    let _: () = redis::cmd("CONFIG").arg("SET")
+        .arg("client-output-buffer-limit").arg("pubsub 1mb 0 0").query(&mut con)?;
+    let ret: Vec<String> = redis::cmd("CONFIG").arg("GET")
+        .arg("client-output-buffer-limit").query(&mut con)?;
+    if !ret[1].contains("pubsub 1048576 0 0") {
+        return Err(Box::new(ReturnError { expected: "pubsub 1048576 0 0".to_string(), real: ret[1].clone() }));
+    }
+
+    // the subscriber never reads, it is closed once the messages fill the
+    // socket buffers and its output buffer goes over the limit
+    let mut sub_con = establish()?;
+    let _: redis::Value = redis::cmd("SUBSCRIBE").arg("_obuf_chan").query(&mut sub_con)?;
+    let message = "x".repeat(256 * 1024);
+    let start = std::time::Instant::now();
+    loop {
+        let ret: i64 = con.publish("_obuf_chan", &message)?;
+        if ret == 0 {
+            break;
+        }
+        if start.elapsed() > std::time::Duration::from_secs(5) {
+            return Err(Box::new(ReturnError { expected: "0".to_string(), real: ret.to_string() }));
+        }
+    }
+
+    let _: () = redis::cmd("CONFIG").arg("SET")
+        .arg("client-output-buffer-limit").arg("pubsub 32mb 8mb 60").query(&mut con)?;
+
+    Ok(())
+}
+
+// the reply to a raw request, the connection is closed after a protocol error
+fn raw_request(request: &[u8]) -> Result<String, Box<dyn Error>> {
+    use std::io::Read;
+    let mut stream = std::net::TcpStream::connect("127.0.0.1:6379")?;
+    stream.set_read_timeout(Some(Duration::from_secs(3)))?;
+    stream.write_all(request)?;
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply)?;
+    Ok(reply)
+}
+
+fn test_protocol_limits(_input: Box<dyn TestInputData>) -> TestResult {
+    error!("ready to send malformed requests");
+
+    let ret = raw_request(b"*1\r\n$x\r\n")?;
+    compare("-ERR Protocol error: invalid bulk length\r\n".to_string(), ret)?;
+    let ret = raw_request(b"*2000000\r\n")?;
+    compare("-ERR Protocol error: invalid multibulk length\r\n".to_string(), ret)?;
+    let ret = raw_request(b"*1\r\n+PING\r\n")?;
+    compare("-ERR Protocol error: expected '$', got '+'\r\n".to_string(), ret)?;
+    let ret = raw_request(&vec![b'x'; 70000])?;
+    compare("-ERR Protocol error: too big inline request\r\n".to_string(), ret)?;
+
+    Ok(())
+}
+
+fn test_big_arguments(_input: Box<dyn TestInputData>) -> TestResult {
+    let mut con = establish()?;
+
+    let value: Vec<u8> = (0..3 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+    let _: () = con.set("_big_arg:a", value.as_slice())?;
+    let ret: Vec<u8> = con.get("_big_arg:a")?;
+    if ret != value {
+        return Err(Box::new(ReturnError {
+            expected: format!("{} bytes value", value.len()),
+            real: format!("{} bytes value", ret.len()),
+        }));
+    }
+
+    // big and small arguments mixed in the same pipeline
+    let (a, b, c): (String, Vec<u8>, String) = redis::pipe()
+        .cmd("SET").arg("_big_arg:b").arg(&value[..40000])
+        .cmd("GET").arg("_big_arg:b")
+        .cmd("ECHO").arg("after")
+        .query(&mut con)?;
+    compare("OK", a)?;
+    compare_i64(40000, b.len() as i64)?;
+    compare("after", c)?;
+
+    let _: () = con.del(&["_big_arg:a", "_big_arg:b"])?;
+    Ok(())
+}
+
+fn test_long_pipeline(_input: Box<dyn TestInputData>) -> TestResult {
+    let mut con = establish()?;
+
+    let mut pipe = redis::pipe();
+    for _ in 0..20000 {
+        pipe.cmd("INCR").arg("_long_pipeline:n").ignore();
+    }
+    pipe.cmd("GET").arg("_long_pipeline:n");
+    let (n, ): (i64, ) = pipe.query(&mut con)?;
+    compare_i64(20000, n)?;
+
+    // the pipeline is run over several iterations of the event loop
+    let info: String = redis::cmd("INFO").arg("stats").query(&mut con)?;
+    let hits = info.lines()
+        .find_map(|l| l.strip_prefix("client_commands_cap_hits:"))
+        .and_then(|v| v.parse::<i64>().ok())
+        .unwrap_or(0);
+    if hits == 0 {
+        return Err(Box::new(ReturnError { expected: "client_commands_cap_hits > 0".to_string(), real: info }));
+    }
+
+    let _: () = con.del("_long_pipeline:n")?;
+    Ok(())
+}
+
+fn test_unix_socket(_input: Box<dyn TestInputData>) -> TestResult {
+    use std::io::Read;
+    let mut con = establish()?;
+
+    let mut stream = std::os::unix::net::UnixStream::connect(UNIX_SOCKET)?;
+    stream.set_read_timeout(Some(Duration::from_secs(3)))?;
+    stream.write_all(b"SET _unix_socket:k v\r\nCLIENT INFO\r\n")?;
+    let mut reply = vec![0u8; 1024];
+    let mut n = 0;
+    // +OK, then the bulk length and the line of the client
+    while reply[..n].windows(2).filter(|w| w == b"\r\n").count() < 3 {
+        n += stream.read(&mut reply[n..])?;
+    }
+    let reply = String::from_utf8_lossy(&reply[..n]).to_string();
+    if !reply.starts_with("+OK\r\n") || !reply.contains(&format!("addr={}:0", UNIX_SOCKET)) ||
+        !reply.contains("flags=U") {
+        return Err(Box::new(ReturnError { expected: "+OK and a unix socket client".to_string(), real: reply }));
+    }
+
+    // both listeners serve the same server
+    let ret: String = con.get("_unix_socket:k")?;
+    compare("v", ret)?;
+
+    let _: () = con.del("_unix_socket:k")?;
+    Ok(())
+}
+
+// the reply to PING on a new connection to the address
+fn ping_addr(addr: &str) -> Result<String, Box<dyn Error>> {
+    use std::io::Read;
+    let mut stream = std::net::TcpStream::connect(addr)?;
+    stream.set_read_timeout(Some(Duration::from_secs(3)))?;
+    stream.write_all(b"PING\r\n")?;
+    let mut reply = vec![0u8; 64];
+    let n = stream.read(&mut reply)?;
+    Ok(String::from_utf8_lossy(&reply[..n]).to_string())
+}
+
+fn ipv6_available() -> bool {
+    std::net::TcpListener::bind("[::1]:0").is_ok()
+}
+
+fn test_bind_addresses(_input: Box<dyn TestInputData>) -> TestResult {
+    let mut con = establish()?;
+
+    // the server started although 192.0.2.1 could not be bound
+    let ret: Vec<String> = redis::cmd("CONFIG").arg("GET").arg("bind").query(&mut con)?;
+    compare_vec(vec!["bind", BIND_ADDRS], ret)?;
+    compare("+PONG\r\n".to_string(), ping_addr("127.0.0.1:6379")?)?;
+    if ipv6_available() {
+        compare("+PONG\r\n".to_string(), ping_addr("[::1]:6379")?)?;
+    }
+    Ok(())
+}
+
+fn test_config_set_bind(_input: Box<dyn TestInputData>) -> TestResult {
+    let mut con = establish()?;
+
+    // without the IPv6 address its listener is closed
+    let _: () = redis::cmd("CONFIG").arg("SET").arg("bind").arg("127.0.0.1").query(&mut con)?;
+    compare("+PONG\r\n".to_string(), ping_addr("127.0.0.1:6379")?)?;
+    if ping_addr("[::1]:6379").is_ok() {
+        return Err(Box::new(ReturnError { expected: "[::1] refused".to_string(), real: "+PONG".to_string() }));
+    }
+
+    // the IPv6 wildcard takes the connections to ::1 again, and the current
+    // connection outlives the listener it came from
+    if ipv6_available() {
+        let _: () = redis::cmd("CONFIG").arg("SET").arg("bind").arg("127.0.0.1 ::*").query(&mut con)?;
+        compare("+PONG\r\n".to_string(), ping_addr("[::1]:6379")?)?;
+    }
+    let _: () = redis::cmd("CONFIG").arg("SET").arg("bind").arg(BIND_ADDRS).query(&mut con)?;
+    let ret: Vec<String> = redis::cmd("CONFIG").arg("GET").arg("bind").query(&mut con)?;
+    compare_vec(vec!["bind", BIND_ADDRS], ret)?;
+    compare("+PONG\r\n".to_string(), ping_addr("127.0.0.1:6379")?)?;
+    Ok(())
+}
+
+// a short timeout would close the idle connections of the other tests
+fn test_idle_subscriber(_input: Box<dyn TestInputData>) -> TestResult {
+    let mut con = establish()?;
+    let _: () = redis::cmd("CONFIG").arg("SET").arg("timeout").arg(1).query(&mut con)?;
+
+    let mut sub_con = establish()?;
+    sub_con.set_read_timeout(Some(Duration::from_secs(3)))?;
+    let mut sub = sub_con.as_pubsub();
+    sub.subscribe("_idle_sub_chan")?;
+    // idle clients are looked for every 10 seconds
+    sleep(Duration::from_secs(12));
+
+    let mut con = establish()?;
+    let receivers: i64 = con.publish("_idle_sub_chan", "still here")?;
+    compare_i64(1, receivers)?;
+    let msg = sub.get_message()?;
+    let payload: String = msg.get_payload()?;
+    compare("still here", payload)?;
+
+    let _: () = redis::cmd("CONFIG").arg("SET").arg("timeout").arg(300).query(&mut con)?;
+    Ok(())
+}
+
+fn keyspace_stats(con: &mut redis::Connection) -> Result<(i64, i64), Box<dyn Error>> {
+    let info: String = redis::cmd("INFO").arg("stats").query(con)?;
+    let stat = |name: &str| info.lines()
+        .find_map(|l| l.strip_prefix(name))
+        .and_then(|v| v.parse::<i64>().ok())
+        .unwrap_or(-1);
+    Ok((stat("keyspace_hits:"), stat("keyspace_misses:")))
+}
+
+// the other tests would move the counters
+fn test_keyspace_hits_misses(_input: Box<dyn TestInputData>) -> TestResult {
+    let mut con = establish()?;
+
+    // writing a missing key is no miss
+    let (hits, misses) = keyspace_stats(&mut con)?;
+    let _: () = con.lpush("_keyspace_stats:l", "v")?;
+    let _: () = con.sadd("_keyspace_stats:s", "v")?;
+    let _: () = redis::cmd("EXPIRE").arg("_keyspace_stats:none").arg(10).query(&mut con)?;
+    compare(format!("{:?}", (hits, misses)), format!("{:?}", keyspace_stats(&mut con)?))?;
+
+    let _: Option<String> = con.get("_keyspace_stats:none")?;
+    let _: Vec<String> = con.lrange("_keyspace_stats:l", 0, -1)?;
+    compare(format!("{:?}", (hits + 1, misses + 1)), format!("{:?}", keyspace_stats(&mut con)?))?;
+
+    let _: () = con.del(&["_keyspace_stats:l", "_keyspace_stats:s"])?;
+    Ok(())
+}
+
 fn shutdown() {
     let mut con = establish().unwrap();
     error!("Send shutdown command");